@@ -0,0 +1,268 @@
+//! Instruction builders that fill in accounts from the on-chain MangoGroup and MarginAccount
+//! so integrators don't have to assemble the account lists by hand. The ordering matches what
+//! each processor function expects, including the trailing secondary oracles when configured.
+//! Only built with the `client` feature
+
+use fixed::types::U64F64;
+use solana_program::account_info::AccountInfo;
+use solana_program::instruction::Instruction;
+use solana_program::program_error::ProgramError;
+use solana_program::pubkey::Pubkey;
+
+use crate::error::{MangoError, MangoErrorCode, MangoResult, SourceFileId};
+use crate::instruction::{borrow, deposit, deposit_native_sol, place_and_settle, settle_funds,
+                         with_secondary_oracles, withdraw, withdraw_native_sol};
+use crate::processor::{settle_all_borrows, socialize_liqee_loss};
+use crate::state::{load_open_orders, MangoGroup, MarginAccount, NUM_MARKETS, NUM_TOKENS, ONE_U64F64, QUOTE_INDEX};
+
+macro_rules! throw {
+    () => {
+        MangoError::MangoErrorCode {
+            mango_error_code: MangoErrorCode::Default,
+            line: line!(),
+            source_file_id: SourceFileId::Client
+        }
+    }
+}
+
+/// Serum dex accounts of one spot market, as found in its MarketState
+#[derive(Copy, Clone, Debug)]
+pub struct SpotMarketAccounts {
+    pub spot_market: Pubkey,
+    pub request_queue: Pubkey,
+    pub event_queue: Pubkey,
+    pub bids: Pubkey,
+    pub asks: Pubkey,
+    pub coin_vault: Pubkey,
+    pub pc_vault: Pubkey,
+    pub vault_signer: Pubkey,
+}
+
+fn has_secondary_oracles(mango_group: &MangoGroup) -> bool {
+    mango_group.oracles_secondary.iter().any(|pk| *pk != Pubkey::default())
+}
+
+/// Append the secondary oracles if the group has any configured
+fn finish(mango_group: &MangoGroup, instruction: Instruction) -> Instruction {
+    if has_secondary_oracles(mango_group) {
+        with_secondary_oracles(instruction, &mango_group.oracles_secondary)
+    } else {
+        instruction
+    }
+}
+
+/// Deposit quantity of the token at token_index. For a native SOL token, token_account_pk is
+/// ignored and the SOL is taken from owner_pk
+pub fn deposit_ix(
+    program_id: &Pubkey,
+    mango_group_pk: &Pubkey,
+    mango_group: &MangoGroup,
+    margin_account_pk: &Pubkey,
+    owner_pk: &Pubkey,
+    token_account_pk: &Pubkey,
+    token_index: usize,
+    quantity: u64
+) -> Result<Instruction, ProgramError> {
+    let vault_pk = &mango_group.vaults[token_index];
+    if mango_group.tokens[token_index] == spl_token::native_mint::ID {
+        deposit_native_sol(program_id, mango_group_pk, margin_account_pk, owner_pk, vault_pk, quantity)
+    } else {
+        deposit(program_id, mango_group_pk, margin_account_pk, owner_pk, token_account_pk, vault_pk, quantity)
+    }
+}
+
+/// Withdraw quantity of the token at token_index. For a native SOL token, token_account_pk is
+/// ignored and the SOL is sent to owner_pk
+pub fn withdraw_ix(
+    program_id: &Pubkey,
+    mango_group_pk: &Pubkey,
+    mango_group: &MangoGroup,
+    margin_account_pk: &Pubkey,
+    margin_account: &MarginAccount,
+    owner_pk: &Pubkey,
+    token_account_pk: &Pubkey,
+    token_index: usize,
+    quantity: u64
+) -> Result<Instruction, ProgramError> {
+    let vault_pk = &mango_group.vaults[token_index];
+    let instruction = if mango_group.tokens[token_index] == spl_token::native_mint::ID {
+        withdraw_native_sol(
+            program_id, mango_group_pk, margin_account_pk, owner_pk, vault_pk, &mango_group.signer_key,
+            &margin_account.open_orders, &mango_group.oracles, quantity
+        )?
+    } else {
+        withdraw(
+            program_id, mango_group_pk, margin_account_pk, owner_pk, token_account_pk, vault_pk,
+            &mango_group.signer_key, &margin_account.open_orders, &mango_group.oracles, quantity
+        )?
+    };
+    Ok(finish(mango_group, instruction))
+}
+
+/// Settle the free funds of the open orders account of market_index into the MarginAccount, then
+/// withdraw quantity of the token at token_index. Send both in one transaction so funds freed on
+/// the dex can be withdrawn without waiting for a separate SettleFunds to land
+pub fn settle_and_withdraw_ixs(
+    program_id: &Pubkey,
+    mango_group_pk: &Pubkey,
+    mango_group: &MangoGroup,
+    margin_account_pk: &Pubkey,
+    margin_account: &MarginAccount,
+    owner_pk: &Pubkey,
+    market_index: usize,
+    market: &SpotMarketAccounts,
+    token_account_pk: &Pubkey,
+    token_index: usize,
+    quantity: u64
+) -> Result<Vec<Instruction>, ProgramError> {
+    let settle_instruction = settle_funds(
+        program_id,
+        mango_group_pk,
+        owner_pk,
+        margin_account_pk,
+        &mango_group.dex_program_id,
+        &market.spot_market,
+        &margin_account.open_orders[market_index],
+        &mango_group.signer_key,
+        &market.coin_vault,
+        &market.pc_vault,
+        &mango_group.vaults[market_index],
+        &mango_group.vaults[QUOTE_INDEX],
+        &market.vault_signer
+    )?;
+    let withdraw_instruction = withdraw_ix(
+        program_id, mango_group_pk, mango_group, margin_account_pk, margin_account, owner_pk,
+        token_account_pk, token_index, quantity
+    )?;
+    Ok(vec![settle_instruction, withdraw_instruction])
+}
+
+/// Native deposits of the token at token_index plus what settling every open orders account would
+/// add to them: the free base of its market, or the free quote of all markets for the quote token.
+/// The most settle_and_withdraw_ixs can withdraw before the collateral ratio check
+pub fn get_withdrawable_with_free_funds(
+    mango_group: &MangoGroup,
+    margin_account: &MarginAccount,
+    open_orders_accs: &[AccountInfo; NUM_MARKETS],
+    token_index: usize
+) -> MangoResult<u64> {
+    let mut available = margin_account.get_native_deposit(&mango_group.indexes[token_index], token_index);
+    for i in 0..NUM_MARKETS {
+        if *open_orders_accs[i].key == Pubkey::default() || (token_index != QUOTE_INDEX && token_index != i) {
+            continue;
+        }
+        let open_orders = load_open_orders(&open_orders_accs[i])?;
+        let free = if token_index == QUOTE_INDEX { open_orders.native_pc_free } else { open_orders.native_coin_free };
+        available = available.checked_add(free).ok_or(throw!())?;
+    }
+    Ok(available)
+}
+
+pub fn borrow_ix(
+    program_id: &Pubkey,
+    mango_group_pk: &Pubkey,
+    mango_group: &MangoGroup,
+    margin_account_pk: &Pubkey,
+    margin_account: &MarginAccount,
+    owner_pk: &Pubkey,
+    token_index: usize,
+    quantity: u64
+) -> Result<Instruction, ProgramError> {
+    let instruction = borrow(
+        program_id, mango_group_pk, margin_account_pk, owner_pk, &margin_account.open_orders,
+        &mango_group.oracles, token_index, quantity
+    )?;
+    Ok(finish(mango_group, instruction))
+}
+
+/// Place an order on the spot market at market_index and settle it. open_orders_pk is the
+/// MarginAccount's open orders account for that market, which may not be saved on it yet
+pub fn place_and_settle_ix(
+    program_id: &Pubkey,
+    mango_group_pk: &Pubkey,
+    mango_group: &MangoGroup,
+    margin_account_pk: &Pubkey,
+    margin_account: &MarginAccount,
+    owner_pk: &Pubkey,
+    market_index: usize,
+    market: &SpotMarketAccounts,
+    open_orders_pk: &Pubkey,
+    order: serum_dex::instruction::NewOrderInstructionV3,
+    worst_fill: u64
+) -> Result<Instruction, ProgramError> {
+    let mut open_orders_pks = margin_account.open_orders;
+    open_orders_pks[market_index] = *open_orders_pk;
+
+    let instruction = place_and_settle(
+        program_id,
+        mango_group_pk,
+        owner_pk,
+        margin_account_pk,
+        &mango_group.dex_program_id,
+        &market.spot_market,
+        &market.request_queue,
+        &market.event_queue,
+        &market.bids,
+        &market.asks,
+        &mango_group.vaults[market_index],
+        &mango_group.vaults[QUOTE_INDEX],
+        &mango_group.signer_key,
+        &market.coin_vault,
+        &market.pc_vault,
+        &mango_group.srm_vault,
+        &market.vault_signer,
+        &open_orders_pks,
+        &mango_group.oracles,
+        order,
+        worst_fill
+    )?;
+    Ok(finish(mango_group, instruction))
+}
+
+/// The deposit_quantities to pass to Liquidate to bring margin_account back to init_coll_ratio.
+/// Runs the same borrow settling and loss socialization as liquidate on copies of the state,
+/// then sizes a quote deposit net of the liquidation_fee the liqor is paid back out of it.
+/// The liqor's bonus is liquidation_fee of the returned quote quantity. All zeros if the
+/// account is not liquidatable at unix timestamp now
+pub fn simulate_liquidation(
+    mango_group: &MangoGroup,
+    margin_account: &MarginAccount,
+    prices: &[U64F64; NUM_TOKENS],
+    open_orders_accs: &[AccountInfo; NUM_MARKETS],
+    now: u64
+) -> MangoResult<[u64; NUM_TOKENS]> {
+    let mut deposit_quantities = [0u64; NUM_TOKENS];
+    let mut mango_group = *mango_group;
+    let mut margin_account = *margin_account;
+
+    let coll_ratio = margin_account.get_collateral_ratio(&mango_group, prices, open_orders_accs, now)?;
+    if coll_ratio >= mango_group.maint_coll_ratio {
+        return Ok(deposit_quantities);
+    }
+
+    settle_all_borrows(&mut mango_group, &mut margin_account)?;
+    let (assets_val, liabs_val, coll_ratio) = margin_account.get_health(&mango_group, prices, open_orders_accs, now)?;
+    if coll_ratio >= mango_group.maint_coll_ratio {
+        return Ok(deposit_quantities);
+    }
+    if coll_ratio < ONE_U64F64 {
+        socialize_liqee_loss(&mut mango_group, &mut margin_account, assets_val, liabs_val)?;
+    }
+
+    let (assets_val, liabs_val, _) = margin_account.get_health(&mango_group, prices, open_orders_accs, now)?;
+    let target_val = liabs_val * mango_group.init_coll_ratio;
+    if assets_val >= target_val {
+        return Ok(deposit_quantities);
+    }
+
+    // each native quote deposited adds (1 - liquidation_fee) * coll_weight of assets
+    let net_weight = ONE_U64F64.checked_sub(mango_group.liquidation_fee).ok_or(throw!())?
+        * mango_group.coll_weights[QUOTE_INDEX];
+    let quantity = (target_val - assets_val)
+        .checked_div(net_weight).ok_or(throw!())?
+        .checked_ceil().ok_or(throw!())?;
+
+    // one extra native unit absorbs rounding through the deposit index
+    deposit_quantities[QUOTE_INDEX] = quantity.to_num::<u64>() + 1;
+    Ok(deposit_quantities)
+}
@@ -10,13 +10,17 @@ pub type MangoResult<T = ()> = Result<T, MangoError>;
 pub enum SourceFileId {
     Processor = 0,
     State = 1,
+    Client = 2,
+    Oracle = 3,
 }
 
 impl std::fmt::Display for SourceFileId {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             SourceFileId::Processor => write!(f, "src/processor.rs"),
-            SourceFileId::State => write!(f, "src/state.rs")
+            SourceFileId::State => write!(f, "src/state.rs"),
+            SourceFileId::Client => write!(f, "src/client.rs"),
+            SourceFileId::Oracle => write!(f, "src/oracle.rs")
         }
     }
 }
@@ -63,6 +67,62 @@ pub enum MangoErrorCode {
     InvalidMangoVault,
     #[error("MangoErrorCode::BeingLiquidated The margin account has restricted functionality while being liquidated")]
     BeingLiquidated,
+    #[error("MangoErrorCode::InvalidAccountVersion The account layout version does not match this program")]
+    InvalidAccountVersion,
+    #[error("MangoErrorCode::DepositLimitExceeded This instruction would exceed the deposit limit")]
+    DepositLimitExceeded,
+    #[error("MangoErrorCode::ReduceOnlyMarket This market only accepts orders that reduce an existing position")]
+    ReduceOnlyMarket,
+    #[error("MangoErrorCode::GroupHalted The MangoGroup is halted; only positions may be unwound")]
+    GroupHalted,
+    #[error("MangoErrorCode::VaultShortfall A vault holds less than the net deposits recorded for its token")]
+    VaultShortfall,
+    #[error("MangoErrorCode::OracleDivergence The primary and secondary oracle prices are too far apart")]
+    OracleDivergence,
+    #[error("MangoErrorCode::InvalidOracle The oracle account does not match the one configured for this market")]
+    InvalidOracle,
+    #[error("MangoErrorCode::InvalidOwner This account is not owned by the wallet address")]
+    InvalidOwner,
+    #[error("MangoErrorCode::DuplicateAccounts The same account was passed for two different roles")]
+    DuplicateAccounts,
+    #[error("MangoErrorCode::OrderTooSmall The order is worth less than the MangoGroup's min_order_notional")]
+    OrderTooSmall,
+    #[error("MangoErrorCode::InvalidTokenAccount The token account is not an spl token account for this token")]
+    InvalidTokenAccount,
+    #[error("MangoErrorCode::InvalidSpotMarket The spot market is not one of the MangoGroup's markets")]
+    InvalidSpotMarket,
+    #[error("MangoErrorCode::MangoGroupMismatch The MangoGroups do not have the same tokens")]
+    MangoGroupMismatch,
+    #[error("MangoErrorCode::OpenOrdersNotClosed Settle and close all open orders accounts first")]
+    OpenOrdersNotClosed,
+    #[error("MangoErrorCode::NotBankrupt The account still has assets or its collateral ratio is at least 1")]
+    NotBankrupt,
+    #[error("MangoErrorCode::InvalidAccountAddress The account is not at the address derived for it")]
+    InvalidAccountAddress,
+    #[error("MangoErrorCode::TooManyOpenOrders The MarginAccount already has max_orders_per_market orders in this market")]
+    TooManyOpenOrders,
+    #[error("MangoErrorCode::SlippageExceeded The settled fill is worse than worst_fill")]
+    SlippageExceeded,
+    #[error("MangoErrorCode::OracleDecimalsMismatch The oracle decimals differ from those cached in the MangoGroup")]
+    OracleDecimalsMismatch,
+    #[error("MangoErrorCode::OpenOrdersNotEmpty Cancel all orders and settle funds of the open orders account first")]
+    OpenOrdersNotEmpty,
+    #[error("MangoErrorCode::ExcessLiquidationDeposit The liquidator deposits are worth more than needed to reach init_coll_ratio")]
+    ExcessLiquidationDeposit,
+    #[error("MangoErrorCode::LiquidationCooldown The MarginAccount was liquidated within liquidation_cooldown and is above maint_coll_ratio")]
+    LiquidationCooldown,
+    #[error("MangoErrorCode::OracleConfidenceTooWide The oracle confidence interval is too wide relative to its price")]
+    OracleConfidenceTooWide,
+    #[error("MangoErrorCode::GroupConfigMismatch The vaults, mints, spot markets or oracles of the MangoGroup don't line up")]
+    GroupConfigMismatch,
+    #[error("MangoErrorCode::OpenInterestLimitExceeded This order would take the open interest of the market over its limit")]
+    OpenInterestLimitExceeded,
+    #[error("MangoErrorCode::PriceMoveTooLarge The oracle price moved further from the last recorded price than max_price_move_bps allows")]
+    PriceMoveTooLarge,
+    #[error("MangoErrorCode::SettleCapExceeded The free funds on the open orders account are over the settle caps")]
+    SettleCapExceeded,
+    #[error("MangoErrorCode::OracleStale The oracle price was published too many slots ago")]
+    OracleStale,
 
     #[error("MangoErrorCode::Default Check the source code for more info")]
     Default = u32::MAX_VALUE,
@@ -6,11 +6,13 @@ use bytemuck::{cast_slice, cast_slice_mut};
 use fixed::types::U64F64;
 use num_enum::TryFromPrimitive;
 use serde::{Deserialize, Serialize};
+use serum_dex::state::ToAlignedBytes;
 use solana_program::instruction::{AccountMeta, Instruction};
 use solana_program::program_error::ProgramError;
 use solana_program::pubkey::Pubkey;
 
 use crate::state::NUM_TOKENS;
+use crate::utils::{get_margin_account_address, get_wrapped_sol_address};
 
 #[repr(C)]
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
@@ -34,7 +36,9 @@ pub enum MangoInstruction {
     ///     vault_accs - Vault owned by signer_acc.key for each of the mints
     ///
     /// 7+2*NUM_TOKENS..7+2*NUM_TOKENS+NUM_MARKETS `[]`
-    ///     spot_market_accs - MarketState account from serum dex for each of the spot markets
+    ///     spot_market_accs - MarketState account from serum dex for each of the spot markets.
+    ///     Pass the default pubkey (and the default pubkey as its oracle) to leave a market slot
+    ///     empty until AddSpotMarket
     /// 7+2*NUM_TOKENS+NUM_MARKETS..7+2*NUM_TOKENS+2*NUM_MARKETS `[]`
     ///     oracle_accs - Solana Flux Aggregator accounts corresponding to each trading pair
     InitMangoGroup {
@@ -65,6 +69,14 @@ pub enum MangoInstruction {
     /// 4. `[writable]` vault_acc - TokenAccount owned by MangoGroup
     /// 5. `[]` token_prog_acc - acc pointed to by SPL token program id
     /// 6. `[]` clock_acc - Clock sysvar account
+    ///
+    /// If the token is native SOL, token_account_acc must be the margin account's temporary
+    /// wrapped SOL address (see `get_wrapped_sol_address`), owner_acc must be writable and pays
+    /// quantity lamports, and three more accounts are expected (see `deposit_native_sol`):
+    ///
+    /// 7. `[]` system_prog_acc - System program
+    /// 8. `[]` native_mint_acc - wrapped SOL mint
+    /// 9. `[]` rent_acc - Rent sysvar account
     Deposit {
         quantity: u64
     },
@@ -84,6 +96,10 @@ pub enum MangoInstruction {
     /// 8..8+NUM_MARKETS `[]` open_orders_accs - open orders for each of the spot market
     /// 8+NUM_MARKETS..8+2*NUM_MARKETS `[]`
     ///     oracle_accs - flux aggregator feed accounts
+    ///
+    /// If the token is native SOL, token_account_acc must be the margin account's temporary
+    /// wrapped SOL address, owner_acc must be writable and receives the SOL, and the system
+    /// program, wrapped SOL mint and rent sysvar accounts follow (see `withdraw_native_sol`)
     Withdraw {
         quantity: u64
     },
@@ -119,19 +135,26 @@ pub enum MangoInstruction {
 
     /// Take over a MarginAccount that is below init_coll_ratio by depositing funds
     ///
-    /// Accounts expected by this instruction (5 + 2 * NUM_MARKETS + 2 * NUM_TOKENS):
+    /// The liquidator is paid liquidation_fee of each token deposited out of the liqee's deposits.
+    /// The account's borrows are then netted against its deposits, so the liquidator takes over a
+    /// net position
+    ///
+    /// Deposits worth more than the collateral deficit plus LIQ_DEPOSIT_TOLERANCE are rejected
+    ///
+    /// Accounts expected by this instruction (6 + 2 * NUM_MARKETS + 2 * NUM_TOKENS):
     ///
     /// 0. `[writable]` mango_group_acc - MangoGroup that this margin account is for
     /// 1. `[signer]` liqor_acc - liquidator's solana account
     /// 2. `[writable]` liqee_margin_account_acc - MarginAccount of liquidatee
-    /// 3. `[]` token_prog_acc - SPL token program id
-    /// 4. `[]` clock_acc - Clock sysvar account
-    /// 5..5+NUM_MARKETS `[]` open_orders_accs - open orders for each of the spot market
-    /// 5+NUM_MARKETS..5+2*NUM_MARKETS `[]`
+    /// 3. `[]` signer_acc - MangoGroup signer key
+    /// 4. `[]` token_prog_acc - SPL token program id
+    /// 5. `[]` clock_acc - Clock sysvar account
+    /// 6..6+NUM_MARKETS `[]` open_orders_accs - open orders for each of the spot market
+    /// 6+NUM_MARKETS..6+2*NUM_MARKETS `[]`
     ///     oracle_accs - flux aggregator feed accounts
-    /// 5+2*NUM_MARKETS..5+2*NUM_MARKETS+NUM_TOKENS `[writable]`
+    /// 6+2*NUM_MARKETS..6+2*NUM_MARKETS+NUM_TOKENS `[writable]`
     ///     vault_accs - MangoGroup vaults
-    /// 5+2*NUM_MARKETS+NUM_TOKENS..5+2*NUM_MARKETS+2*NUM_TOKENS `[writable]`
+    /// 6+2*NUM_MARKETS+NUM_TOKENS..6+2*NUM_MARKETS+2*NUM_TOKENS `[writable]`
     ///     liqor_token_account_accs - Liquidator's token wallets
     Liquidate {
         /// Quantity of each token liquidator is depositing in order to bring account above maint
@@ -228,7 +251,7 @@ pub enum MangoInstruction {
     ///
     /// 0. `[writable]` mango_group_acc - MangoGroup that this margin account is for
     /// 1. `[signer]` owner_acc - MarginAccount owner
-    /// 2. `[]` margin_account_acc - MarginAccount
+    /// 2. `[writable]` margin_account_acc - MarginAccount
     /// 3. `[]` clock_acc - Clock sysvar account
     /// 4. `[]` dex_prog_acc - program id of serum dex
     /// 5. `[writable]` spot_market_acc - serum dex MarketState
@@ -247,7 +270,7 @@ pub enum MangoInstruction {
     ///
     /// 0. `[writable]` mango_group_acc - MangoGroup that this margin account is for
     /// 1. `[signer]` owner_acc - MarginAccount owner
-    /// 2. `[]` margin_account_acc - MarginAccount
+    /// 2. `[writable]` margin_account_acc - MarginAccount
     /// 3. `[]` clock_acc - Clock sysvar account
     /// 4. `[]` dex_prog_acc - program id of serum dex
     /// 5. `[writable]` spot_market_acc - serum dex MarketState
@@ -262,6 +285,7 @@ pub enum MangoInstruction {
 
     /// Change the borrow limit using admin key. This will not affect any open positions on any MarginAccount
     /// This is intended to be an instruction only in alpha stage while liquidity is slowly improved
+    /// Ends the token's part of any borrow limit ramp at borrow_limit (see SetBorrowLimitRamp)
     ///
     /// Accounts expected by this instruction (2):
     ///
@@ -273,6 +297,10 @@ pub enum MangoInstruction {
     },
 
     /// Place an order on the Serum Dex and settle funds from the open orders account
+    /// With the fill-log feature (on by default) this logs a line of the form
+    /// `MANGO-FILL market=<usize> side=<bid|ask> in_token=<usize> native_in=<u64> out_token=<usize>
+    /// native_out=<i128> borrowed=<bool> ratio=<U64F64>` (see `format_fill_log`)
+    /// The instruction fails if the quote vault moved past worst_fill once funds are settled
     ///
     /// Accounts expected by this instruction (19 + 2 * NUM_MARKETS):
     ///
@@ -299,10 +327,16 @@ pub enum MangoInstruction {
     /// 19+NUM_MARKETS..19+2*NUM_MARKETS `[]`
     ///     oracle_accs - flux aggregator feed accounts
     PlaceAndSettle {
-        order: serum_dex::instruction::NewOrderInstructionV3
+        order: serum_dex::instruction::NewOrderInstructionV3,
+        /// For asks the least native quote that must be received, for bids the most native quote
+        /// that may be spent. 0 means no check. Optional in the instruction data; missing means 0
+        worst_fill: u64
     },
 
-    /// Allow a liquidator to cancel open orders and settle to recoup funds for partial liquidation
+    /// Allow anyone to cancel open orders of an account below maint_coll_ratio, or of one already
+    /// being liquidated and still below init_coll_ratio, and settle funds to recoup funds for
+    /// partial liquidation. An account being liquidated that is back at init_coll_ratio has its
+    /// being_liquidated flag cleared instead
     ///
     /// Accounts expected by this instruction (16 + 2 * NUM_MARKETS):
     ///
@@ -326,6 +360,8 @@ pub enum MangoInstruction {
     /// 16+NUM_MARKETS..16+2*NUM_MARKETS `[]`
     ///     oracle_accs - flux aggregator feed accounts
     ForceCancelOrders {
+        /// Index of the spot market whose orders should be cancelled
+        market_index: usize,
         /// Max orders to cancel -- could be useful to lower this if running into compute limits
         /// Recommended: 5
         limit: u8
@@ -353,190 +389,1188 @@ pub enum MangoInstruction {
         max_deposit: u64
     },
 
-}
-
+    /// Change the portion of borrow interest kept by the protocol as reserves using admin key
+    ///
+    /// Accounts expected by this instruction (2):
+    ///
+    /// 0. `[writable]` mango_group_acc - MangoGroup to change
+    /// 1. `[signer]` admin_acc - admin of the MangoGroup
+    ChangeReserveFactor {
+        /// Must be between 0 and 1 inclusive
+        reserve_factor: U64F64
+    },
 
-impl MangoInstruction {
-    pub fn unpack(input: &[u8]) -> Option<Self> {
-        let (&discrim, data) = array_refs![input, 4; ..;];
-        let discrim = u32::from_le_bytes(discrim);
-        Some(match discrim {
-            0 => {
-                let data = array_ref![data, 0, 40 + 8 * NUM_TOKENS];
-                let (
-                    signer_nonce,
-                    maint_coll_ratio,
-                    init_coll_ratio,
-                    borrow_limits
-                ) = array_refs![data, 8, 16, 16, 8 * NUM_TOKENS];
+    /// Sweep the accrued reserves of one token out of the vault using admin key
+    ///
+    /// Accounts expected by this instruction (7):
+    ///
+    /// 0. `[writable]` mango_group_acc - MangoGroup to sweep fees from
+    /// 1. `[signer]` admin_acc - admin of the MangoGroup
+    /// 2. `[writable]` token_account_acc - TokenAccount which will be receiving the fees
+    /// 3. `[writable]` vault_acc - TokenAccount owned by MangoGroup which will be sending
+    /// 4. `[]` signer_acc - acc pointed to by signer_key
+    /// 5. `[]` token_prog_acc - acc pointed to by SPL token program id
+    /// 6. `[]` clock_acc - Clock sysvar account
+    WithdrawFees,
 
-                let mut aligned_borrow_limits = [0u64; NUM_TOKENS];
-                let buffer: &mut [u8] = cast_slice_mut(&mut aligned_borrow_limits);
-                buffer.copy_from_slice(borrow_limits);
+    /// Move a v0 or v1 MarginAccount (MARGIN_ACCOUNT_V1_SIZE bytes) to a new account of the
+    /// current size. The old bytes are copied over, the new fields start zeroed and the old
+    /// account is closed, refunding its lamports to the owner. Clients must use the new address
+    ///
+    /// Accounts expected by this instruction (5):
+    ///
+    /// 0. `[]` mango_group_acc - MangoGroup that this margin account is for, v1 or current
+    /// 1. `[writable]` old_margin_account_acc - the v0 or v1 margin account to close
+    /// 2. `[writable]` new_margin_account_acc - zeroed, rent exempt and owned by the program
+    /// 3. `[signer, writable]` owner_acc - owner of the margin account, receives the old lamports
+    /// 4. `[]` rent_acc - Rent sysvar account
+    UpgradeMarginAccount,
+
+    /// Change the portion of a token's deposit value that counts as collateral using admin key
+    ///
+    /// Accounts expected by this instruction (2):
+    ///
+    /// 0. `[writable]` mango_group_acc - MangoGroup to change
+    /// 1. `[signer]` admin_acc - admin of the MangoGroup
+    ChangeCollWeight {
+        token_index: usize,
+        /// Must be between 0 and 1 inclusive
+        coll_weight: U64F64
+    },
 
-                MangoInstruction::InitMangoGroup {
-                    signer_nonce: u64::from_le_bytes(*signer_nonce),
-                    maint_coll_ratio: U64F64::from_le_bytes(*maint_coll_ratio),
-                    init_coll_ratio: U64F64::from_le_bytes(*init_coll_ratio),
-                    borrow_limits: aligned_borrow_limits
-                }
-            }
-            1 => {
+    /// Change the multiplier applied to a token's borrow value when counting liabilities using admin key
+    ///
+    /// Accounts expected by this instruction (2):
+    ///
+    /// 0. `[writable]` mango_group_acc - MangoGroup to change
+    /// 1. `[signer]` admin_acc - admin of the MangoGroup
+    ChangeLiabWeight {
+        token_index: usize,
+        /// Must be at least 1
+        liab_weight: U64F64
+    },
 
-                MangoInstruction::InitMarginAccount
-            },
-            2 => {
-                let quantity = array_ref![data, 0, 8];
-                MangoInstruction::Deposit { quantity: u64::from_le_bytes(*quantity) }
-            },
-            3 => {
-                let data = array_ref![data, 0, 8];
-                MangoInstruction::Withdraw {
-                    quantity: u64::from_le_bytes(*data)
-                }
-            },
-            4 => {
-                let data = array_ref![data, 0, 16];
-                let (token_index, quantity) = array_refs![data, 8, 8];
+    /// Change the limit on total deposits of a token using admin key. Existing deposits are not
+    /// affected, but no new deposits are accepted while the total is above the limit
+    ///
+    /// Accounts expected by this instruction (2):
+    ///
+    /// 0. `[writable]` mango_group_acc - MangoGroup to change
+    /// 1. `[signer]` admin_acc - admin of the MangoGroup
+    ChangeDepositLimit {
+        token_index: usize,
+        /// 0 means unlimited
+        deposit_limit: u64
+    },
 
-                MangoInstruction::Borrow {
-                    token_index: usize::from_le_bytes(*token_index),
-                    quantity: u64::from_le_bytes(*quantity)
-                }
-            },
-            5 => {
-                let data = array_ref![data, 0, 16];
-                let (token_index, quantity) = array_refs![data, 8, 8];
+    /// Change the bonus paid to liquidators using admin key
+    ///
+    /// Accounts expected by this instruction (2):
+    ///
+    /// 0. `[writable]` mango_group_acc - MangoGroup to change
+    /// 1. `[signer]` admin_acc - admin of the MangoGroup
+    ChangeLiquidationFee {
+        /// Portion of each token deposited by the liquidator paid back to them; must be less than 1
+        liquidation_fee: U64F64
+    },
 
-                MangoInstruction::SettleBorrow {
-                    token_index: usize::from_le_bytes(*token_index),
-                    quantity: u64::from_le_bytes(*quantity)
-                }
-            },
-            6 => {
-                if data.len() < 8 * NUM_TOKENS { return None; }
-                let data = array_ref![data, 0, 8 * NUM_TOKENS];
+    /// Change the maintenance and initial collateral ratios using admin key
+    /// maint_coll_ratio may only be raised by MAX_MAINT_COLL_RATIO_STEP at a time
+    ///
+    /// Accounts expected by this instruction (2):
+    ///
+    /// 0. `[writable]` mango_group_acc - MangoGroup to change
+    /// 1. `[signer]` admin_acc - admin of the MangoGroup
+    SetCollRatios {
+        /// Must be greater than 1
+        maint_coll_ratio: U64F64,
+        /// Must be at least maint_coll_ratio
+        init_coll_ratio: U64F64
+    },
 
-                let mut aligned_arr = [0u64; NUM_TOKENS];
-                let buffer: &mut [u8] = cast_slice_mut(&mut aligned_arr);
-                buffer.copy_from_slice(data);
+    /// Put a spot market in or out of reduce only mode using admin key
+    /// In reduce only mode orders may only close out existing positions and may not borrow.
+    /// Settling, cancelling and liquidations are unaffected
+    ///
+    /// Accounts expected by this instruction (2):
+    ///
+    /// 0. `[writable]` mango_group_acc - MangoGroup to change
+    /// 1. `[signer]` admin_acc - admin of the MangoGroup
+    SetMarketMode {
+        market_index: usize,
+        reduce_only: bool
+    },
 
-                let deposit_quantities: &[u64] = cast_slice(buffer);
-                let deposit_quantities = array_ref![deposit_quantities, 0, NUM_TOKENS];
-                MangoInstruction::Liquidate {
-                    deposit_quantities: *deposit_quantities
-                }
-            },
-            7 => {
-                let quantity = array_ref![data, 0, 8];
-                MangoInstruction::DepositSrm { quantity: u64::from_le_bytes(*quantity) }
-            }
-            8 => {
-                let quantity = array_ref![data, 0, 8];
-                MangoInstruction::WithdrawSrm { quantity: u64::from_le_bytes(*quantity) }
-            }
-            9 => {
-                let data_arr = array_ref![data, 0, 46];
-                let order = unpack_dex_new_order_v3(data_arr)?;
-                MangoInstruction::PlaceOrder {
-                    order
-                }
+    /// Halt or resume the MangoGroup using admin key
+    /// While halted Deposit, Withdraw, Borrow, PlaceOrder and PlaceAndSettle fail.
+    /// Liquidations, settles and cancels still work so positions can be unwound
+    ///
+    /// Accounts expected by this instruction (2):
+    ///
+    /// 0. `[writable]` mango_group_acc - MangoGroup to change
+    /// 1. `[signer]` admin_acc - admin of the MangoGroup
+    SetHalt {
+        is_halted: bool
+    },
 
-            },
-            10 => {
-                MangoInstruction::SettleFunds
-            },
-            11 => {
-                let data_array = array_ref![data, 0, 20];
-                let fields = array_refs![data_array, 4, 16];
-                let side = match u32::from_le_bytes(*fields.0) {
-                    0 => serum_dex::matching::Side::Bid,
-                    1 => serum_dex::matching::Side::Ask,
-                    _ => return None,
-                };
-                let order_id = u128::from_le_bytes(*fields.1);
-                let order = serum_dex::instruction::CancelOrderInstructionV2 {
-                    side,
-                    order_id,
-                };
+    /// Log the assets, liabilities and collateral ratio of a MarginAccount at current prices.
+    /// Makes no state changes. The log line has the form
+    /// `MANGO-HEALTH assets=<U64F64> liabs=<U64F64> ratio=<U64F64>` with 6 decimals (see format_u64f64)
+    ///
+    /// Accounts expected by this instruction (3 + 2 * NUM_MARKETS):
+    ///
+    /// 0. `[]` mango_group_acc - MangoGroup that this margin account is for
+    /// 1. `[]` margin_account_acc - MarginAccount to inspect
+    /// 2. `[]` clock_acc - Clock sysvar account
+    /// 3..3+NUM_MARKETS `[]` open_orders_accs - open orders for each of the spot market
+    /// 3+NUM_MARKETS..3+2*NUM_MARKETS `[]`
+    ///     oracle_accs - flux aggregator feed accounts
+    GetMarginAccountDetails,
 
-                MangoInstruction::CancelOrder {
-                    order
-                }
-            },
-            12 => {
-                let client_id = array_ref![data, 0, 8];
-                MangoInstruction::CancelOrderByClientId {
-                    client_id: u64::from_le_bytes(*client_id)
-                }
+    /// Check that every vault holds at least the net deposits (deposits - borrows) recorded for
+    /// its token. Logs each shortfall and fails if there is any. Makes no state changes
+    ///
+    /// Accounts expected by this instruction (1 + NUM_TOKENS):
+    ///
+    /// 0. `[]` mango_group_acc - MangoGroup to audit
+    /// 1..1+NUM_TOKENS `[]` vault_accs - MangoGroup vaults
+    AuditVaults,
 
-            }
-            13 => {
-                let data = array_ref![data, 0, 16];
-                let (token_index, borrow_limit) = array_refs![data, 8, 8];
-                MangoInstruction::ChangeBorrowLimit {
-                    token_index: usize::from_le_bytes(*token_index),
-                    borrow_limit: u64::from_le_bytes(*borrow_limit)
-                }
-            }
-            14 => {
-                let data_arr = array_ref![data, 0, 46];
-                let order = unpack_dex_new_order_v3(data_arr)?;
-                MangoInstruction::PlaceAndSettle {
-                    order
-                }
-            }
-            15 => {
-                let limit = array_ref![data, 0, 1];
-                MangoInstruction::ForceCancelOrders {
-                    limit: u8::from_le_bytes(*limit)
-                }
-            }
-            16 => {
-                let max_deposit = array_ref![data, 0, 8];
-                MangoInstruction::PartialLiquidate {
-                    max_deposit: u64::from_le_bytes(*max_deposit)
-                }
-            }
-            _ => { return None; }
-        })
-    }
-    pub fn pack(&self) -> Vec<u8> {
-        bincode::serialize(self).unwrap()
-    }
-}
+    /// Set or clear the secondary oracle of a spot market using admin key. When set, every
+    /// instruction that reads prices must be passed the NUM_MARKETS secondary oracle accounts
+    /// after all of its other accounts (see `with_secondary_oracles`)
+    ///
+    /// Accounts expected by this instruction (3):
+    ///
+    /// 0. `[writable]` mango_group_acc - MangoGroup to change
+    /// 1. `[signer]` admin_acc - admin of the MangoGroup
+    /// 2. `[]` oracle_acc - flux aggregator feed account; default pubkey to clear
+    SetSecondaryOracle {
+        market_index: usize,
+        /// Max difference between the two prices relative to the lower one, e.g. 0.02 for 2%
+        max_divergence: U64F64
+    },
 
+    /// Change the admin key of the MangoGroup using the current admin key.
+    /// If new_admin_acc signs, it becomes the admin right away. Otherwise it is only proposed
+    /// and must sign AcceptAdmin before it takes over, so an unusable key can't lock out the group
+    ///
+    /// Accounts expected by this instruction (3):
+    ///
+    /// 0. `[writable]` mango_group_acc - MangoGroup to change
+    /// 1. `[signer]` admin_acc - admin of the MangoGroup
+    /// 2. `[]` new_admin_acc - new admin; may optionally be a signer
+    SetAdmin,
 
-fn unpack_dex_new_order_v3(data: &[u8; 46]) -> Option<serum_dex::instruction::NewOrderInstructionV3> {
-    let (
-        &side_arr,
-        &price_arr,
-        &max_coin_qty_arr,
-        &max_native_pc_qty_arr,
-        &self_trade_behavior_arr,
-        &otype_arr,
-        &client_order_id_bytes,
-        &limit_arr,
-    ) = array_refs![data, 4, 8, 8, 8, 4, 4, 8, 2];
+    /// Become the admin of the MangoGroup after being proposed by SetAdmin
+    ///
+    /// Accounts expected by this instruction (2):
+    ///
+    /// 0. `[writable]` mango_group_acc - MangoGroup to change
+    /// 1. `[signer]` pending_admin_acc - admin proposed by SetAdmin
+    AcceptAdmin,
+
+    /// Change the dust threshold of a token using admin key. Whenever a deposit or borrow is
+    /// reduced and less than dust_threshold native units remain, the remainder is zeroed.
+    /// Dust deposits go to fees_accrued and dust borrows are written off against fees_accrued,
+    /// with whatever fees_accrued can't cover added to bad_debt
+    ///
+    /// Accounts expected by this instruction (2):
+    ///
+    /// 0. `[writable]` mango_group_acc - MangoGroup to change
+    /// 1. `[signer]` admin_acc - admin of the MangoGroup
+    ChangeDustThreshold {
+        token_index: usize,
+        /// 0 means off
+        dust_threshold: u64
+    },
 
-    let side = serum_dex::matching::Side::try_from_primitive(u32::from_le_bytes(side_arr).try_into().ok()?).ok()?;
-    let limit_price = NonZeroU64::new(u64::from_le_bytes(price_arr))?;
-    let max_coin_qty = NonZeroU64::new(u64::from_le_bytes(max_coin_qty_arr))?;
-    let max_native_pc_qty_including_fees =
-        NonZeroU64::new(u64::from_le_bytes(max_native_pc_qty_arr))?;
-    let self_trade_behavior = serum_dex::instruction::SelfTradeBehavior::try_from_primitive(
-        u32::from_le_bytes(self_trade_behavior_arr)
-            .try_into()
-            .ok()?,
-    )
-        .ok()?;
-    let order_type = serum_dex::matching::OrderType::try_from_primitive(u32::from_le_bytes(otype_arr).try_into().ok()?).ok()?;
-    let client_order_id = u64::from_le_bytes(client_order_id_bytes);
-    let limit = u16::from_le_bytes(limit_arr);
+    /// Same as Deposit, but also records referrer_acc as the referrer of the MarginAccount
+    /// if it doesn't have one yet. The referrer has no effect on margin calculations
+    ///
+    /// Accounts expected by this instruction (8):
+    ///
+    /// 0..7 - same accounts as Deposit
+    /// 7. `[]` referrer_acc - referrer to record
+    /// 8.. - native SOL accounts if needed, same as Deposit
+    DepositWithReferrer {
+        quantity: u64
+    },
 
-    Some(serum_dex::instruction::NewOrderInstructionV3 {
-        side,
-        limit_price,
+    /// Unwind the position of a MarginAccount below maint_coll_ratio (or already being liquidated
+    /// and below init_coll_ratio) in one spot market on the serum dex, for when no liquidator takes
+    /// it. Orders are cancelled and settled, then an IOC order is placed with the mango signer that
+    /// buys back borrowed base with quote deposits or sells base deposits to repay quote borrows, at
+    /// most FORCE_LIQ_MAX_SLIPPAGE away from the oracle price. A thin book leaves a partial unwind;
+    /// send the instruction again to continue
+    ///
+    /// Accounts expected by this instruction (19 + 2 * NUM_MARKETS):
+    ///
+    /// 0. `[writable]` mango_group_acc - MangoGroup that this margin account is for
+    /// 1. `[signer]` liqor_acc - caller's solana account
+    /// 2. `[writable]` liqee_margin_account_acc - MarginAccount to unwind
+    /// 3. `[]` clock_acc - Clock sysvar account
+    /// 4. `[]` dex_prog_acc - program id of serum dex
+    /// 5. `[writable]` spot_market_acc - serum dex MarketState
+    /// 6. `[writable]` dex_request_queue_acc - serum dex request queue for this market
+    /// 7. `[writable]` dex_event_queue - serum dex event queue for this market
+    /// 8. `[writable]` bids_acc - serum dex bids for this market
+    /// 9. `[writable]` asks_acc - serum dex asks for this market
+    /// 10. `[writable]` base_vault_acc - mango vault for base currency
+    /// 11. `[writable]` quote_vault_acc - mango vault for quote currency
+    /// 12. `[]` signer_acc - mango signer key
+    /// 13. `[writable]` dex_base_acc - serum dex market's vault for base (coin) currency
+    /// 14. `[writable]` dex_quote_acc - serum dex market's vault for quote (pc) currency
+    /// 15. `[]` spl token program
+    /// 16. `[]` the rent sysvar
+    /// 17. `[writable]` srm_vault_acc - MangoGroup's srm_vault used for fee reduction
+    /// 18. `[]` dex_signer_acc - signer for serum dex MarketState
+    /// 19..19+NUM_MARKETS `[writable]` open_orders_accs - open orders for each of the spot market
+    /// 19+NUM_MARKETS..19+2*NUM_MARKETS `[]`
+    ///     oracle_accs - flux aggregator feed accounts
+    ForceLiquidateOnDex {
+        /// Index of the spot market to unwind in
+        market_index: usize,
+        /// Max orders to cancel first; see ForceCancelOrders
+        limit: u8
+    },
+
+    /// Add a spot market and its oracle to a market slot left empty at InitMangoGroup.
+    /// The market must trade tokens[market_index] against the quote token
+    ///
+    /// Accounts expected by this instruction (4):
+    ///
+    /// 0. `[writable]` mango_group_acc - MangoGroup to change
+    /// 1. `[signer]` admin_acc - admin of the MangoGroup
+    /// 2. `[]` spot_market_acc - serum dex MarketState
+    /// 3. `[]` oracle_acc - flux aggregator feed for the market
+    AddSpotMarket {
+        market_index: usize
+    },
+
+    /// Change the min value in native quote, at the oracle price, of orders placed by
+    /// PlaceOrder and PlaceAndSettle using admin key
+    ///
+    /// Accounts expected by this instruction (2):
+    ///
+    /// 0. `[writable]` mango_group_acc - MangoGroup to change
+    /// 1. `[signer]` admin_acc - admin of the MangoGroup
+    ChangeMinOrderNotional {
+        /// 0 means off
+        min_order_notional: u64
+    },
+
+    /// Cancel all of the owner's orders in one spot market, up to limit orders. Funds stay in
+    /// the open orders account until SettleFunds
+    ///
+    /// Accounts expected by this instruction (11):
+    ///
+    /// 0..11 - same accounts as CancelOrder
+    CancelAllMyOrders {
+        market_index: usize,
+        /// Max orders to cancel; bounded by compute
+        limit: u8
+    },
+
+    /// Same as Deposit, but funder_acc may be anyone, e.g. a service topping up client accounts.
+    /// The deposit is credited to margin_account_acc whoever owns it
+    ///
+    /// Accounts expected by this instruction (7):
+    ///
+    /// 0. `[writable]` mango_group_acc - MangoGroup that this margin account is for
+    /// 1. `[writable]` margin_account_acc - the margin account to credit
+    /// 2. `[signer]` funder_acc - authority of token_account_acc
+    /// 3. `[writable]` token_account_acc - TokenAccount which will be sending the funds
+    /// 4. `[writable]` vault_acc - TokenAccount owned by MangoGroup
+    /// 5. `[]` token_prog_acc - acc pointed to by SPL token program id
+    /// 6. `[]` clock_acc - Clock sysvar account
+    /// 7.. - native SOL accounts if needed, same as Deposit
+    DepositForAccount {
+        quantity: u64
+    },
+
+    /// Same as SettleBorrow, but settles just enough of this token's borrow to bring the
+    /// collateral ratio up to target_ratio. Nothing is settled if the ratio is already there or
+    /// settling this token can't raise it
+    ///
+    /// Accounts expected by this instruction (4 + 2 * NUM_MARKETS):
+    ///
+    /// 0..4 - same accounts as SettleBorrow
+    /// 4..4+NUM_MARKETS `[]` open_orders_accs - open orders for each of the spot market
+    /// 4+NUM_MARKETS..4+2*NUM_MARKETS `[]`
+    ///     oracle_accs - flux aggregator feed accounts
+    SettleBorrowToRatio {
+        token_index: usize,
+        /// Must be above 1
+        target_ratio: U64F64
+    },
+
+    /// Crank the serum dex event queue of a market for open orders accounts owned by the mango
+    /// signer, so fills show up in their native_coin_total and native_pc_total. Serum stops at
+    /// the first event whose open orders account is not passed in
+    ///
+    /// Accounts expected by this instruction (6 + number of open orders accounts):
+    ///
+    /// 0. `[]` mango_group_acc - MangoGroup the market belongs to
+    /// 1. `[]` dex_prog_acc - program id of serum dex
+    /// 2. `[writable]` spot_market_acc - serum dex MarketState
+    /// 3. `[writable]` dex_event_queue_acc - serum dex event queue for this market
+    /// 4. `[writable]` coin_fee_receivable_acc - passed through to serum dex
+    /// 5. `[writable]` pc_fee_receivable_acc - passed through to serum dex
+    /// 6.. `[writable]` open_orders_accs - open orders owned by the mango signer, sorted
+    ///     as serum expects (see `consume_events`)
+    ConsumeEvents {
+        limit: u16
+    },
+
+    /// Change the max native borrow of a single MarginAccount in a token using admin key.
+    /// Checked by Borrow, PlaceOrder and PlaceAndSettle; existing borrows are not affected
+    ///
+    /// Accounts expected by this instruction (2):
+    ///
+    /// 0. `[writable]` mango_group_acc - MangoGroup to change
+    /// 1. `[signer]` admin_acc - admin of the MangoGroup
+    ChangeMaxBorrowPerAccount {
+        token_index: usize,
+        /// 0 means no cap
+        max_borrow: u64
+    },
+
+    /// Log the total value of deposits and borrows across all tokens of a MangoGroup at current
+    /// prices. Makes no state changes. The log line has the form
+    /// `MANGO-SOLVENCY deposits=<U64F64> borrows=<U64F64>` with 6 decimals (see format_u64f64)
+    ///
+    /// Accounts expected by this instruction (2 + NUM_MARKETS):
+    ///
+    /// 0. `[]` mango_group_acc - MangoGroup to inspect
+    /// 1. `[]` clock_acc - Clock sysvar account
+    /// 2..2+NUM_MARKETS `[]` oracle_accs - flux aggregator feed accounts
+    CheckSolvency,
+
+    /// Change how long new deposits count at a reduced collateral weight using admin key.
+    /// Deposits younger than grace_period seconds count at GRACE_COLL_WEIGHT of the token's
+    /// coll weight; only the amount deposited is reduced, not what the account already held.
+    /// 0 turns this off
+    ///
+    /// Accounts expected by this instruction (2):
+    ///
+    /// 0. `[writable]` mango_group_acc - MangoGroup to change
+    /// 1. `[signer]` admin_acc - admin of the MangoGroup
+    ChangeCollateralGracePeriod {
+        grace_period: u64
+    },
+
+    /// Move a MarginAccount from a MangoGroup to a new MangoGroup with the same tokens in the same
+    /// order. Deposits and borrows are carried over at their native value, and the net of each
+    /// token moves between the two groups' vaults. All open orders accounts must be settled and
+    /// closed first. The new group's deposit limits, borrow limits and init_coll_ratio must hold
+    /// for the account once moved, as if it had deposited and borrowed there. The old group may
+    /// still have the v1 layout, so accounts can leave groups that can't be loaded anymore
+    ///
+    /// Accounts expected by this instruction (8 + 2 * NUM_TOKENS + NUM_MARKETS):
+    ///
+    /// 0. `[writable]` old_mango_group_acc - MangoGroup the margin account is in now
+    /// 1. `[writable]` new_mango_group_acc - MangoGroup to move the margin account to
+    /// 2. `[writable]` margin_account_acc - the margin account to migrate
+    /// 3. `[signer]` owner_acc - owner of the margin account
+    /// 4. `[]` old_signer_acc - signer_key of old_mango_group_acc
+    /// 5. `[]` new_signer_acc - signer_key of new_mango_group_acc
+    /// 6. `[]` token_prog_acc - acc pointed to by SPL token program id
+    /// 7. `[]` clock_acc - Clock sysvar account
+    /// 8..8+NUM_TOKENS `[writable]` old_vault_accs - vaults of old_mango_group_acc
+    /// 8+NUM_TOKENS..8+2*NUM_TOKENS `[writable]` new_vault_accs - vaults of new_mango_group_acc
+    /// 8+2*NUM_TOKENS..8+2*NUM_TOKENS+NUM_MARKETS `[]`
+    ///     oracle_accs - flux aggregator feed accounts of new_mango_group_acc
+    MigrateMarginAccount,
+
+    /// Write off all borrows of a MarginAccount with no deposits, nothing in open orders and a
+    /// collateral ratio below 1. Lenders of each borrowed token take the loss through the deposit
+    /// index, up to MAX_SOCIALIZED_LOSS of their deposits; the rest comes out of fees_accrued or
+    /// is recorded in bad_debt. Anyone may call this
+    ///
+    /// Accounts expected by this instruction (3 + 2 * NUM_MARKETS):
+    ///
+    /// 0. `[writable]` mango_group_acc - MangoGroup that this margin account is for
+    /// 1. `[writable]` margin_account_acc - the bankrupt margin account
+    /// 2. `[]` clock_acc - Clock sysvar account
+    /// 3..3+NUM_MARKETS `[]` open_orders_accs - open orders for each of the spot market
+    /// 3+NUM_MARKETS..3+2*NUM_MARKETS `[]`
+    ///     oracle_accs - flux aggregator feed accounts
+    ResolveBankruptcy,
+
+    /// Same as SettleFunds, but settles only if no more than max_base and max_quote native would
+    /// be credited to the MarginAccount. Serum dex can't settle part of the free funds, so the caps
+    /// are not applied to the settled amount: if either free balance is over its cap the
+    /// instruction succeeds without settling and both balances stay on the open orders account
+    /// where new orders can use them
+    ///
+    /// Accounts expected by this instruction are the same as SettleFunds (14)
+    SettleFundsPartial {
+        max_base: u64,
+        max_quote: u64
+    },
+
+    /// Create and initialize a margin account at the program address derived from the MangoGroup
+    /// and owner (see `get_margin_account_address`). bump must be the canonical bump seed
+    ///
+    /// Accounts expected by this instruction (5):
+    ///
+    /// 0. `[]` mango_group_acc - MangoGroup that this margin account is for
+    /// 1. `[writable]` margin_account_acc - the derived margin account address
+    /// 2. `[writable, signer]` owner_acc - owner of the margin account; pays the rent
+    /// 3. `[]` system_prog_acc - System program
+    /// 4. `[]` rent_acc - Rent sysvar account
+    InitMarginAccountPda {
+        bump: u8
+    },
+
+    /// Start moving the borrow limit of every token linearly to its entry in targets over
+    /// ramp_seconds using admin key. All tokens share one ramp. It starts from the current limits,
+    /// so a running ramp is replaced without a jump. While a ramp is set, Borrow, PlaceOrder and
+    /// PlaceAndSettle enforce the ramped limit on each MarginAccount's borrows of every token;
+    /// pass the current limit as the target of a token that shouldn't move. ramp_seconds of 0
+    /// stops the ramp at the current limits
+    ///
+    /// Accounts expected by this instruction (3):
+    ///
+    /// 0. `[writable]` mango_group_acc - MangoGroup to change
+    /// 1. `[signer]` admin_acc - admin of the MangoGroup
+    /// 2. `[]` clock_acc - Clock sysvar account
+    SetBorrowLimitRamp {
+        targets: [u64; NUM_TOKENS],
+        ramp_seconds: u64
+    },
+
+    /// Change the max number of orders a MarginAccount may have in one market using admin key.
+    /// Checked by PlaceOrder and PlaceAndSettle; orders already resting are not affected
+    ///
+    /// Accounts expected by this instruction (2):
+    ///
+    /// 0. `[writable]` mango_group_acc - MangoGroup to change
+    /// 1. `[signer]` admin_acc - admin of the MangoGroup
+    ChangeMaxOrdersPerMarket {
+        /// 0 means no cap
+        max_orders: u64
+    },
+
+    /// Bring the MangoGroup's indexes up to date and log each token's index. Makes no other state
+    /// changes. There is one log line per token of the form
+    /// `MANGO-INDEX token=<usize> borrow=<U64F64> deposit=<U64F64> last_update=<u64>` with 12 decimals
+    ///
+    /// Accounts expected by this instruction (2):
+    ///
+    /// 0. `[writable]` mango_group_acc - MangoGroup to log
+    /// 1. `[]` clock_acc - Clock sysvar account
+    LogIndexes,
+
+    /// Change the borrow limit of every token using admin key. Same as ChangeBorrowLimit for each
+    /// token, and cancels any borrow limit ramp
+    ///
+    /// Accounts expected by this instruction (2):
+    ///
+    /// 0. `[writable]` mango_group_acc - MangoGroup to change
+    /// 1. `[signer]` admin_acc - admin of the MangoGroup
+    ChangeAllBorrowLimits {
+        limits: [u64; NUM_TOKENS]
+    },
+
+    /// Deposit several tokens into a margin account at once. Entries of quantities that are 0 are
+    /// skipped and their token accounts aren't touched. Native SOL must already be wrapped
+    ///
+    /// Accounts expected by this instruction (5 + 2 * NUM_TOKENS):
+    ///
+    /// 0. `[writable]` mango_group_acc - MangoGroup that this margin account is for
+    /// 1. `[writable]` margin_account_acc - the margin account for this user
+    /// 2. `[signer]` owner_acc - Solana account of owner of the margin account
+    /// 3. `[]` token_prog_acc - acc pointed to by SPL token program id
+    /// 4. `[]` clock_acc - Clock sysvar account
+    /// 5..5+NUM_TOKENS `[writable]` token_account_accs - TokenAccounts owned by user which will be
+    ///     sending the funds, one per token
+    /// 5+NUM_TOKENS..5+2*NUM_TOKENS `[writable]` vault_accs - MangoGroup vaults
+    DepositMulti {
+        quantities: [u64; NUM_TOKENS]
+    },
+
+    /// Re-read the decimals of each market's flux aggregator and update the cached
+    /// oracle_decimals using admin key. Use this if the decimals of an aggregator were changed
+    ///
+    /// Accounts expected by this instruction (2 + NUM_MARKETS):
+    ///
+    /// 0. `[writable]` mango_group_acc - MangoGroup to change
+    /// 1. `[signer]` admin_acc - admin of the MangoGroup
+    /// 2..2+NUM_MARKETS `[]` oracle_accs - flux aggregator feed accounts
+    RefreshOracleDecimals,
+
+    /// Withdraw SRM in the MangoGroup's srm_vault that isn't owed to any MangoSrmAccount, e.g.
+    /// fee rebates, using admin key. SRM deposited by users can't be withdrawn this way
+    ///
+    /// Accounts expected by this instruction (6):
+    ///
+    /// 0. `[writable]` mango_group_acc - MangoGroup to withdraw from
+    /// 1. `[signer]` admin_acc - admin of the MangoGroup
+    /// 2. `[writable]` srm_account_acc - TokenAccount which will be receiving the SRM
+    /// 3. `[writable]` vault_acc - SRM vault of MangoGroup
+    /// 4. `[]` signer_acc - acc pointed to by signer_key
+    /// 5. `[]` token_prog_acc - acc pointed to by SPL token program id
+    WithdrawExcessSrm {
+        quantity: u64
+    },
+
+    /// Close the open orders account of a spot market through the dex and clear it from the
+    /// margin account so a new one can be used. The open orders account must have no orders
+    /// and no funds left; its rent goes to the owner
+    ///
+    /// Accounts expected by this instruction (7):
+    ///
+    /// 0. `[]` mango_group_acc - MangoGroup that this margin account is for
+    /// 1. `[writable, signer]` owner_acc - MarginAccount owner, receives the rent
+    /// 2. `[writable]` margin_account_acc - MarginAccount
+    /// 3. `[]` dex_prog_acc - program id of serum dex
+    /// 4. `[]` spot_market_acc - dex MarketState account
+    /// 5. `[writable]` open_orders_acc - open orders account for this market to close
+    /// 6. `[]` signer_acc - MangoGroup signer key
+    CloseOpenOrders {
+        market_index: usize
+    },
+
+    /// Change the max seconds of interest accrued per index update using admin key
+    ///
+    /// Accounts expected by this instruction (2):
+    ///
+    /// 0. `[writable]` mango_group_acc - MangoGroup to change
+    /// 1. `[signer]` admin_acc - admin of the MangoGroup
+    ChangeMaxAccrualInterval {
+        /// 0 means no cap
+        max_accrual_interval: u64
+    },
+
+    /// Initialize the open orders account of a spot market through the dex ahead of the first
+    /// order and record it on the margin account, so PlaceOrder and PlaceAndSettle don't have to.
+    /// The open orders account must be allocated and owned by the dex, and the slot empty
+    ///
+    /// Accounts expected by this instruction (8):
+    ///
+    /// 0. `[]` mango_group_acc - MangoGroup that this margin account is for
+    /// 1. `[signer]` owner_acc - MarginAccount owner
+    /// 2. `[writable]` margin_account_acc - MarginAccount
+    /// 3. `[]` dex_prog_acc - program id of serum dex
+    /// 4. `[]` spot_market_acc - dex MarketState account
+    /// 5. `[writable]` open_orders_acc - uninitialized open orders account for this market
+    /// 6. `[]` signer_acc - MangoGroup signer key
+    /// 7. `[]` rent_acc - Rent sysvar account
+    InitOpenOrders {
+        market_index: usize
+    },
+
+    /// Change the liquidation cooldown using admin key
+    ///
+    /// Accounts expected by this instruction (2):
+    ///
+    /// 0. `[writable]` mango_group_acc - MangoGroup to change
+    /// 1. `[signer]` admin_acc - admin of the MangoGroup
+    ChangeLiquidationCooldown {
+        /// 0 means off
+        liquidation_cooldown: u64
+    },
+
+    /// Replace the primary oracle of an added spot market using admin key. Flux aggregators cache
+    /// their decimals; Pyth prices are rescaled to the quote decimals and rejected when their
+    /// confidence interval is wider than PYTH_MAX_CONF_RATIO of the price or they were published
+    /// more than PYTH_MAX_SLOT_AGE slots ago
+    ///
+    /// Accounts expected by this instruction (3):
+    ///
+    /// 0. `[writable]` mango_group_acc - MangoGroup to change
+    /// 1. `[signer]` admin_acc - admin of the MangoGroup
+    /// 2. `[]` oracle_acc - flux aggregator feed or Pyth price account
+    SetOracle {
+        market_index: usize,
+        /// OracleType as u8
+        oracle_type: u8
+    },
+
+    /// Settle the free funds on the open orders account of a MarginAccount below maint_coll_ratio
+    /// into its deposits, so liquidators see the collateral that was locked on the dex
+    ///
+    /// Accounts expected by this instruction (13 + 2 * NUM_MARKETS):
+    ///
+    /// 0. `[writable]` mango_group_acc - MangoGroup that this margin account is for
+    /// 1. `[signer]` liqor_acc - liquidator's solana account
+    /// 2. `[writable]` liqee_margin_account_acc - MarginAccount of liquidatee
+    /// 3. `[writable]` base_vault_acc - mango vault for base currency
+    /// 4. `[writable]` quote_vault_acc - mango vault for quote currency
+    /// 5. `[writable]` spot_market_acc - serum dex MarketState
+    /// 6. `[]` signer_acc - mango signer key
+    /// 7. `[writable]` dex_base_acc - serum dex market's vault for base (coin) currency
+    /// 8. `[writable]` dex_quote_acc - serum dex market's vault for quote (pc) currency
+    /// 9. `[]` dex_signer_acc - signer for serum dex MarketState
+    /// 10. `[]` token_prog_acc - SPL token program
+    /// 11. `[]` dex_prog_acc - Serum dex program id
+    /// 12. `[]` clock_acc - Clock sysvar account
+    /// 13..13+NUM_MARKETS `[writable]` open_orders_accs - open orders for each of the spot market
+    /// 13+NUM_MARKETS..13+2*NUM_MARKETS `[]`
+    ///     oracle_accs - flux aggregator feed accounts
+    ForceSettleFunds {
+        /// Index of the spot market whose funds should be settled
+        market_index: usize
+    },
+
+    /// Re-run the mint, vault, spot market and oracle checks of InitMangoGroup against the
+    /// current configuration of the MangoGroup. Logs each discrepancy and fails if there is any.
+    /// Makes no state changes
+    ///
+    /// Accounts expected by this instruction (1 + 2 * NUM_TOKENS + 2 * NUM_MARKETS):
+    ///
+    /// 0. `[]` mango_group_acc - MangoGroup to verify
+    /// 1..1+NUM_TOKENS `[]` token_mint_accs - mint of each token, quote last
+    /// 1+NUM_TOKENS..1+2*NUM_TOKENS `[]` vault_accs - MangoGroup vaults
+    /// 1+2*NUM_TOKENS..1+2*NUM_TOKENS+NUM_MARKETS `[]` spot_market_accs - MarketState of each market
+    /// 1+2*NUM_TOKENS+NUM_MARKETS..1+2*NUM_TOKENS+2*NUM_MARKETS `[]`
+    ///     oracle_accs - flux aggregator feed or Pyth price accounts
+    VerifyGroupConfig,
+
+    /// Change the open interest limit of a spot market using admin key. Orders that don't reduce
+    /// a position are rejected while they would take the market over it; see check_open_interest
+    ///
+    /// Accounts expected by this instruction (2):
+    ///
+    /// 0. `[writable]` mango_group_acc - MangoGroup to change
+    /// 1. `[signer]` admin_acc - admin of the MangoGroup
+    ChangeOiLimit {
+        market_index: usize,
+        /// In native quote; 0 means no cap
+        oi_limit: u64
+    },
+
+    /// Cancel an order using dex instruction, then settle the freed funds into the MarginAccount
+    /// deposits like SettleFunds
+    ///
+    /// Accounts expected by this instruction (17):
+    ///
+    /// 0. `[writable]` mango_group_acc - MangoGroup that this margin account is for
+    /// 1. `[signer]` owner_acc - MarginAccount owner
+    /// 2. `[writable]` margin_account_acc - MarginAccount
+    /// 3. `[]` clock_acc - Clock sysvar account
+    /// 4. `[]` dex_prog_acc - program id of serum dex
+    /// 5. `[writable]` spot_market_acc - serum dex MarketState
+    /// 6. `[writable]` bids_acc - serum dex bids
+    /// 7. `[writable]` asks_acc - serum dex asks
+    /// 8. `[writable]` open_orders_acc - OpenOrders for the market this order belongs to
+    /// 9. `[]` signer_acc - MangoGroup signer key
+    /// 10. `[writable]` dex_event_queue_acc - serum dex event queue for this market
+    /// 11. `[writable]` dex_base_acc - base vault for dex MarketState
+    /// 12. `[writable]` dex_quote_acc - quote vault for dex MarketState
+    /// 13. `[writable]` base_vault_acc - MangoGroup base vault acc
+    /// 14. `[writable]` quote_vault_acc - MangoGroup quote vault acc
+    /// 15. `[]` dex_signer_acc - dex Market signer account
+    /// 16. `[]` spl token program
+    CancelOrderAndSettle {
+        order: serum_dex::instruction::CancelOrderInstructionV2
+    },
+
+    /// Change the max relative move of an oracle price between reads using admin key. Every
+    /// market's last recorded price is cleared, so the next read of each starts the band afresh
+    ///
+    /// Accounts expected by this instruction (2):
+    ///
+    /// 0. `[writable]` mango_group_acc - MangoGroup to change
+    /// 1. `[signer]` admin_acc - admin of the MangoGroup
+    ChangeMaxPriceMove {
+        /// e.g. 2000 to reject prices more than 20% away from the last one; 0 means off
+        max_price_move_bps: u16,
+        /// Seconds without an accepted read after which a price out of the band is accepted;
+        /// must be positive while the band is on
+        price_band_timeout: u16
+    },
+
+}
+
+
+impl MangoInstruction {
+    pub fn unpack(input: &[u8]) -> Option<Self> {
+        let (&discrim, data) = array_refs![input, 4; ..;];
+        let discrim = u32::from_le_bytes(discrim);
+        Some(match discrim {
+            0 => {
+                let data = array_ref![data, 0, 40 + 8 * NUM_TOKENS];
+                let (
+                    signer_nonce,
+                    maint_coll_ratio,
+                    init_coll_ratio,
+                    borrow_limits
+                ) = array_refs![data, 8, 16, 16, 8 * NUM_TOKENS];
+
+                let mut aligned_borrow_limits = [0u64; NUM_TOKENS];
+                let buffer: &mut [u8] = cast_slice_mut(&mut aligned_borrow_limits);
+                buffer.copy_from_slice(borrow_limits);
+
+                MangoInstruction::InitMangoGroup {
+                    signer_nonce: u64::from_le_bytes(*signer_nonce),
+                    maint_coll_ratio: U64F64::from_le_bytes(*maint_coll_ratio),
+                    init_coll_ratio: U64F64::from_le_bytes(*init_coll_ratio),
+                    borrow_limits: aligned_borrow_limits
+                }
+            }
+            1 => {
+
+                MangoInstruction::InitMarginAccount
+            },
+            2 => {
+                let quantity = array_ref![data, 0, 8];
+                MangoInstruction::Deposit { quantity: u64::from_le_bytes(*quantity) }
+            },
+            3 => {
+                let data = array_ref![data, 0, 8];
+                MangoInstruction::Withdraw {
+                    quantity: u64::from_le_bytes(*data)
+                }
+            },
+            4 => {
+                let data = array_ref![data, 0, 16];
+                let (token_index, quantity) = array_refs![data, 8, 8];
+
+                MangoInstruction::Borrow {
+                    token_index: usize::from_le_bytes(*token_index),
+                    quantity: u64::from_le_bytes(*quantity)
+                }
+            },
+            5 => {
+                let data = array_ref![data, 0, 16];
+                let (token_index, quantity) = array_refs![data, 8, 8];
+
+                MangoInstruction::SettleBorrow {
+                    token_index: usize::from_le_bytes(*token_index),
+                    quantity: u64::from_le_bytes(*quantity)
+                }
+            },
+            6 => {
+                if data.len() < 8 * NUM_TOKENS { return None; }
+                let data = array_ref![data, 0, 8 * NUM_TOKENS];
+
+                let mut aligned_arr = [0u64; NUM_TOKENS];
+                let buffer: &mut [u8] = cast_slice_mut(&mut aligned_arr);
+                buffer.copy_from_slice(data);
+
+                let deposit_quantities: &[u64] = cast_slice(buffer);
+                let deposit_quantities = array_ref![deposit_quantities, 0, NUM_TOKENS];
+                MangoInstruction::Liquidate {
+                    deposit_quantities: *deposit_quantities
+                }
+            },
+            7 => {
+                let quantity = array_ref![data, 0, 8];
+                MangoInstruction::DepositSrm { quantity: u64::from_le_bytes(*quantity) }
+            }
+            8 => {
+                let quantity = array_ref![data, 0, 8];
+                MangoInstruction::WithdrawSrm { quantity: u64::from_le_bytes(*quantity) }
+            }
+            9 => {
+                let data_arr = array_ref![data, 0, 46];
+                let order = unpack_dex_new_order_v3(data_arr)?;
+                MangoInstruction::PlaceOrder {
+                    order
+                }
+
+            },
+            10 => {
+                MangoInstruction::SettleFunds
+            },
+            11 => {
+                let data_array = array_ref![data, 0, 20];
+                let fields = array_refs![data_array, 4, 16];
+                let side = match u32::from_le_bytes(*fields.0) {
+                    0 => serum_dex::matching::Side::Bid,
+                    1 => serum_dex::matching::Side::Ask,
+                    _ => return None,
+                };
+                let order_id = u128::from_le_bytes(*fields.1);
+                let order = serum_dex::instruction::CancelOrderInstructionV2 {
+                    side,
+                    order_id,
+                };
+
+                MangoInstruction::CancelOrder {
+                    order
+                }
+            },
+            12 => {
+                let client_id = array_ref![data, 0, 8];
+                MangoInstruction::CancelOrderByClientId {
+                    client_id: u64::from_le_bytes(*client_id)
+                }
+
+            }
+            13 => {
+                let data = array_ref![data, 0, 16];
+                let (token_index, borrow_limit) = array_refs![data, 8, 8];
+                MangoInstruction::ChangeBorrowLimit {
+                    token_index: usize::from_le_bytes(*token_index),
+                    borrow_limit: u64::from_le_bytes(*borrow_limit)
+                }
+            }
+            14 => {
+                let worst_fill = data.get(46..54)
+                    .map_or(0, |b| u64::from_le_bytes(*array_ref![b, 0, 8]));
+                let data_arr = array_ref![data, 0, 46];
+                let order = unpack_dex_new_order_v3(data_arr)?;
+                MangoInstruction::PlaceAndSettle {
+                    order,
+                    worst_fill
+                }
+            }
+            15 => {
+                let data = array_ref![data, 0, 9];
+                let (market_index, limit) = array_refs![data, 8, 1];
+                MangoInstruction::ForceCancelOrders {
+                    market_index: usize::from_le_bytes(*market_index),
+                    limit: u8::from_le_bytes(*limit)
+                }
+            }
+            16 => {
+                let max_deposit = array_ref![data, 0, 8];
+                MangoInstruction::PartialLiquidate {
+                    max_deposit: u64::from_le_bytes(*max_deposit)
+                }
+            }
+            17 => {
+                let reserve_factor = array_ref![data, 0, 16];
+                MangoInstruction::ChangeReserveFactor {
+                    reserve_factor: U64F64::from_le_bytes(*reserve_factor)
+                }
+            }
+            18 => {
+                MangoInstruction::WithdrawFees
+            }
+            19 => {
+                MangoInstruction::UpgradeMarginAccount
+            }
+            20 => {
+                let data = array_ref![data, 0, 24];
+                let (token_index, coll_weight) = array_refs![data, 8, 16];
+                MangoInstruction::ChangeCollWeight {
+                    token_index: usize::from_le_bytes(*token_index),
+                    coll_weight: U64F64::from_le_bytes(*coll_weight)
+                }
+            }
+            21 => {
+                let data = array_ref![data, 0, 24];
+                let (token_index, liab_weight) = array_refs![data, 8, 16];
+                MangoInstruction::ChangeLiabWeight {
+                    token_index: usize::from_le_bytes(*token_index),
+                    liab_weight: U64F64::from_le_bytes(*liab_weight)
+                }
+            }
+            22 => {
+                let data = array_ref![data, 0, 16];
+                let (token_index, deposit_limit) = array_refs![data, 8, 8];
+                MangoInstruction::ChangeDepositLimit {
+                    token_index: usize::from_le_bytes(*token_index),
+                    deposit_limit: u64::from_le_bytes(*deposit_limit)
+                }
+            }
+            23 => {
+                let liquidation_fee = array_ref![data, 0, 16];
+                MangoInstruction::ChangeLiquidationFee {
+                    liquidation_fee: U64F64::from_le_bytes(*liquidation_fee)
+                }
+            }
+            24 => {
+                let data = array_ref![data, 0, 32];
+                let (maint_coll_ratio, init_coll_ratio) = array_refs![data, 16, 16];
+                MangoInstruction::SetCollRatios {
+                    maint_coll_ratio: U64F64::from_le_bytes(*maint_coll_ratio),
+                    init_coll_ratio: U64F64::from_le_bytes(*init_coll_ratio)
+                }
+            }
+            25 => {
+                let data = array_ref![data, 0, 9];
+                let (market_index, reduce_only) = array_refs![data, 8, 1];
+                MangoInstruction::SetMarketMode {
+                    market_index: usize::from_le_bytes(*market_index),
+                    reduce_only: reduce_only[0] != 0
+                }
+            }
+            26 => {
+                let is_halted = array_ref![data, 0, 1];
+                MangoInstruction::SetHalt {
+                    is_halted: is_halted[0] != 0
+                }
+            }
+            27 => {
+                MangoInstruction::GetMarginAccountDetails
+            }
+            28 => {
+                MangoInstruction::AuditVaults
+            }
+            29 => {
+                let data = array_ref![data, 0, 24];
+                let (market_index, max_divergence) = array_refs![data, 8, 16];
+                MangoInstruction::SetSecondaryOracle {
+                    market_index: usize::from_le_bytes(*market_index),
+                    max_divergence: U64F64::from_le_bytes(*max_divergence)
+                }
+            }
+            30 => {
+                MangoInstruction::SetAdmin
+            }
+            31 => {
+                MangoInstruction::AcceptAdmin
+            }
+            32 => {
+                let data = array_ref![data, 0, 16];
+                let (token_index, dust_threshold) = array_refs![data, 8, 8];
+                MangoInstruction::ChangeDustThreshold {
+                    token_index: usize::from_le_bytes(*token_index),
+                    dust_threshold: u64::from_le_bytes(*dust_threshold)
+                }
+            }
+            33 => {
+                let quantity = array_ref![data, 0, 8];
+                MangoInstruction::DepositWithReferrer {
+                    quantity: u64::from_le_bytes(*quantity)
+                }
+            }
+            34 => {
+                let data = array_ref![data, 0, 9];
+                let (market_index, limit) = array_refs![data, 8, 1];
+                MangoInstruction::ForceLiquidateOnDex {
+                    market_index: usize::from_le_bytes(*market_index),
+                    limit: u8::from_le_bytes(*limit)
+                }
+            }
+            35 => {
+                let market_index = array_ref![data, 0, 8];
+                MangoInstruction::AddSpotMarket {
+                    market_index: usize::from_le_bytes(*market_index)
+                }
+            }
+            36 => {
+                let min_order_notional = array_ref![data, 0, 8];
+                MangoInstruction::ChangeMinOrderNotional {
+                    min_order_notional: u64::from_le_bytes(*min_order_notional)
+                }
+            }
+            37 => {
+                let data = array_ref![data, 0, 9];
+                let (market_index, limit) = array_refs![data, 8, 1];
+                MangoInstruction::CancelAllMyOrders {
+                    market_index: usize::from_le_bytes(*market_index),
+                    limit: u8::from_le_bytes(*limit)
+                }
+            }
+            38 => {
+                let quantity = array_ref![data, 0, 8];
+                MangoInstruction::DepositForAccount {
+                    quantity: u64::from_le_bytes(*quantity)
+                }
+            }
+            39 => {
+                let data = array_ref![data, 0, 24];
+                let (token_index, target_ratio) = array_refs![data, 8, 16];
+                MangoInstruction::SettleBorrowToRatio {
+                    token_index: usize::from_le_bytes(*token_index),
+                    target_ratio: U64F64::from_le_bytes(*target_ratio)
+                }
+            }
+            40 => {
+                let limit = array_ref![data, 0, 2];
+                MangoInstruction::ConsumeEvents {
+                    limit: u16::from_le_bytes(*limit)
+                }
+            }
+            41 => {
+                let data = array_ref![data, 0, 16];
+                let (token_index, max_borrow) = array_refs![data, 8, 8];
+                MangoInstruction::ChangeMaxBorrowPerAccount {
+                    token_index: usize::from_le_bytes(*token_index),
+                    max_borrow: u64::from_le_bytes(*max_borrow)
+                }
+            }
+            42 => {
+                MangoInstruction::CheckSolvency
+            }
+            43 => {
+                let grace_period = array_ref![data, 0, 8];
+                MangoInstruction::ChangeCollateralGracePeriod {
+                    grace_period: u64::from_le_bytes(*grace_period)
+                }
+            }
+            44 => {
+                MangoInstruction::MigrateMarginAccount
+            }
+            45 => {
+                MangoInstruction::ResolveBankruptcy
+            }
+            46 => {
+                let data = array_ref![data, 0, 16];
+                let (max_base, max_quote) = array_refs![data, 8, 8];
+                MangoInstruction::SettleFundsPartial {
+                    max_base: u64::from_le_bytes(*max_base),
+                    max_quote: u64::from_le_bytes(*max_quote)
+                }
+            }
+            47 => {
+                let bump = array_ref![data, 0, 1];
+                MangoInstruction::InitMarginAccountPda {
+                    bump: bump[0]
+                }
+            }
+            48 => {
+                if data.len() < 8 * NUM_TOKENS + 8 { return None; }
+                let data = array_ref![data, 0, 8 * NUM_TOKENS + 8];
+                let (targets_data, ramp_seconds) = array_refs![data, 8 * NUM_TOKENS, 8];
+                let mut targets = [0u64; NUM_TOKENS];
+                for (i, target) in targets_data.chunks_exact(8).enumerate() {
+                    targets[i] = u64::from_le_bytes(*array_ref![target, 0, 8]);
+                }
+                MangoInstruction::SetBorrowLimitRamp {
+                    targets,
+                    ramp_seconds: u64::from_le_bytes(*ramp_seconds)
+                }
+            }
+            49 => {
+                let max_orders = array_ref![data, 0, 8];
+                MangoInstruction::ChangeMaxOrdersPerMarket {
+                    max_orders: u64::from_le_bytes(*max_orders)
+                }
+            }
+            50 => {
+                MangoInstruction::LogIndexes
+            }
+            51 => {
+                if data.len() < 8 * NUM_TOKENS { return None; }
+                let data = array_ref![data, 0, 8 * NUM_TOKENS];
+                let mut limits = [0u64; NUM_TOKENS];
+                for (i, limit) in data.chunks_exact(8).enumerate() {
+                    limits[i] = u64::from_le_bytes(*array_ref![limit, 0, 8]);
+                }
+                MangoInstruction::ChangeAllBorrowLimits { limits }
+            }
+            52 => {
+                if data.len() < 8 * NUM_TOKENS { return None; }
+                let data = array_ref![data, 0, 8 * NUM_TOKENS];
+                let mut quantities = [0u64; NUM_TOKENS];
+                for (i, quantity) in data.chunks_exact(8).enumerate() {
+                    quantities[i] = u64::from_le_bytes(*array_ref![quantity, 0, 8]);
+                }
+                MangoInstruction::DepositMulti { quantities }
+            }
+            53 => {
+                MangoInstruction::RefreshOracleDecimals
+            }
+            54 => {
+                let quantity = array_ref![data, 0, 8];
+                MangoInstruction::WithdrawExcessSrm { quantity: u64::from_le_bytes(*quantity) }
+            }
+            55 => {
+                let market_index = array_ref![data, 0, 8];
+                MangoInstruction::CloseOpenOrders { market_index: usize::from_le_bytes(*market_index) }
+            }
+            56 => {
+                let max_accrual_interval = array_ref![data, 0, 8];
+                MangoInstruction::ChangeMaxAccrualInterval {
+                    max_accrual_interval: u64::from_le_bytes(*max_accrual_interval)
+                }
+            }
+            57 => {
+                let market_index = array_ref![data, 0, 8];
+                MangoInstruction::InitOpenOrders { market_index: usize::from_le_bytes(*market_index) }
+            }
+            58 => {
+                let liquidation_cooldown = array_ref![data, 0, 8];
+                MangoInstruction::ChangeLiquidationCooldown {
+                    liquidation_cooldown: u64::from_le_bytes(*liquidation_cooldown)
+                }
+            }
+            59 => {
+                let data = array_ref![data, 0, 9];
+                let (market_index, oracle_type) = array_refs![data, 8, 1];
+                MangoInstruction::SetOracle {
+                    market_index: usize::from_le_bytes(*market_index),
+                    oracle_type: oracle_type[0]
+                }
+            }
+            60 => {
+                let market_index = array_ref![data, 0, 8];
+                MangoInstruction::ForceSettleFunds { market_index: usize::from_le_bytes(*market_index) }
+            }
+            61 => {
+                MangoInstruction::VerifyGroupConfig
+            }
+            62 => {
+                let data = array_ref![data, 0, 16];
+                let (market_index, oi_limit) = array_refs![data, 8, 8];
+                MangoInstruction::ChangeOiLimit {
+                    market_index: usize::from_le_bytes(*market_index),
+                    oi_limit: u64::from_le_bytes(*oi_limit)
+                }
+            }
+            63 => {
+                let data_array = array_ref![data, 0, 20];
+                let fields = array_refs![data_array, 4, 16];
+                let side = match u32::from_le_bytes(*fields.0) {
+                    0 => serum_dex::matching::Side::Bid,
+                    1 => serum_dex::matching::Side::Ask,
+                    _ => return None,
+                };
+                let order_id = u128::from_le_bytes(*fields.1);
+                let order = serum_dex::instruction::CancelOrderInstructionV2 {
+                    side,
+                    order_id,
+                };
+
+                MangoInstruction::CancelOrderAndSettle {
+                    order
+                }
+            }
+            64 => {
+                let data = array_ref![data, 0, 4];
+                let (max_price_move_bps, price_band_timeout) = array_refs![data, 2, 2];
+                MangoInstruction::ChangeMaxPriceMove {
+                    max_price_move_bps: u16::from_le_bytes(*max_price_move_bps),
+                    price_band_timeout: u16::from_le_bytes(*price_band_timeout)
+                }
+            }
+            _ => { return None; }
+        })
+    }
+    pub fn pack(&self) -> Vec<u8> {
+        bincode::serialize(self).unwrap()
+    }
+}
+
+
+fn unpack_dex_new_order_v3(data: &[u8; 46]) -> Option<serum_dex::instruction::NewOrderInstructionV3> {
+    let (
+        &side_arr,
+        &price_arr,
+        &max_coin_qty_arr,
+        &max_native_pc_qty_arr,
+        &self_trade_behavior_arr,
+        &otype_arr,
+        &client_order_id_bytes,
+        &limit_arr,
+    ) = array_refs![data, 4, 8, 8, 8, 4, 4, 8, 2];
+
+    let side = serum_dex::matching::Side::try_from_primitive(u32::from_le_bytes(side_arr).try_into().ok()?).ok()?;
+    let limit_price = NonZeroU64::new(u64::from_le_bytes(price_arr))?;
+    let max_coin_qty = NonZeroU64::new(u64::from_le_bytes(max_coin_qty_arr))?;
+    let max_native_pc_qty_including_fees =
+        NonZeroU64::new(u64::from_le_bytes(max_native_pc_qty_arr))?;
+    let self_trade_behavior = serum_dex::instruction::SelfTradeBehavior::try_from_primitive(
+        u32::from_le_bytes(self_trade_behavior_arr)
+            .try_into()
+            .ok()?,
+    )
+        .ok()?;
+    let order_type = serum_dex::matching::OrderType::try_from_primitive(u32::from_le_bytes(otype_arr).try_into().ok()?).ok()?;
+    let client_order_id = u64::from_le_bytes(client_order_id_bytes);
+    let limit = u16::from_le_bytes(limit_arr);
+
+    Some(serum_dex::instruction::NewOrderInstructionV3 {
+        side,
+        limit_price,
         max_coin_qty,
         max_native_pc_qty_including_fees,
         self_trade_behavior,
@@ -547,51 +1581,1320 @@ fn unpack_dex_new_order_v3(data: &[u8; 46]) -> Option<serum_dex::instruction::Ne
 }
 
 
-pub fn init_mango_group(
+pub fn init_mango_group(
+    program_id: &Pubkey,
+    mango_group_pk: &Pubkey,
+    signer_pk: &Pubkey,
+    dex_prog_id: &Pubkey,
+    srm_vault_pk: &Pubkey,
+    admin_pk: &Pubkey,
+    mint_pks: &[Pubkey],
+    vault_pks: &[Pubkey],
+    spot_market_pks: &[Pubkey],
+    oracle_pks: &[Pubkey],
+    signer_nonce: u64,
+    maint_coll_ratio: U64F64,
+    init_coll_ratio: U64F64,
+    borrow_limits: [u64; NUM_TOKENS]
+) -> Result<Instruction, ProgramError> {
+    let mut accounts = vec![
+        AccountMeta::new(*mango_group_pk, false),
+        AccountMeta::new_readonly(solana_program::sysvar::rent::ID, false),
+        AccountMeta::new_readonly(solana_program::sysvar::clock::ID, false),
+        AccountMeta::new_readonly(*signer_pk, false),
+        AccountMeta::new_readonly(*dex_prog_id, false),
+        AccountMeta::new_readonly(*srm_vault_pk, false),
+        AccountMeta::new_readonly(*admin_pk, true)
+    ];
+    accounts.extend(mint_pks.iter().map(
+        |pk| AccountMeta::new_readonly(*pk, false))
+    );
+    accounts.extend(vault_pks.iter().map(
+        |pk| AccountMeta::new_readonly(*pk, false))
+    );
+    accounts.extend(spot_market_pks.iter().map(
+        |pk| AccountMeta::new_readonly(*pk, false))
+    );
+    accounts.extend(oracle_pks.iter().map(
+        |pk| AccountMeta::new_readonly(*pk, false))
+    );
+
+    let instr = MangoInstruction::InitMangoGroup {
+        signer_nonce,
+        maint_coll_ratio,
+        init_coll_ratio,
+        borrow_limits
+    };
+
+    let data = instr.pack();
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data
+    })
+}
+
+pub fn init_margin_account(
+    program_id: &Pubkey,
+    mango_group_pk: &Pubkey,
+    margin_account_pk: &Pubkey,
+    owner_pk: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+    let accounts = vec![
+        AccountMeta::new_readonly(*mango_group_pk, false),
+        AccountMeta::new(*margin_account_pk, false),
+        AccountMeta::new_readonly(*owner_pk, true),
+        AccountMeta::new_readonly(solana_program::sysvar::rent::ID, false),
+    ];
+
+    let instr = MangoInstruction::InitMarginAccount;
+    let data = instr.pack();
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data
+    })
+}
+
+pub fn deposit(
+    program_id: &Pubkey,
+    mango_group_pk: &Pubkey,
+    margin_account_pk: &Pubkey,
+    owner_pk: &Pubkey,
+    token_account_pk: &Pubkey,
+    vault_pk: &Pubkey,
+    quantity: u64
+) -> Result<Instruction, ProgramError> {
+    let accounts = vec![
+        AccountMeta::new(*mango_group_pk, false),
+        AccountMeta::new(*margin_account_pk, false),
+        AccountMeta::new_readonly(*owner_pk, true),
+        AccountMeta::new(*token_account_pk, false),
+        AccountMeta::new(*vault_pk, false),
+        AccountMeta::new_readonly(spl_token::ID, false),
+        AccountMeta::new_readonly(solana_program::sysvar::clock::ID, false),
+    ];
+
+    let instr = MangoInstruction::Deposit { quantity };
+    let data = instr.pack();
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data
+    })
+}
+
+pub fn withdraw(
+    program_id: &Pubkey,
+    mango_group_pk: &Pubkey,
+    margin_account_pk: &Pubkey,
+    owner_pk: &Pubkey,
+    token_account_pk: &Pubkey,
+    vault_pk: &Pubkey,
+    signer_pk: &Pubkey,
+    open_orders_pks: &[Pubkey],
+    oracle_pks: &[Pubkey],
+    quantity: u64
+) -> Result<Instruction, ProgramError> {
+    let mut accounts = vec![
+        AccountMeta::new(*mango_group_pk, false),
+        AccountMeta::new(*margin_account_pk, false),
+        AccountMeta::new_readonly(*owner_pk, true),
+        AccountMeta::new(*token_account_pk, false),
+        AccountMeta::new(*vault_pk, false),
+        AccountMeta::new_readonly(*signer_pk, false),
+        AccountMeta::new_readonly(spl_token::ID, false),
+        AccountMeta::new_readonly(solana_program::sysvar::clock::ID, false),
+    ];
+
+    accounts.extend(open_orders_pks.iter().map(
+        |pk| AccountMeta::new_readonly(*pk, false))
+    );
+    accounts.extend(oracle_pks.iter().map(
+        |pk| AccountMeta::new_readonly(*pk, false))
+    );
+
+    let instr = MangoInstruction::Withdraw { quantity };
+    let data = instr.pack();
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data
+    })
+}
+
+pub fn borrow(
+    program_id: &Pubkey,
+    mango_group_pk: &Pubkey,
+    margin_account_pk: &Pubkey,
+    owner_pk: &Pubkey,
+    open_orders_pks: &[Pubkey],
+    oracle_pks: &[Pubkey],
+    token_index: usize,
+    quantity: u64
+) -> Result<Instruction, ProgramError> {
+    let mut accounts = vec![
+        AccountMeta::new(*mango_group_pk, false),
+        AccountMeta::new(*margin_account_pk, false),
+        AccountMeta::new_readonly(*owner_pk, true),
+        AccountMeta::new_readonly(solana_program::sysvar::clock::ID, false),
+    ];
+
+    accounts.extend(open_orders_pks.iter().map(
+        |pk| AccountMeta::new_readonly(*pk, false))
+    );
+    accounts.extend(oracle_pks.iter().map(
+        |pk| AccountMeta::new_readonly(*pk, false))
+    );
+
+    let instr = MangoInstruction::Borrow { token_index, quantity };
+    let data = instr.pack();
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data
+    })
+}
+
+pub fn settle_borrow(
+    program_id: &Pubkey,
+    mango_group_pk: &Pubkey,
+    margin_account_pk: &Pubkey,
+    owner_pk: &Pubkey,
+    token_index: usize,
+    quantity: u64
+) -> Result<Instruction, ProgramError> {
+    let accounts = vec![
+        AccountMeta::new(*mango_group_pk, false),
+        AccountMeta::new(*margin_account_pk, false),
+        AccountMeta::new_readonly(*owner_pk, true),
+        AccountMeta::new_readonly(solana_program::sysvar::clock::ID, false),
+    ];
+
+    let instr = MangoInstruction::SettleBorrow { token_index, quantity };
+    let data = instr.pack();
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data
+    })
+}
+
+pub fn liquidate(
+    program_id: &Pubkey,
+    mango_group_pk: &Pubkey,
+    liqor_pk: &Pubkey,
+    liqee_margin_account_pk: &Pubkey,
+    signer_pk: &Pubkey,
+    open_orders_pks: &[Pubkey],
+    oracle_pks: &[Pubkey],
+    vault_pks: &[Pubkey],
+    liqor_token_account_pks: &[Pubkey],
+    deposit_quantities: [u64; NUM_TOKENS]
+) -> Result<Instruction, ProgramError> {
+    let mut accounts = vec![
+        AccountMeta::new(*mango_group_pk, false),
+        AccountMeta::new_readonly(*liqor_pk, true),
+        AccountMeta::new(*liqee_margin_account_pk, false),
+        AccountMeta::new_readonly(*signer_pk, false),
+        AccountMeta::new_readonly(spl_token::ID, false),
+        AccountMeta::new_readonly(solana_program::sysvar::clock::ID, false),
+    ];
+
+    accounts.extend(open_orders_pks.iter().map(
+        |pk| AccountMeta::new_readonly(*pk, false))
+    );
+    accounts.extend(oracle_pks.iter().map(
+        |pk| AccountMeta::new_readonly(*pk, false))
+    );
+    accounts.extend(vault_pks.iter().map(
+        |pk| AccountMeta::new(*pk, false))
+    );
+    accounts.extend(liqor_token_account_pks.iter().map(
+        |pk| AccountMeta::new(*pk, false))
+    );
+
+    let instr = MangoInstruction::Liquidate { deposit_quantities };
+    let data = instr.pack();
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data
+    })
+}
+
+pub fn deposit_srm(
+    program_id: &Pubkey,
+    mango_group_pk: &Pubkey,
+    mango_srm_account_pk: &Pubkey,
+    owner_pk: &Pubkey,
+    srm_account_pk: &Pubkey,
+    vault_pk: &Pubkey,
+    quantity: u64
+) -> Result<Instruction, ProgramError> {
+    let accounts = vec![
+        AccountMeta::new(*mango_group_pk, false),
+        AccountMeta::new(*mango_srm_account_pk, false),
+        AccountMeta::new_readonly(*owner_pk, true),
+        AccountMeta::new(*srm_account_pk, false),
+        AccountMeta::new(*vault_pk, false),
+        AccountMeta::new_readonly(spl_token::ID, false),
+        AccountMeta::new_readonly(solana_program::sysvar::clock::ID, false),
+        AccountMeta::new_readonly(solana_program::sysvar::rent::ID, false),
+    ];
+
+    let instr = MangoInstruction::DepositSrm { quantity };
+    let data = instr.pack();
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data
+    })
+}
+
+pub fn withdraw_srm(
+    program_id: &Pubkey,
+    mango_group_pk: &Pubkey,
+    mango_srm_account_pk: &Pubkey,
+    owner_pk: &Pubkey,
+    srm_account_pk: &Pubkey,
+    vault_pk: &Pubkey,
+    signer_pk: &Pubkey,
+    quantity: u64
+) -> Result<Instruction, ProgramError> {
+    let accounts = vec![
+        AccountMeta::new(*mango_group_pk, false),
+        AccountMeta::new(*mango_srm_account_pk, false),
+        AccountMeta::new_readonly(*owner_pk, true),
+        AccountMeta::new(*srm_account_pk, false),
+        AccountMeta::new(*vault_pk, false),
+        AccountMeta::new_readonly(*signer_pk, false),
+        AccountMeta::new_readonly(spl_token::ID, false),
+        AccountMeta::new_readonly(solana_program::sysvar::clock::ID, false),
+    ];
+
+    let instr = MangoInstruction::WithdrawSrm { quantity };
+    let data = instr.pack();
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data
+    })
+}
+
+pub fn place_order(
+    program_id: &Pubkey,
+    mango_group_pk: &Pubkey,
+    owner_pk: &Pubkey,
+    margin_account_pk: &Pubkey,
+    dex_prog_id: &Pubkey,
+    spot_market_pk: &Pubkey,
+    dex_request_queue_pk: &Pubkey,
+    dex_event_queue_pk: &Pubkey,
+    bids_pk: &Pubkey,
+    asks_pk: &Pubkey,
+    vault_pk: &Pubkey,
+    signer_pk: &Pubkey,
+    dex_base_pk: &Pubkey,
+    dex_quote_pk: &Pubkey,
+    srm_vault_pk: &Pubkey,
+    open_orders_pks: &[Pubkey],
+    oracle_pks: &[Pubkey],
+    order: serum_dex::instruction::NewOrderInstructionV3
+) -> Result<Instruction, ProgramError> {
+
+    let mut accounts = vec![
+        AccountMeta::new(*mango_group_pk, false),
+        AccountMeta::new_readonly(*owner_pk, true),
+        AccountMeta::new(*margin_account_pk, false),
+        AccountMeta::new_readonly(solana_program::sysvar::clock::ID, false),
+        AccountMeta::new_readonly(*dex_prog_id, false),
+        AccountMeta::new(*spot_market_pk, false),
+        AccountMeta::new(*dex_request_queue_pk, false),
+        AccountMeta::new(*dex_event_queue_pk, false),
+        AccountMeta::new(*bids_pk, false),
+        AccountMeta::new(*asks_pk, false),
+        AccountMeta::new(*vault_pk, false),
+        AccountMeta::new_readonly(*signer_pk, false),
+        AccountMeta::new(*dex_base_pk, false),
+        AccountMeta::new(*dex_quote_pk, false),
+        AccountMeta::new_readonly(spl_token::ID, false),
+        AccountMeta::new_readonly(solana_program::sysvar::rent::ID, false),
+        AccountMeta::new(*srm_vault_pk, false),
+    ];
+
+    accounts.extend(open_orders_pks.iter().map(
+        |pk| AccountMeta::new(*pk, false))
+    );
+    accounts.extend(oracle_pks.iter().map(
+        |pk| AccountMeta::new_readonly(*pk, false))
+    );
+
+    let instr = MangoInstruction::PlaceOrder { order };
+    let data = instr.pack();
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data
+    })
+}
+
+pub fn place_and_settle(
+    program_id: &Pubkey,
+    mango_group_pk: &Pubkey,
+    owner_pk: &Pubkey,
+    margin_account_pk: &Pubkey,
+    dex_prog_id: &Pubkey,
+    spot_market_pk: &Pubkey,
+    dex_request_queue_pk: &Pubkey,
+    dex_event_queue_pk: &Pubkey,
+    bids_pk: &Pubkey,
+    asks_pk: &Pubkey,
+    base_vault_pk: &Pubkey,
+    quote_vault_pk: &Pubkey,
+    signer_pk: &Pubkey,
+    dex_base_pk: &Pubkey,
+    dex_quote_pk: &Pubkey,
+    srm_vault_pk: &Pubkey,
+    dex_signer_pk: &Pubkey,
+    open_orders_pks: &[Pubkey],
+    oracle_pks: &[Pubkey],
+    order: serum_dex::instruction::NewOrderInstructionV3,
+    worst_fill: u64
+) -> Result<Instruction, ProgramError> {
+
+    let mut accounts = vec![
+        AccountMeta::new(*mango_group_pk, false),
+        AccountMeta::new_readonly(*owner_pk, true),
+        AccountMeta::new(*margin_account_pk, false),
+        AccountMeta::new_readonly(solana_program::sysvar::clock::ID, false),
+        AccountMeta::new_readonly(*dex_prog_id, false),
+        AccountMeta::new(*spot_market_pk, false),
+        AccountMeta::new(*dex_request_queue_pk, false),
+        AccountMeta::new(*dex_event_queue_pk, false),
+        AccountMeta::new(*bids_pk, false),
+        AccountMeta::new(*asks_pk, false),
+        AccountMeta::new(*base_vault_pk, false),
+        AccountMeta::new(*quote_vault_pk, false),
+        AccountMeta::new_readonly(*signer_pk, false),
+        AccountMeta::new(*dex_base_pk, false),
+        AccountMeta::new(*dex_quote_pk, false),
+        AccountMeta::new_readonly(spl_token::ID, false),
+        AccountMeta::new_readonly(solana_program::sysvar::rent::ID, false),
+        AccountMeta::new(*srm_vault_pk, false),
+        AccountMeta::new_readonly(*dex_signer_pk, false),
+    ];
+
+    accounts.extend(open_orders_pks.iter().map(
+        |pk| AccountMeta::new(*pk, false))
+    );
+    accounts.extend(oracle_pks.iter().map(
+        |pk| AccountMeta::new_readonly(*pk, false))
+    );
+
+    let instr = MangoInstruction::PlaceAndSettle { order, worst_fill };
+    let data = instr.pack();
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data
+    })
+}
+
+
+pub fn settle_funds(
+    program_id: &Pubkey,
+    mango_group_pk: &Pubkey,
+    owner_pk: &Pubkey,
+    margin_account_pk: &Pubkey,
+    dex_prog_id: &Pubkey,
+    spot_market_pk: &Pubkey,
+    open_orders_pk: &Pubkey,
+    signer_pk: &Pubkey,
+    dex_base_pk: &Pubkey,
+    dex_quote_pk: &Pubkey,
+    base_vault_pk: &Pubkey,
+    quote_vault_pk: &Pubkey,
+    dex_signer_pk: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+
+    let accounts = vec![
+        AccountMeta::new(*mango_group_pk, false),
+        AccountMeta::new_readonly(*owner_pk, true),
+        AccountMeta::new(*margin_account_pk, false),
+        AccountMeta::new_readonly(solana_program::sysvar::clock::ID, false),
+        AccountMeta::new_readonly(*dex_prog_id, false),
+        AccountMeta::new(*spot_market_pk, false),
+        AccountMeta::new(*open_orders_pk, false),
+        AccountMeta::new_readonly(*signer_pk, false),
+        AccountMeta::new(*dex_base_pk, false),
+        AccountMeta::new(*dex_quote_pk, false),
+        AccountMeta::new(*base_vault_pk, false),
+        AccountMeta::new(*quote_vault_pk, false),
+        AccountMeta::new_readonly(*dex_signer_pk, false),
+        AccountMeta::new_readonly(spl_token::ID, false),
+    ];
+
+    let instr = MangoInstruction::SettleFunds;
+    let data = instr.pack();
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data
+    })
+}
+
+pub fn cancel_order(
+    program_id: &Pubkey,
+    mango_group_pk: &Pubkey,
+    owner_pk: &Pubkey,
+    margin_account_pk: &Pubkey,
+    dex_prog_id: &Pubkey,
+    spot_market_pk: &Pubkey,
+    bids_pk: &Pubkey,
+    asks_pk: &Pubkey,
+    open_orders_pk: &Pubkey,
+    signer_pk: &Pubkey,
+    dex_event_queue_pk: &Pubkey,
+    order: serum_dex::instruction::CancelOrderInstructionV2
+) -> Result<Instruction, ProgramError> {
+    let accounts = vec![
+        AccountMeta::new(*mango_group_pk, false),
+        AccountMeta::new_readonly(*owner_pk, true),
+        AccountMeta::new(*margin_account_pk, false),
+        AccountMeta::new_readonly(solana_program::sysvar::clock::ID, false),
+        AccountMeta::new_readonly(*dex_prog_id, false),
+        AccountMeta::new(*spot_market_pk, false),
+        AccountMeta::new(*bids_pk, false),
+        AccountMeta::new(*asks_pk, false),
+        AccountMeta::new(*open_orders_pk, false),
+        AccountMeta::new_readonly(*signer_pk, false),
+        AccountMeta::new(*dex_event_queue_pk, false),
+    ];
+
+    let instr = MangoInstruction::CancelOrder { order };
+    let data = instr.pack();
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data
+    })
+}
+
+pub fn cancel_order_by_client_id(
+    program_id: &Pubkey,
+    mango_group_pk: &Pubkey,
+    owner_pk: &Pubkey,
+    margin_account_pk: &Pubkey,
+    dex_prog_id: &Pubkey,
+    spot_market_pk: &Pubkey,
+    bids_pk: &Pubkey,
+    asks_pk: &Pubkey,
+    open_orders_pk: &Pubkey,
+    signer_pk: &Pubkey,
+    dex_event_queue_pk: &Pubkey,
+    client_id: u64
+) -> Result<Instruction, ProgramError> {
+    let accounts = vec![
+        AccountMeta::new(*mango_group_pk, false),
+        AccountMeta::new_readonly(*owner_pk, true),
+        AccountMeta::new(*margin_account_pk, false),
+        AccountMeta::new_readonly(solana_program::sysvar::clock::ID, false),
+        AccountMeta::new_readonly(*dex_prog_id, false),
+        AccountMeta::new(*spot_market_pk, false),
+        AccountMeta::new(*bids_pk, false),
+        AccountMeta::new(*asks_pk, false),
+        AccountMeta::new(*open_orders_pk, false),
+        AccountMeta::new_readonly(*signer_pk, false),
+        AccountMeta::new(*dex_event_queue_pk, false),
+    ];
+    let instr = MangoInstruction::CancelOrderByClientId { client_id };
+    let data = instr.pack();
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data
+    })
+}
+
+
+pub fn change_borrow_limit(
+    program_id: &Pubkey,
+    mango_group_pk: &Pubkey,
+    admin_pk: &Pubkey,
+    token_index: usize,
+    borrow_limit: u64
+) -> Result<Instruction, ProgramError> {
+    let accounts = vec![
+        AccountMeta::new(*mango_group_pk, false),
+        AccountMeta::new_readonly(*admin_pk, true),
+    ];
+
+    let instr = MangoInstruction::ChangeBorrowLimit { token_index, borrow_limit };
+    let data = instr.pack();
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data
+    })
+}
+
+pub fn force_cancel_orders(
+    program_id: &Pubkey,
+    mango_group_pk: &Pubkey,
+    liqor_pk: &Pubkey,
+    liqee_margin_account_acc: &Pubkey,
+    base_vault_pk: &Pubkey,
+    quote_vault_pk: &Pubkey,
+    spot_market_pk: &Pubkey,
+    bids_pk: &Pubkey,
+    asks_pk: &Pubkey,
+    signer_pk: &Pubkey,
+    dex_event_queue_pk: &Pubkey,
+    dex_base_pk: &Pubkey,
+    dex_quote_pk: &Pubkey,
+    dex_signer_pk: &Pubkey,
+    dex_prog_id: &Pubkey,
+    open_orders_pks: &[Pubkey],
+    oracle_pks: &[Pubkey],
+    market_index: usize,
+    limit: u8
+) -> Result<Instruction, ProgramError> {
+
+    let mut accounts = vec![
+        AccountMeta::new(*mango_group_pk, false),
+        AccountMeta::new_readonly(*liqor_pk, true),
+        AccountMeta::new(*liqee_margin_account_acc, false),
+        AccountMeta::new(*base_vault_pk, false),
+        AccountMeta::new(*quote_vault_pk, false),
+        AccountMeta::new(*spot_market_pk, false),
+        AccountMeta::new(*bids_pk, false),
+        AccountMeta::new(*asks_pk, false),
+        AccountMeta::new_readonly(*signer_pk, false),
+        AccountMeta::new(*dex_event_queue_pk, false),
+        AccountMeta::new(*dex_base_pk, false),
+        AccountMeta::new(*dex_quote_pk, false),
+        AccountMeta::new_readonly(*dex_signer_pk, false),
+        AccountMeta::new_readonly(spl_token::ID, false),
+        AccountMeta::new_readonly(*dex_prog_id, false),
+        AccountMeta::new_readonly(solana_program::sysvar::clock::ID, false),
+    ];
+
+    accounts.extend(open_orders_pks.iter().map(
+        |pk| AccountMeta::new(*pk, false))
+    );
+    accounts.extend(oracle_pks.iter().map(
+        |pk| AccountMeta::new_readonly(*pk, false))
+    );
+
+    let instr = MangoInstruction::ForceCancelOrders { market_index, limit };
+    let data = instr.pack();
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data
+    })
+}
+
+
+pub fn partial_liquidate(
+    program_id: &Pubkey,
+    mango_group_pk: &Pubkey,
+    liqor_pk: &Pubkey,
+    liqor_in_token_pk: &Pubkey,
+    liqor_out_token_pk: &Pubkey,
+    liqee_margin_account_acc: &Pubkey,
+    in_vault_pk: &Pubkey,
+    out_vault_pk: &Pubkey,
+    signer_pk: &Pubkey,
+    open_orders_pks: &[Pubkey],
+    oracle_pks: &[Pubkey],
+    max_deposit: u64
+) -> Result<Instruction, ProgramError> {
+
+    let mut accounts = vec![
+        AccountMeta::new(*mango_group_pk, false),
+        AccountMeta::new_readonly(*liqor_pk, true),
+        AccountMeta::new(*liqor_in_token_pk, false),
+        AccountMeta::new(*liqor_out_token_pk, false),
+        AccountMeta::new(*liqee_margin_account_acc, false),
+        AccountMeta::new(*in_vault_pk, false),
+        AccountMeta::new(*out_vault_pk, false),
+        AccountMeta::new_readonly(*signer_pk, false),
+        AccountMeta::new_readonly(spl_token::ID, false),
+        AccountMeta::new_readonly(solana_program::sysvar::clock::ID, false),
+    ];
+
+    accounts.extend(open_orders_pks.iter().map(
+        |pk| AccountMeta::new_readonly(*pk, false))
+    );
+    accounts.extend(oracle_pks.iter().map(
+        |pk| AccountMeta::new_readonly(*pk, false))
+    );
+
+    let instr = MangoInstruction::PartialLiquidate { max_deposit };
+    let data = instr.pack();
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data
+    })
+}
+
+pub fn change_reserve_factor(
+    program_id: &Pubkey,
+    mango_group_pk: &Pubkey,
+    admin_pk: &Pubkey,
+    reserve_factor: U64F64
+) -> Result<Instruction, ProgramError> {
+    let accounts = vec![
+        AccountMeta::new(*mango_group_pk, false),
+        AccountMeta::new_readonly(*admin_pk, true),
+    ];
+
+    let instr = MangoInstruction::ChangeReserveFactor { reserve_factor };
+    let data = instr.pack();
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data
+    })
+}
+
+pub fn withdraw_fees(
+    program_id: &Pubkey,
+    mango_group_pk: &Pubkey,
+    admin_pk: &Pubkey,
+    token_account_pk: &Pubkey,
+    vault_pk: &Pubkey,
+    signer_pk: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+    let accounts = vec![
+        AccountMeta::new(*mango_group_pk, false),
+        AccountMeta::new_readonly(*admin_pk, true),
+        AccountMeta::new(*token_account_pk, false),
+        AccountMeta::new(*vault_pk, false),
+        AccountMeta::new_readonly(*signer_pk, false),
+        AccountMeta::new_readonly(spl_token::ID, false),
+        AccountMeta::new_readonly(solana_program::sysvar::clock::ID, false),
+    ];
+
+    let instr = MangoInstruction::WithdrawFees;
+    let data = instr.pack();
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data
+    })
+}
+
+pub fn upgrade_margin_account(
+    program_id: &Pubkey,
+    mango_group_pk: &Pubkey,
+    old_margin_account_pk: &Pubkey,
+    new_margin_account_pk: &Pubkey,
+    owner_pk: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+    let accounts = vec![
+        AccountMeta::new_readonly(*mango_group_pk, false),
+        AccountMeta::new(*old_margin_account_pk, false),
+        AccountMeta::new(*new_margin_account_pk, false),
+        AccountMeta::new(*owner_pk, true),
+        AccountMeta::new_readonly(solana_program::sysvar::rent::ID, false),
+    ];
+
+    let instr = MangoInstruction::UpgradeMarginAccount;
+    let data = instr.pack();
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data
+    })
+}
+
+pub fn change_coll_weight(
+    program_id: &Pubkey,
+    mango_group_pk: &Pubkey,
+    admin_pk: &Pubkey,
+    token_index: usize,
+    coll_weight: U64F64
+) -> Result<Instruction, ProgramError> {
+    let accounts = vec![
+        AccountMeta::new(*mango_group_pk, false),
+        AccountMeta::new_readonly(*admin_pk, true),
+    ];
+
+    let instr = MangoInstruction::ChangeCollWeight { token_index, coll_weight };
+    let data = instr.pack();
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data
+    })
+}
+
+pub fn change_liab_weight(
+    program_id: &Pubkey,
+    mango_group_pk: &Pubkey,
+    admin_pk: &Pubkey,
+    token_index: usize,
+    liab_weight: U64F64
+) -> Result<Instruction, ProgramError> {
+    let accounts = vec![
+        AccountMeta::new(*mango_group_pk, false),
+        AccountMeta::new_readonly(*admin_pk, true),
+    ];
+
+    let instr = MangoInstruction::ChangeLiabWeight { token_index, liab_weight };
+    let data = instr.pack();
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data
+    })
+}
+
+pub fn change_deposit_limit(
+    program_id: &Pubkey,
+    mango_group_pk: &Pubkey,
+    admin_pk: &Pubkey,
+    token_index: usize,
+    deposit_limit: u64
+) -> Result<Instruction, ProgramError> {
+    let accounts = vec![
+        AccountMeta::new(*mango_group_pk, false),
+        AccountMeta::new_readonly(*admin_pk, true),
+    ];
+
+    let instr = MangoInstruction::ChangeDepositLimit { token_index, deposit_limit };
+    let data = instr.pack();
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data
+    })
+}
+
+pub fn change_liquidation_fee(
+    program_id: &Pubkey,
+    mango_group_pk: &Pubkey,
+    admin_pk: &Pubkey,
+    liquidation_fee: U64F64
+) -> Result<Instruction, ProgramError> {
+    let accounts = vec![
+        AccountMeta::new(*mango_group_pk, false),
+        AccountMeta::new_readonly(*admin_pk, true),
+    ];
+
+    let instr = MangoInstruction::ChangeLiquidationFee { liquidation_fee };
+    let data = instr.pack();
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data
+    })
+}
+
+pub fn set_coll_ratios(
+    program_id: &Pubkey,
+    mango_group_pk: &Pubkey,
+    admin_pk: &Pubkey,
+    maint_coll_ratio: U64F64,
+    init_coll_ratio: U64F64
+) -> Result<Instruction, ProgramError> {
+    let accounts = vec![
+        AccountMeta::new(*mango_group_pk, false),
+        AccountMeta::new_readonly(*admin_pk, true),
+    ];
+
+    let instr = MangoInstruction::SetCollRatios { maint_coll_ratio, init_coll_ratio };
+    let data = instr.pack();
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data
+    })
+}
+
+pub fn set_market_mode(
+    program_id: &Pubkey,
+    mango_group_pk: &Pubkey,
+    admin_pk: &Pubkey,
+    market_index: usize,
+    reduce_only: bool
+) -> Result<Instruction, ProgramError> {
+    let accounts = vec![
+        AccountMeta::new(*mango_group_pk, false),
+        AccountMeta::new_readonly(*admin_pk, true),
+    ];
+
+    let instr = MangoInstruction::SetMarketMode { market_index, reduce_only };
+    let data = instr.pack();
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data
+    })
+}
+
+pub fn set_halt(
+    program_id: &Pubkey,
+    mango_group_pk: &Pubkey,
+    admin_pk: &Pubkey,
+    is_halted: bool
+) -> Result<Instruction, ProgramError> {
+    let accounts = vec![
+        AccountMeta::new(*mango_group_pk, false),
+        AccountMeta::new_readonly(*admin_pk, true),
+    ];
+
+    let instr = MangoInstruction::SetHalt { is_halted };
+    let data = instr.pack();
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data
+    })
+}
+
+pub fn get_margin_account_details(
+    program_id: &Pubkey,
+    mango_group_pk: &Pubkey,
+    margin_account_pk: &Pubkey,
+    open_orders_pks: &[Pubkey],
+    oracle_pks: &[Pubkey]
+) -> Result<Instruction, ProgramError> {
+    let mut accounts = vec![
+        AccountMeta::new_readonly(*mango_group_pk, false),
+        AccountMeta::new_readonly(*margin_account_pk, false),
+        AccountMeta::new_readonly(solana_program::sysvar::clock::ID, false),
+    ];
+
+    accounts.extend(open_orders_pks.iter().map(
+        |pk| AccountMeta::new_readonly(*pk, false))
+    );
+    accounts.extend(oracle_pks.iter().map(
+        |pk| AccountMeta::new_readonly(*pk, false))
+    );
+
+    let instr = MangoInstruction::GetMarginAccountDetails;
+    let data = instr.pack();
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data
+    })
+}
+
+pub fn audit_vaults(
+    program_id: &Pubkey,
+    mango_group_pk: &Pubkey,
+    vault_pks: &[Pubkey]
+) -> Result<Instruction, ProgramError> {
+    let mut accounts = vec![
+        AccountMeta::new_readonly(*mango_group_pk, false),
+    ];
+
+    accounts.extend(vault_pks.iter().map(
+        |pk| AccountMeta::new_readonly(*pk, false))
+    );
+
+    let instr = MangoInstruction::AuditVaults;
+    let data = instr.pack();
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data
+    })
+}
+
+pub fn set_secondary_oracle(
+    program_id: &Pubkey,
+    mango_group_pk: &Pubkey,
+    admin_pk: &Pubkey,
+    oracle_pk: &Pubkey,
+    market_index: usize,
+    max_divergence: U64F64
+) -> Result<Instruction, ProgramError> {
+    let accounts = vec![
+        AccountMeta::new(*mango_group_pk, false),
+        AccountMeta::new_readonly(*admin_pk, true),
+        AccountMeta::new_readonly(*oracle_pk, false),
+    ];
+
+    let instr = MangoInstruction::SetSecondaryOracle { market_index, max_divergence };
+    let data = instr.pack();
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data
+    })
+}
+
+/// Set new_admin_pk as the admin, or propose it if it won't sign the transaction
+pub fn set_admin(
+    program_id: &Pubkey,
+    mango_group_pk: &Pubkey,
+    admin_pk: &Pubkey,
+    new_admin_pk: &Pubkey,
+    new_admin_is_signer: bool
+) -> Result<Instruction, ProgramError> {
+    let accounts = vec![
+        AccountMeta::new(*mango_group_pk, false),
+        AccountMeta::new_readonly(*admin_pk, true),
+        AccountMeta::new_readonly(*new_admin_pk, new_admin_is_signer),
+    ];
+
+    let instr = MangoInstruction::SetAdmin;
+    let data = instr.pack();
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data
+    })
+}
+
+pub fn accept_admin(
+    program_id: &Pubkey,
+    mango_group_pk: &Pubkey,
+    pending_admin_pk: &Pubkey
+) -> Result<Instruction, ProgramError> {
+    let accounts = vec![
+        AccountMeta::new(*mango_group_pk, false),
+        AccountMeta::new_readonly(*pending_admin_pk, true),
+    ];
+
+    let instr = MangoInstruction::AcceptAdmin;
+    let data = instr.pack();
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data
+    })
+}
+
+pub fn change_dust_threshold(
     program_id: &Pubkey,
     mango_group_pk: &Pubkey,
-    signer_pk: &Pubkey,
+    admin_pk: &Pubkey,
+    token_index: usize,
+    dust_threshold: u64
+) -> Result<Instruction, ProgramError> {
+    let accounts = vec![
+        AccountMeta::new(*mango_group_pk, false),
+        AccountMeta::new_readonly(*admin_pk, true),
+    ];
+
+    let instr = MangoInstruction::ChangeDustThreshold { token_index, dust_threshold };
+    let data = instr.pack();
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data
+    })
+}
+
+pub fn deposit_with_referrer(
+    program_id: &Pubkey,
+    mango_group_pk: &Pubkey,
+    margin_account_pk: &Pubkey,
+    owner_pk: &Pubkey,
+    token_account_pk: &Pubkey,
+    vault_pk: &Pubkey,
+    referrer_pk: &Pubkey,
+    quantity: u64
+) -> Result<Instruction, ProgramError> {
+    let mut instruction = deposit(
+        program_id, mango_group_pk, margin_account_pk, owner_pk, token_account_pk, vault_pk, quantity
+    )?;
+    instruction.accounts.insert(7, AccountMeta::new_readonly(*referrer_pk, false));
+
+    let instr = MangoInstruction::DepositWithReferrer { quantity };
+    instruction.data = instr.pack();
+    Ok(instruction)
+}
+
+pub fn force_liquidate_on_dex(
+    program_id: &Pubkey,
+    mango_group_pk: &Pubkey,
+    liqor_pk: &Pubkey,
+    liqee_margin_account_pk: &Pubkey,
     dex_prog_id: &Pubkey,
+    spot_market_pk: &Pubkey,
+    dex_request_queue_pk: &Pubkey,
+    dex_event_queue_pk: &Pubkey,
+    bids_pk: &Pubkey,
+    asks_pk: &Pubkey,
+    base_vault_pk: &Pubkey,
+    quote_vault_pk: &Pubkey,
+    signer_pk: &Pubkey,
+    dex_base_pk: &Pubkey,
+    dex_quote_pk: &Pubkey,
     srm_vault_pk: &Pubkey,
-    admin_pk: &Pubkey,
-    mint_pks: &[Pubkey],
-    vault_pks: &[Pubkey],
-    spot_market_pks: &[Pubkey],
+    dex_signer_pk: &Pubkey,
+    open_orders_pks: &[Pubkey],
     oracle_pks: &[Pubkey],
-    signer_nonce: u64,
-    maint_coll_ratio: U64F64,
-    init_coll_ratio: U64F64,
-    borrow_limits: [u64; NUM_TOKENS]
+    market_index: usize,
+    limit: u8
 ) -> Result<Instruction, ProgramError> {
     let mut accounts = vec![
         AccountMeta::new(*mango_group_pk, false),
-        AccountMeta::new_readonly(solana_program::sysvar::rent::ID, false),
+        AccountMeta::new_readonly(*liqor_pk, true),
+        AccountMeta::new(*liqee_margin_account_pk, false),
         AccountMeta::new_readonly(solana_program::sysvar::clock::ID, false),
-        AccountMeta::new_readonly(*signer_pk, false),
         AccountMeta::new_readonly(*dex_prog_id, false),
-        AccountMeta::new_readonly(*srm_vault_pk, false),
-        AccountMeta::new_readonly(*admin_pk, true)
+        AccountMeta::new(*spot_market_pk, false),
+        AccountMeta::new(*dex_request_queue_pk, false),
+        AccountMeta::new(*dex_event_queue_pk, false),
+        AccountMeta::new(*bids_pk, false),
+        AccountMeta::new(*asks_pk, false),
+        AccountMeta::new(*base_vault_pk, false),
+        AccountMeta::new(*quote_vault_pk, false),
+        AccountMeta::new_readonly(*signer_pk, false),
+        AccountMeta::new(*dex_base_pk, false),
+        AccountMeta::new(*dex_quote_pk, false),
+        AccountMeta::new_readonly(spl_token::ID, false),
+        AccountMeta::new_readonly(solana_program::sysvar::rent::ID, false),
+        AccountMeta::new(*srm_vault_pk, false),
+        AccountMeta::new_readonly(*dex_signer_pk, false),
     ];
-    accounts.extend(mint_pks.iter().map(
+
+    accounts.extend(open_orders_pks.iter().map(
+        |pk| AccountMeta::new(*pk, false))
+    );
+    accounts.extend(oracle_pks.iter().map(
         |pk| AccountMeta::new_readonly(*pk, false))
     );
-    accounts.extend(vault_pks.iter().map(
+
+    let instr = MangoInstruction::ForceLiquidateOnDex { market_index, limit };
+    let data = instr.pack();
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data
+    })
+}
+
+/// Append the secondary oracle accounts to an instruction that reads prices
+pub fn with_secondary_oracles(
+    mut instruction: Instruction,
+    secondary_oracle_pks: &[Pubkey]
+) -> Instruction {
+    instruction.accounts.extend(secondary_oracle_pks.iter().map(
         |pk| AccountMeta::new_readonly(*pk, false))
     );
-    accounts.extend(spot_market_pks.iter().map(
+    instruction
+}
+
+fn native_sol_account_metas() -> Vec<AccountMeta> {
+    vec![
+        AccountMeta::new_readonly(solana_program::system_program::ID, false),
+        AccountMeta::new_readonly(spl_token::native_mint::ID, false),
+        AccountMeta::new_readonly(solana_program::sysvar::rent::ID, false),
+    ]
+}
+
+/// Deposit native SOL from owner_pk's lamports into a vault whose mint is wrapped SOL
+pub fn deposit_native_sol(
+    program_id: &Pubkey,
+    mango_group_pk: &Pubkey,
+    margin_account_pk: &Pubkey,
+    owner_pk: &Pubkey,
+    vault_pk: &Pubkey,
+    quantity: u64
+) -> Result<Instruction, ProgramError> {
+    let (wrapped_pk, _) = get_wrapped_sol_address(program_id, margin_account_pk);
+    let mut instruction = deposit(
+        program_id, mango_group_pk, margin_account_pk, owner_pk, &wrapped_pk, vault_pk, quantity
+    )?;
+    instruction.accounts[2] = AccountMeta::new(*owner_pk, true);
+    instruction.accounts.extend(native_sol_account_metas());
+    Ok(instruction)
+}
+
+/// Withdraw from a vault whose mint is wrapped SOL straight to owner_pk as native SOL
+pub fn withdraw_native_sol(
+    program_id: &Pubkey,
+    mango_group_pk: &Pubkey,
+    margin_account_pk: &Pubkey,
+    owner_pk: &Pubkey,
+    vault_pk: &Pubkey,
+    signer_pk: &Pubkey,
+    open_orders_pks: &[Pubkey],
+    oracle_pks: &[Pubkey],
+    quantity: u64
+) -> Result<Instruction, ProgramError> {
+    let (wrapped_pk, _) = get_wrapped_sol_address(program_id, margin_account_pk);
+    let mut instruction = withdraw(
+        program_id, mango_group_pk, margin_account_pk, owner_pk, &wrapped_pk, vault_pk, signer_pk,
+        open_orders_pks, oracle_pks, quantity
+    )?;
+    instruction.accounts[2] = AccountMeta::new(*owner_pk, true);
+    instruction.accounts.extend(native_sol_account_metas());
+    Ok(instruction)
+}
+
+pub fn add_spot_market(
+    program_id: &Pubkey,
+    mango_group_pk: &Pubkey,
+    admin_pk: &Pubkey,
+    spot_market_pk: &Pubkey,
+    oracle_pk: &Pubkey,
+    market_index: usize
+) -> Result<Instruction, ProgramError> {
+    let accounts = vec![
+        AccountMeta::new(*mango_group_pk, false),
+        AccountMeta::new_readonly(*admin_pk, true),
+        AccountMeta::new_readonly(*spot_market_pk, false),
+        AccountMeta::new_readonly(*oracle_pk, false),
+    ];
+
+    let instr = MangoInstruction::AddSpotMarket { market_index };
+    let data = instr.pack();
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data
+    })
+}
+
+pub fn change_min_order_notional(
+    program_id: &Pubkey,
+    mango_group_pk: &Pubkey,
+    admin_pk: &Pubkey,
+    min_order_notional: u64
+) -> Result<Instruction, ProgramError> {
+    let accounts = vec![
+        AccountMeta::new(*mango_group_pk, false),
+        AccountMeta::new_readonly(*admin_pk, true),
+    ];
+
+    let instr = MangoInstruction::ChangeMinOrderNotional { min_order_notional };
+    let data = instr.pack();
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data
+    })
+}
+
+pub fn cancel_all_my_orders(
+    program_id: &Pubkey,
+    mango_group_pk: &Pubkey,
+    owner_pk: &Pubkey,
+    margin_account_pk: &Pubkey,
+    dex_prog_id: &Pubkey,
+    spot_market_pk: &Pubkey,
+    bids_pk: &Pubkey,
+    asks_pk: &Pubkey,
+    open_orders_pk: &Pubkey,
+    signer_pk: &Pubkey,
+    dex_event_queue_pk: &Pubkey,
+    market_index: usize,
+    limit: u8
+) -> Result<Instruction, ProgramError> {
+    let accounts = vec![
+        AccountMeta::new(*mango_group_pk, false),
+        AccountMeta::new_readonly(*owner_pk, true),
+        AccountMeta::new(*margin_account_pk, false),
+        AccountMeta::new_readonly(solana_program::sysvar::clock::ID, false),
+        AccountMeta::new_readonly(*dex_prog_id, false),
+        AccountMeta::new(*spot_market_pk, false),
+        AccountMeta::new(*bids_pk, false),
+        AccountMeta::new(*asks_pk, false),
+        AccountMeta::new(*open_orders_pk, false),
+        AccountMeta::new_readonly(*signer_pk, false),
+        AccountMeta::new(*dex_event_queue_pk, false),
+    ];
+
+    let instr = MangoInstruction::CancelAllMyOrders { market_index, limit };
+    let data = instr.pack();
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data
+    })
+}
+
+pub fn deposit_for_account(
+    program_id: &Pubkey,
+    mango_group_pk: &Pubkey,
+    margin_account_pk: &Pubkey,
+    funder_pk: &Pubkey,
+    token_account_pk: &Pubkey,
+    vault_pk: &Pubkey,
+    quantity: u64
+) -> Result<Instruction, ProgramError> {
+    let mut instruction = deposit(
+        program_id, mango_group_pk, margin_account_pk, funder_pk, token_account_pk, vault_pk, quantity
+    )?;
+
+    let instr = MangoInstruction::DepositForAccount { quantity };
+    instruction.data = instr.pack();
+    Ok(instruction)
+}
+
+pub fn settle_borrow_to_ratio(
+    program_id: &Pubkey,
+    mango_group_pk: &Pubkey,
+    margin_account_pk: &Pubkey,
+    owner_pk: &Pubkey,
+    open_orders_pks: &[Pubkey],
+    oracle_pks: &[Pubkey],
+    token_index: usize,
+    target_ratio: U64F64
+) -> Result<Instruction, ProgramError> {
+    let mut accounts = vec![
+        AccountMeta::new(*mango_group_pk, false),
+        AccountMeta::new(*margin_account_pk, false),
+        AccountMeta::new_readonly(*owner_pk, true),
+        AccountMeta::new_readonly(solana_program::sysvar::clock::ID, false),
+    ];
+
+    accounts.extend(open_orders_pks.iter().map(
         |pk| AccountMeta::new_readonly(*pk, false))
     );
     accounts.extend(oracle_pks.iter().map(
         |pk| AccountMeta::new_readonly(*pk, false))
     );
 
-    let instr = MangoInstruction::InitMangoGroup {
-        signer_nonce,
-        maint_coll_ratio,
-        init_coll_ratio,
-        borrow_limits
-    };
-
+    let instr = MangoInstruction::SettleBorrowToRatio { token_index, target_ratio };
+    let data = instr.pack();
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data
+    })
+}
+
+pub fn consume_events(
+    program_id: &Pubkey,
+    mango_group_pk: &Pubkey,
+    dex_prog_id: &Pubkey,
+    spot_market_pk: &Pubkey,
+    dex_event_queue_pk: &Pubkey,
+    coin_fee_receivable_pk: &Pubkey,
+    pc_fee_receivable_pk: &Pubkey,
+    open_orders_pks: &[Pubkey],
+    limit: u16
+) -> Result<Instruction, ProgramError> {
+    let mut accounts = vec![
+        AccountMeta::new_readonly(*mango_group_pk, false),
+        AccountMeta::new_readonly(*dex_prog_id, false),
+        AccountMeta::new(*spot_market_pk, false),
+        AccountMeta::new(*dex_event_queue_pk, false),
+        AccountMeta::new(*coin_fee_receivable_pk, false),
+        AccountMeta::new(*pc_fee_receivable_pk, false),
+    ];
+
+    // serum binary searches these by their aligned bytes
+    let mut open_orders_pks = open_orders_pks.to_vec();
+    open_orders_pks.sort_by_key(|pk| pk.to_aligned_bytes());
+    accounts.extend(open_orders_pks.iter().map(
+        |pk| AccountMeta::new(*pk, false))
+    );
+
+    let instr = MangoInstruction::ConsumeEvents { limit };
     let data = instr.pack();
     Ok(Instruction {
         program_id: *program_id,
@@ -600,20 +2903,42 @@ pub fn init_mango_group(
     })
 }
 
-pub fn init_margin_account(
+pub fn change_max_borrow_per_account(
     program_id: &Pubkey,
     mango_group_pk: &Pubkey,
-    margin_account_pk: &Pubkey,
-    owner_pk: &Pubkey,
+    admin_pk: &Pubkey,
+    token_index: usize,
+    max_borrow: u64
 ) -> Result<Instruction, ProgramError> {
     let accounts = vec![
+        AccountMeta::new(*mango_group_pk, false),
+        AccountMeta::new_readonly(*admin_pk, true),
+    ];
+
+    let instr = MangoInstruction::ChangeMaxBorrowPerAccount { token_index, max_borrow };
+    let data = instr.pack();
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data
+    })
+}
+
+pub fn check_solvency(
+    program_id: &Pubkey,
+    mango_group_pk: &Pubkey,
+    oracle_pks: &[Pubkey]
+) -> Result<Instruction, ProgramError> {
+    let mut accounts = vec![
         AccountMeta::new_readonly(*mango_group_pk, false),
-        AccountMeta::new(*margin_account_pk, false),
-        AccountMeta::new_readonly(*owner_pk, true),
-        AccountMeta::new_readonly(solana_program::sysvar::rent::ID, false),
+        AccountMeta::new_readonly(solana_program::sysvar::clock::ID, false),
     ];
 
-    let instr = MangoInstruction::InitMarginAccount;
+    accounts.extend(oracle_pks.iter().map(
+        |pk| AccountMeta::new_readonly(*pk, false))
+    );
+
+    let instr = MangoInstruction::CheckSolvency;
     let data = instr.pack();
     Ok(Instruction {
         program_id: *program_id,
@@ -622,26 +2947,18 @@ pub fn init_margin_account(
     })
 }
 
-pub fn deposit(
+pub fn change_collateral_grace_period(
     program_id: &Pubkey,
     mango_group_pk: &Pubkey,
-    margin_account_pk: &Pubkey,
-    owner_pk: &Pubkey,
-    token_account_pk: &Pubkey,
-    vault_pk: &Pubkey,
-    quantity: u64
+    admin_pk: &Pubkey,
+    grace_period: u64
 ) -> Result<Instruction, ProgramError> {
     let accounts = vec![
         AccountMeta::new(*mango_group_pk, false),
-        AccountMeta::new(*margin_account_pk, false),
-        AccountMeta::new_readonly(*owner_pk, true),
-        AccountMeta::new(*token_account_pk, false),
-        AccountMeta::new(*vault_pk, false),
-        AccountMeta::new_readonly(spl_token::ID, false),
-        AccountMeta::new_readonly(solana_program::sysvar::clock::ID, false),
+        AccountMeta::new_readonly(*admin_pk, true),
     ];
 
-    let instr = MangoInstruction::Deposit { quantity };
+    let instr = MangoInstruction::ChangeCollateralGracePeriod { grace_period };
     let data = instr.pack();
     Ok(Instruction {
         program_id: *program_id,
@@ -650,37 +2967,40 @@ pub fn deposit(
     })
 }
 
-pub fn withdraw(
+pub fn migrate_margin_account(
     program_id: &Pubkey,
-    mango_group_pk: &Pubkey,
+    old_mango_group_pk: &Pubkey,
+    new_mango_group_pk: &Pubkey,
     margin_account_pk: &Pubkey,
     owner_pk: &Pubkey,
-    token_account_pk: &Pubkey,
-    vault_pk: &Pubkey,
-    signer_pk: &Pubkey,
-    open_orders_pks: &[Pubkey],
-    oracle_pks: &[Pubkey],
-    quantity: u64
+    old_signer_pk: &Pubkey,
+    new_signer_pk: &Pubkey,
+    old_vault_pks: &[Pubkey],
+    new_vault_pks: &[Pubkey],
+    oracle_pks: &[Pubkey]
 ) -> Result<Instruction, ProgramError> {
     let mut accounts = vec![
-        AccountMeta::new(*mango_group_pk, false),
+        AccountMeta::new(*old_mango_group_pk, false),
+        AccountMeta::new(*new_mango_group_pk, false),
         AccountMeta::new(*margin_account_pk, false),
         AccountMeta::new_readonly(*owner_pk, true),
-        AccountMeta::new(*token_account_pk, false),
-        AccountMeta::new(*vault_pk, false),
-        AccountMeta::new_readonly(*signer_pk, false),
+        AccountMeta::new_readonly(*old_signer_pk, false),
+        AccountMeta::new_readonly(*new_signer_pk, false),
         AccountMeta::new_readonly(spl_token::ID, false),
         AccountMeta::new_readonly(solana_program::sysvar::clock::ID, false),
     ];
 
-    accounts.extend(open_orders_pks.iter().map(
-        |pk| AccountMeta::new_readonly(*pk, false))
+    accounts.extend(old_vault_pks.iter().map(
+        |pk| AccountMeta::new(*pk, false))
+    );
+    accounts.extend(new_vault_pks.iter().map(
+        |pk| AccountMeta::new(*pk, false))
     );
     accounts.extend(oracle_pks.iter().map(
         |pk| AccountMeta::new_readonly(*pk, false))
     );
 
-    let instr = MangoInstruction::Withdraw { quantity };
+    let instr = MangoInstruction::MigrateMarginAccount;
     let data = instr.pack();
     Ok(Instruction {
         program_id: *program_id,
@@ -689,20 +3009,16 @@ pub fn withdraw(
     })
 }
 
-pub fn borrow(
+pub fn resolve_bankruptcy(
     program_id: &Pubkey,
     mango_group_pk: &Pubkey,
     margin_account_pk: &Pubkey,
-    owner_pk: &Pubkey,
     open_orders_pks: &[Pubkey],
-    oracle_pks: &[Pubkey],
-    token_index: usize,
-    quantity: u64
+    oracle_pks: &[Pubkey]
 ) -> Result<Instruction, ProgramError> {
     let mut accounts = vec![
         AccountMeta::new(*mango_group_pk, false),
         AccountMeta::new(*margin_account_pk, false),
-        AccountMeta::new_readonly(*owner_pk, true),
         AccountMeta::new_readonly(solana_program::sysvar::clock::ID, false),
     ];
 
@@ -713,7 +3029,7 @@ pub fn borrow(
         |pk| AccountMeta::new_readonly(*pk, false))
     );
 
-    let instr = MangoInstruction::Borrow { token_index, quantity };
+    let instr = MangoInstruction::ResolveBankruptcy;
     let data = instr.pack();
     Ok(Instruction {
         program_id: *program_id,
@@ -722,22 +3038,60 @@ pub fn borrow(
     })
 }
 
-pub fn settle_borrow(
+pub fn settle_funds_partial(
     program_id: &Pubkey,
     mango_group_pk: &Pubkey,
+    owner_pk: &Pubkey,
     margin_account_pk: &Pubkey,
+    dex_prog_id: &Pubkey,
+    spot_market_pk: &Pubkey,
+    open_orders_pk: &Pubkey,
+    signer_pk: &Pubkey,
+    dex_base_pk: &Pubkey,
+    dex_quote_pk: &Pubkey,
+    base_vault_pk: &Pubkey,
+    quote_vault_pk: &Pubkey,
+    dex_signer_pk: &Pubkey,
+    max_base: u64,
+    max_quote: u64
+) -> Result<Instruction, ProgramError> {
+    let mut instruction = settle_funds(
+        program_id, mango_group_pk, owner_pk, margin_account_pk, dex_prog_id, spot_market_pk,
+        open_orders_pk, signer_pk, dex_base_pk, dex_quote_pk, base_vault_pk, quote_vault_pk, dex_signer_pk
+    )?;
+
+    let instr = MangoInstruction::SettleFundsPartial { max_base, max_quote };
+    instruction.data = instr.pack();
+    Ok(instruction)
+}
+
+/// Initialize the margin account of owner_pk at its derived address. Use
+/// `init_margin_account_pda_at` to pass the address and bump explicitly
+pub fn init_margin_account_pda(
+    program_id: &Pubkey,
+    mango_group_pk: &Pubkey,
     owner_pk: &Pubkey,
-    token_index: usize,
-    quantity: u64
+) -> Result<Instruction, ProgramError> {
+    let (margin_account_pk, bump) = get_margin_account_address(program_id, mango_group_pk, owner_pk);
+    init_margin_account_pda_at(program_id, mango_group_pk, &margin_account_pk, owner_pk, bump)
+}
+
+pub fn init_margin_account_pda_at(
+    program_id: &Pubkey,
+    mango_group_pk: &Pubkey,
+    margin_account_pk: &Pubkey,
+    owner_pk: &Pubkey,
+    bump: u8
 ) -> Result<Instruction, ProgramError> {
     let accounts = vec![
-        AccountMeta::new(*mango_group_pk, false),
+        AccountMeta::new_readonly(*mango_group_pk, false),
         AccountMeta::new(*margin_account_pk, false),
-        AccountMeta::new_readonly(*owner_pk, true),
-        AccountMeta::new_readonly(solana_program::sysvar::clock::ID, false),
+        AccountMeta::new(*owner_pk, true),
+        AccountMeta::new_readonly(solana_program::system_program::id(), false),
+        AccountMeta::new_readonly(solana_program::sysvar::rent::ID, false),
     ];
 
-    let instr = MangoInstruction::SettleBorrow { token_index, quantity };
+    let instr = MangoInstruction::InitMarginAccountPda { bump };
     let data = instr.pack();
     Ok(Instruction {
         program_id: *program_id,
@@ -746,39 +3100,20 @@ pub fn settle_borrow(
     })
 }
 
-pub fn liquidate(
+pub fn set_borrow_limit_ramp(
     program_id: &Pubkey,
     mango_group_pk: &Pubkey,
-    liqor_pk: &Pubkey,
-    liqee_margin_account_pk: &Pubkey,
-    open_orders_pks: &[Pubkey],
-    oracle_pks: &[Pubkey],
-    vault_pks: &[Pubkey],
-    liqor_token_account_pks: &[Pubkey],
-    deposit_quantities: [u64; NUM_TOKENS]
+    admin_pk: &Pubkey,
+    targets: [u64; NUM_TOKENS],
+    ramp_seconds: u64
 ) -> Result<Instruction, ProgramError> {
-    let mut accounts = vec![
+    let accounts = vec![
         AccountMeta::new(*mango_group_pk, false),
-        AccountMeta::new_readonly(*liqor_pk, true),
-        AccountMeta::new(*liqee_margin_account_pk, false),
-        AccountMeta::new_readonly(spl_token::ID, false),
+        AccountMeta::new_readonly(*admin_pk, true),
         AccountMeta::new_readonly(solana_program::sysvar::clock::ID, false),
     ];
 
-    accounts.extend(open_orders_pks.iter().map(
-        |pk| AccountMeta::new_readonly(*pk, false))
-    );
-    accounts.extend(oracle_pks.iter().map(
-        |pk| AccountMeta::new_readonly(*pk, false))
-    );
-    accounts.extend(vault_pks.iter().map(
-        |pk| AccountMeta::new(*pk, false))
-    );
-    accounts.extend(liqor_token_account_pks.iter().map(
-        |pk| AccountMeta::new(*pk, false))
-    );
-
-    let instr = MangoInstruction::Liquidate { deposit_quantities };
+    let instr = MangoInstruction::SetBorrowLimitRamp { targets, ramp_seconds };
     let data = instr.pack();
     Ok(Instruction {
         program_id: *program_id,
@@ -787,27 +3122,36 @@ pub fn liquidate(
     })
 }
 
-pub fn deposit_srm(
+pub fn change_max_orders_per_market(
     program_id: &Pubkey,
     mango_group_pk: &Pubkey,
-    mango_srm_account_pk: &Pubkey,
-    owner_pk: &Pubkey,
-    srm_account_pk: &Pubkey,
-    vault_pk: &Pubkey,
-    quantity: u64
+    admin_pk: &Pubkey,
+    max_orders: u64
+) -> Result<Instruction, ProgramError> {
+    let accounts = vec![
+        AccountMeta::new(*mango_group_pk, false),
+        AccountMeta::new_readonly(*admin_pk, true),
+    ];
+
+    let instr = MangoInstruction::ChangeMaxOrdersPerMarket { max_orders };
+    let data = instr.pack();
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data
+    })
+}
+
+pub fn log_indexes(
+    program_id: &Pubkey,
+    mango_group_pk: &Pubkey
 ) -> Result<Instruction, ProgramError> {
     let accounts = vec![
         AccountMeta::new(*mango_group_pk, false),
-        AccountMeta::new(*mango_srm_account_pk, false),
-        AccountMeta::new_readonly(*owner_pk, true),
-        AccountMeta::new(*srm_account_pk, false),
-        AccountMeta::new(*vault_pk, false),
-        AccountMeta::new_readonly(spl_token::ID, false),
         AccountMeta::new_readonly(solana_program::sysvar::clock::ID, false),
-        AccountMeta::new_readonly(solana_program::sysvar::rent::ID, false),
     ];
 
-    let instr = MangoInstruction::DepositSrm { quantity };
+    let instr = MangoInstruction::LogIndexes;
     let data = instr.pack();
     Ok(Instruction {
         program_id: *program_id,
@@ -816,28 +3160,50 @@ pub fn deposit_srm(
     })
 }
 
-pub fn withdraw_srm(
+pub fn change_all_borrow_limits(
     program_id: &Pubkey,
     mango_group_pk: &Pubkey,
-    mango_srm_account_pk: &Pubkey,
-    owner_pk: &Pubkey,
-    srm_account_pk: &Pubkey,
-    vault_pk: &Pubkey,
-    signer_pk: &Pubkey,
-    quantity: u64
+    admin_pk: &Pubkey,
+    limits: [u64; NUM_TOKENS]
 ) -> Result<Instruction, ProgramError> {
     let accounts = vec![
         AccountMeta::new(*mango_group_pk, false),
-        AccountMeta::new(*mango_srm_account_pk, false),
+        AccountMeta::new_readonly(*admin_pk, true),
+    ];
+
+    let instr = MangoInstruction::ChangeAllBorrowLimits { limits };
+    let data = instr.pack();
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data
+    })
+}
+
+pub fn deposit_multi(
+    program_id: &Pubkey,
+    mango_group_pk: &Pubkey,
+    margin_account_pk: &Pubkey,
+    owner_pk: &Pubkey,
+    token_account_pks: &[Pubkey],
+    vault_pks: &[Pubkey],
+    quantities: [u64; NUM_TOKENS]
+) -> Result<Instruction, ProgramError> {
+    let mut accounts = vec![
+        AccountMeta::new(*mango_group_pk, false),
+        AccountMeta::new(*margin_account_pk, false),
         AccountMeta::new_readonly(*owner_pk, true),
-        AccountMeta::new(*srm_account_pk, false),
-        AccountMeta::new(*vault_pk, false),
-        AccountMeta::new_readonly(*signer_pk, false),
         AccountMeta::new_readonly(spl_token::ID, false),
         AccountMeta::new_readonly(solana_program::sysvar::clock::ID, false),
     ];
+    accounts.extend(token_account_pks.iter().map(
+        |pk| AccountMeta::new(*pk, false))
+    );
+    accounts.extend(vault_pks.iter().map(
+        |pk| AccountMeta::new(*pk, false))
+    );
 
-    let instr = MangoInstruction::WithdrawSrm { quantity };
+    let instr = MangoInstruction::DepositMulti { quantities };
     let data = instr.pack();
     Ok(Instruction {
         program_id: *program_id,
@@ -846,55 +3212,48 @@ pub fn withdraw_srm(
     })
 }
 
-pub fn place_order(
+pub fn refresh_oracle_decimals(
     program_id: &Pubkey,
     mango_group_pk: &Pubkey,
-    owner_pk: &Pubkey,
-    margin_account_pk: &Pubkey,
-    dex_prog_id: &Pubkey,
-    spot_market_pk: &Pubkey,
-    dex_request_queue_pk: &Pubkey,
-    dex_event_queue_pk: &Pubkey,
-    bids_pk: &Pubkey,
-    asks_pk: &Pubkey,
+    admin_pk: &Pubkey,
+    oracle_pks: &[Pubkey]
+) -> Result<Instruction, ProgramError> {
+    let mut accounts = vec![
+        AccountMeta::new(*mango_group_pk, false),
+        AccountMeta::new_readonly(*admin_pk, true),
+    ];
+    accounts.extend(oracle_pks.iter().map(
+        |pk| AccountMeta::new_readonly(*pk, false))
+    );
+
+    let instr = MangoInstruction::RefreshOracleDecimals;
+    let data = instr.pack();
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data
+    })
+}
+
+pub fn withdraw_excess_srm(
+    program_id: &Pubkey,
+    mango_group_pk: &Pubkey,
+    admin_pk: &Pubkey,
+    srm_account_pk: &Pubkey,
     vault_pk: &Pubkey,
     signer_pk: &Pubkey,
-    dex_base_pk: &Pubkey,
-    dex_quote_pk: &Pubkey,
-    srm_vault_pk: &Pubkey,
-    open_orders_pks: &[Pubkey],
-    oracle_pks: &[Pubkey],
-    order: serum_dex::instruction::NewOrderInstructionV3
+    quantity: u64
 ) -> Result<Instruction, ProgramError> {
-
-    let mut accounts = vec![
+    let accounts = vec![
         AccountMeta::new(*mango_group_pk, false),
-        AccountMeta::new_readonly(*owner_pk, true),
-        AccountMeta::new(*margin_account_pk, false),
-        AccountMeta::new_readonly(solana_program::sysvar::clock::ID, false),
-        AccountMeta::new_readonly(*dex_prog_id, false),
-        AccountMeta::new(*spot_market_pk, false),
-        AccountMeta::new(*dex_request_queue_pk, false),
-        AccountMeta::new(*dex_event_queue_pk, false),
-        AccountMeta::new(*bids_pk, false),
-        AccountMeta::new(*asks_pk, false),
+        AccountMeta::new_readonly(*admin_pk, true),
+        AccountMeta::new(*srm_account_pk, false),
         AccountMeta::new(*vault_pk, false),
         AccountMeta::new_readonly(*signer_pk, false),
-        AccountMeta::new(*dex_base_pk, false),
-        AccountMeta::new(*dex_quote_pk, false),
         AccountMeta::new_readonly(spl_token::ID, false),
-        AccountMeta::new_readonly(solana_program::sysvar::rent::ID, false),
-        AccountMeta::new(*srm_vault_pk, false),
     ];
 
-    accounts.extend(open_orders_pks.iter().map(
-        |pk| AccountMeta::new(*pk, false))
-    );
-    accounts.extend(oracle_pks.iter().map(
-        |pk| AccountMeta::new_readonly(*pk, false))
-    );
-
-    let instr = MangoInstruction::PlaceOrder { order };
+    let instr = MangoInstruction::WithdrawExcessSrm { quantity };
     let data = instr.pack();
     Ok(Instruction {
         program_id: *program_id,
@@ -903,59 +3262,28 @@ pub fn place_order(
     })
 }
 
-pub fn place_and_settle(
+pub fn close_open_orders(
     program_id: &Pubkey,
     mango_group_pk: &Pubkey,
     owner_pk: &Pubkey,
     margin_account_pk: &Pubkey,
     dex_prog_id: &Pubkey,
     spot_market_pk: &Pubkey,
-    dex_request_queue_pk: &Pubkey,
-    dex_event_queue_pk: &Pubkey,
-    bids_pk: &Pubkey,
-    asks_pk: &Pubkey,
-    base_vault_pk: &Pubkey,
-    quote_vault_pk: &Pubkey,
+    open_orders_pk: &Pubkey,
     signer_pk: &Pubkey,
-    dex_base_pk: &Pubkey,
-    dex_quote_pk: &Pubkey,
-    srm_vault_pk: &Pubkey,
-    dex_signer_pk: &Pubkey,
-    open_orders_pks: &[Pubkey],
-    oracle_pks: &[Pubkey],
-    order: serum_dex::instruction::NewOrderInstructionV3
+    market_index: usize
 ) -> Result<Instruction, ProgramError> {
-
-    let mut accounts = vec![
-        AccountMeta::new(*mango_group_pk, false),
-        AccountMeta::new_readonly(*owner_pk, true),
+    let accounts = vec![
+        AccountMeta::new_readonly(*mango_group_pk, false),
+        AccountMeta::new(*owner_pk, true),
         AccountMeta::new(*margin_account_pk, false),
-        AccountMeta::new_readonly(solana_program::sysvar::clock::ID, false),
         AccountMeta::new_readonly(*dex_prog_id, false),
-        AccountMeta::new(*spot_market_pk, false),
-        AccountMeta::new(*dex_request_queue_pk, false),
-        AccountMeta::new(*dex_event_queue_pk, false),
-        AccountMeta::new(*bids_pk, false),
-        AccountMeta::new(*asks_pk, false),
-        AccountMeta::new(*base_vault_pk, false),
-        AccountMeta::new(*quote_vault_pk, false),
+        AccountMeta::new_readonly(*spot_market_pk, false),
+        AccountMeta::new(*open_orders_pk, false),
         AccountMeta::new_readonly(*signer_pk, false),
-        AccountMeta::new(*dex_base_pk, false),
-        AccountMeta::new(*dex_quote_pk, false),
-        AccountMeta::new_readonly(spl_token::ID, false),
-        AccountMeta::new_readonly(solana_program::sysvar::rent::ID, false),
-        AccountMeta::new(*srm_vault_pk, false),
-        AccountMeta::new_readonly(*dex_signer_pk, false),
     ];
 
-    accounts.extend(open_orders_pks.iter().map(
-        |pk| AccountMeta::new(*pk, false))
-    );
-    accounts.extend(oracle_pks.iter().map(
-        |pk| AccountMeta::new_readonly(*pk, false))
-    );
-
-    let instr = MangoInstruction::PlaceAndSettle { order };
+    let instr = MangoInstruction::CloseOpenOrders { market_index };
     let data = instr.pack();
     Ok(Instruction {
         program_id: *program_id,
@@ -964,41 +3292,18 @@ pub fn place_and_settle(
     })
 }
 
-
-pub fn settle_funds(
+pub fn change_max_accrual_interval(
     program_id: &Pubkey,
     mango_group_pk: &Pubkey,
-    owner_pk: &Pubkey,
-    margin_account_pk: &Pubkey,
-    dex_prog_id: &Pubkey,
-    spot_market_pk: &Pubkey,
-    open_orders_pk: &Pubkey,
-    signer_pk: &Pubkey,
-    dex_base_pk: &Pubkey,
-    dex_quote_pk: &Pubkey,
-    base_vault_pk: &Pubkey,
-    quote_vault_pk: &Pubkey,
-    dex_signer_pk: &Pubkey,
+    admin_pk: &Pubkey,
+    max_accrual_interval: u64
 ) -> Result<Instruction, ProgramError> {
-
     let accounts = vec![
         AccountMeta::new(*mango_group_pk, false),
-        AccountMeta::new_readonly(*owner_pk, true),
-        AccountMeta::new(*margin_account_pk, false),
-        AccountMeta::new_readonly(solana_program::sysvar::clock::ID, false),
-        AccountMeta::new_readonly(*dex_prog_id, false),
-        AccountMeta::new(*spot_market_pk, false),
-        AccountMeta::new(*open_orders_pk, false),
-        AccountMeta::new_readonly(*signer_pk, false),
-        AccountMeta::new(*dex_base_pk, false),
-        AccountMeta::new(*dex_quote_pk, false),
-        AccountMeta::new(*base_vault_pk, false),
-        AccountMeta::new(*quote_vault_pk, false),
-        AccountMeta::new_readonly(*dex_signer_pk, false),
-        AccountMeta::new_readonly(spl_token::ID, false),
+        AccountMeta::new_readonly(*admin_pk, true),
     ];
 
-    let instr = MangoInstruction::SettleFunds;
+    let instr = MangoInstruction::ChangeMaxAccrualInterval { max_accrual_interval };
     let data = instr.pack();
     Ok(Instruction {
         program_id: *program_id,
@@ -1007,35 +3312,29 @@ pub fn settle_funds(
     })
 }
 
-pub fn cancel_order(
+pub fn init_open_orders(
     program_id: &Pubkey,
     mango_group_pk: &Pubkey,
     owner_pk: &Pubkey,
     margin_account_pk: &Pubkey,
     dex_prog_id: &Pubkey,
     spot_market_pk: &Pubkey,
-    bids_pk: &Pubkey,
-    asks_pk: &Pubkey,
     open_orders_pk: &Pubkey,
     signer_pk: &Pubkey,
-    dex_event_queue_pk: &Pubkey,
-    order: serum_dex::instruction::CancelOrderInstructionV2
+    market_index: usize
 ) -> Result<Instruction, ProgramError> {
     let accounts = vec![
-        AccountMeta::new(*mango_group_pk, false),
+        AccountMeta::new_readonly(*mango_group_pk, false),
         AccountMeta::new_readonly(*owner_pk, true),
-        AccountMeta::new_readonly(*margin_account_pk, false),
-        AccountMeta::new_readonly(solana_program::sysvar::clock::ID, false),
+        AccountMeta::new(*margin_account_pk, false),
         AccountMeta::new_readonly(*dex_prog_id, false),
-        AccountMeta::new(*spot_market_pk, false),
-        AccountMeta::new(*bids_pk, false),
-        AccountMeta::new(*asks_pk, false),
+        AccountMeta::new_readonly(*spot_market_pk, false),
         AccountMeta::new(*open_orders_pk, false),
         AccountMeta::new_readonly(*signer_pk, false),
-        AccountMeta::new(*dex_event_queue_pk, false),
+        AccountMeta::new_readonly(solana_program::sysvar::rent::ID, false),
     ];
 
-    let instr = MangoInstruction::CancelOrder { order };
+    let instr = MangoInstruction::InitOpenOrders { market_index };
     let data = instr.pack();
     Ok(Instruction {
         program_id: *program_id,
@@ -1044,34 +3343,18 @@ pub fn cancel_order(
     })
 }
 
-pub fn cancel_order_by_client_id(
+pub fn change_liquidation_cooldown(
     program_id: &Pubkey,
     mango_group_pk: &Pubkey,
-    owner_pk: &Pubkey,
-    margin_account_pk: &Pubkey,
-    dex_prog_id: &Pubkey,
-    spot_market_pk: &Pubkey,
-    bids_pk: &Pubkey,
-    asks_pk: &Pubkey,
-    open_orders_pk: &Pubkey,
-    signer_pk: &Pubkey,
-    dex_event_queue_pk: &Pubkey,
-    client_id: u64
+    admin_pk: &Pubkey,
+    liquidation_cooldown: u64
 ) -> Result<Instruction, ProgramError> {
     let accounts = vec![
         AccountMeta::new(*mango_group_pk, false),
-        AccountMeta::new_readonly(*owner_pk, true),
-        AccountMeta::new_readonly(*margin_account_pk, false),
-        AccountMeta::new_readonly(solana_program::sysvar::clock::ID, false),
-        AccountMeta::new_readonly(*dex_prog_id, false),
-        AccountMeta::new(*spot_market_pk, false),
-        AccountMeta::new(*bids_pk, false),
-        AccountMeta::new(*asks_pk, false),
-        AccountMeta::new(*open_orders_pk, false),
-        AccountMeta::new_readonly(*signer_pk, false),
-        AccountMeta::new(*dex_event_queue_pk, false),
+        AccountMeta::new_readonly(*admin_pk, true),
     ];
-    let instr = MangoInstruction::CancelOrderByClientId { client_id };
+
+    let instr = MangoInstruction::ChangeLiquidationCooldown { liquidation_cooldown };
     let data = instr.pack();
     Ok(Instruction {
         program_id: *program_id,
@@ -1080,20 +3363,21 @@ pub fn cancel_order_by_client_id(
     })
 }
 
-
-pub fn change_borrow_limit(
+pub fn set_oracle(
     program_id: &Pubkey,
     mango_group_pk: &Pubkey,
     admin_pk: &Pubkey,
-    token_index: usize,
-    borrow_limit: u64
+    oracle_pk: &Pubkey,
+    market_index: usize,
+    oracle_type: u8
 ) -> Result<Instruction, ProgramError> {
     let accounts = vec![
         AccountMeta::new(*mango_group_pk, false),
         AccountMeta::new_readonly(*admin_pk, true),
+        AccountMeta::new_readonly(*oracle_pk, false),
     ];
 
-    let instr = MangoInstruction::ChangeBorrowLimit { token_index, borrow_limit };
+    let instr = MangoInstruction::SetOracle { market_index, oracle_type };
     let data = instr.pack();
     Ok(Instruction {
         program_id: *program_id,
@@ -1102,7 +3386,7 @@ pub fn change_borrow_limit(
     })
 }
 
-pub fn force_cancel_orders(
+pub fn force_settle_funds(
     program_id: &Pubkey,
     mango_group_pk: &Pubkey,
     liqor_pk: &Pubkey,
@@ -1110,19 +3394,15 @@ pub fn force_cancel_orders(
     base_vault_pk: &Pubkey,
     quote_vault_pk: &Pubkey,
     spot_market_pk: &Pubkey,
-    bids_pk: &Pubkey,
-    asks_pk: &Pubkey,
     signer_pk: &Pubkey,
-    dex_event_queue_pk: &Pubkey,
     dex_base_pk: &Pubkey,
     dex_quote_pk: &Pubkey,
     dex_signer_pk: &Pubkey,
     dex_prog_id: &Pubkey,
     open_orders_pks: &[Pubkey],
     oracle_pks: &[Pubkey],
-    limit: u8
+    market_index: usize
 ) -> Result<Instruction, ProgramError> {
-
     let mut accounts = vec![
         AccountMeta::new(*mango_group_pk, false),
         AccountMeta::new_readonly(*liqor_pk, true),
@@ -1130,10 +3410,7 @@ pub fn force_cancel_orders(
         AccountMeta::new(*base_vault_pk, false),
         AccountMeta::new(*quote_vault_pk, false),
         AccountMeta::new(*spot_market_pk, false),
-        AccountMeta::new(*bids_pk, false),
-        AccountMeta::new(*asks_pk, false),
         AccountMeta::new_readonly(*signer_pk, false),
-        AccountMeta::new(*dex_event_queue_pk, false),
         AccountMeta::new(*dex_base_pk, false),
         AccountMeta::new(*dex_quote_pk, false),
         AccountMeta::new_readonly(*dex_signer_pk, false),
@@ -1149,7 +3426,7 @@ pub fn force_cancel_orders(
         |pk| AccountMeta::new_readonly(*pk, false))
     );
 
-    let instr = MangoInstruction::ForceCancelOrders { limit };
+    let instr = MangoInstruction::ForceSettleFunds { market_index };
     let data = instr.pack();
     Ok(Instruction {
         program_id: *program_id,
@@ -1158,43 +3435,122 @@ pub fn force_cancel_orders(
     })
 }
 
-
-pub fn partial_liquidate(
+pub fn verify_group_config(
     program_id: &Pubkey,
     mango_group_pk: &Pubkey,
-    liqor_pk: &Pubkey,
-    liqor_in_token_pk: &Pubkey,
-    liqor_out_token_pk: &Pubkey,
-    liqee_margin_account_acc: &Pubkey,
-    in_vault_pk: &Pubkey,
-    out_vault_pk: &Pubkey,
-    signer_pk: &Pubkey,
-    open_orders_pks: &[Pubkey],
-    oracle_pks: &[Pubkey],
-    max_deposit: u64
+    mint_pks: &[Pubkey],
+    vault_pks: &[Pubkey],
+    spot_market_pks: &[Pubkey],
+    oracle_pks: &[Pubkey]
 ) -> Result<Instruction, ProgramError> {
-
     let mut accounts = vec![
-        AccountMeta::new(*mango_group_pk, false),
-        AccountMeta::new_readonly(*liqor_pk, true),
-        AccountMeta::new(*liqor_in_token_pk, false),
-        AccountMeta::new(*liqor_out_token_pk, false),
-        AccountMeta::new(*liqee_margin_account_acc, false),
-        AccountMeta::new(*in_vault_pk, false),
-        AccountMeta::new(*out_vault_pk, false),
-        AccountMeta::new_readonly(*signer_pk, false),
-        AccountMeta::new_readonly(spl_token::ID, false),
-        AccountMeta::new_readonly(solana_program::sysvar::clock::ID, false),
+        AccountMeta::new_readonly(*mango_group_pk, false),
     ];
 
-    accounts.extend(open_orders_pks.iter().map(
+    accounts.extend(mint_pks.iter().map(
+        |pk| AccountMeta::new_readonly(*pk, false))
+    );
+    accounts.extend(vault_pks.iter().map(
+        |pk| AccountMeta::new_readonly(*pk, false))
+    );
+    accounts.extend(spot_market_pks.iter().map(
         |pk| AccountMeta::new_readonly(*pk, false))
     );
     accounts.extend(oracle_pks.iter().map(
         |pk| AccountMeta::new_readonly(*pk, false))
     );
 
-    let instr = MangoInstruction::PartialLiquidate { max_deposit };
+    let instr = MangoInstruction::VerifyGroupConfig;
+    let data = instr.pack();
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data
+    })
+}
+
+pub fn change_oi_limit(
+    program_id: &Pubkey,
+    mango_group_pk: &Pubkey,
+    admin_pk: &Pubkey,
+    market_index: usize,
+    oi_limit: u64
+) -> Result<Instruction, ProgramError> {
+    let accounts = vec![
+        AccountMeta::new(*mango_group_pk, false),
+        AccountMeta::new_readonly(*admin_pk, true),
+    ];
+
+    let instr = MangoInstruction::ChangeOiLimit { market_index, oi_limit };
+    let data = instr.pack();
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data
+    })
+}
+
+pub fn cancel_order_and_settle(
+    program_id: &Pubkey,
+    mango_group_pk: &Pubkey,
+    owner_pk: &Pubkey,
+    margin_account_pk: &Pubkey,
+    dex_prog_id: &Pubkey,
+    spot_market_pk: &Pubkey,
+    bids_pk: &Pubkey,
+    asks_pk: &Pubkey,
+    open_orders_pk: &Pubkey,
+    signer_pk: &Pubkey,
+    dex_event_queue_pk: &Pubkey,
+    dex_base_pk: &Pubkey,
+    dex_quote_pk: &Pubkey,
+    base_vault_pk: &Pubkey,
+    quote_vault_pk: &Pubkey,
+    dex_signer_pk: &Pubkey,
+    order: serum_dex::instruction::CancelOrderInstructionV2
+) -> Result<Instruction, ProgramError> {
+    let accounts = vec![
+        AccountMeta::new(*mango_group_pk, false),
+        AccountMeta::new_readonly(*owner_pk, true),
+        AccountMeta::new(*margin_account_pk, false),
+        AccountMeta::new_readonly(solana_program::sysvar::clock::ID, false),
+        AccountMeta::new_readonly(*dex_prog_id, false),
+        AccountMeta::new(*spot_market_pk, false),
+        AccountMeta::new(*bids_pk, false),
+        AccountMeta::new(*asks_pk, false),
+        AccountMeta::new(*open_orders_pk, false),
+        AccountMeta::new_readonly(*signer_pk, false),
+        AccountMeta::new(*dex_event_queue_pk, false),
+        AccountMeta::new(*dex_base_pk, false),
+        AccountMeta::new(*dex_quote_pk, false),
+        AccountMeta::new(*base_vault_pk, false),
+        AccountMeta::new(*quote_vault_pk, false),
+        AccountMeta::new_readonly(*dex_signer_pk, false),
+        AccountMeta::new_readonly(spl_token::ID, false),
+    ];
+
+    let instr = MangoInstruction::CancelOrderAndSettle { order };
+    let data = instr.pack();
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data
+    })
+}
+
+pub fn change_max_price_move(
+    program_id: &Pubkey,
+    mango_group_pk: &Pubkey,
+    admin_pk: &Pubkey,
+    max_price_move_bps: u16,
+    price_band_timeout: u16
+) -> Result<Instruction, ProgramError> {
+    let accounts = vec![
+        AccountMeta::new(*mango_group_pk, false),
+        AccountMeta::new_readonly(*admin_pk, true),
+    ];
+
+    let instr = MangoInstruction::ChangeMaxPriceMove { max_price_move_bps, price_band_timeout };
     let data = instr.pack();
     Ok(Instruction {
         program_id: *program_id,
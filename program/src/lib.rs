@@ -4,7 +4,11 @@ pub mod error;
 pub mod processor;
 pub mod state;
 pub mod instruction;
+pub mod oracle;
 pub mod utils;
 
+#[cfg(feature = "client")]
+pub mod client;
+
 #[cfg(not(feature = "no-entrypoint"))]
 pub mod entrypoint;
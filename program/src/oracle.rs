@@ -0,0 +1,99 @@
+//! Reading Pyth price accounts. Flux aggregators are read with flux_aggregator::read_median;
+//! MangoGroup::oracle_types says which kind each market's primary oracle is
+
+use arrayref::{array_ref, array_refs};
+use fixed::types::U64F64;
+use fixed_macro::types::U64F64;
+use num_enum::TryFromPrimitive;
+use solana_program::account_info::AccountInfo;
+
+use crate::error::{check_assert, MangoError, MangoErrorCode, MangoResult, SourceFileId};
+
+macro_rules! check {
+    ($cond:expr, $err:expr) => {
+        check_assert($cond, $err, line!(), SourceFileId::Oracle)
+    }
+}
+
+macro_rules! throw {
+    () => {
+        MangoError::MangoErrorCode {
+            mango_error_code: MangoErrorCode::Default,
+            line: line!(),
+            source_file_id: SourceFileId::Oracle
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, TryFromPrimitive)]
+#[repr(u8)]
+pub enum OracleType {
+    Flux = 0,
+    Pyth = 1,
+}
+
+pub const PYTH_MAGIC: u32 = 0xa1b2c3d4;
+pub const PYTH_VERSION: u32 = 2;
+pub const PYTH_ACCOUNT_TYPE_PRICE: u32 = 3;
+pub const PYTH_STATUS_TRADING: u32 = 1;
+/// Bytes of a Pyth price account up to and including the aggregate price
+pub const PYTH_PRICE_HEADER_LEN: usize = 240;
+/// Widest confidence interval accepted, relative to the price
+pub const PYTH_MAX_CONF_RATIO: U64F64 = U64F64!(0.02);
+/// Most slots the aggregate price may have been published before the current slot, ~20 seconds
+pub const PYTH_MAX_SLOT_AGE: u64 = 50;
+
+/// Aggregate price of a Pyth price account; the price is price * 10^expo +- conf * 10^expo
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct PythPrice {
+    pub price: i64,
+    pub conf: u64,
+    pub expo: i32,
+    pub status: u32,
+    pub pub_slot: u64,  // slot the aggregate price was published in
+}
+
+/// Parse the aggregate price out of a Pyth price account
+pub fn load_pyth_price(acc: &AccountInfo) -> MangoResult<PythPrice> {
+    let data = acc.try_borrow_data()?;
+    check!(data.len() >= PYTH_PRICE_HEADER_LEN, MangoErrorCode::InvalidOracle)?;
+    let data = array_ref![data, 0, PYTH_PRICE_HEADER_LEN];
+    let (magic, version, account_type, _size, _price_type, expo, _, price, conf, status, _, pub_slot) =
+        array_refs![data, 4, 4, 4, 4, 4, 4, 184, 8, 8, 4, 4, 8];
+
+    check!(u32::from_le_bytes(*magic) == PYTH_MAGIC, MangoErrorCode::InvalidOracle)?;
+    check!(u32::from_le_bytes(*version) == PYTH_VERSION, MangoErrorCode::InvalidOracle)?;
+    check!(u32::from_le_bytes(*account_type) == PYTH_ACCOUNT_TYPE_PRICE, MangoErrorCode::InvalidOracle)?;
+    Ok(PythPrice {
+        price: i64::from_le_bytes(*price),
+        conf: u64::from_le_bytes(*conf),
+        expo: i32::from_le_bytes(*expo),
+        status: u32::from_le_bytes(*status),
+        pub_slot: u64::from_le_bytes(*pub_slot),
+    })
+}
+
+/// The price of a trading Pyth feed published within PYTH_MAX_SLOT_AGE of current_slot with a
+/// narrow enough confidence interval, in units of 10^-decimals like the median of a flux
+/// aggregator with that many decimals
+pub fn get_pyth_value(pyth_price: &PythPrice, decimals: u8, current_slot: u64) -> MangoResult<U64F64> {
+    check!(pyth_price.status == PYTH_STATUS_TRADING, MangoErrorCode::InvalidOracle)?;
+    check!(current_slot.saturating_sub(pyth_price.pub_slot) <= PYTH_MAX_SLOT_AGE, MangoErrorCode::OracleStale)?;
+    check!(pyth_price.price > 0, MangoErrorCode::InvalidOracle)?;
+    let price = U64F64::from_num(pyth_price.price);
+    let max_conf = price.checked_mul(PYTH_MAX_CONF_RATIO).ok_or(throw!())?;
+    check!(U64F64::from_num(pyth_price.conf) <= max_conf, MangoErrorCode::OracleConfidenceTooWide)?;
+
+    let exp = pyth_price.expo.checked_add(decimals as i32).ok_or(throw!())?;
+    let scale = U64F64::from_num(10u64.checked_pow(exp.abs() as u32).ok_or(throw!())?);
+    if exp >= 0 {
+        price.checked_mul(scale).ok_or(throw!())
+    } else {
+        price.checked_div(scale).ok_or(throw!())
+    }
+}
+
+/// Read a Pyth price account as a value with decimals decimals, see get_pyth_value
+pub fn read_pyth_value(acc: &AccountInfo, decimals: u8, current_slot: u64) -> MangoResult<U64F64> {
+    get_pyth_value(&load_pyth_price(acc)?, decimals, current_slot)
+}
@@ -1,11 +1,14 @@
+use std::cell::RefMut;
 use std::cmp;
 use std::cmp::min;
 use std::mem::size_of;
+use std::num::NonZeroU64;
 
 use arrayref::{array_ref, array_refs};
-use fixed::types::U64F64;
+use fixed::types::{U32F32, U64F64};
 use fixed_macro::types::U64F64;
 use flux_aggregator::borsh_state::InitBorshState;
+use num_enum::TryFromPrimitive;
 use serum_dex::matching::Side;
 use serum_dex::state::ToAlignedBytes;
 use solana_program::account_info::AccountInfo;
@@ -17,13 +20,15 @@ use solana_program::program_error::ProgramError;
 use solana_program::program_pack::{IsInitialized, Pack};
 use solana_program::pubkey::Pubkey;
 use solana_program::rent::Rent;
+use solana_program::system_instruction;
 use solana_program::sysvar::Sysvar;
 use spl_token::state::{Account, Mint};
 
 use crate::error::{check_assert, MangoError, MangoErrorCode, MangoResult, SourceFileId};
 use crate::instruction::MangoInstruction;
-use crate::state::{AccountFlag, check_open_orders, DUST_THRESHOLD, load_asks_mut, load_bids_mut, load_market_state, load_open_orders, Loadable, MangoGroup, MangoIndex, MangoSrmAccount, MarginAccount, NUM_MARKETS, NUM_TOKENS, ONE_U64F64, PARTIAL_LIQ_INCENTIVE, ZERO_U64F64};
-use crate::utils::{gen_signer_key, gen_signer_seeds};
+use crate::oracle::{load_pyth_price, OracleType, read_pyth_value};
+use crate::state::{AccountFlag, check_open_orders, check_open_orders_unique, DAY, DUST_THRESHOLD, load_asks_mut, load_bids_mut, load_market_state, load_market_state_checked, load_open_orders, load_open_orders_totals, LIQ_DEPOSIT_TOLERANCE, Loadable, MANGO_GROUP_SIZE, MANGO_GROUP_V1_SIZE, MANGO_GROUP_VERSION, MangoGroup, MangoGroupV1, MangoIndex, MangoSrmAccount, MARGIN_ACCOUNT_V1_SIZE, MARGIN_ACCOUNT_VERSION, MarketFlag, MarginAccount, MAX_MAINT_COLL_RATIO_STEP, MAX_SOCIALIZED_LOSS, median_oracle_price, NUM_MARKETS, NUM_TOKENS, ONE_U64F64, OpenOrdersTotals, PARTIAL_LIQ_INCENTIVE, QUOTE_INDEX, verify_vault_authority, ZERO_U64F64};
+use crate::utils::{format_u64f64, gen_signer_key, gen_signer_seeds, get_margin_account_address, get_wrapped_sol_address, WRAPPED_SOL_SEED};
 
 macro_rules! check_default {
     ($cond:expr) => {
@@ -50,6 +55,19 @@ macro_rules! check_eq {
     }
 }
 
+macro_rules! throw {
+    () => {
+        throw!(MangoErrorCode::Default)
+    };
+    ($err:expr) => {
+        MangoError::MangoErrorCode {
+            mango_error_code: $err,
+            line: line!(),
+            source_file_id: SourceFileId::Processor
+        }
+    }
+}
+
 macro_rules! throw_err {
     ($err:expr) => {
         Err(MangoError::MangoErrorCode { mango_error_code: $err, line: line!(), source_file_id: SourceFileId::Processor })
@@ -66,6 +84,8 @@ pub mod srm_token {
 }
 
 pub const LIQ_MIN_COLL_RATIO: U64F64 = U64F64!(1.01);
+/// How far past the oracle price ForceLiquidateOnDex orders may fill
+pub const FORCE_LIQ_MAX_SLIPPAGE: U64F64 = U64F64!(0.05);
 
 pub struct Processor {}
 
@@ -104,19 +124,24 @@ impl Processor {
         let clock = Clock::from_account_info(clock_acc)?;
 
         // TODO this may not be necessary since load_mut maps the data and will fail if size incorrect
-        check_eq!(size_of::<MangoGroup>(), mango_group_acc.data_len(), MangoErrorCode::InvalidMangoGroupSize)?;
+        check_eq!(MANGO_GROUP_SIZE, mango_group_acc.data_len(), MangoErrorCode::InvalidMangoGroupSize)?;
 
         let mut mango_group = MangoGroup::load_mut(mango_group_acc)?;
 
         check_eq!(mango_group_acc.owner, program_id, MangoErrorCode::InvalidGroupOwner)?;
         check_eq!(mango_group.account_flags, 0, MangoErrorCode::InvalidGroupFlags)?;
         mango_group.account_flags = (AccountFlag::Initialized | AccountFlag::MangoGroup).bits();
+        mango_group.version = MANGO_GROUP_VERSION;
 
         check!(rent.is_exempt(mango_group_acc.lamports(), size_of::<MangoGroup>()), MangoErrorCode::GroupNotRentExempt)?;
         check!(gen_signer_key(signer_nonce, mango_group_acc.key, program_id)? == *signer_acc.key, MangoErrorCode::InvalidSignerKey)?;
         mango_group.signer_nonce = signer_nonce;
         mango_group.signer_key = *signer_acc.key;
         mango_group.dex_program_id = *dex_prog_acc.key;
+
+        // A maint_coll_ratio at or below 1 only liquidates accounts that are already insolvent
+        check_default!(maint_coll_ratio > ONE_U64F64)?;
+        check_default!(init_coll_ratio > maint_coll_ratio)?;
         mango_group.maint_coll_ratio = maint_coll_ratio;
         mango_group.init_coll_ratio = init_coll_ratio;
 
@@ -127,11 +152,13 @@ impl Processor {
         check_eq!(srm_token::ID, srm_vault.mint, MangoErrorCode::Default)?;
         check_eq!(srm_vault_acc.owner, &spl_token::id(), MangoErrorCode::Default)?;
         mango_group.srm_vault = *srm_vault_acc.key;
+        mango_group.srm_vault_balance = srm_vault.amount;
 
         // Set the admin key and make sure it's a signer
         check!(admin_acc.is_signer, MangoErrorCode::Default)?;
         mango_group.admin = *admin_acc.key;
         mango_group.borrow_limits = borrow_limits;
+        mango_group.max_accrual_interval = DAY;
 
         let curr_ts = clock.unix_timestamp as u64;
         for i in 0..NUM_TOKENS {
@@ -151,17 +178,23 @@ impl Processor {
                 deposit: ONE_U64F64  // Smallest unit of interest is 0.0001% or 0.000001
             };
             mango_group.mint_decimals[i] = mint.decimals;
+            mango_group.coll_weights[i] = ONE_U64F64;  // full weight until admin changes it
+            mango_group.liab_weights[i] = ONE_U64F64;
         }
 
         for i in 0..NUM_MARKETS {
             let spot_market_acc: &AccountInfo = &spot_market_accs[i];
-            let spot_market = load_market_state(
+            if *spot_market_acc.key == Pubkey::default() {  // reserved for AddSpotMarket
+                check_eq_default!(oracle_accs[i].key, &Pubkey::default())?;
+                continue;
+            }
+            let spot_market = load_market_state_checked(
                 spot_market_acc, dex_prog_acc.key
             )?;
             let sm_base_mint = spot_market.coin_mint;
             let sm_quote_mint = spot_market.pc_mint;
             check_eq!(sm_base_mint, token_mint_accs[i].key.to_aligned_bytes(), MangoErrorCode::Default)?;
-            check_eq!(sm_quote_mint, token_mint_accs[NUM_MARKETS].key.to_aligned_bytes(), MangoErrorCode::Default)?;
+            check_eq!(sm_quote_mint, token_mint_accs[QUOTE_INDEX].key.to_aligned_bytes(), MangoErrorCode::Default)?;
             mango_group.spot_markets[i] = *spot_market_acc.key;
             mango_group.oracles[i] = *oracle_accs[i].key;
 
@@ -194,12 +227,128 @@ impl Processor {
         check_eq_default!(margin_account_acc.owner, program_id)?;
         check_default!(rent.is_exempt(margin_account_acc.lamports(), size_of::<MarginAccount>()))?;
         check_eq_default!(margin_account.account_flags, 0)?;
-        check_default!(owner_acc.is_signer)?;
+        check!(owner_acc.is_signer, MangoErrorCode::SignerNecessary)?;
+
+        margin_account.account_flags = (AccountFlag::Initialized | AccountFlag::MarginAccount).bits();
+        margin_account.mango_group = *mango_group_acc.key;
+        margin_account.owner = *owner_acc.key;
+        margin_account.version = MARGIN_ACCOUNT_VERSION;
+
+        Ok(())
+    }
+
+    /// Same as init_margin_account, but creates the account at the address derived from the
+    /// MangoGroup and owner (see get_margin_account_address) with owner_acc paying the rent
+    #[inline(never)]
+    fn init_margin_account_pda(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        bump: u8
+    ) -> MangoResult<()> {
+        const NUM_FIXED: usize = 5;
+        let accounts = array_ref![accounts, 0, NUM_FIXED];
+
+        let [
+            mango_group_acc,
+            margin_account_acc,
+            owner_acc,
+            system_prog_acc,
+            rent_acc
+        ] = accounts;
+
+        let _mango_group = MangoGroup::load_checked(mango_group_acc, program_id)?;
+        check!(owner_acc.is_signer, MangoErrorCode::SignerNecessary)?;
+        check_eq!(system_prog_acc.key, &solana_program::system_program::id(), MangoErrorCode::InvalidProgramId)?;
 
+        let (margin_account_pk, canonical_bump) = get_margin_account_address(
+            program_id, mango_group_acc.key, owner_acc.key
+        );
+        check_eq!(margin_account_acc.key, &margin_account_pk, MangoErrorCode::InvalidAccountAddress)?;
+        check_eq!(bump, canonical_bump, MangoErrorCode::InvalidAccountAddress)?;
+
+        let rent = Rent::from_account_info(rent_acc)?;
+        let bump_seed = [bump];
+        let margin_account_seeds: [&[u8]; 3] = [mango_group_acc.key.as_ref(), owner_acc.key.as_ref(), &bump_seed];
+        invoke_create_pda_account(
+            owner_acc,
+            margin_account_acc,
+            system_prog_acc,
+            rent.minimum_balance(size_of::<MarginAccount>()),
+            size_of::<MarginAccount>() as u64,
+            program_id,
+            &margin_account_seeds
+        )?;
+
+        let mut margin_account = MarginAccount::load_mut(margin_account_acc)?;
         margin_account.account_flags = (AccountFlag::Initialized | AccountFlag::MarginAccount).bits();
         margin_account.mango_group = *mango_group_acc.key;
         margin_account.owner = *owner_acc.key;
+        margin_account.version = MARGIN_ACCOUNT_VERSION;
+
+        Ok(())
+    }
+
+    /// Move a v0 or v1 MarginAccount, which is only MARGIN_ACCOUNT_V1_SIZE bytes, over to a new
+    /// account of the current size. Solana can't grow an account in place, so the first
+    /// MARGIN_ACCOUNT_V1_SIZE bytes are copied into new_margin_account, every field added since
+    /// stays zeroed, and old_margin_account is closed with its lamports going back to the owner.
+    /// The open orders accounts are owned by the MangoGroup signer so they move over as they are
+    #[inline(never)]
+    fn upgrade_margin_account(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo]
+    ) -> MangoResult<()> {
+        const NUM_FIXED: usize = 5;
+        let accounts = array_ref![accounts, 0, NUM_FIXED];
+
+        let [
+            mango_group_acc,
+            old_margin_account_acc,
+            new_margin_account_acc,
+            owner_acc,
+            rent_acc
+        ] = accounts;
+
+        // Accounts of v1 groups are upgraded too so they can be migrated out
+        if mango_group_acc.data_len() == MANGO_GROUP_V1_SIZE {
+            MangoGroupV1::load_checked(mango_group_acc, program_id)?;
+        } else {
+            MangoGroup::load_checked(mango_group_acc, program_id)?;
+        }
+        check!(owner_acc.is_signer, MangoErrorCode::SignerNecessary)?;
+        check!(old_margin_account_acc.key != new_margin_account_acc.key, MangoErrorCode::DuplicateAccounts)?;
+
+        check_eq_default!(old_margin_account_acc.owner, program_id)?;
+        check_eq!(old_margin_account_acc.data_len(), MARGIN_ACCOUNT_V1_SIZE, MangoErrorCode::InvalidAccountVersion)?;
+        let mut old_data = old_margin_account_acc.try_borrow_mut_data()?;
+        {
+            let old_bytes = array_ref![old_data, 0, MARGIN_ACCOUNT_V1_SIZE];
+            let (account_flags, mango_group, owner, _, version, _) = array_refs![old_bytes, 8, 32, 32, 161, 1, 6];
+            check_eq_default!(u64::from_le_bytes(*account_flags), (AccountFlag::Initialized | AccountFlag::MarginAccount).bits())?;
+            check_eq_default!(mango_group, &mango_group_acc.key.to_bytes())?;
+            check_eq!(owner, &owner_acc.key.to_bytes(), MangoErrorCode::InvalidMarginAccountOwner)?;
+            check!(version[0] < MARGIN_ACCOUNT_VERSION, MangoErrorCode::InvalidAccountVersion)?;
+        }
+
+        let rent = Rent::from_account_info(rent_acc)?;
+        check_eq_default!(new_margin_account_acc.owner, program_id)?;
+        check_eq_default!(new_margin_account_acc.data_len(), size_of::<MarginAccount>())?;
+        check_default!(rent.is_exempt(new_margin_account_acc.lamports(), size_of::<MarginAccount>()))?;
+        {
+            let mut new_data = new_margin_account_acc.try_borrow_mut_data()?;
+            check_default!(new_data.iter().all(|b| *b == 0))?;
+            new_data[..MARGIN_ACCOUNT_V1_SIZE].copy_from_slice(&old_data[..]);
+        }
+        let mut margin_account = MarginAccount::load_mut(new_margin_account_acc)?;
+        margin_account.version = MARGIN_ACCOUNT_VERSION;
 
+        // close the old account; zeroing the data keeps it from being upgraded twice in this tx
+        for b in old_data.iter_mut() {
+            *b = 0;
+        }
+        let owner_lamports = owner_acc.lamports().checked_add(old_margin_account_acc.lamports()).ok_or(throw!())?;
+        **owner_acc.try_borrow_mut_lamports()? = owner_lamports;
+        **old_margin_account_acc.try_borrow_mut_lamports()? = 0;
         Ok(())
     }
 
@@ -208,8 +357,29 @@ impl Processor {
         program_id: &Pubkey,
         accounts: &[AccountInfo],
         quantity: u64
+    ) -> MangoResult<()> {
+        Self::deposit_into(program_id, accounts, quantity, true)
+    }
+
+    /// Same as deposit, but the funder doesn't have to own the margin account
+    #[inline(never)]
+    fn deposit_for_account(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        quantity: u64
+    ) -> MangoResult<()> {
+        Self::deposit_into(program_id, accounts, quantity, false)
+    }
+
+    #[inline(never)]
+    fn deposit_into(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        quantity: u64,
+        owner_only: bool
     ) -> MangoResult<()> {
         const NUM_FIXED: usize = 7;
+        let (accounts, native_accs) = accounts.split_at(NUM_FIXED);
         let accounts = array_ref![accounts, 0, NUM_FIXED];
         let [
             mango_group_acc,
@@ -222,6 +392,7 @@ impl Processor {
         ] = accounts;
 
         let mut mango_group = MangoGroup::load_mut_checked(mango_group_acc, program_id)?;
+        check!(!mango_group.is_halted, MangoErrorCode::GroupHalted)?;
         let mut margin_account = MarginAccount::load_mut_checked(
             program_id, margin_account_acc, mango_group_acc.key
         )?;
@@ -229,12 +400,26 @@ impl Processor {
         let clock = Clock::from_account_info(clock_acc)?;
         mango_group.update_indexes(&clock)?;
 
-        check_eq!(&margin_account.owner, owner_acc.key, MangoErrorCode::InvalidMarginAccountOwner)?;
+        if owner_only {
+            check_eq!(&margin_account.owner, owner_acc.key, MangoErrorCode::InvalidMarginAccountOwner)?;
+        } else {
+            // Anyone may fund the account, but they must sign for the transfer out of token_account_acc
+            check!(owner_acc.is_signer, MangoErrorCode::SignerNecessary)?;
+        }
+
+        let token_index = mango_group.get_token_index_with_vault(vault_acc.key)
+            .ok_or(throw!(MangoErrorCode::InvalidMangoVault))?;
+        check_eq!(&mango_group.vaults[token_index], vault_acc.key, MangoErrorCode::InvalidMangoVault)?;
+
+        check_eq!(token_prog_acc.key, &spl_token::id(), MangoErrorCode::InvalidProgramId)?;
 
-        let token_index = mango_group.get_token_index_with_vault(vault_acc.key).unwrap();
-        check_eq_default!(&mango_group.vaults[token_index], vault_acc.key)?;
+        // For native SOL, token_account_acc is a temporary wrapped SOL account funded by owner_acc
+        let is_native = mango_group.tokens[token_index] == spl_token::native_mint::id();
+        if is_native {
+            invoke_create_wrapped_sol(program_id, margin_account_acc, token_account_acc, owner_acc,
+                                      native_accs, token_prog_acc, quantity)?;
+        }
 
-        check_eq_default!(token_prog_acc.key, &spl_token::id())?;
         let deposit_instruction = spl_token::instruction::transfer(
             &spl_token::id(),
             token_account_acc.key,
@@ -250,12 +435,102 @@ impl Processor {
 
         solana_program::program::invoke_signed(&deposit_instruction, &deposit_accs, &[])?;
 
+        if is_native {
+            invoke_close_wrapped_sol(token_account_acc, owner_acc, token_prog_acc)?;
+        }
+
         let deposit: U64F64 = U64F64::from_num(quantity) / mango_group.indexes[token_index].deposit;
         checked_add_deposit(&mut mango_group, &mut margin_account, token_index, deposit)?;
+        margin_account.record_grace_deposit(&mango_group, token_index, quantity, clock.unix_timestamp as u64);
+
+        let deposit_limit = mango_group.deposit_limits[token_index];
+        check!(
+            deposit_limit == 0 || mango_group.get_total_native_deposit(token_index)? <= deposit_limit,
+            MangoErrorCode::DepositLimitExceeded
+        )?;
+
+        Ok(())
+    }
+
+    #[inline(never)]
+    fn deposit_multi(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        quantities: [u64; NUM_TOKENS]
+    ) -> MangoResult<()> {
+        const NUM_FIXED: usize = 5;
+        let accounts = array_ref![accounts, 0, NUM_FIXED + 2 * NUM_TOKENS];
+        let (
+            fixed_accs,
+            token_account_accs,
+            vault_accs,
+        ) = array_refs![accounts, NUM_FIXED, NUM_TOKENS, NUM_TOKENS];
+        let [
+            mango_group_acc,
+            margin_account_acc,
+            owner_acc,
+            token_prog_acc,
+            clock_acc,
+        ] = fixed_accs;
+
+        let mut mango_group = MangoGroup::load_mut_checked(mango_group_acc, program_id)?;
+        check!(!mango_group.is_halted, MangoErrorCode::GroupHalted)?;
+        let mut margin_account = MarginAccount::load_mut_checked(
+            program_id, margin_account_acc, mango_group_acc.key
+        )?;
+        check_eq!(&margin_account.owner, owner_acc.key, MangoErrorCode::InvalidMarginAccountOwner)?;
+        check_eq!(token_prog_acc.key, &spl_token::id(), MangoErrorCode::InvalidProgramId)?;
+
+        let clock = Clock::from_account_info(clock_acc)?;
+        mango_group.update_indexes(&clock)?;
+
+        for i in 0..NUM_TOKENS {
+            let quantity = quantities[i];
+            if quantity == 0 {
+                continue;
+            }
+            check_eq!(vault_accs[i].key, &mango_group.vaults[i], MangoErrorCode::InvalidMangoVault)?;
+            invoke_transfer(token_prog_acc, &token_account_accs[i], &vault_accs[i], owner_acc, &[], quantity)?;
+
+            let deposit: U64F64 = U64F64::from_num(quantity) / mango_group.indexes[i].deposit;
+            checked_add_deposit(&mut mango_group, &mut margin_account, i, deposit)?;
+            margin_account.record_grace_deposit(&mango_group, i, quantity, clock.unix_timestamp as u64);
+
+            let deposit_limit = mango_group.deposit_limits[i];
+            check!(
+                deposit_limit == 0 || mango_group.get_total_native_deposit(i)? <= deposit_limit,
+                MangoErrorCode::DepositLimitExceeded
+            )?;
+        }
 
         Ok(())
     }
 
+    #[inline(never)]
+    fn deposit_with_referrer(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        quantity: u64
+    ) -> MangoResult<()> {
+        const NUM_FIXED: usize = 8;
+        check_default!(accounts.len() >= NUM_FIXED)?;
+        let referrer_acc = &accounts[NUM_FIXED - 1];
+
+        // Deposit accounts are the same minus the referrer
+        let deposit_accs = [&accounts[..NUM_FIXED - 1], &accounts[NUM_FIXED..]].concat();
+        Self::deposit(program_id, &deposit_accs, quantity)?;
+
+        let mango_group_acc = &accounts[0];
+        let margin_account_acc = &accounts[1];
+        let mut margin_account = MarginAccount::load_mut_checked(
+            program_id, margin_account_acc, mango_group_acc.key
+        )?;
+        if margin_account.referrer == Pubkey::default() {
+            margin_account.referrer = *referrer_acc.key;
+        }
+        Ok(())
+    }
+
     #[inline(never)]
     fn withdraw(
         program_id: &Pubkey,
@@ -264,6 +539,7 @@ impl Processor {
     ) -> MangoResult<()> {
 
         const NUM_FIXED: usize = 8;
+        let (accounts, extra_accs) = accounts.split_at(NUM_FIXED + 2 * NUM_MARKETS);
         let accounts = array_ref![accounts, 0, NUM_FIXED + 2 * NUM_MARKETS];
         let (
             fixed_accs,
@@ -286,6 +562,7 @@ impl Processor {
         let mut mango_group = MangoGroup::load_mut_checked(
             mango_group_acc, program_id
         )?;
+        check!(!mango_group.is_halted, MangoErrorCode::GroupHalted)?;
         let mut margin_account = MarginAccount::load_mut_checked(
             program_id, margin_account_acc, mango_group_acc.key
         )?;
@@ -293,35 +570,59 @@ impl Processor {
         let clock = Clock::from_account_info(clock_acc)?;
         mango_group.update_indexes(&clock)?;
 
-        check_default!(owner_acc.is_signer)?;
-        check_eq_default!(&margin_account.owner, owner_acc.key)?;
+        check!(owner_acc.is_signer, MangoErrorCode::SignerNecessary)?;
+        check_eq!(&margin_account.owner, owner_acc.key, MangoErrorCode::InvalidMarginAccountOwner)?;
 
+        check_open_orders_unique(open_orders_accs)?;
         for i in 0..NUM_MARKETS {
-            check_eq_default!(open_orders_accs[i].key, &margin_account.open_orders[i])?;
+            check_eq!(open_orders_accs[i].key, &margin_account.open_orders[i], MangoErrorCode::InvalidOpenOrdersAccount)?;
             check_open_orders(&open_orders_accs[i], signer_acc.key)?;
         }
 
-        let token_index = mango_group.get_token_index_with_vault(vault_acc.key).unwrap();
-        check_eq_default!(&mango_group.vaults[token_index], vault_acc.key)?;
+        let token_index = mango_group.get_token_index_with_vault(vault_acc.key)
+            .ok_or(throw!(MangoErrorCode::InvalidMangoVault))?;
+        check_eq!(&mango_group.vaults[token_index], vault_acc.key, MangoErrorCode::InvalidMangoVault)?;
+        verify_vault_authority(vault_acc, &mango_group.signer_key)?;
 
-        let index: &MangoIndex = &mango_group.indexes[token_index];
+        // native SOL accounts come before any secondary oracles
+        let is_native = mango_group.tokens[token_index] == spl_token::native_mint::id();
+        let (native_accs, secondary_oracle_accs) = extra_accs.split_at(
+            if is_native { NUM_NATIVE_SOL_ACCS } else { 0 }
+        );
+
+        // Validate the destination up front so a wrong account fails before any bookkeeping.
+        // The native SOL account doesn't exist yet and is checked against its address when created
+        if !is_native {
+            check_eq!(token_account_acc.owner, &spl_token::id(), MangoErrorCode::InvalidTokenAccount)?;
+            let token_account = Account::unpack(&token_account_acc.try_borrow_data()?)?;
+            check_eq!(token_account.mint, mango_group.tokens[token_index], MangoErrorCode::InvalidTokenAccount)?;
+        }
+
+        let index: MangoIndex = mango_group.indexes[token_index];
         let native_deposits: u64 = (margin_account.deposits[token_index].checked_mul(index.deposit).unwrap()).to_num();
         let available = native_deposits;
 
         check!(available >= quantity, MangoErrorCode::InsufficientFunds)?;
         // TODO just borrow (quantity - available)
-        let prices = get_prices(&mango_group, oracle_accs)?;
+        let prices = get_prices(&mut mango_group, oracle_accs, secondary_oracle_accs, &clock)?;
         // Withdraw from deposit
         let withdrew: U64F64 = U64F64::from_num(quantity) / index.deposit;
         checked_sub_deposit(&mut mango_group, &mut margin_account, token_index, withdrew)?;
 
         // Make sure accounts are in valid state after withdrawal
-        let coll_ratio = margin_account.get_collateral_ratio(&mango_group, &prices, open_orders_accs)?;
+        let coll_ratio = margin_account.get_collateral_ratio(&mango_group, &prices, open_orders_accs, clock.unix_timestamp as u64)?;
         check!(coll_ratio >= mango_group.init_coll_ratio, MangoErrorCode::CollateralRatioLimit)?;
-        check_default!(mango_group.has_valid_deposits_borrows(token_index))?;
+        check_default!(mango_group.has_valid_deposits_borrows(token_index)?)?;
 
         // Send out withdraw instruction to SPL token program
-        check_eq_default!(token_prog_acc.key, &spl_token::id())?;
+        check_eq!(token_prog_acc.key, &spl_token::id(), MangoErrorCode::InvalidProgramId)?;
+
+        // For native SOL, token_account_acc is a temporary wrapped SOL account closed to owner_acc
+        if is_native {
+            invoke_create_wrapped_sol(program_id, margin_account_acc, token_account_acc, owner_acc,
+                                      native_accs, token_prog_acc, 0)?;
+        }
+
         let withdraw_instruction = spl_token::instruction::transfer(
             &spl_token::ID,
             vault_acc.key,
@@ -340,6 +641,10 @@ impl Processor {
         let signer_seeds = gen_signer_seeds(&mango_group.signer_nonce, mango_group_acc.key);
         solana_program::program::invoke_signed(&withdraw_instruction, &withdraw_accs, &[&signer_seeds])?;
 
+        if is_native {
+            invoke_close_wrapped_sol(token_account_acc, owner_acc, token_prog_acc)?;
+        }
+
         Ok(())
     }
 
@@ -350,7 +655,9 @@ impl Processor {
         token_index: usize,
         quantity: u64
     ) -> MangoResult<()> {
+        check_default!(token_index < NUM_TOKENS)?;
         const NUM_FIXED: usize = 4;
+        let (accounts, secondary_oracle_accs) = accounts.split_at(NUM_FIXED + 2 * NUM_MARKETS);
         let accounts = array_ref![accounts, 0, NUM_FIXED + 2 * NUM_MARKETS];
         let (
             fixed_accs,
@@ -366,16 +673,20 @@ impl Processor {
         ] = fixed_accs;
 
         let mut mango_group = MangoGroup::load_mut_checked(mango_group_acc, program_id)?;
+        check!(!mango_group.is_halted, MangoErrorCode::GroupHalted)?;
         let mut margin_account = MarginAccount::load_mut_checked(
             program_id, margin_account_acc, mango_group_acc.key
         )?;
-        check_default!(owner_acc.is_signer)?;
-        check_eq_default!(&margin_account.owner, owner_acc.key)?;
+        check!(owner_acc.is_signer, MangoErrorCode::SignerNecessary)?;
+        check_eq!(&margin_account.owner, owner_acc.key, MangoErrorCode::InvalidMarginAccountOwner)?;
 
+        check_open_orders_unique(open_orders_accs)?;
         for i in 0..NUM_MARKETS {
-            check_eq_default!(open_orders_accs[i].key, &margin_account.open_orders[i])?;
+            check_eq!(open_orders_accs[i].key, &margin_account.open_orders[i], MangoErrorCode::InvalidOpenOrdersAccount)?;
             check_open_orders(&open_orders_accs[i], &mango_group.signer_key)?;
         }
+        // tokens without a spot market have no price, so they can't be borrowed
+        check_default!(token_index == QUOTE_INDEX || mango_group.spot_markets[token_index] != Pubkey::default())?;
         let clock = Clock::from_account_info(clock_acc)?;
         mango_group.update_indexes(&clock)?;
 
@@ -386,12 +697,14 @@ impl Processor {
 
         checked_add_deposit(&mut mango_group, &mut margin_account, token_index, deposit)?;
         checked_add_borrow(&mut mango_group, &mut margin_account, token_index, borrow)?;
+        check_max_borrow_per_account(&mango_group, &margin_account, token_index)?;
+        check_borrow_limit_ramp(&mango_group, &margin_account, token_index, clock.unix_timestamp as u64)?;
 
-        let prices = get_prices(&mango_group, oracle_accs)?;
-        let coll_ratio = margin_account.get_collateral_ratio(&mango_group, &prices, open_orders_accs)?;
+        let prices = get_prices(&mut mango_group, oracle_accs, secondary_oracle_accs, &clock)?;
+        let coll_ratio = margin_account.get_collateral_ratio(&mango_group, &prices, open_orders_accs, clock.unix_timestamp as u64)?;
 
-        check_default!(coll_ratio >= mango_group.init_coll_ratio)?;
-        check_default!(mango_group.has_valid_deposits_borrows(token_index))?;
+        check!(coll_ratio >= mango_group.init_coll_ratio, MangoErrorCode::CollateralRatioLimit)?;
+        check_default!(mango_group.has_valid_deposits_borrows(token_index)?)?;
         Ok(())
     }
 
@@ -402,6 +715,7 @@ impl Processor {
         token_index: usize,
         quantity: u64
     ) -> MangoResult<()> {
+        check_default!(token_index < NUM_TOKENS)?;
         const NUM_FIXED: usize = 4;
         let accounts = array_ref![accounts, 0, NUM_FIXED];
         let [
@@ -417,20 +731,67 @@ impl Processor {
         )?;
         let clock = Clock::from_account_info(clock_acc)?;
         mango_group.update_indexes(&clock)?;
-        check_default!(owner_acc.is_signer)?;
-        check_eq_default!(&margin_account.owner, owner_acc.key)?;
+        check!(owner_acc.is_signer, MangoErrorCode::SignerNecessary)?;
+        check_eq!(&margin_account.owner, owner_acc.key, MangoErrorCode::InvalidMarginAccountOwner)?;
 
         settle_borrow_unchecked(&mut mango_group, &mut margin_account, token_index, quantity)?;
         Ok(())
     }
 
+    #[inline(never)]
+    fn settle_borrow_to_ratio(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        token_index: usize,
+        target_ratio: U64F64
+    ) -> MangoResult<()> {
+        const NUM_FIXED: usize = 4;
+        let (accounts, secondary_oracle_accs) = accounts.split_at(NUM_FIXED + 2 * NUM_MARKETS);
+        let accounts = array_ref![accounts, 0, NUM_FIXED + 2 * NUM_MARKETS];
+        let (
+            fixed_accs,
+            open_orders_accs,
+            oracle_accs,
+        ) = array_refs![accounts, NUM_FIXED, NUM_MARKETS, NUM_MARKETS];
+
+        let [
+            mango_group_acc,
+            margin_account_acc,
+            owner_acc,
+            clock_acc,
+        ] = fixed_accs;
+
+        let mut mango_group = MangoGroup::load_mut_checked(mango_group_acc, program_id)?;
+        let mut margin_account = MarginAccount::load_mut_checked(
+            program_id, margin_account_acc, mango_group_acc.key
+        )?;
+        check!(owner_acc.is_signer, MangoErrorCode::SignerNecessary)?;
+        check_eq!(&margin_account.owner, owner_acc.key, MangoErrorCode::InvalidMarginAccountOwner)?;
+        check_default!(token_index < NUM_TOKENS)?;
+
+        check_open_orders_unique(open_orders_accs)?;
+        for i in 0..NUM_MARKETS {
+            check_eq!(open_orders_accs[i].key, &margin_account.open_orders[i], MangoErrorCode::InvalidOpenOrdersAccount)?;
+            check_open_orders(&open_orders_accs[i], &mango_group.signer_key)?;
+        }
+        let clock = Clock::from_account_info(clock_acc)?;
+        mango_group.update_indexes(&clock)?;
+
+        let prices = get_prices(&mut mango_group, oracle_accs, secondary_oracle_accs, &clock)?;
+        let quantity = get_settle_quantity_for_ratio(
+            &mango_group, &margin_account, &prices, open_orders_accs, token_index, target_ratio, clock.unix_timestamp as u64
+        )?;
+        settle_borrow_unchecked(&mut mango_group, &mut margin_account, token_index, quantity)
+    }
+
     #[inline(never)]
     fn liquidate(
         program_id: &Pubkey,
         accounts: &[AccountInfo],
         deposit_quantities: [u64; NUM_TOKENS]
     ) -> MangoResult<()> {
-        const NUM_FIXED: usize = 5;
+        const NUM_FIXED: usize = 6;
+        let (accounts, secondary_oracle_accs) = accounts.split_at(NUM_FIXED + 2 * NUM_MARKETS + 2 * NUM_TOKENS);
         let accounts = array_ref![accounts, 0, NUM_FIXED + 2 * NUM_MARKETS + 2 * NUM_TOKENS];
         let (
             fixed_accs,
@@ -444,11 +805,13 @@ impl Processor {
             mango_group_acc,
             liqor_acc,
             liqee_margin_account_acc,
+            signer_acc,
             token_prog_acc,
             clock_acc
         ] = fixed_accs;
 
-        check_default!(liqor_acc.is_signer)?;
+        check!(liqor_acc.is_signer, MangoErrorCode::SignerNecessary)?;
+        check!(liqor_acc.key != liqee_margin_account_acc.key, MangoErrorCode::DuplicateAccounts)?;
         let mut mango_group = MangoGroup::load_mut_checked(
             mango_group_acc, program_id
         )?;
@@ -456,19 +819,23 @@ impl Processor {
             program_id, liqee_margin_account_acc, mango_group_acc.key
         )?;
         let clock = Clock::from_account_info(clock_acc)?;
+        let now = clock.unix_timestamp as u64;
         mango_group.update_indexes(&clock)?;
 
+        check_open_orders_unique(open_orders_accs)?;
         for i in 0..NUM_MARKETS {
-            check_eq_default!(open_orders_accs[i].key, &liqee_margin_account.open_orders[i])?;
+            check_eq!(open_orders_accs[i].key, &liqee_margin_account.open_orders[i], MangoErrorCode::InvalidOpenOrdersAccount)?;
             check_open_orders(&open_orders_accs[i], &mango_group.signer_key)?;
         }
 
-        let prices = get_prices(&mango_group, oracle_accs)?;
-        let coll_ratio = liqee_margin_account.get_collateral_ratio(
-            &mango_group, &prices, open_orders_accs
+        // Nothing below trades on the dex, so prices and open orders are read once and reused
+        let prices = get_prices(&mut mango_group, oracle_accs, secondary_oracle_accs, &clock)?;
+        let open_orders_totals = load_open_orders_totals(open_orders_accs)?;
+        let (_, _, coll_ratio) = liqee_margin_account.get_health_from_totals(
+            &mango_group, &prices, &open_orders_totals, now
         )?;
 
-        let starting_assets = liqee_margin_account.get_total_assets(&mango_group, open_orders_accs).unwrap();
+        let starting_assets = liqee_margin_account.get_total_assets_from_totals(&mango_group, &open_orders_totals).unwrap();
         let starting_liabs = liqee_margin_account.get_total_liabs(&mango_group).unwrap();
         msg!("Liquidation details: {{ \"assets\": {:?}, \"liabs\": {:?}, \"prices\": {:?}, \"coll_ratio\": {}, \"unused\": {} }}", starting_assets, starting_liabs, prices, coll_ratio, 0);
 
@@ -476,51 +843,43 @@ impl Processor {
         check!(coll_ratio < mango_group.maint_coll_ratio, MangoErrorCode::NotLiquidatable)?;
 
         // Settle borrows to see if it gets us above maint
-        for i in 0..NUM_TOKENS {
-            let native_borrow = liqee_margin_account.get_native_borrow(&mango_group.indexes[i], i);
-            settle_borrow_unchecked(&mut mango_group, &mut liqee_margin_account, i, native_borrow)?;
-        }
-        let coll_ratio = liqee_margin_account.get_collateral_ratio(
-            &mango_group, &prices, open_orders_accs
+        settle_all_borrows(&mut mango_group, &mut liqee_margin_account)?;
+        let (assets_val, liabs_val, coll_ratio) = liqee_margin_account.get_health_from_totals(
+            &mango_group, &prices, &open_orders_totals, now
         )?;
         if coll_ratio >= mango_group.maint_coll_ratio {  // if account not liquidatable after settle borrow, then return
             return Ok(())
         }
 
-        // TODO liquidator may forcefully SettleFunds and SettleBorrow on account with less than maint
+        // TODO liquidator may forcefully SettleBorrow on account with less than maint; free funds on the
+        //  dex can be settled first with ForceSettleFunds
 
         if coll_ratio < ONE_U64F64 {
-            let liabs = liqee_margin_account.get_total_liabs(&mango_group)?;
-            let liabs_val = liqee_margin_account.get_liabs_val(&mango_group, &prices)?;
-            let assets_val = liqee_margin_account.get_assets_val(&mango_group, &prices, open_orders_accs)?;
-
-            // reduction_val = amount of quote currency value to reduce liabilities by to get coll_ratio = 1.01
-            let reduction_val = liabs_val
-                .checked_sub(assets_val / LIQ_MIN_COLL_RATIO).unwrap();
-
-            for i in 0..NUM_TOKENS {
-                let proportion = U64F64::from_num(liabs[i])
-                    .checked_div(liabs_val).unwrap();
-
-                let token_reduce = proportion.checked_mul(reduction_val).unwrap();
-                socialize_loss(&mut mango_group, &mut liqee_margin_account, i, token_reduce)?;
-                // TODO this will reduce deposits of liqee as well which could put actual value below; way to fix is to SettleBorrow first
-                // TODO Can socialize loss cause more liquidations? Perhaps other accounts then go below threshold
-                // TODO what happens if unable to socialize loss? If not enough deposits in currency
-            }
+            socialize_liqee_loss(&mut mango_group, &mut liqee_margin_account, assets_val, liabs_val)?;
         }
 
+        let deficit = liqee_margin_account.get_collateral_deficit_from_totals(
+            &mango_group, &prices, &open_orders_totals, now
+        )?;
+        check_liquidation_deposits(&mango_group, &prices, &deposit_quantities, deficit)?;
+
         // Pull deposits from liqor's token wallets
-        check_eq_default!(token_prog_acc.key, &spl_token::id())?;
+        check_eq!(token_prog_acc.key, &spl_token::id(), MangoErrorCode::InvalidProgramId)?;
         for i in 0..NUM_TOKENS {
             let quantity = deposit_quantities[i];
             if quantity == 0 {
                 continue;
             }
 
+            // vaults and token wallets are matched to token i by address and mint, so passing them
+            // in another order can't credit the deposit to the wrong token
             let vault_acc: &AccountInfo = &vault_accs[i];
-            check_eq_default!(&mango_group.vaults[i], vault_acc.key)?;
+            check_eq!(&mango_group.vaults[i], vault_acc.key, MangoErrorCode::InvalidMangoVault)?;
+            verify_vault_authority(vault_acc, &mango_group.signer_key)?;
             let token_account_acc: &AccountInfo = &liqor_token_account_accs[i];
+            check_eq!(token_account_acc.owner, &spl_token::id(), MangoErrorCode::InvalidTokenAccount)?;
+            let token_account = Account::unpack(&token_account_acc.try_borrow_data()?)?;
+            check_eq!(token_account.mint, mango_group.tokens[i], MangoErrorCode::InvalidTokenAccount)?;
             let deposit_instruction = spl_token::instruction::transfer(
                 &spl_token::id(),
                 token_account_acc.key,
@@ -539,12 +898,37 @@ impl Processor {
             checked_add_deposit(&mut mango_group, &mut liqee_margin_account, i, deposit)?;
         }
 
+        // Pay the liqor a bonus out of the liqee's deposits in the same tokens the liqor deposited
+        if mango_group.liquidation_fee > ZERO_U64F64 {
+            check_eq!(signer_acc.key, &mango_group.signer_key, MangoErrorCode::InvalidSignerKey)?;
+            let signer_seeds = gen_signer_seeds(&mango_group.signer_nonce, mango_group_acc.key);
+            for i in 0..NUM_TOKENS {
+                let native_deposit = liqee_margin_account.get_native_deposit(&mango_group.indexes[i], i);
+                let bonus: u64 = (mango_group.liquidation_fee * U64F64::from_num(deposit_quantities[i]))
+                    .checked_floor().ok_or(throw!())?.to_num();
+                let bonus = min(bonus, native_deposit);
+                if bonus == 0 {
+                    continue;
+                }
+
+                let deposit = U64F64::from_num(bonus) / mango_group.indexes[i].deposit;
+                saturating_sub_deposit(&mut mango_group, &mut liqee_margin_account, i, deposit)?;
+                invoke_transfer(token_prog_acc, &vault_accs[i], &liqor_token_account_accs[i], signer_acc,
+                                &[&signer_seeds], bonus)?;
+            }
+        }
+
         // Check to make sure liqor's deposits brought account above init_coll_ratio
-        let coll_ratio = liqee_margin_account.get_collateral_ratio(&mango_group, &prices, open_orders_accs)?;
-        check_default!(coll_ratio >= mango_group.init_coll_ratio)?;
+        let (_, _, coll_ratio) = liqee_margin_account.get_health_from_totals(
+            &mango_group, &prices, &open_orders_totals, now
+        )?;
+        check!(coll_ratio >= mango_group.init_coll_ratio, MangoErrorCode::CollateralRatioLimit)?;
 
-        // If all deposits are good, transfer ownership of margin account to liqor
+        // If all deposits are good, transfer ownership of margin account to liqor, netted with the
+        // indexes the liqor's deposits were just credited at
         liqee_margin_account.owner = *liqor_acc.key;
+        liqee_margin_account.last_liquidation_ts = now;
+        settle_all_borrows(&mut mango_group, &mut liqee_margin_account)?;
 
         Ok(())
     }
@@ -571,30 +955,23 @@ impl Processor {
         // prog_assert!(owner_acc.is_signer)?; // anyone can deposit, not just owner
 
         let mut mango_group = MangoGroup::load_mut_checked(mango_group_acc, program_id)?;
+        // check before anything is written so a failed call can't leave a half initialized account
+        check_eq!(vault_acc.key, &mango_group.srm_vault, MangoErrorCode::InvalidMangoVault)?;
+        check_eq!(token_prog_acc.key, &spl_token::id(), MangoErrorCode::InvalidProgramId)?;
 
         // if MangoSrmAccount is empty, initialize it
-        check_eq_default!(mango_srm_account_acc.data_len(), size_of::<MangoSrmAccount>())?;
-        let mut mango_srm_account = MangoSrmAccount::load_mut(mango_srm_account_acc)?;
         check_eq_default!(mango_srm_account_acc.owner, program_id)?;
-
-        if mango_srm_account.account_flags == 0 {
-            let rent = Rent::from_account_info(rent_acc)?;
-            check_default!(rent.is_exempt(mango_srm_account_acc.lamports(), size_of::<MangoSrmAccount>()))?;
-
-            mango_srm_account.account_flags = (AccountFlag::Initialized | AccountFlag::MangoSrmAccount).bits();
-            mango_srm_account.mango_group = *mango_group_acc.key;
-            check_default!(owner_acc.is_signer)?;  // this is not necessary but whatever
-            mango_srm_account.owner = *owner_acc.key;
+        check_eq_default!(mango_srm_account_acc.data_len(), size_of::<MangoSrmAccount>())?;
+        let is_initialized = MangoSrmAccount::load(mango_srm_account_acc)?.account_flags != 0;
+        let mut mango_srm_account = if is_initialized {
+            MangoSrmAccount::load_mut_checked(program_id, mango_srm_account_acc, mango_group_acc.key)?
         } else {
-            check_eq_default!(mango_srm_account.account_flags, (AccountFlag::Initialized | AccountFlag::MangoSrmAccount).bits())?;
-            check_eq_default!(&mango_srm_account.mango_group, mango_group_acc.key)?;
-        }
+            init_mango_srm_account(program_id, mango_srm_account_acc, mango_group_acc.key, owner_acc, rent_acc)?
+        };
 
         let clock = Clock::from_account_info(clock_acc)?;
         mango_group.update_indexes(&clock)?;
 
-        check_eq_default!(vault_acc.key, &mango_group.srm_vault)?;
-        check_eq_default!(token_prog_acc.key, &spl_token::id())?;
         let deposit_instruction = spl_token::instruction::transfer(
             &spl_token::id(),
             srm_account_acc.key,
@@ -610,6 +987,9 @@ impl Processor {
 
         solana_program::program::invoke_signed(&deposit_instruction, &deposit_accs, &[])?;
         mango_srm_account.amount = mango_srm_account.amount.checked_add(quantity).unwrap();
+        mango_group.total_user_srm = mango_group.total_user_srm.checked_add(quantity).unwrap();
+        // read back the vault rather than adding quantity so SRM sent straight to it is counted too
+        mango_group.srm_vault_balance = Account::unpack(&vault_acc.try_borrow_data()?)?.amount;
         Ok(())
     }
 
@@ -636,13 +1016,20 @@ impl Processor {
         let mut mango_srm_account = MangoSrmAccount::load_mut_checked(
             program_id, mango_srm_account_acc, mango_group_acc.key)?;
 
+        check!(owner_acc.is_signer, MangoErrorCode::SignerNecessary)?;
+        check_eq!(&mango_srm_account.owner, owner_acc.key, MangoErrorCode::InvalidOwner)?;
+        check_eq!(vault_acc.key, &mango_group.srm_vault, MangoErrorCode::InvalidMangoVault)?;
+        verify_vault_authority(vault_acc, &mango_group.signer_key)?;
+        check_eq!(signer_acc.key, &mango_group.signer_key, MangoErrorCode::InvalidSignerKey)?;
+        check_eq!(token_prog_acc.key, &spl_token::id(), MangoErrorCode::InvalidProgramId)?;
+
+        // Work out the new balances before anything is changed or transferred
+        let srm_amount = mango_srm_account.amount.checked_sub(quantity)
+            .ok_or(throw!(MangoErrorCode::InsufficientFunds))?;
+        let total_user_srm = mango_group.total_user_srm.checked_sub(quantity).ok_or(throw!())?;
+
         let clock = Clock::from_account_info(clock_acc)?;
         mango_group.update_indexes(&clock)?;
-        check_default!(owner_acc.is_signer)?;
-        check_eq_default!(&mango_srm_account.owner, owner_acc.key)?;
-        check_eq_default!(vault_acc.key, &mango_group.srm_vault)?;
-        check_default!(mango_srm_account.amount >= quantity)?;
-        check_eq_default!(token_prog_acc.key, &spl_token::id())?;
 
         // Send out withdraw instruction to SPL token program
         let withdraw_instruction = spl_token::instruction::transfer(
@@ -661,47 +1048,1279 @@ impl Processor {
         ];
         let signer_seeds = gen_signer_seeds(&mango_group.signer_nonce, mango_group_acc.key);
         solana_program::program::invoke_signed(&withdraw_instruction, &withdraw_accs, &[&signer_seeds])?;
-        mango_srm_account.amount = mango_srm_account.amount.checked_sub(quantity).unwrap();
+        mango_srm_account.amount = srm_amount;
+        mango_group.total_user_srm = total_user_srm;
+        mango_group.srm_vault_balance = Account::unpack(&vault_acc.try_borrow_data()?)?.amount;
 
         Ok(())
     }
 
     #[inline(never)]
-    fn change_borrow_limit(
+    fn withdraw_excess_srm(
         program_id: &Pubkey,
         accounts: &[AccountInfo],
-        token_index: usize,
-        borrow_limit: u64
+        quantity: u64
     ) -> MangoResult<()> {
-        const NUM_FIXED: usize = 2;
+        const NUM_FIXED: usize = 6;
         let accounts = array_ref![accounts, 0, NUM_FIXED];
         let [
             mango_group_acc,
             admin_acc,
+            srm_account_acc,
+            vault_acc,
+            signer_acc,
+            token_prog_acc,
         ] = accounts;
 
-        let mut mango_group = MangoGroup::load_mut_checked(
-            mango_group_acc,
-            program_id
-        )?;
-
+        let mut mango_group = MangoGroup::load_mut_checked(mango_group_acc, program_id)?;
         check_eq_default!(admin_acc.key, &mango_group.admin)?;
         check_default!(admin_acc.is_signer)?;
+        check_eq!(vault_acc.key, &mango_group.srm_vault, MangoErrorCode::InvalidMangoVault)?;
+        verify_vault_authority(vault_acc, &mango_group.signer_key)?;
+        check_eq!(token_prog_acc.key, &spl_token::id(), MangoErrorCode::InvalidProgramId)?;
 
-        mango_group.borrow_limits[token_index] = borrow_limit;
-        Ok(())
-    }
+        // only SRM beyond what MangoSrmAccounts are owed, e.g. rebates from serum, may leave
+        let vault_amount = Account::unpack(&vault_acc.try_borrow_data()?)?.amount;
+        let excess = vault_amount.checked_sub(mango_group.total_user_srm).ok_or(throw!())?;
+        check!(quantity <= excess, MangoErrorCode::InsufficientFunds)?;
 
-    #[inline(never)]
-    fn place_order(
-        program_id: &Pubkey,
-        accounts: &[AccountInfo],
-        order: serum_dex::instruction::NewOrderInstructionV3
-    ) -> MangoResult<()> {
-        const NUM_FIXED: usize = 17;
-        let accounts = array_ref![accounts, 0, NUM_FIXED + 2 * NUM_MARKETS];
-        let (
-            fixed_accs,
+        let withdraw_instruction = spl_token::instruction::transfer(
+            &spl_token::id(),
+            vault_acc.key,
+            srm_account_acc.key,
+            signer_acc.key,
+            &[],
+            quantity
+        )?;
+        let withdraw_accs = [
+            vault_acc.clone(),
+            srm_account_acc.clone(),
+            signer_acc.clone(),
+            token_prog_acc.clone()
+        ];
+        let signer_seeds = gen_signer_seeds(&mango_group.signer_nonce, mango_group_acc.key);
+        solana_program::program::invoke_signed(&withdraw_instruction, &withdraw_accs, &[&signer_seeds])?;
+        mango_group.srm_vault_balance = Account::unpack(&vault_acc.try_borrow_data()?)?.amount;
+
+        Ok(())
+    }
+
+    #[inline(never)]
+    fn change_borrow_limit(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        token_index: usize,
+        borrow_limit: u64
+    ) -> MangoResult<()> {
+        check_default!(token_index < NUM_TOKENS)?;
+        const NUM_FIXED: usize = 2;
+        let accounts = array_ref![accounts, 0, NUM_FIXED];
+        let [
+            mango_group_acc,
+            admin_acc,
+        ] = accounts;
+
+        let mut mango_group = MangoGroup::load_mut_checked(
+            mango_group_acc,
+            program_id
+        )?;
+
+        check_eq_default!(admin_acc.key, &mango_group.admin)?;
+        check_default!(admin_acc.is_signer)?;
+
+        // The token's ramp ends here; a running ramp keeps it at borrow_limit from now on
+        mango_group.borrow_limits[token_index] = borrow_limit;
+        mango_group.borrow_limit_target[token_index] = borrow_limit;
+        Ok(())
+    }
+
+    #[inline(never)]
+    fn change_oi_limit(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        market_index: usize,
+        oi_limit: u64
+    ) -> MangoResult<()> {
+        check_default!(market_index < NUM_MARKETS)?;
+        const NUM_FIXED: usize = 2;
+        let accounts = array_ref![accounts, 0, NUM_FIXED];
+        let [
+            mango_group_acc,
+            admin_acc,
+        ] = accounts;
+
+        let mut mango_group = MangoGroup::load_mut_checked(
+            mango_group_acc,
+            program_id
+        )?;
+
+        check_eq_default!(admin_acc.key, &mango_group.admin)?;
+        check_default!(admin_acc.is_signer)?;
+
+        mango_group.oi_limits[market_index] = oi_limit;
+        Ok(())
+    }
+
+    #[inline(never)]
+    fn change_max_price_move(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        max_price_move_bps: u16,
+        price_band_timeout: u16
+    ) -> MangoResult<()> {
+        const NUM_FIXED: usize = 2;
+        let accounts = array_ref![accounts, 0, NUM_FIXED];
+        let [
+            mango_group_acc,
+            admin_acc,
+        ] = accounts;
+
+        let mut mango_group = MangoGroup::load_mut_checked(
+            mango_group_acc,
+            program_id
+        )?;
+
+        check_eq_default!(admin_acc.key, &mango_group.admin)?;
+        check_default!(admin_acc.is_signer)?;
+        // without a timeout a genuine move past the band would block the market until reset here
+        check_default!(max_price_move_bps == 0 || price_band_timeout > 0)?;
+
+        // The next read of each market becomes its new last_price
+        mango_group.max_price_move_bps = max_price_move_bps;
+        mango_group.price_band_timeout = price_band_timeout;
+        mango_group.last_price = [U32F32::from_num(0); NUM_MARKETS];
+        Ok(())
+    }
+
+    #[inline(never)]
+    fn change_all_borrow_limits(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        limits: [u64; NUM_TOKENS]
+    ) -> MangoResult<()> {
+        const NUM_FIXED: usize = 2;
+        let accounts = array_ref![accounts, 0, NUM_FIXED];
+        let [
+            mango_group_acc,
+            admin_acc,
+        ] = accounts;
+
+        let mut mango_group = MangoGroup::load_mut_checked(
+            mango_group_acc,
+            program_id
+        )?;
+
+        check_eq_default!(admin_acc.key, &mango_group.admin)?;
+        check_default!(admin_acc.is_signer)?;
+
+        mango_group.borrow_limits = limits;
+        mango_group.borrow_limit_target = limits;
+        mango_group.borrow_limit_ramp_seconds = 0;
+        Ok(())
+    }
+
+    #[inline(never)]
+    fn set_borrow_limit_ramp(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        targets: [u64; NUM_TOKENS],
+        ramp_seconds: u64
+    ) -> MangoResult<()> {
+        const NUM_FIXED: usize = 3;
+        let accounts = array_ref![accounts, 0, NUM_FIXED];
+        let [
+            mango_group_acc,
+            admin_acc,
+            clock_acc,
+        ] = accounts;
+
+        let mut mango_group = MangoGroup::load_mut_checked(
+            mango_group_acc,
+            program_id
+        )?;
+
+        check_eq_default!(admin_acc.key, &mango_group.admin)?;
+        check_default!(admin_acc.is_signer)?;
+
+        // Start every token from wherever a running ramp has got to
+        let now = Clock::from_account_info(clock_acc)?.unix_timestamp as u64;
+        for i in 0..NUM_TOKENS {
+            mango_group.borrow_limits[i] = mango_group.get_borrow_limit(i, now);
+        }
+        mango_group.borrow_limit_target = targets;
+        mango_group.borrow_limit_start_ts = now;
+        mango_group.borrow_limit_ramp_seconds = ramp_seconds;
+        Ok(())
+    }
+
+    #[inline(never)]
+    fn change_max_orders_per_market(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        max_orders: u64
+    ) -> MangoResult<()> {
+        const NUM_FIXED: usize = 2;
+        let accounts = array_ref![accounts, 0, NUM_FIXED];
+        let [
+            mango_group_acc,
+            admin_acc,
+        ] = accounts;
+
+        let mut mango_group = MangoGroup::load_mut_checked(
+            mango_group_acc,
+            program_id
+        )?;
+
+        check_eq_default!(admin_acc.key, &mango_group.admin)?;
+        check_default!(admin_acc.is_signer)?;
+
+        mango_group.max_orders_per_market = max_orders;
+        Ok(())
+    }
+
+    #[inline(never)]
+    fn change_max_accrual_interval(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        max_accrual_interval: u64
+    ) -> MangoResult<()> {
+        const NUM_FIXED: usize = 2;
+        let accounts = array_ref![accounts, 0, NUM_FIXED];
+        let [
+            mango_group_acc,
+            admin_acc,
+        ] = accounts;
+
+        let mut mango_group = MangoGroup::load_mut_checked(
+            mango_group_acc,
+            program_id
+        )?;
+
+        check_eq_default!(admin_acc.key, &mango_group.admin)?;
+        check_default!(admin_acc.is_signer)?;
+
+        mango_group.max_accrual_interval = max_accrual_interval;
+        Ok(())
+    }
+
+    #[inline(never)]
+    fn change_liquidation_cooldown(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        liquidation_cooldown: u64
+    ) -> MangoResult<()> {
+        const NUM_FIXED: usize = 2;
+        let accounts = array_ref![accounts, 0, NUM_FIXED];
+        let [
+            mango_group_acc,
+            admin_acc,
+        ] = accounts;
+
+        let mut mango_group = MangoGroup::load_mut_checked(
+            mango_group_acc,
+            program_id
+        )?;
+
+        check_eq_default!(admin_acc.key, &mango_group.admin)?;
+        check_default!(admin_acc.is_signer)?;
+
+        mango_group.liquidation_cooldown = liquidation_cooldown;
+        Ok(())
+    }
+
+    #[inline(never)]
+    fn change_deposit_limit(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        token_index: usize,
+        deposit_limit: u64
+    ) -> MangoResult<()> {
+        const NUM_FIXED: usize = 2;
+        let accounts = array_ref![accounts, 0, NUM_FIXED];
+        let [
+            mango_group_acc,
+            admin_acc,
+        ] = accounts;
+
+        let mut mango_group = MangoGroup::load_mut_checked(
+            mango_group_acc,
+            program_id
+        )?;
+
+        check_eq_default!(admin_acc.key, &mango_group.admin)?;
+        check_default!(admin_acc.is_signer)?;
+        check_default!(token_index < NUM_TOKENS)?;
+
+        mango_group.deposit_limits[token_index] = deposit_limit;
+        Ok(())
+    }
+
+    #[inline(never)]
+    fn change_dust_threshold(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        token_index: usize,
+        dust_threshold: u64
+    ) -> MangoResult<()> {
+        const NUM_FIXED: usize = 2;
+        let accounts = array_ref![accounts, 0, NUM_FIXED];
+        let [
+            mango_group_acc,
+            admin_acc,
+        ] = accounts;
+
+        let mut mango_group = MangoGroup::load_mut_checked(
+            mango_group_acc,
+            program_id
+        )?;
+
+        check_eq_default!(admin_acc.key, &mango_group.admin)?;
+        check_default!(admin_acc.is_signer)?;
+        check_default!(token_index < NUM_TOKENS)?;
+
+        mango_group.dust_thresholds[token_index] = dust_threshold;
+        Ok(())
+    }
+
+    #[inline(never)]
+    fn change_min_order_notional(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        min_order_notional: u64
+    ) -> MangoResult<()> {
+        const NUM_FIXED: usize = 2;
+        let accounts = array_ref![accounts, 0, NUM_FIXED];
+        let [
+            mango_group_acc,
+            admin_acc,
+        ] = accounts;
+
+        let mut mango_group = MangoGroup::load_mut_checked(
+            mango_group_acc,
+            program_id
+        )?;
+
+        check_eq_default!(admin_acc.key, &mango_group.admin)?;
+        check_default!(admin_acc.is_signer)?;
+
+        mango_group.min_order_notional = min_order_notional;
+        Ok(())
+    }
+
+    #[inline(never)]
+    fn change_max_borrow_per_account(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        token_index: usize,
+        max_borrow: u64
+    ) -> MangoResult<()> {
+        const NUM_FIXED: usize = 2;
+        let accounts = array_ref![accounts, 0, NUM_FIXED];
+        let [
+            mango_group_acc,
+            admin_acc,
+        ] = accounts;
+
+        let mut mango_group = MangoGroup::load_mut_checked(
+            mango_group_acc,
+            program_id
+        )?;
+
+        check_eq_default!(admin_acc.key, &mango_group.admin)?;
+        check_default!(admin_acc.is_signer)?;
+        check_default!(token_index < NUM_TOKENS)?;
+
+        mango_group.max_borrow_per_account[token_index] = max_borrow;
+        Ok(())
+    }
+
+    #[inline(never)]
+    fn change_collateral_grace_period(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        grace_period: u64
+    ) -> MangoResult<()> {
+        const NUM_FIXED: usize = 2;
+        let accounts = array_ref![accounts, 0, NUM_FIXED];
+        let [
+            mango_group_acc,
+            admin_acc,
+        ] = accounts;
+
+        let mut mango_group = MangoGroup::load_mut_checked(
+            mango_group_acc,
+            program_id
+        )?;
+
+        check_eq_default!(admin_acc.key, &mango_group.admin)?;
+        check_default!(admin_acc.is_signer)?;
+
+        mango_group.collateral_grace_period = grace_period;
+        Ok(())
+    }
+
+    /// Move a MarginAccount's deposits and borrows from one MangoGroup to another with the same
+    /// tokens, along with the net tokens (deposits - borrows) backing them in the vaults
+    #[inline(never)]
+    fn migrate_margin_account(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo]
+    ) -> MangoResult<()> {
+        const NUM_FIXED: usize = 8;
+        let (accounts, secondary_oracle_accs) = accounts.split_at(NUM_FIXED + 2 * NUM_TOKENS + NUM_MARKETS);
+        let accounts = array_ref![accounts, 0, NUM_FIXED + 2 * NUM_TOKENS + NUM_MARKETS];
+        let (
+            fixed_accs,
+            old_vault_accs,
+            new_vault_accs,
+            oracle_accs,
+        ) = array_refs![accounts, NUM_FIXED, NUM_TOKENS, NUM_TOKENS, NUM_MARKETS];
+
+        let [
+            old_mango_group_acc,
+            new_mango_group_acc,
+            margin_account_acc,
+            owner_acc,
+            old_signer_acc,
+            new_signer_acc,
+            token_prog_acc,
+            clock_acc,
+        ] = fixed_accs;
+
+        check!(old_mango_group_acc.key != new_mango_group_acc.key, MangoErrorCode::DuplicateAccounts)?;
+
+        // The old group may still have the v1 layout. Work on a copy and store it back at the end
+        let old_is_v1 = old_mango_group_acc.data_len() == MANGO_GROUP_V1_SIZE;
+        let mut old_mango_group = if old_is_v1 {
+            MangoGroupV1::load_checked(old_mango_group_acc, program_id)?.to_mango_group()
+        } else {
+            *MangoGroup::load_checked(old_mango_group_acc, program_id)?
+        };
+        let mut new_mango_group = MangoGroup::load_mut_checked(new_mango_group_acc, program_id)?;
+        check!(!new_mango_group.is_halted, MangoErrorCode::GroupHalted)?;
+
+        let mut margin_account = MarginAccount::load_mut_checked(
+            program_id, margin_account_acc, old_mango_group_acc.key
+        )?;
+        check_eq!(&margin_account.owner, owner_acc.key, MangoErrorCode::InvalidMarginAccountOwner)?;
+        check!(owner_acc.is_signer, MangoErrorCode::SignerNecessary)?;
+        check!(!margin_account.being_liquidated, MangoErrorCode::BeingLiquidated)?;
+
+        // Open orders accounts belong to the old group's signer and can't move with the account
+        for i in 0..NUM_MARKETS {
+            check!(!margin_account.has_open_orders(i), MangoErrorCode::OpenOrdersNotClosed)?;
+        }
+
+        check_eq!(old_signer_acc.key, &old_mango_group.signer_key, MangoErrorCode::InvalidSignerKey)?;
+        check_eq!(new_signer_acc.key, &new_mango_group.signer_key, MangoErrorCode::InvalidSignerKey)?;
+        check_eq!(token_prog_acc.key, &spl_token::id(), MangoErrorCode::InvalidProgramId)?;
+
+        let clock = Clock::from_account_info(clock_acc)?;
+        old_mango_group.update_indexes(&clock)?;
+        new_mango_group.update_indexes(&clock)?;
+
+        let old_signer_seeds = gen_signer_seeds(&old_mango_group.signer_nonce, old_mango_group_acc.key);
+        let new_signer_seeds = gen_signer_seeds(&new_mango_group.signer_nonce, new_mango_group_acc.key);
+
+        for i in 0..NUM_TOKENS {
+            check_eq!(old_mango_group.tokens[i], new_mango_group.tokens[i], MangoErrorCode::MangoGroupMismatch)?;
+            check_eq!(old_vault_accs[i].key, &old_mango_group.vaults[i], MangoErrorCode::InvalidMangoVault)?;
+            check_eq!(new_vault_accs[i].key, &new_mango_group.vaults[i], MangoErrorCode::InvalidMangoVault)?;
+
+            let native_deposit = margin_account.get_native_deposit(&old_mango_group.indexes[i], i);
+            let native_borrow = margin_account.get_native_borrow(&old_mango_group.indexes[i], i);
+
+            let deposit = margin_account.deposits[i];
+            let borrow = margin_account.borrows[i];
+            let borrow_principal = margin_account.borrow_principal[i];
+            saturating_sub_deposit(&mut old_mango_group, &mut margin_account, i, deposit)?;
+            saturating_sub_borrow(&mut old_mango_group, &mut margin_account, i, borrow)?;
+
+            let index: MangoIndex = new_mango_group.indexes[i];
+            checked_add_deposit(&mut new_mango_group, &mut margin_account, i, U64F64::from_num(native_deposit) / index.deposit)?;
+            checked_add_borrow(&mut new_mango_group, &mut margin_account, i, U64F64::from_num(native_borrow) / index.borrow)?;
+            margin_account.borrow_principal[i] = min(borrow_principal, native_borrow);
+
+            // Net deposits follow the account; net borrows are repaid to the old group by the new one
+            if native_deposit > native_borrow {
+                invoke_transfer(token_prog_acc, &old_vault_accs[i], &new_vault_accs[i], old_signer_acc,
+                                &[&old_signer_seeds], native_deposit - native_borrow)?;
+            } else if native_borrow > native_deposit {
+                invoke_transfer(token_prog_acc, &new_vault_accs[i], &old_vault_accs[i], new_signer_acc,
+                                &[&new_signer_seeds], native_borrow - native_deposit)?;
+            }
+        }
+
+        // The new group's limits apply to what the account brings in, as if it deposited and borrowed there
+        let now = clock.unix_timestamp as u64;
+        for i in 0..NUM_TOKENS {
+            if margin_account.deposits[i] > ZERO_U64F64 {
+                let deposit_limit = new_mango_group.deposit_limits[i];
+                check!(
+                    deposit_limit == 0 || new_mango_group.get_total_native_deposit(i)? <= deposit_limit,
+                    MangoErrorCode::DepositLimitExceeded
+                )?;
+            }
+            if margin_account.borrows[i] > ZERO_U64F64 {
+                check_max_borrow_per_account(&new_mango_group, &margin_account, i)?;
+                check_borrow_limit_ramp(&new_mango_group, &margin_account, i, now)?;
+                check_default!(new_mango_group.has_valid_deposits_borrows(i)?)?;
+            }
+        }
+
+        // Open orders are all closed, so only deposits count as assets
+        let prices = get_prices(&mut new_mango_group, oracle_accs, secondary_oracle_accs, &clock)?;
+        let (_, _, coll_ratio) = margin_account.get_health_from_totals(
+            &new_mango_group, &prices, &OpenOrdersTotals::default(), now
+        )?;
+        check!(coll_ratio >= new_mango_group.init_coll_ratio, MangoErrorCode::CollateralRatioLimit)?;
+
+        if old_is_v1 {
+            MangoGroupV1::load_mut_checked(old_mango_group_acc, program_id)?.store_totals(&old_mango_group)?;
+        } else {
+            *MangoGroup::load_mut_checked(old_mango_group_acc, program_id)? = old_mango_group;
+        }
+        margin_account.mango_group = *new_mango_group_acc.key;
+        Ok(())
+    }
+
+    /// Write off all borrows of an account with no deposits left and a coll ratio below 1.
+    /// Liquidators have nothing to take from such an account, so lenders take the loss
+    #[inline(never)]
+    fn resolve_bankruptcy(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo]
+    ) -> MangoResult<()> {
+        const NUM_FIXED: usize = 3;
+        let (accounts, secondary_oracle_accs) = accounts.split_at(NUM_FIXED + 2 * NUM_MARKETS);
+        let accounts = array_ref![accounts, 0, NUM_FIXED + 2 * NUM_MARKETS];
+        let (
+            fixed_accs,
+            open_orders_accs,
+            oracle_accs,
+        ) = array_refs![accounts, NUM_FIXED, NUM_MARKETS, NUM_MARKETS];
+
+        let [
+            mango_group_acc,
+            margin_account_acc,
+            clock_acc,
+        ] = fixed_accs;
+
+        let mut mango_group = MangoGroup::load_mut_checked(mango_group_acc, program_id)?;
+        let mut margin_account = MarginAccount::load_mut_checked(
+            program_id, margin_account_acc, mango_group_acc.key
+        )?;
+        let clock = Clock::from_account_info(clock_acc)?;
+        mango_group.update_indexes(&clock)?;
+
+        check_open_orders_unique(open_orders_accs)?;
+        for i in 0..NUM_MARKETS {
+            check_eq!(open_orders_accs[i].key, &margin_account.open_orders[i], MangoErrorCode::InvalidOpenOrdersAccount)?;
+            check_open_orders(&open_orders_accs[i], &mango_group.signer_key)?;
+        }
+
+        for i in 0..NUM_TOKENS {
+            check_eq!(margin_account.deposits[i], ZERO_U64F64, MangoErrorCode::NotBankrupt)?;
+        }
+        // Funds left in open orders count as assets, so those must be gone too
+        let prices = get_prices(&mut mango_group, oracle_accs, secondary_oracle_accs, &clock)?;
+        let (assets_val, _, coll_ratio) = margin_account.get_health(&mango_group, &prices, open_orders_accs, clock.unix_timestamp as u64)?;
+        check!(assets_val < DUST_THRESHOLD && coll_ratio < ONE_U64F64, MangoErrorCode::NotBankrupt)?;
+
+        for i in 0..NUM_TOKENS {
+            let native_borrow = margin_account.borrows[i] * mango_group.indexes[i].borrow;
+            if native_borrow > 0 {
+                socialize_loss(&mut mango_group, &mut margin_account, i, native_borrow)?;
+            }
+
+            // Whatever rounding left behind is written off as well
+            let remaining = margin_account.borrows[i];
+            saturating_sub_borrow(&mut mango_group, &mut margin_account, i, remaining)?;
+        }
+        margin_account.being_liquidated = false;
+        Ok(())
+    }
+
+    /// Fill a market slot that was left empty at InitMangoGroup
+    #[inline(never)]
+    fn add_spot_market(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        market_index: usize
+    ) -> MangoResult<()> {
+        const NUM_FIXED: usize = 4;
+        let accounts = array_ref![accounts, 0, NUM_FIXED];
+        let [
+            mango_group_acc,
+            admin_acc,
+            spot_market_acc,
+            oracle_acc,
+        ] = accounts;
+
+        let mut mango_group = MangoGroup::load_mut_checked(
+            mango_group_acc,
+            program_id
+        )?;
+
+        check_eq_default!(admin_acc.key, &mango_group.admin)?;
+        check_default!(admin_acc.is_signer)?;
+        check_default!(market_index < NUM_MARKETS)?;
+        check_eq_default!(mango_group.spot_markets[market_index], Pubkey::default())?;  // never overwrite
+
+        {
+            let spot_market = load_market_state_checked(spot_market_acc, &mango_group.dex_program_id)?;
+            let sm_base_mint = spot_market.coin_mint;
+            let sm_quote_mint = spot_market.pc_mint;
+            check_eq_default!(sm_base_mint, mango_group.tokens[market_index].to_aligned_bytes())?;
+            check_eq_default!(sm_quote_mint, mango_group.tokens[QUOTE_INDEX].to_aligned_bytes())?;
+        }
+
+        let oracle = flux_aggregator::state::Aggregator::load_initialized(oracle_acc)?;
+        mango_group.spot_markets[market_index] = *spot_market_acc.key;
+        mango_group.oracles[market_index] = *oracle_acc.key;
+        mango_group.oracle_decimals[market_index] = oracle.config.decimals;
+        Ok(())
+    }
+
+    #[inline(never)]
+    fn change_liquidation_fee(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        liquidation_fee: U64F64
+    ) -> MangoResult<()> {
+        const NUM_FIXED: usize = 2;
+        let accounts = array_ref![accounts, 0, NUM_FIXED];
+        let [
+            mango_group_acc,
+            admin_acc,
+        ] = accounts;
+
+        let mut mango_group = MangoGroup::load_mut_checked(
+            mango_group_acc,
+            program_id
+        )?;
+
+        check_eq_default!(admin_acc.key, &mango_group.admin)?;
+        check_default!(admin_acc.is_signer)?;
+        check_default!(liquidation_fee < ONE_U64F64)?;
+
+        mango_group.liquidation_fee = liquidation_fee;
+        Ok(())
+    }
+
+    #[inline(never)]
+    fn set_coll_ratios(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        maint_coll_ratio: U64F64,
+        init_coll_ratio: U64F64
+    ) -> MangoResult<()> {
+        const NUM_FIXED: usize = 2;
+        let accounts = array_ref![accounts, 0, NUM_FIXED];
+        let [
+            mango_group_acc,
+            admin_acc,
+        ] = accounts;
+
+        let mut mango_group = MangoGroup::load_mut_checked(
+            mango_group_acc,
+            program_id
+        )?;
+
+        check_eq_default!(admin_acc.key, &mango_group.admin)?;
+        check_default!(admin_acc.is_signer)?;
+        check_default!(maint_coll_ratio > ONE_U64F64)?;
+        check_default!(init_coll_ratio >= maint_coll_ratio)?;
+
+        // Raising maint in small steps keeps accounts from becoming liquidatable all at once
+        let max_maint = mango_group.maint_coll_ratio.checked_add(MAX_MAINT_COLL_RATIO_STEP).ok_or(throw!())?;
+        check_default!(maint_coll_ratio <= max_maint)?;
+
+        mango_group.maint_coll_ratio = maint_coll_ratio;
+        mango_group.init_coll_ratio = init_coll_ratio;
+        Ok(())
+    }
+
+    #[inline(never)]
+    fn set_market_mode(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        market_index: usize,
+        reduce_only: bool
+    ) -> MangoResult<()> {
+        const NUM_FIXED: usize = 2;
+        let accounts = array_ref![accounts, 0, NUM_FIXED];
+        let [
+            mango_group_acc,
+            admin_acc,
+        ] = accounts;
+
+        let mut mango_group = MangoGroup::load_mut_checked(
+            mango_group_acc,
+            program_id
+        )?;
+
+        check_eq_default!(admin_acc.key, &mango_group.admin)?;
+        check_default!(admin_acc.is_signer)?;
+        check_default!(market_index < NUM_MARKETS)?;
+
+        if reduce_only {
+            mango_group.market_flags[market_index] |= MarketFlag::ReduceOnly as u8;
+        } else {
+            mango_group.market_flags[market_index] &= !(MarketFlag::ReduceOnly as u8);
+        }
+        Ok(())
+    }
+
+    #[inline(never)]
+    fn set_halt(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        is_halted: bool
+    ) -> MangoResult<()> {
+        const NUM_FIXED: usize = 2;
+        let accounts = array_ref![accounts, 0, NUM_FIXED];
+        let [
+            mango_group_acc,
+            admin_acc,
+        ] = accounts;
+
+        let mut mango_group = MangoGroup::load_mut_checked(
+            mango_group_acc,
+            program_id
+        )?;
+
+        check_eq_default!(admin_acc.key, &mango_group.admin)?;
+        check_default!(admin_acc.is_signer)?;
+
+        mango_group.is_halted = is_halted;
+        Ok(())
+    }
+
+    #[inline(never)]
+    fn set_admin(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo]
+    ) -> MangoResult<()> {
+        const NUM_FIXED: usize = 3;
+        let accounts = array_ref![accounts, 0, NUM_FIXED];
+        let [
+            mango_group_acc,
+            admin_acc,
+            new_admin_acc,
+        ] = accounts;
+
+        let mut mango_group = MangoGroup::load_mut_checked(
+            mango_group_acc,
+            program_id
+        )?;
+
+        check_eq_default!(admin_acc.key, &mango_group.admin)?;
+        check_default!(admin_acc.is_signer)?;
+        check_default!(*new_admin_acc.key != Pubkey::default())?;
+
+        if new_admin_acc.is_signer {
+            mango_group.admin = *new_admin_acc.key;
+            mango_group.pending_admin = Pubkey::default();
+        } else {
+            mango_group.pending_admin = *new_admin_acc.key;
+        }
+        Ok(())
+    }
+
+    #[inline(never)]
+    fn accept_admin(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo]
+    ) -> MangoResult<()> {
+        const NUM_FIXED: usize = 2;
+        let accounts = array_ref![accounts, 0, NUM_FIXED];
+        let [
+            mango_group_acc,
+            pending_admin_acc,
+        ] = accounts;
+
+        let mut mango_group = MangoGroup::load_mut_checked(
+            mango_group_acc,
+            program_id
+        )?;
+
+        check_default!(mango_group.pending_admin != Pubkey::default())?;
+        check_eq_default!(pending_admin_acc.key, &mango_group.pending_admin)?;
+        check_default!(pending_admin_acc.is_signer)?;
+
+        mango_group.admin = mango_group.pending_admin;
+        mango_group.pending_admin = Pubkey::default();
+        Ok(())
+    }
+
+    #[inline(never)]
+    fn get_margin_account_details(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo]
+    ) -> MangoResult<()> {
+        const NUM_FIXED: usize = 3;
+        let (accounts, secondary_oracle_accs) = accounts.split_at(NUM_FIXED + 2 * NUM_MARKETS);
+        let accounts = array_ref![accounts, 0, NUM_FIXED + 2 * NUM_MARKETS];
+        let (
+            fixed_accs,
+            open_orders_accs,
+            oracle_accs,
+        ) = array_refs![accounts, NUM_FIXED, NUM_MARKETS, NUM_MARKETS];
+
+        let [
+            mango_group_acc,
+            margin_account_acc,
+            clock_acc,
+        ] = fixed_accs;
+
+        // Work on a copy so the indexes can be brought up to date without writing to the account
+        let mut mango_group = *MangoGroup::load_checked(mango_group_acc, program_id)?;
+        let margin_account = MarginAccount::load_checked(
+            program_id, margin_account_acc, mango_group_acc.key
+        )?;
+
+        let clock = Clock::from_account_info(clock_acc)?;
+        mango_group.update_indexes(&clock)?;
+
+        check_open_orders_unique(open_orders_accs)?;
+        for i in 0..NUM_MARKETS {
+            check_eq!(open_orders_accs[i].key, &margin_account.open_orders[i], MangoErrorCode::InvalidOpenOrdersAccount)?;
+            check_open_orders(&open_orders_accs[i], &mango_group.signer_key)?;
+        }
+
+        let prices = get_prices(&mut mango_group, oracle_accs, secondary_oracle_accs, &clock)?;
+        let (assets, liabs, coll_ratio) = margin_account.get_health(&mango_group, &prices, open_orders_accs, clock.unix_timestamp as u64)?;
+        msg!(
+            "MANGO-HEALTH assets={} liabs={} ratio={}",
+            format_u64f64(assets, 6), format_u64f64(liabs, 6), format_u64f64(coll_ratio, 6)
+        );
+        Ok(())
+    }
+
+    #[inline(never)]
+    fn log_indexes(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo]
+    ) -> MangoResult<()> {
+        const NUM_FIXED: usize = 2;
+        let accounts = array_ref![accounts, 0, NUM_FIXED];
+        let [
+            mango_group_acc,
+            clock_acc,
+        ] = accounts;
+
+        let mut mango_group = MangoGroup::load_mut_checked(mango_group_acc, program_id)?;
+        let clock = Clock::from_account_info(clock_acc)?;
+        mango_group.update_indexes(&clock)?;
+
+        for i in 0..NUM_TOKENS {
+            msg!("{}", format_index_log(i, &mango_group.indexes[i]));
+        }
+        Ok(())
+    }
+
+    #[inline(never)]
+    fn check_solvency(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo]
+    ) -> MangoResult<()> {
+        const NUM_FIXED: usize = 2;
+        let (accounts, secondary_oracle_accs) = accounts.split_at(NUM_FIXED + NUM_MARKETS);
+        let accounts = array_ref![accounts, 0, NUM_FIXED + NUM_MARKETS];
+        let (fixed_accs, oracle_accs) = array_refs![accounts, NUM_FIXED, NUM_MARKETS];
+        let [
+            mango_group_acc,
+            clock_acc,
+        ] = fixed_accs;
+
+        // Work on a copy so the indexes can be brought up to date without writing to the account
+        let mut mango_group = *MangoGroup::load_checked(mango_group_acc, program_id)?;
+        let clock = Clock::from_account_info(clock_acc)?;
+        mango_group.update_indexes(&clock)?;
+
+        let prices = get_prices(&mut mango_group, oracle_accs, secondary_oracle_accs, &clock)?;
+        let (deposits_val, borrows_val) = mango_group.get_solvency(&prices);
+        msg!("MANGO-SOLVENCY deposits={} borrows={}", format_u64f64(deposits_val, 6), format_u64f64(borrows_val, 6));
+        Ok(())
+    }
+
+    #[inline(never)]
+    fn audit_vaults(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo]
+    ) -> MangoResult<()> {
+        const NUM_FIXED: usize = 1;
+        let accounts = array_ref![accounts, 0, NUM_FIXED + NUM_TOKENS];
+        let (fixed_accs, vault_accs) = array_refs![accounts, NUM_FIXED, NUM_TOKENS];
+        let [mango_group_acc] = fixed_accs;
+
+        let mango_group = MangoGroup::load_checked(mango_group_acc, program_id)?;
+
+        let mut solvent = true;
+        for i in 0..NUM_TOKENS {
+            check_eq!(vault_accs[i].key, &mango_group.vaults[i], MangoErrorCode::InvalidMangoVault)?;
+            let vault = Account::unpack(&vault_accs[i].try_borrow_data()?)?;
+            let shortfall = mango_group.get_vault_shortfall(i, vault.amount)?;
+            if shortfall > 0 {
+                msg!("Vault shortfall: {{ \"token_index\": {}, \"vault_amount\": {}, \"shortfall\": {} }}", i, vault.amount, shortfall);
+                solvent = false;
+            }
+        }
+        check!(solvent, MangoErrorCode::VaultShortfall)
+    }
+
+    #[inline(never)]
+    fn verify_group_config(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo]
+    ) -> MangoResult<()> {
+        const NUM_FIXED: usize = 1;
+        let accounts = array_ref![accounts, 0, NUM_FIXED + 2 * NUM_TOKENS + 2 * NUM_MARKETS];
+        let (
+            fixed_accs,
+            token_mint_accs,
+            vault_accs,
+            spot_market_accs,
+            oracle_accs,
+        ) = array_refs![accounts, NUM_FIXED, NUM_TOKENS, NUM_TOKENS, NUM_MARKETS, NUM_MARKETS];
+        let [mango_group_acc] = fixed_accs;
+
+        let mango_group = MangoGroup::load_checked(mango_group_acc, program_id)?;
+
+        let mut valid = true;
+        let mut mismatch = |check: &str, index: usize| {
+            msg!("Group config mismatch: {{ \"check\": \"{}\", \"index\": {} }}", check, index);
+            valid = false;
+        };
+
+        for i in 0..NUM_TOKENS {
+            if token_mint_accs[i].key != &mango_group.tokens[i] {
+                mismatch("mint", i);
+            }
+            match Mint::unpack(&token_mint_accs[i].try_borrow_data()?) {
+                Ok(mint) if mint.decimals == mango_group.mint_decimals[i] => {}
+                _ => mismatch("mint_decimals", i)
+            }
+
+            if vault_accs[i].key != &mango_group.vaults[i] {
+                mismatch("vault", i);
+            }
+            let vault_ok = match Account::unpack(&vault_accs[i].try_borrow_data()?) {
+                Ok(vault) => {
+                    vault.is_initialized() && vault.owner == mango_group.signer_key && vault.mint == mango_group.tokens[i]
+                }
+                Err(_) => false
+            };
+            if !vault_ok || vault_accs[i].owner != &spl_token::id() {
+                mismatch("vault_token_account", i);
+            }
+        }
+
+        for i in 0..NUM_MARKETS {
+            if spot_market_accs[i].key != &mango_group.spot_markets[i] {
+                mismatch("spot_market", i);
+            }
+            if oracle_accs[i].key != &mango_group.oracles[i] {
+                mismatch("oracle", i);
+            }
+            if mango_group.spot_markets[i] == Pubkey::default() {  // reserved for AddSpotMarket
+                continue;
+            }
+
+            let market_ok = match load_market_state_checked(&spot_market_accs[i], &mango_group.dex_program_id) {
+                Ok(spot_market) => {
+                    spot_market.coin_mint == mango_group.tokens[i].to_aligned_bytes()
+                        && spot_market.pc_mint == mango_group.tokens[QUOTE_INDEX].to_aligned_bytes()
+                }
+                Err(_) => false
+            };
+            if !market_ok {
+                mismatch("spot_market_mints", i);
+            }
+
+            let oracle_ok = if mango_group.oracle_types[i] == OracleType::Pyth as u8 {
+                load_pyth_price(&oracle_accs[i]).is_ok()
+                    && mango_group.oracle_decimals[i] == mango_group.mint_decimals[QUOTE_INDEX]
+            } else {
+                match flux_aggregator::state::Aggregator::load_initialized(&oracle_accs[i]) {
+                    Ok(oracle) => oracle.config.decimals == mango_group.oracle_decimals[i],
+                    Err(_) => false
+                }
+            };
+            if !oracle_ok {
+                mismatch("oracle_decimals", i);
+            }
+        }
+
+        check!(valid, MangoErrorCode::GroupConfigMismatch)
+    }
+
+    #[inline(never)]
+    fn set_secondary_oracle(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        market_index: usize,
+        max_divergence: U64F64
+    ) -> MangoResult<()> {
+        const NUM_FIXED: usize = 3;
+        let accounts = array_ref![accounts, 0, NUM_FIXED];
+        let [
+            mango_group_acc,
+            admin_acc,
+            oracle_acc,
+        ] = accounts;
+
+        let mut mango_group = MangoGroup::load_mut_checked(
+            mango_group_acc,
+            program_id
+        )?;
+
+        check_eq_default!(admin_acc.key, &mango_group.admin)?;
+        check_default!(admin_acc.is_signer)?;
+        check_default!(market_index < NUM_MARKETS)?;
+
+        if *oracle_acc.key != Pubkey::default() {
+            // prices are scaled using the primary oracle's decimals
+            let oracle = flux_aggregator::state::Aggregator::load_initialized(oracle_acc)?;
+            check_eq_default!(oracle.config.decimals, mango_group.oracle_decimals[market_index])?;
+        }
+
+        mango_group.oracles_secondary[market_index] = *oracle_acc.key;
+        mango_group.oracle_max_divergences[market_index] = max_divergence;
+        Ok(())
+    }
+
+    #[inline(never)]
+    fn set_oracle(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        market_index: usize,
+        oracle_type: u8
+    ) -> MangoResult<()> {
+        const NUM_FIXED: usize = 3;
+        let accounts = array_ref![accounts, 0, NUM_FIXED];
+        let [
+            mango_group_acc,
+            admin_acc,
+            oracle_acc,
+        ] = accounts;
+
+        let mut mango_group = MangoGroup::load_mut_checked(
+            mango_group_acc,
+            program_id
+        )?;
+
+        check_eq_default!(admin_acc.key, &mango_group.admin)?;
+        check_default!(admin_acc.is_signer)?;
+        check_default!(market_index < NUM_MARKETS)?;
+        check!(mango_group.spot_markets[market_index] != Pubkey::default(), MangoErrorCode::InvalidSpotMarket)?;
+
+        let oracle_type = OracleType::try_from_primitive(oracle_type).map_err(|_| throw!())?;
+        let decimals = match oracle_type {
+            OracleType::Flux => flux_aggregator::state::Aggregator::load_initialized(oracle_acc)?.config.decimals,
+            OracleType::Pyth => {
+                // Pyth prices are rescaled to the quote decimals; loading one now validates the account
+                let quote_decimals = mango_group.mint_decimals[QUOTE_INDEX];
+                load_pyth_price(oracle_acc)?;
+                quote_decimals
+            }
+        };
+
+        // the secondary oracle is read with the primary's decimals
+        check_default!(
+            mango_group.oracles_secondary[market_index] == Pubkey::default() ||
+            decimals == mango_group.oracle_decimals[market_index]
+        )?;
+
+        mango_group.oracles[market_index] = *oracle_acc.key;
+        mango_group.oracle_types[market_index] = oracle_type as u8;
+        mango_group.oracle_decimals[market_index] = decimals;
+        mango_group.last_price[market_index] = U32F32::from_num(0);  // the next read starts the price band afresh
+        Ok(())
+    }
+
+    #[inline(never)]
+    fn refresh_oracle_decimals(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo]
+    ) -> MangoResult<()> {
+        const NUM_FIXED: usize = 2;
+        let accounts = array_ref![accounts, 0, NUM_FIXED + NUM_MARKETS];
+        let (
+            fixed_accs,
+            oracle_accs,
+        ) = array_refs![accounts, NUM_FIXED, NUM_MARKETS];
+        let [
+            mango_group_acc,
+            admin_acc,
+        ] = fixed_accs;
+
+        let mut mango_group = MangoGroup::load_mut_checked(
+            mango_group_acc,
+            program_id
+        )?;
+
+        check_eq_default!(admin_acc.key, &mango_group.admin)?;
+        check_default!(admin_acc.is_signer)?;
+
+        for i in 0..NUM_MARKETS {
+            check_eq!(&mango_group.oracles[i], oracle_accs[i].key, MangoErrorCode::InvalidOracle)?;
+            // skip markets not added yet and Pyth oracles, which have no decimals to refresh
+            if mango_group.oracles[i] == Pubkey::default() || mango_group.oracle_types[i] == OracleType::Pyth as u8 {
+                continue;
+            }
+            let oracle = flux_aggregator::state::Aggregator::load_initialized(&oracle_accs[i])?;
+            if oracle.config.decimals != mango_group.oracle_decimals[i] {
+                msg!("market {} oracle decimals {} -> {}", i, mango_group.oracle_decimals[i], oracle.config.decimals);
+                mango_group.oracle_decimals[i] = oracle.config.decimals;
+            }
+        }
+        Ok(())
+    }
+
+    #[inline(never)]
+    fn change_reserve_factor(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        reserve_factor: U64F64
+    ) -> MangoResult<()> {
+        const NUM_FIXED: usize = 2;
+        let accounts = array_ref![accounts, 0, NUM_FIXED];
+        let [
+            mango_group_acc,
+            admin_acc,
+        ] = accounts;
+
+        let mut mango_group = MangoGroup::load_mut_checked(
+            mango_group_acc,
+            program_id
+        )?;
+
+        check_eq_default!(admin_acc.key, &mango_group.admin)?;
+        check_default!(admin_acc.is_signer)?;
+        check_default!(reserve_factor <= ONE_U64F64)?;
+
+        mango_group.reserve_factor = reserve_factor;
+        Ok(())
+    }
+
+    #[inline(never)]
+    fn change_coll_weight(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        token_index: usize,
+        coll_weight: U64F64
+    ) -> MangoResult<()> {
+        const NUM_FIXED: usize = 2;
+        let accounts = array_ref![accounts, 0, NUM_FIXED];
+        let [
+            mango_group_acc,
+            admin_acc,
+        ] = accounts;
+
+        let mut mango_group = MangoGroup::load_mut_checked(
+            mango_group_acc,
+            program_id
+        )?;
+
+        check_eq_default!(admin_acc.key, &mango_group.admin)?;
+        check_default!(admin_acc.is_signer)?;
+        check_default!(token_index < NUM_TOKENS)?;
+        check_default!(coll_weight <= ONE_U64F64)?;
+
+        mango_group.coll_weights[token_index] = coll_weight;
+        Ok(())
+    }
+
+    #[inline(never)]
+    fn change_liab_weight(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        token_index: usize,
+        liab_weight: U64F64
+    ) -> MangoResult<()> {
+        const NUM_FIXED: usize = 2;
+        let accounts = array_ref![accounts, 0, NUM_FIXED];
+        let [
+            mango_group_acc,
+            admin_acc,
+        ] = accounts;
+
+        let mut mango_group = MangoGroup::load_mut_checked(
+            mango_group_acc,
+            program_id
+        )?;
+
+        check_eq_default!(admin_acc.key, &mango_group.admin)?;
+        check_default!(admin_acc.is_signer)?;
+        check_default!(token_index < NUM_TOKENS)?;
+        check_default!(liab_weight >= ONE_U64F64)?;
+
+        mango_group.liab_weights[token_index] = liab_weight;
+        Ok(())
+    }
+
+    #[inline(never)]
+    fn withdraw_fees(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo]
+    ) -> MangoResult<()> {
+        const NUM_FIXED: usize = 7;
+        let accounts = array_ref![accounts, 0, NUM_FIXED];
+        let [
+            mango_group_acc,
+            admin_acc,
+            token_account_acc,
+            vault_acc,
+            signer_acc,
+            token_prog_acc,
+            clock_acc,
+        ] = accounts;
+
+        let mut mango_group = MangoGroup::load_mut_checked(
+            mango_group_acc,
+            program_id
+        )?;
+
+        check_eq_default!(admin_acc.key, &mango_group.admin)?;
+        check_default!(admin_acc.is_signer)?;
+        check_eq!(token_prog_acc.key, &spl_token::id(), MangoErrorCode::InvalidProgramId)?;
+
+        let clock = Clock::from_account_info(clock_acc)?;
+        mango_group.update_indexes(&clock)?;
+
+        let token_index = mango_group.get_token_index_with_vault(vault_acc.key)
+            .ok_or(throw!(MangoErrorCode::InvalidMangoVault))?;
+        check_eq!(&mango_group.vaults[token_index], vault_acc.key, MangoErrorCode::InvalidMangoVault)?;
+
+        // Sweep the whole native amount; the fractional remainder stays accrued
+        let deposit_index = mango_group.indexes[token_index].deposit;
+        let native_fees: u64 = mango_group.fees_accrued[token_index].checked_mul(deposit_index).unwrap()
+            .checked_floor().unwrap().to_num();
+        if native_fees == 0 {
+            return Ok(());
+        }
+        mango_group.fees_accrued[token_index] = mango_group.fees_accrued[token_index]
+            .checked_sub(U64F64::from_num(native_fees) / deposit_index).unwrap_or(ZERO_U64F64);
+
+        let signer_seeds = gen_signer_seeds(&mango_group.signer_nonce, mango_group_acc.key);
+        invoke_transfer(token_prog_acc, vault_acc, token_account_acc, signer_acc,
+                        &[&signer_seeds], native_fees)?;
+        Ok(())
+    }
+
+    #[inline(never)]
+    fn place_order(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        order: serum_dex::instruction::NewOrderInstructionV3
+    ) -> MangoResult<()> {
+        const NUM_FIXED: usize = 17;
+        let (accounts, secondary_oracle_accs) = accounts.split_at(NUM_FIXED + 2 * NUM_MARKETS);
+        let accounts = array_ref![accounts, 0, NUM_FIXED + 2 * NUM_MARKETS];
+        let (
+            fixed_accs,
             open_orders_accs,
             oracle_accs,
         ) = array_refs![accounts, NUM_FIXED, NUM_MARKETS, NUM_MARKETS];
@@ -727,15 +2346,17 @@ impl Processor {
         ] = fixed_accs;
 
         let mut mango_group = MangoGroup::load_mut_checked(mango_group_acc, program_id)?;
+        check!(!mango_group.is_halted, MangoErrorCode::GroupHalted)?;
         let mut margin_account = MarginAccount::load_mut_checked(
             program_id, margin_account_acc, mango_group_acc.key
         )?;
 
         let clock = Clock::from_account_info(clock_acc)?;
+        let now = clock.unix_timestamp as u64;
         mango_group.update_indexes(&clock)?;
 
-        let prices = get_prices(&mango_group, oracle_accs)?;
-        let coll_ratio = margin_account.get_collateral_ratio(&mango_group, &prices, open_orders_accs)?;
+        let prices = get_prices(&mut mango_group, oracle_accs, secondary_oracle_accs, &clock)?;
+        let coll_ratio = margin_account.get_collateral_ratio(&mango_group, &prices, open_orders_accs, now)?;
         if margin_account.being_liquidated {
             if coll_ratio >= mango_group.init_coll_ratio {
                 margin_account.being_liquidated = false;
@@ -743,40 +2364,55 @@ impl Processor {
                 throw_err!(MangoErrorCode::BeingLiquidated)?;
             }
         }
-        let reduce_only = coll_ratio < mango_group.init_coll_ratio;
+        check!(owner_acc.is_signer, MangoErrorCode::SignerNecessary)?;
+        check_eq!(&margin_account.owner, owner_acc.key, MangoErrorCode::InvalidMarginAccountOwner)?;
 
-        check_default!(owner_acc.is_signer)?;
-        check_eq_default!(&margin_account.owner, owner_acc.key)?;
+        let market_i = mango_group.get_market_index(spot_market_acc.key)
+            .ok_or(throw!(MangoErrorCode::InvalidSpotMarket))?;
+        check_eq!(dex_prog_acc.key, &mango_group.dex_program_id, MangoErrorCode::InvalidProgramId)?;
+        let coin_lot_size = load_market_state_checked(spot_market_acc, dex_prog_acc.key)?.coin_lot_size;
+        // the dex takes the fee tier from this account, which get_group_fee_tier mirrors
+        check_eq!(srm_vault_acc.key, &mango_group.srm_vault, MangoErrorCode::InvalidMangoVault)?;
+        check_min_order_notional(&mango_group, &prices, market_i, order.max_coin_qty.get(), coin_lot_size)?;
+        check_open_interest(
+            &mango_group, &margin_account, &prices, market_i, order.side, order.max_coin_qty.get(), coin_lot_size
+        )?;
+        let market_reduce_only = mango_group.is_reduce_only(market_i);
+        check!(
+            !market_reduce_only || margin_account.is_reducing_order(market_i, order.side),
+            MangoErrorCode::ReduceOnlyMarket
+        )?;
+        let reduce_only = market_reduce_only || coll_ratio < mango_group.init_coll_ratio;
 
-        let market_i = mango_group.get_market_index(spot_market_acc.key).unwrap();
         let token_i = match order.side {
-            Side::Bid => NUM_MARKETS,
+            Side::Bid => QUOTE_INDEX,
             Side::Ask => market_i
         };
-        check_eq_default!(&mango_group.vaults[token_i], vault_acc.key)?;
+        check_eq!(&mango_group.vaults[token_i], vault_acc.key, MangoErrorCode::InvalidMangoVault)?;
 
         let pre_amount = {  // this is to keep track of how much funds were transferred out
             let vault = Account::unpack(&vault_acc.try_borrow_data()?)?;
             vault.amount
         };
 
+        check_open_orders_unique(open_orders_accs)?;
         for i in 0..NUM_MARKETS {
             let open_orders_acc = &open_orders_accs[i];
             if i == market_i {  // this one must not be default pubkey
-                check_default!(*open_orders_acc.key != Pubkey::default())?;
-                if margin_account.open_orders[i] == Pubkey::default() {
-                    let open_orders = load_open_orders(open_orders_acc)?;
-                    check_eq_default!(open_orders.account_flags, 0)?;
-                    margin_account.open_orders[i] = *open_orders_acc.key;
-                }
+                check_or_assign_open_orders(
+                    &mut margin_account, i, open_orders_acc, spot_market_acc.key, &mango_group.signer_key
+                )?;
             } else {
-                check_eq_default!(open_orders_accs[i].key, &margin_account.open_orders[i])?;
+                check_eq!(open_orders_accs[i].key, &margin_account.open_orders[i], MangoErrorCode::InvalidOpenOrdersAccount)?;
                 check_open_orders(&open_orders_accs[i], &mango_group.signer_key)?;
             }
         }
 
-        check_eq_default!(token_prog_acc.key, &spl_token::id())?;
-        check_eq_default!(dex_prog_acc.key, &mango_group.dex_program_id)?;
+        margin_account.update_num_open_orders(market_i, &open_orders_accs[market_i])?;
+        let pre_num_open_orders = margin_account.num_open_orders[market_i];
+        check_max_orders_per_market(&mango_group, &margin_account, market_i)?;
+
+        check_eq!(token_prog_acc.key, &spl_token::id(), MangoErrorCode::InvalidProgramId)?;
         let data = serum_dex::instruction::MarketInstruction::NewOrderV3(order).pack();
         let instruction = Instruction {
             program_id: *dex_prog_acc.key,
@@ -816,55 +2452,309 @@ impl Processor {
 
         let signer_seeds = gen_signer_seeds(&mango_group.signer_nonce, mango_group_acc.key);
         solana_program::program::invoke_signed(&instruction, &account_infos, &[&signer_seeds])?;
+        margin_account.update_num_open_orders(market_i, &open_orders_accs[market_i])?;
+        debug_assert!(margin_account.num_open_orders[market_i] <= pre_num_open_orders + 1);
 
         let post_amount = {
             let vault = Account::unpack(&vault_acc.try_borrow_data()?)?;
             vault.amount
         };
 
-        let spent = pre_amount.checked_sub(post_amount).unwrap();
-        let index: MangoIndex = mango_group.indexes[token_i];
-        let native_deposit = margin_account.get_native_deposit(&index, token_i);
+        debit_order_funds(
+            &mut mango_group, &mut margin_account, token_i, pre_amount, post_amount, reduce_only, now
+        )?;
 
-        // user deposits will be used first.
-        // If user does not want that to happen, they must first issue a borrow command
-        if native_deposit >= spent {
-            let spent_deposit = U64F64::from_num(spent) / index.deposit;
-            checked_sub_deposit(&mut mango_group, &mut margin_account, token_i, spent_deposit)?;
-        } else {
+        let coll_ratio = margin_account.get_collateral_ratio(&mango_group, &prices, open_orders_accs, now)?;
+        check!(reduce_only || coll_ratio >= mango_group.init_coll_ratio, MangoErrorCode::CollateralRatioLimit)?;
 
-            let avail_deposit = margin_account.deposits[token_i];
-            checked_sub_deposit(&mut mango_group, &mut margin_account, token_i, avail_deposit)?;
-            let rem_spend = U64F64::from_num(spent - native_deposit);
+        check_default!(mango_group.has_valid_deposits_borrows(token_i)?)?;
+        Ok(())
+    }
 
-            check_default!(!reduce_only)?;  // Cannot borrow more in reduce only mode
-            checked_add_borrow(&mut mango_group, &mut margin_account, token_i , rem_spend / index.borrow)?;
+    #[inline(never)]
+    fn settle_funds(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo]
+    ) -> MangoResult<()> {
+        Self::settle_funds_capped(program_id, accounts, u64::MAX, u64::MAX)
+    }
+
+    /// Same as settle_funds, but only settles if no more than max_base and max_quote would be
+    /// credited. Otherwise nothing moves and the funds stay on the open orders account
+    #[inline(never)]
+    fn settle_funds_capped(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        max_base: u64,
+        max_quote: u64
+    ) -> MangoResult<()> {
+        const NUM_FIXED: usize = 14;
+        let accounts = array_ref![accounts, 0, NUM_FIXED];
+        let [
+            mango_group_acc,
+            owner_acc,  // signer
+            margin_account_acc,
+            clock_acc,
+
+            dex_prog_acc,
+            spot_market_acc,
+            open_orders_acc,
+            signer_acc,
+            dex_base_acc,
+            dex_quote_acc,
+            base_vault_acc,
+            quote_vault_acc,
+            dex_signer_acc,
+            token_prog_acc,
+        ] = accounts;
+
+        let mut mango_group = MangoGroup::load_mut_checked(mango_group_acc, program_id)?;
+        let mut margin_account = MarginAccount::load_mut_checked(
+            program_id,
+            margin_account_acc,
+            mango_group_acc.key
+        )?;
+        let clock = Clock::from_account_info(clock_acc)?;
+        mango_group.update_indexes(&clock)?;
+
+        let market_i = mango_group.get_market_index(spot_market_acc.key)
+            .ok_or(throw!(MangoErrorCode::InvalidSpotMarket))?;
+
+        check!(owner_acc.is_signer, MangoErrorCode::SignerNecessary)?;
+        check_eq!(owner_acc.key, &margin_account.owner, MangoErrorCode::InvalidMarginAccountOwner)?;
+        check_eq!(&margin_account.open_orders[market_i], open_orders_acc.key, MangoErrorCode::InvalidOpenOrdersAccount)?;
+        check_eq!(base_vault_acc.key, &mango_group.vaults[market_i], MangoErrorCode::InvalidMangoVault)?;
+        check_eq!(quote_vault_acc.key, &mango_group.vaults[QUOTE_INDEX], MangoErrorCode::InvalidMangoVault)?;
+        check_eq!(token_prog_acc.key, &spl_token::id(), MangoErrorCode::InvalidProgramId)?;
+        check_eq!(dex_prog_acc.key, &mango_group.dex_program_id, MangoErrorCode::InvalidProgramId)?;
+
+        if !margin_account.has_open_orders(market_i) {
+            return Ok(());
+        }
+        margin_account.update_num_open_orders(market_i, open_orders_acc)?;
+
+        let (pre_base, pre_quote) = {
+            let open_orders = load_open_orders(open_orders_acc)?;
+            (open_orders.native_coin_free, open_orders.native_pc_free)
+        };
+
+        if pre_base == 0 && pre_quote == 0 {
+            return Ok(());
         }
 
-        let coll_ratio = margin_account.get_collateral_ratio(&mango_group, &prices, open_orders_accs)?;
-        check_default!(reduce_only || coll_ratio >= mango_group.init_coll_ratio)?;
+        // Serum's SettleFunds always pulls all free funds of both tokens, so a cap can't be applied
+        // to part of them, and there is no way to hand an excess back to the dex. Leave everything
+        // on the open orders account, where new orders can use it, rather than fail the transaction
+        if pre_base > max_base || pre_quote > max_quote {
+            msg!("Settle skipped: {{ \"base_free\": {}, \"quote_free\": {} }}", pre_base, pre_quote);
+            return Ok(());
+        }
+
+        let signer_seeds = gen_signer_seeds(&mango_group.signer_nonce, mango_group_acc.key);
+        invoke_settle_funds(
+            dex_prog_acc,
+            spot_market_acc,
+            open_orders_acc,
+            signer_acc,
+            dex_base_acc,
+            dex_quote_acc,
+            base_vault_acc,
+            quote_vault_acc,
+            dex_signer_acc,
+            token_prog_acc,
+            &[&signer_seeds]
+        )?;
+
+        let (post_base, post_quote) = {
+            let open_orders = load_open_orders(open_orders_acc)?;
+            (open_orders.native_coin_free, open_orders.native_pc_free)
+        };
+
+        credit_settled_funds(
+            &mut mango_group, &mut margin_account, market_i, (pre_base, pre_quote), (post_base, post_quote)
+        )
+    }
+
+    #[inline(never)]
+    fn init_open_orders(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        market_index: usize
+    ) -> MangoResult<()> {
+        check_default!(market_index < NUM_MARKETS)?;
+        const NUM_FIXED: usize = 8;
+        let accounts = array_ref![accounts, 0, NUM_FIXED];
+        let [
+            mango_group_acc,
+            owner_acc,  // signer
+            margin_account_acc,
+            dex_prog_acc,
+            spot_market_acc,
+            open_orders_acc,
+            signer_acc,
+            rent_acc,
+        ] = accounts;
+
+        let mango_group = MangoGroup::load_checked(mango_group_acc, program_id)?;
+        let mut margin_account = MarginAccount::load_mut_checked(
+            program_id,
+            margin_account_acc,
+            mango_group_acc.key
+        )?;
+
+        check!(owner_acc.is_signer, MangoErrorCode::SignerNecessary)?;
+        check_eq!(owner_acc.key, &margin_account.owner, MangoErrorCode::InvalidMarginAccountOwner)?;
+        check_eq!(spot_market_acc.key, &mango_group.spot_markets[market_index], MangoErrorCode::InvalidSpotMarket)?;
+        check_eq!(dex_prog_acc.key, &mango_group.dex_program_id, MangoErrorCode::InvalidProgramId)?;
+        check!(!margin_account.has_open_orders(market_index), MangoErrorCode::InvalidOpenOrdersAccount)?;
+        check_or_assign_open_orders(
+            &mut margin_account, market_index, open_orders_acc, spot_market_acc.key, &mango_group.signer_key
+        )?;
+
+        let signer_seeds = gen_signer_seeds(&mango_group.signer_nonce, mango_group_acc.key);
+        invoke_init_open_orders(
+            dex_prog_acc,
+            open_orders_acc,
+            signer_acc,
+            spot_market_acc,
+            rent_acc,
+            &[&signer_seeds]
+        )?;
+
+        check_open_orders(open_orders_acc, &mango_group.signer_key)
+    }
+
+    #[inline(never)]
+    fn close_open_orders(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        market_index: usize
+    ) -> MangoResult<()> {
+        check_default!(market_index < NUM_MARKETS)?;
+        const NUM_FIXED: usize = 7;
+        let accounts = array_ref![accounts, 0, NUM_FIXED];
+        let [
+            mango_group_acc,
+            owner_acc,  // signer
+            margin_account_acc,
+            dex_prog_acc,
+            spot_market_acc,
+            open_orders_acc,
+            signer_acc,
+        ] = accounts;
+
+        let mango_group = MangoGroup::load_checked(mango_group_acc, program_id)?;
+        let mut margin_account = MarginAccount::load_mut_checked(
+            program_id,
+            margin_account_acc,
+            mango_group_acc.key
+        )?;
+
+        check!(owner_acc.is_signer, MangoErrorCode::SignerNecessary)?;
+        check_eq!(owner_acc.key, &margin_account.owner, MangoErrorCode::InvalidMarginAccountOwner)?;
+        check_eq!(spot_market_acc.key, &mango_group.spot_markets[market_index], MangoErrorCode::InvalidSpotMarket)?;
+        check!(margin_account.has_open_orders(market_index), MangoErrorCode::InvalidOpenOrdersAccount)?;
+        check_eq!(&margin_account.open_orders[market_index], open_orders_acc.key, MangoErrorCode::InvalidOpenOrdersAccount)?;
+        check_eq!(dex_prog_acc.key, &mango_group.dex_program_id, MangoErrorCode::InvalidProgramId)?;
+        check_open_orders_empty(open_orders_acc)?;
+
+        // the rent of the open orders account goes back to the owner
+        let signer_seeds = gen_signer_seeds(&mango_group.signer_nonce, mango_group_acc.key);
+        invoke_close_open_orders(
+            dex_prog_acc,
+            open_orders_acc,
+            signer_acc,
+            owner_acc,
+            spot_market_acc,
+            &[&signer_seeds]
+        )?;
+
+        margin_account.clear_open_orders(market_index);
+        Ok(())
+    }
+
+    #[inline(never)]
+    fn cancel_order(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        data: Vec<u8>
+    ) -> MangoResult<()> {
+        const NUM_FIXED: usize = 11;
+        let accounts = array_ref![accounts, 0, NUM_FIXED];
+
+        let [
+            mango_group_acc,
+            owner_acc,  // signer
+            margin_account_acc,
+            clock_acc,
+            dex_prog_acc,
+            spot_market_acc,
+            bids_acc,
+            asks_acc,
+            open_orders_acc,
+            signer_acc,
+            dex_event_queue_acc,
+        ] = accounts;
+
+        let mut mango_group = MangoGroup::load_mut_checked(mango_group_acc, program_id)?;
+        let mut margin_account = MarginAccount::load_mut_checked(
+            program_id,
+            margin_account_acc,
+            mango_group_acc.key
+        )?;
+        let clock = Clock::from_account_info(clock_acc)?;
+        mango_group.update_indexes(&clock)?;
+        check_eq!(dex_prog_acc.key, &mango_group.dex_program_id, MangoErrorCode::InvalidProgramId)?;
+
+        check!(owner_acc.is_signer, MangoErrorCode::SignerNecessary)?;
+        check_eq!(&margin_account.owner, owner_acc.key, MangoErrorCode::InvalidMarginAccountOwner)?;
+        let market_i = mango_group.get_market_index(spot_market_acc.key)
+            .ok_or(throw!(MangoErrorCode::InvalidSpotMarket))?;
+        check_eq!(&margin_account.open_orders[market_i], open_orders_acc.key, MangoErrorCode::InvalidOpenOrdersAccount)?;
+
+        margin_account.update_num_open_orders(market_i, open_orders_acc)?;
+        let pre_num_open_orders = margin_account.num_open_orders[market_i];
 
-        check_default!(mango_group.has_valid_deposits_borrows(token_i))?;
+        let signer_seeds = gen_signer_seeds(&mango_group.signer_nonce, mango_group_acc.key);
+        invoke_cancel_order(
+            dex_prog_acc,
+            spot_market_acc,
+            bids_acc,
+            asks_acc,
+            open_orders_acc,
+            signer_acc,
+            dex_event_queue_acc,
+            data,
+            &[&signer_seeds]
+        )?;
+        margin_account.update_num_open_orders(market_i, open_orders_acc)?;
+        debug_assert!(margin_account.num_open_orders[market_i] <= pre_num_open_orders);
         Ok(())
     }
 
+    /// Same as cancel_order, then settles all free funds of the open orders account, including
+    /// those the cancel freed, into the deposits of the MarginAccount
     #[inline(never)]
-    fn settle_funds(
+    fn cancel_order_and_settle(
         program_id: &Pubkey,
-        accounts: &[AccountInfo]
+        accounts: &[AccountInfo],
+        data: Vec<u8>
     ) -> MangoResult<()> {
-        const NUM_FIXED: usize = 14;
+        const NUM_FIXED: usize = 17;
         let accounts = array_ref![accounts, 0, NUM_FIXED];
+
         let [
             mango_group_acc,
             owner_acc,  // signer
             margin_account_acc,
             clock_acc,
-
             dex_prog_acc,
             spot_market_acc,
+            bids_acc,
+            asks_acc,
             open_orders_acc,
             signer_acc,
+            dex_event_queue_acc,
             dex_base_acc,
             dex_quote_acc,
             base_vault_acc,
@@ -881,20 +2771,34 @@ impl Processor {
         )?;
         let clock = Clock::from_account_info(clock_acc)?;
         mango_group.update_indexes(&clock)?;
+        check_eq!(dex_prog_acc.key, &mango_group.dex_program_id, MangoErrorCode::InvalidProgramId)?;
+        check_eq!(token_prog_acc.key, &spl_token::id(), MangoErrorCode::InvalidProgramId)?;
 
-        let market_i = mango_group.get_market_index(spot_market_acc.key).unwrap();
+        check!(owner_acc.is_signer, MangoErrorCode::SignerNecessary)?;
+        check_eq!(&margin_account.owner, owner_acc.key, MangoErrorCode::InvalidMarginAccountOwner)?;
+        let market_i = mango_group.get_market_index(spot_market_acc.key)
+            .ok_or(throw!(MangoErrorCode::InvalidSpotMarket))?;
+        check_eq!(&margin_account.open_orders[market_i], open_orders_acc.key, MangoErrorCode::InvalidOpenOrdersAccount)?;
+        check_eq!(base_vault_acc.key, &mango_group.vaults[market_i], MangoErrorCode::InvalidMangoVault)?;
+        check_eq!(quote_vault_acc.key, &mango_group.vaults[QUOTE_INDEX], MangoErrorCode::InvalidMangoVault)?;
 
-        check_default!(owner_acc.is_signer)?;
-        check_eq_default!(owner_acc.key, &margin_account.owner)?;
-        check_eq_default!(&margin_account.open_orders[market_i], open_orders_acc.key)?;
-        check_eq_default!(base_vault_acc.key, &mango_group.vaults[market_i])?;
-        check_eq_default!(quote_vault_acc.key, &mango_group.vaults[NUM_MARKETS])?;
-        check_eq_default!(token_prog_acc.key, &spl_token::id())?;
-        check_eq_default!(dex_prog_acc.key, &mango_group.dex_program_id)?;
+        margin_account.update_num_open_orders(market_i, open_orders_acc)?;
+        let pre_num_open_orders = margin_account.num_open_orders[market_i];
 
-        if *open_orders_acc.key == Pubkey::default() {
-            return Ok(());
-        }
+        let signer_seeds = gen_signer_seeds(&mango_group.signer_nonce, mango_group_acc.key);
+        invoke_cancel_order(
+            dex_prog_acc,
+            spot_market_acc,
+            bids_acc,
+            asks_acc,
+            open_orders_acc,
+            signer_acc,
+            dex_event_queue_acc,
+            data,
+            &[&signer_seeds]
+        )?;
+        margin_account.update_num_open_orders(market_i, open_orders_acc)?;
+        debug_assert!(margin_account.num_open_orders[market_i] <= pre_num_open_orders);
 
         let (pre_base, pre_quote) = {
             let open_orders = load_open_orders(open_orders_acc)?;
@@ -905,7 +2809,6 @@ impl Processor {
             return Ok(());
         }
 
-        let signer_seeds = gen_signer_seeds(&mango_group.signer_nonce, mango_group_acc.key);
         invoke_settle_funds(
             dex_prog_acc,
             spot_market_acc,
@@ -925,22 +2828,17 @@ impl Processor {
             (open_orders.native_coin_free, open_orders.native_pc_free)
         };
 
-        check_default!(post_base <= pre_base)?;
-        check_default!(post_quote <= pre_quote)?;
-
-        let base_change = U64F64::from_num(pre_base - post_base) / mango_group.indexes[market_i].deposit;
-        let quote_change = U64F64::from_num(pre_quote - post_quote) / mango_group.indexes[NUM_MARKETS].deposit;
-
-        checked_add_deposit(&mut mango_group, &mut margin_account, market_i, base_change)?;
-        checked_add_deposit(&mut mango_group, &mut margin_account, NUM_MARKETS, quote_change)?;
-        Ok(())
+        credit_settled_funds(
+            &mut mango_group, &mut margin_account, market_i, (pre_base, pre_quote), (post_base, post_quote)
+        )
     }
 
     #[inline(never)]
-    fn cancel_order(
+    fn cancel_all_my_orders(
         program_id: &Pubkey,
         accounts: &[AccountInfo],
-        data: Vec<u8>
+        market_index: usize,
+        limit: u8
     ) -> MangoResult<()> {
         const NUM_FIXED: usize = 11;
         let accounts = array_ref![accounts, 0, NUM_FIXED];
@@ -960,32 +2858,60 @@ impl Processor {
         ] = accounts;
 
         let mut mango_group = MangoGroup::load_mut_checked(mango_group_acc, program_id)?;
-        let margin_account = MarginAccount::load_checked(
+        let mut margin_account = MarginAccount::load_mut_checked(
             program_id,
             margin_account_acc,
             mango_group_acc.key
         )?;
         let clock = Clock::from_account_info(clock_acc)?;
         mango_group.update_indexes(&clock)?;
-        check_eq_default!(dex_prog_acc.key, &mango_group.dex_program_id)?;
 
-        check_default!(owner_acc.is_signer)?;
-        check_eq_default!(&margin_account.owner, owner_acc.key)?;
-        let market_i = mango_group.get_market_index(spot_market_acc.key).unwrap();
-        check_eq_default!(&margin_account.open_orders[market_i], open_orders_acc.key)?;
+        check!(owner_acc.is_signer, MangoErrorCode::SignerNecessary)?;
+        check_eq!(&margin_account.owner, owner_acc.key, MangoErrorCode::InvalidMarginAccountOwner)?;
+        check_eq!(dex_prog_acc.key, &mango_group.dex_program_id, MangoErrorCode::InvalidProgramId)?;
+        check_eq!(signer_acc.key, &mango_group.signer_key, MangoErrorCode::InvalidSignerKey)?;
+
+        check_default!(market_index < NUM_MARKETS)?;
+        check_eq_default!(spot_market_acc.key, &mango_group.spot_markets[market_index])?;
+        check!(*open_orders_acc.key != Pubkey::default(), MangoErrorCode::InvalidOpenOrdersAccount)?;
+        check_eq!(&margin_account.open_orders[market_index], open_orders_acc.key, MangoErrorCode::InvalidOpenOrdersAccount)?;
 
         let signer_seeds = gen_signer_seeds(&mango_group.signer_nonce, mango_group_acc.key);
-        invoke_cancel_order(
+        invoke_cancel_orders(open_orders_acc, dex_prog_acc, spot_market_acc, bids_acc, asks_acc, signer_acc,
+                             dex_event_queue_acc, &[&signer_seeds], limit)?;
+        margin_account.update_num_open_orders(market_index, open_orders_acc)?;
+        Ok(())
+    }
+
+    #[inline(never)]
+    fn consume_events(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        limit: u16
+    ) -> MangoResult<()> {
+        const NUM_FIXED: usize = 6;
+        check_default!(accounts.len() > NUM_FIXED)?;
+        let (fixed_accs, open_orders_accs) = accounts.split_at(NUM_FIXED);
+        let fixed_accs = array_ref![fixed_accs, 0, NUM_FIXED];
+        let [
+            mango_group_acc,
             dex_prog_acc,
             spot_market_acc,
-            bids_acc,
-            asks_acc,
-            open_orders_acc,
-            signer_acc,
             dex_event_queue_acc,
-            data,
-            &[&signer_seeds]
-        )?;
+            coin_fee_receivable_acc,
+            pc_fee_receivable_acc,
+        ] = fixed_accs;
+
+        let mango_group = MangoGroup::load_checked(mango_group_acc, program_id)?;
+        check_eq!(dex_prog_acc.key, &mango_group.dex_program_id, MangoErrorCode::InvalidProgramId)?;
+        check!(mango_group.get_market_index(spot_market_acc.key).is_some(), MangoErrorCode::InvalidSpotMarket)?;
+        for open_orders_acc in open_orders_accs.iter() {
+            check!(*open_orders_acc.key != Pubkey::default(), MangoErrorCode::InvalidOpenOrdersAccount)?;
+            check_open_orders(open_orders_acc, &mango_group.signer_key)?;
+        }
+
+        invoke_consume_events(dex_prog_acc, open_orders_accs, spot_market_acc, dex_event_queue_acc,
+                              coin_fee_receivable_acc, pc_fee_receivable_acc, limit)?;
         Ok(())
     }
 
@@ -993,9 +2919,11 @@ impl Processor {
     fn place_and_settle(
         program_id: &Pubkey,
         accounts: &[AccountInfo],
-        order: serum_dex::instruction::NewOrderInstructionV3
+        order: serum_dex::instruction::NewOrderInstructionV3,
+        worst_fill: u64
     ) -> MangoResult<()> {
         const NUM_FIXED: usize = 19;
+        let (accounts, secondary_oracle_accs) = accounts.split_at(NUM_FIXED + 2 * NUM_MARKETS);
         let accounts = array_ref![accounts, 0, NUM_FIXED + 2 * NUM_MARKETS];
         let (
             fixed_accs,
@@ -1026,15 +2954,17 @@ impl Processor {
         ] = fixed_accs;
 
         let mut mango_group = MangoGroup::load_mut_checked(mango_group_acc, program_id)?;
+        check!(!mango_group.is_halted, MangoErrorCode::GroupHalted)?;
         let mut margin_account = MarginAccount::load_mut_checked(
             program_id, margin_account_acc, mango_group_acc.key
         )?;
 
         let clock = Clock::from_account_info(clock_acc)?;
+        let now = clock.unix_timestamp as u64;
         mango_group.update_indexes(&clock)?;
 
-        let prices = get_prices(&mango_group, oracle_accs)?;
-        let coll_ratio = margin_account.get_collateral_ratio(&mango_group, &prices, open_orders_accs)?;
+        let prices = get_prices(&mut mango_group, oracle_accs, secondary_oracle_accs, &clock)?;
+        let coll_ratio = margin_account.get_collateral_ratio(&mango_group, &prices, open_orders_accs, now)?;
 
         if margin_account.being_liquidated {
             if coll_ratio >= mango_group.init_coll_ratio {
@@ -1044,47 +2974,55 @@ impl Processor {
             }
         }
 
-        let reduce_only = coll_ratio < mango_group.init_coll_ratio;
-
-        check_default!(owner_acc.is_signer)?;
-        check_eq_default!(&margin_account.owner, owner_acc.key)?;
+        check!(owner_acc.is_signer, MangoErrorCode::SignerNecessary)?;
+        check_eq!(&margin_account.owner, owner_acc.key, MangoErrorCode::InvalidMarginAccountOwner)?;
 
-        let market_i = mango_group.get_market_index(spot_market_acc.key).unwrap();
+        let market_i = mango_group.get_market_index(spot_market_acc.key)
+            .ok_or(throw!(MangoErrorCode::InvalidSpotMarket))?;
+        check_eq!(dex_prog_acc.key, &mango_group.dex_program_id, MangoErrorCode::InvalidProgramId)?;
+        let coin_lot_size = load_market_state_checked(spot_market_acc, dex_prog_acc.key)?.coin_lot_size;
+        // the dex takes the fee tier from this account, which get_group_fee_tier mirrors
+        check_eq!(srm_vault_acc.key, &mango_group.srm_vault, MangoErrorCode::InvalidMangoVault)?;
+        check_min_order_notional(&mango_group, &prices, market_i, order.max_coin_qty.get(), coin_lot_size)?;
         let side = order.side;
+        check_open_interest(&mango_group, &margin_account, &prices, market_i, side, order.max_coin_qty.get(), coin_lot_size)?;
+        let market_reduce_only = mango_group.is_reduce_only(market_i);
+        check!(
+            !market_reduce_only || margin_account.is_reducing_order(market_i, side),
+            MangoErrorCode::ReduceOnlyMarket
+        )?;
+        let reduce_only = market_reduce_only || coll_ratio < mango_group.init_coll_ratio;
+
         let (in_token_i, out_token_i, vault_acc) = match side {
-            Side::Bid => (market_i, NUM_MARKETS, quote_vault_acc),
-            Side::Ask => (NUM_MARKETS, market_i, base_vault_acc)
+            Side::Bid => (market_i, QUOTE_INDEX, quote_vault_acc),
+            Side::Ask => (QUOTE_INDEX, market_i, base_vault_acc)
         };
-        check_eq_default!(&mango_group.vaults[market_i], base_vault_acc.key)?;
-        check_eq_default!(&mango_group.vaults[NUM_MARKETS], quote_vault_acc.key)?;
+        check_eq!(&mango_group.vaults[market_i], base_vault_acc.key, MangoErrorCode::InvalidMangoVault)?;
+        check_eq!(&mango_group.vaults[QUOTE_INDEX], quote_vault_acc.key, MangoErrorCode::InvalidMangoVault)?;
 
         let (pre_base, pre_quote) = {
             (Account::unpack(&base_vault_acc.try_borrow_data()?)?.amount,
              Account::unpack(&quote_vault_acc.try_borrow_data()?)?.amount)
         };
 
+        check_open_orders_unique(open_orders_accs)?;
         for i in 0..NUM_MARKETS {
             let open_orders_acc = &open_orders_accs[i];
             if i == market_i {  // this one must not be default pubkey
-                check_default!(*open_orders_acc.key != Pubkey::default())?;
-
-                // if this is first time using this open_orders_acc, check and save it
-                if margin_account.open_orders[i] == Pubkey::default() {
-                    let open_orders = load_open_orders(open_orders_acc)?;
-                    check_eq_default!(open_orders.account_flags, 0)?;
-                    margin_account.open_orders[i] = *open_orders_acc.key;
-                } else {
-                    check_eq_default!(open_orders_accs[i].key, &margin_account.open_orders[i])?;
-                    check_open_orders(&open_orders_accs[i], &mango_group.signer_key)?;
-                }
+                check_or_assign_open_orders(
+                    &mut margin_account, i, open_orders_acc, spot_market_acc.key, &mango_group.signer_key
+                )?;
             } else {
-                check_eq_default!(open_orders_accs[i].key, &margin_account.open_orders[i])?;
+                check_eq!(open_orders_accs[i].key, &margin_account.open_orders[i], MangoErrorCode::InvalidOpenOrdersAccount)?;
                 check_open_orders(&open_orders_accs[i], &mango_group.signer_key)?;
             }
         }
 
-        check_eq_default!(token_prog_acc.key, &spl_token::id())?;
-        check_eq_default!(dex_prog_acc.key, &mango_group.dex_program_id)?;
+        margin_account.update_num_open_orders(market_i, &open_orders_accs[market_i])?;
+        let pre_num_open_orders = margin_account.num_open_orders[market_i];
+        check_max_orders_per_market(&mango_group, &margin_account, market_i)?;
+
+        check_eq!(token_prog_acc.key, &spl_token::id(), MangoErrorCode::InvalidProgramId)?;
         let data = serum_dex::instruction::MarketInstruction::NewOrderV3(order).pack();
         let instruction = Instruction {
             program_id: *dex_prog_acc.key,
@@ -1124,6 +3062,8 @@ impl Processor {
 
         let signer_seeds = gen_signer_seeds(&mango_group.signer_nonce, mango_group_acc.key);
         solana_program::program::invoke_signed(&instruction, &account_infos, &[&signer_seeds])?;
+        margin_account.update_num_open_orders(market_i, &open_orders_accs[market_i])?;
+        debug_assert!(margin_account.num_open_orders[market_i] <= pre_num_open_orders + 1);
 
         // Settle funds for this market
         invoke_settle_funds(
@@ -1144,61 +3084,257 @@ impl Processor {
             (Account::unpack(&base_vault_acc.try_borrow_data()?)?.amount,
              Account::unpack(&quote_vault_acc.try_borrow_data()?)?.amount)
         };
+        check_worst_fill(side, pre_quote, post_quote, worst_fill)?;
 
         let (pre_in, pre_out, post_in, post_out) = match side {
             Side::Bid => (pre_base, pre_quote, post_base, post_quote),
             Side::Ask => (pre_quote, pre_base, post_quote, post_base)
         };
+        let borrowed = credit_settled_fill(
+            &mut mango_group, &mut margin_account, in_token_i, out_token_i, pre_in, post_in, pre_out,
+            post_out, reduce_only, now
+        )?;
 
-        // It's possible the net change was positive for both tokens
-        // It's not possible for in_token to be negative
-        let out_index: MangoIndex = mango_group.indexes[out_token_i];
-        let in_index: MangoIndex = mango_group.indexes[in_token_i];
+        let coll_ratio = check_post_trade_health(
+            &mango_group, &margin_account, &prices, open_orders_accs, reduce_only, borrowed,
+            cfg!(feature = "fill-log"), now
+        )?;
+        check_default!(mango_group.has_valid_deposits_borrows(out_token_i)?)?;
 
-        // if out token was net negative, then you may need to borrow more
-        if post_out < pre_out {
-            let total_out = pre_out.checked_sub(post_out).unwrap();
-            let native_deposit = margin_account.get_native_deposit(&out_index, out_token_i);
-            if native_deposit < total_out {  // need to borrow
-                let avail_deposit = margin_account.deposits[out_token_i];
-                checked_sub_deposit(&mut mango_group, &mut margin_account, out_token_i, avail_deposit)?;
-                let rem_spend = U64F64::from_num(total_out - native_deposit);
+        #[cfg(feature = "fill-log")]
+        msg!("{}", format_fill_log(
+            market_i, side, in_token_i, post_in - pre_in, out_token_i,
+            pre_out as i128 - post_out as i128, borrowed, coll_ratio.ok_or(throw!())?
+        ));
+        #[cfg(not(feature = "fill-log"))]
+        let _ = coll_ratio;
 
-                check_default!(!reduce_only)?;  // Cannot borrow more in reduce only mode
-                checked_add_borrow(&mut mango_group, &mut margin_account, out_token_i, rem_spend / out_index.borrow)?;
-            } else {  // just spend user deposits
-                let mango_spent = U64F64::from_num(total_out) / out_index.deposit;
-                checked_sub_deposit(&mut mango_group, &mut margin_account, out_token_i, mango_spent)?;
+        Ok(())
+    }
+
+    /// Anyone is allowed to cancel orders of an account that is below maint_coll_ratio, or that is
+    /// already being liquidated and still below init_coll_ratio. An account being liquidated that
+    /// is back at init_coll_ratio has its being_liquidated flag cleared instead and nothing is
+    /// cancelled. This will also settle funds
+    #[inline(never)]
+    fn force_cancel_orders(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        market_index: usize,
+        limit: u8
+    ) -> MangoResult<()> {
+        const NUM_FIXED: usize = 16;
+        let (accounts, secondary_oracle_accs) = accounts.split_at(NUM_FIXED + 2 * NUM_MARKETS);
+        let accounts = array_ref![accounts, 0, NUM_FIXED + 2 * NUM_MARKETS];
+        let (
+            fixed_accs,
+            open_orders_accs,
+            oracle_accs,
+        ) = array_refs![accounts, NUM_FIXED, NUM_MARKETS, NUM_MARKETS];
+
+        let [
+            mango_group_acc,
+            liqor_acc,
+            liqee_margin_account_acc,
+            base_vault_acc,
+            quote_vault_acc,
+            spot_market_acc,
+            bids_acc,
+            asks_acc,
+            signer_acc,
+            dex_event_queue_acc,
+            dex_base_acc,
+            dex_quote_acc,
+            dex_signer_acc,
+            token_prog_acc,
+            dex_prog_acc,
+            clock_acc
+        ] = fixed_accs;
+
+        check_eq!(token_prog_acc.key, &spl_token::id(), MangoErrorCode::InvalidProgramId)?;
+        check!(liqor_acc.is_signer, MangoErrorCode::SignerNecessary)?;
+        check!(liqor_acc.key != liqee_margin_account_acc.key, MangoErrorCode::DuplicateAccounts)?;
+        let mut mango_group = MangoGroup::load_mut_checked(
+            mango_group_acc, program_id
+        )?;
+        check_eq!(dex_prog_acc.key, &mango_group.dex_program_id, MangoErrorCode::InvalidProgramId)?;
+        check_eq!(signer_acc.key, &mango_group.signer_key, MangoErrorCode::InvalidSignerKey)?;
+
+        check_default!(market_index < NUM_MARKETS)?;
+        let market_i = market_index;
+        check_eq_default!(spot_market_acc.key, &mango_group.spot_markets[market_i])?;
+        check_eq!(&mango_group.vaults[market_i], base_vault_acc.key, MangoErrorCode::InvalidMangoVault)?;
+        check_eq!(&mango_group.vaults[QUOTE_INDEX], quote_vault_acc.key, MangoErrorCode::InvalidMangoVault)?;
+
+        let mut liqee_margin_account = MarginAccount::load_mut_checked(
+            program_id, liqee_margin_account_acc, mango_group_acc.key
+        )?;
+
+        check_open_orders_unique(open_orders_accs)?;
+        for i in 0..NUM_MARKETS {
+            check_eq!(open_orders_accs[i].key, &liqee_margin_account.open_orders[i],
+                MangoErrorCode::InvalidOpenOrdersAccount)?;
+            check_open_orders(&open_orders_accs[i], &mango_group.signer_key)?;
+        }
+
+        let clock = Clock::from_account_info(clock_acc)?;
+        mango_group.update_indexes(&clock)?;
+        let prices = get_prices(&mut mango_group, oracle_accs, secondary_oracle_accs, &clock)?;
+        let coll_ratio = liqee_margin_account.get_collateral_ratio(
+            &mango_group, &prices, open_orders_accs, clock.unix_timestamp as u64)?;
+
+        // Only allow force cancels on accounts already being liquidated and below init or accounts below maint
+        if liqee_margin_account.being_liquidated {
+            if coll_ratio >= mango_group.init_coll_ratio {
+                liqee_margin_account.being_liquidated = false;
+                return Ok(());
             }
-        } else {  // Add out token deposit
-            let deposit = U64F64::from_num(post_out.checked_sub(pre_out).unwrap()) / out_index.deposit;
-            checked_add_deposit(&mut mango_group, &mut margin_account, out_token_i, deposit)?;
+        } else if coll_ratio < mango_group.maint_coll_ratio {
+            liqee_margin_account.being_liquidated = true;
+        } else {
+            throw_err!(MangoErrorCode::NotLiquidatable)?;
+        }
+
+        let open_orders_acc = &open_orders_accs[market_i];
+        if !liqee_margin_account.has_open_orders(market_i) {  // nothing to cancel in this market
+            return Ok(());
         }
+        let signers_seeds = gen_signer_seeds(&mango_group.signer_nonce, mango_group_acc.key);
 
-        let total_in = U64F64::from_num(post_in.checked_sub(pre_in).unwrap()) / in_index.deposit;
-        checked_add_deposit(&mut mango_group, &mut margin_account, in_token_i, total_in)?;
+        invoke_cancel_orders(open_orders_acc, dex_prog_acc, spot_market_acc, bids_acc, asks_acc, signer_acc,
+                             dex_event_queue_acc, &[&signers_seeds], limit)?;
+        liqee_margin_account.update_num_open_orders(market_i, open_orders_acc)?;
 
-        // Settle borrow
-        // TODO only do ops on tokens that have borrows and deposits
-        settle_borrow_full_unchecked(&mut mango_group, &mut margin_account, out_token_i)?;
-        settle_borrow_full_unchecked(&mut mango_group, &mut margin_account, in_token_i)?;
+        let (pre_base, pre_quote) = {
+            let open_orders = load_open_orders(open_orders_acc)?;
+            (open_orders.native_coin_free, open_orders.native_pc_free)
+        };
 
-        let coll_ratio = margin_account.get_collateral_ratio(&mango_group, &prices, open_orders_accs)?;
-        check!(reduce_only || coll_ratio >= mango_group.init_coll_ratio, MangoErrorCode::CollateralRatioLimit)?;
-        check_default!(mango_group.has_valid_deposits_borrows(out_token_i))?;
+        if pre_base == 0 && pre_quote == 0 {
+            return Ok(());
+        }
 
-        Ok(())
+        invoke_settle_funds(dex_prog_acc, spot_market_acc, open_orders_acc, signer_acc, dex_base_acc,
+                            dex_quote_acc, base_vault_acc, quote_vault_acc, dex_signer_acc,
+                            token_prog_acc, &[&signers_seeds])?;
+
+        let (post_base, post_quote) = {
+            let open_orders = load_open_orders(open_orders_acc)?;
+            (open_orders.native_coin_free, open_orders.native_pc_free)
+        };
+
+        credit_settled_funds(
+            &mut mango_group, &mut liqee_margin_account, market_i, (pre_base, pre_quote), (post_base, post_quote)
+        )
     }
 
-    /// Liquidator is allowed to cancel orders of an account that is being_liquidated
-    /// This will also settle funds
+    /// Settle the free funds of an account below maint_coll_ratio on the dex, leaving its orders
+    /// in place. Funds freed by fills sit on the open orders account until the owner settles them;
+    /// this lets a liquidator pull them into the vaults and the account's deposits first
+    #[inline(never)]
+    fn force_settle_funds(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        market_index: usize
+    ) -> MangoResult<()> {
+        const NUM_FIXED: usize = 13;
+        let (accounts, secondary_oracle_accs) = accounts.split_at(NUM_FIXED + 2 * NUM_MARKETS);
+        let accounts = array_ref![accounts, 0, NUM_FIXED + 2 * NUM_MARKETS];
+        let (
+            fixed_accs,
+            open_orders_accs,
+            oracle_accs,
+        ) = array_refs![accounts, NUM_FIXED, NUM_MARKETS, NUM_MARKETS];
+
+        let [
+            mango_group_acc,
+            liqor_acc,
+            liqee_margin_account_acc,
+            base_vault_acc,
+            quote_vault_acc,
+            spot_market_acc,
+            signer_acc,
+            dex_base_acc,
+            dex_quote_acc,
+            dex_signer_acc,
+            token_prog_acc,
+            dex_prog_acc,
+            clock_acc
+        ] = fixed_accs;
+
+        check_eq!(token_prog_acc.key, &spl_token::id(), MangoErrorCode::InvalidProgramId)?;
+        check!(liqor_acc.is_signer, MangoErrorCode::SignerNecessary)?;
+        let mut mango_group = MangoGroup::load_mut_checked(
+            mango_group_acc, program_id
+        )?;
+        check_eq!(dex_prog_acc.key, &mango_group.dex_program_id, MangoErrorCode::InvalidProgramId)?;
+        check_eq!(signer_acc.key, &mango_group.signer_key, MangoErrorCode::InvalidSignerKey)?;
+
+        check_default!(market_index < NUM_MARKETS)?;
+        let market_i = market_index;
+        check_eq!(spot_market_acc.key, &mango_group.spot_markets[market_i], MangoErrorCode::InvalidSpotMarket)?;
+        check_eq!(&mango_group.vaults[market_i], base_vault_acc.key, MangoErrorCode::InvalidMangoVault)?;
+        check_eq!(&mango_group.vaults[QUOTE_INDEX], quote_vault_acc.key, MangoErrorCode::InvalidMangoVault)?;
+
+        let mut liqee_margin_account = MarginAccount::load_mut_checked(
+            program_id, liqee_margin_account_acc, mango_group_acc.key
+        )?;
+
+        check_open_orders_unique(open_orders_accs)?;
+        for i in 0..NUM_MARKETS {
+            check_eq!(open_orders_accs[i].key, &liqee_margin_account.open_orders[i],
+                MangoErrorCode::InvalidOpenOrdersAccount)?;
+            check_open_orders(&open_orders_accs[i], &mango_group.signer_key)?;
+        }
+
+        let clock = Clock::from_account_info(clock_acc)?;
+        mango_group.update_indexes(&clock)?;
+        let prices = get_prices(&mut mango_group, oracle_accs, secondary_oracle_accs, &clock)?;
+        let coll_ratio = liqee_margin_account.get_collateral_ratio(
+            &mango_group, &prices, open_orders_accs, clock.unix_timestamp as u64)?;
+        check!(coll_ratio < mango_group.maint_coll_ratio, MangoErrorCode::NotLiquidatable)?;
+
+        let open_orders_acc = &open_orders_accs[market_i];
+        if !liqee_margin_account.has_open_orders(market_i) {  // nothing to settle in this market
+            return Ok(());
+        }
+
+        let (pre_base, pre_quote) = {
+            let open_orders = load_open_orders(open_orders_acc)?;
+            (open_orders.native_coin_free, open_orders.native_pc_free)
+        };
+
+        if pre_base == 0 && pre_quote == 0 {
+            return Ok(());
+        }
+
+        let signers_seeds = gen_signer_seeds(&mango_group.signer_nonce, mango_group_acc.key);
+        invoke_settle_funds(dex_prog_acc, spot_market_acc, open_orders_acc, signer_acc, dex_base_acc,
+                            dex_quote_acc, base_vault_acc, quote_vault_acc, dex_signer_acc,
+                            token_prog_acc, &[&signers_seeds])?;
+
+        let (post_base, post_quote) = {
+            let open_orders = load_open_orders(open_orders_acc)?;
+            (open_orders.native_coin_free, open_orders.native_pc_free)
+        };
+
+        credit_settled_funds(
+            &mut mango_group, &mut liqee_margin_account, market_i, (pre_base, pre_quote), (post_base, post_quote)
+        )
+    }
+    /// Unwind the position of an account below maint_coll_ratio in one market on the serum dex
+    /// when no liquidator takes it. Cancels its orders, settles funds and then sends an IOC order
+    /// with the mango signer that buys back borrowed base with quote or sells base to repay quote.
+    /// A thin book only fills part of the order; the instruction can then be sent again
     #[inline(never)]
-    fn force_cancel_orders(
+    fn force_liquidate_on_dex(
         program_id: &Pubkey,
         accounts: &[AccountInfo],
+        market_index: usize,
         limit: u8
     ) -> MangoResult<()> {
-        const NUM_FIXED: usize = 16;
+        const NUM_FIXED: usize = 19;
+        let (accounts, secondary_oracle_accs) = accounts.split_at(NUM_FIXED + 2 * NUM_MARKETS);
         let accounts = array_ref![accounts, 0, NUM_FIXED + 2 * NUM_MARKETS];
         let (
             fixed_accs,
@@ -1210,38 +3346,45 @@ impl Processor {
             mango_group_acc,
             liqor_acc,
             liqee_margin_account_acc,
-            base_vault_acc,
-            quote_vault_acc,
+            clock_acc,
+            dex_prog_acc,
             spot_market_acc,
+            dex_request_queue_acc,
+            dex_event_queue_acc,
             bids_acc,
             asks_acc,
+            base_vault_acc,
+            quote_vault_acc,
             signer_acc,
-            dex_event_queue_acc,
             dex_base_acc,
             dex_quote_acc,
-            dex_signer_acc,
             token_prog_acc,
-            dex_prog_acc,
-            clock_acc
+            rent_acc,
+            srm_vault_acc,
+            dex_signer_acc
         ] = fixed_accs;
 
         check_eq!(token_prog_acc.key, &spl_token::id(), MangoErrorCode::InvalidProgramId)?;
         check!(liqor_acc.is_signer, MangoErrorCode::SignerNecessary)?;
+        check!(liqor_acc.key != liqee_margin_account_acc.key, MangoErrorCode::DuplicateAccounts)?;
         let mut mango_group = MangoGroup::load_mut_checked(
             mango_group_acc, program_id
         )?;
         check_eq!(dex_prog_acc.key, &mango_group.dex_program_id, MangoErrorCode::InvalidProgramId)?;
         check_eq!(signer_acc.key, &mango_group.signer_key, MangoErrorCode::InvalidSignerKey)?;
+        check_eq!(srm_vault_acc.key, &mango_group.srm_vault, MangoErrorCode::InvalidMangoVault)?;
 
-        let market_i = mango_group.get_market_index(spot_market_acc.key).unwrap();
-        check_eq!(&mango_group.vaults[market_i], base_vault_acc.key, MangoErrorCode::InvalidMangoVault)?;
-        check_eq!(&mango_group.vaults[NUM_MARKETS], quote_vault_acc.key, MangoErrorCode::InvalidMangoVault)?;
+        check_default!(market_index < NUM_MARKETS)?;
+        let market_i = market_index;
         check_eq_default!(spot_market_acc.key, &mango_group.spot_markets[market_i])?;
+        check_eq!(&mango_group.vaults[market_i], base_vault_acc.key, MangoErrorCode::InvalidMangoVault)?;
+        check_eq!(&mango_group.vaults[QUOTE_INDEX], quote_vault_acc.key, MangoErrorCode::InvalidMangoVault)?;
 
         let mut liqee_margin_account = MarginAccount::load_mut_checked(
             program_id, liqee_margin_account_acc, mango_group_acc.key
         )?;
 
+        check_open_orders_unique(open_orders_accs)?;
         for i in 0..NUM_MARKETS {
             check_eq!(open_orders_accs[i].key, &liqee_margin_account.open_orders[i],
                 MangoErrorCode::InvalidOpenOrdersAccount)?;
@@ -1249,57 +3392,123 @@ impl Processor {
         }
 
         let clock = Clock::from_account_info(clock_acc)?;
+        let now = clock.unix_timestamp as u64;
         mango_group.update_indexes(&clock)?;
-        let prices = get_prices(&mango_group, oracle_accs)?;
+        let prices = get_prices(&mut mango_group, oracle_accs, secondary_oracle_accs, &clock)?;
         let coll_ratio = liqee_margin_account.get_collateral_ratio(
-            &mango_group, &prices, open_orders_accs)?;
+            &mango_group, &prices, open_orders_accs, now)?;
 
-        // Only allow liquidations on accounts already being liquidated and below init or accounts below maint
+        // Only allow on accounts already being liquidated and below init or accounts below maint
         if liqee_margin_account.being_liquidated {
             if coll_ratio >= mango_group.init_coll_ratio {
                 liqee_margin_account.being_liquidated = false;
                 return Ok(());
             }
-        } else if coll_ratio < mango_group.maint_coll_ratio {
-            liqee_margin_account.being_liquidated = true;
-        } else {
+        } else if coll_ratio >= mango_group.maint_coll_ratio {
             throw_err!(MangoErrorCode::NotLiquidatable)?;
         }
+        liqee_margin_account.being_liquidated = true;
+
         let open_orders_acc = &open_orders_accs[market_i];
-        let signers_seeds = gen_signer_seeds(&mango_group.signer_nonce, mango_group_acc.key);
+        check!(liqee_margin_account.has_open_orders(market_i), MangoErrorCode::InvalidOpenOrdersAccount)?;
+        let signer_nonce = mango_group.signer_nonce;
+        let signers_seeds = gen_signer_seeds(&signer_nonce, mango_group_acc.key);
 
+        // Free up any funds locked in orders of this market
         invoke_cancel_orders(open_orders_acc, dex_prog_acc, spot_market_acc, bids_acc, asks_acc, signer_acc,
                              dex_event_queue_acc, &[&signers_seeds], limit)?;
-
+        liqee_margin_account.update_num_open_orders(market_i, open_orders_acc)?;
         let (pre_base, pre_quote) = {
-            let open_orders = load_open_orders(open_orders_acc)?;
-            (open_orders.native_coin_free, open_orders.native_pc_free)
+            (Account::unpack(&base_vault_acc.try_borrow_data()?)?.amount,
+             Account::unpack(&quote_vault_acc.try_borrow_data()?)?.amount)
+        };
+        invoke_settle_funds(dex_prog_acc, spot_market_acc, open_orders_acc, signer_acc, dex_base_acc,
+                            dex_quote_acc, base_vault_acc, quote_vault_acc, dex_signer_acc,
+                            token_prog_acc, &[&signers_seeds])?;
+        let (post_base, post_quote) = {
+            (Account::unpack(&base_vault_acc.try_borrow_data()?)?.amount,
+             Account::unpack(&quote_vault_acc.try_borrow_data()?)?.amount)
         };
+        let base_change = U64F64::from_num(post_base.checked_sub(pre_base).ok_or(throw!())?)
+            / mango_group.indexes[market_i].deposit;
+        let quote_change = U64F64::from_num(post_quote.checked_sub(pre_quote).ok_or(throw!())?)
+            / mango_group.indexes[QUOTE_INDEX].deposit;
+        checked_add_deposit(&mut mango_group, &mut liqee_margin_account, market_i, base_change)?;
+        checked_add_deposit(&mut mango_group, &mut liqee_margin_account, QUOTE_INDEX, quote_change)?;
+        settle_borrow_full_unchecked(&mut mango_group, &mut liqee_margin_account, market_i)?;
+        settle_borrow_full_unchecked(&mut mango_group, &mut liqee_margin_account, QUOTE_INDEX)?;
 
-        if pre_base == 0 && pre_quote == 0 {
-            return Ok(());
-        }
+        let (coin_lot_size, pc_lot_size) = {
+            let market = load_market_state_checked(spot_market_acc, dex_prog_acc.key)?;
+            (market.coin_lot_size, market.pc_lot_size)
+        };
+        let order = match get_force_liquidation_order(
+            &mango_group, &liqee_margin_account, &prices, market_i, coin_lot_size, pc_lot_size
+        )? {
+            None => { return Ok(()); }  // nothing left to unwind in this market
+            Some(order) => order
+        };
+
+        let side = order.side;
+        let (in_token_i, out_token_i, vault_acc) = match side {
+            Side::Bid => (market_i, QUOTE_INDEX, quote_vault_acc),
+            Side::Ask => (QUOTE_INDEX, market_i, base_vault_acc)
+        };
+        let (pre_base, pre_quote) = {
+            (Account::unpack(&base_vault_acc.try_borrow_data()?)?.amount,
+             Account::unpack(&quote_vault_acc.try_borrow_data()?)?.amount)
+        };
 
+        invoke_new_order(dex_prog_acc, spot_market_acc, open_orders_acc, dex_request_queue_acc,
+                         dex_event_queue_acc, bids_acc, asks_acc, vault_acc, signer_acc, dex_base_acc,
+                         dex_quote_acc, token_prog_acc, rent_acc, srm_vault_acc, order, &[&signers_seeds])?;
+        liqee_margin_account.update_num_open_orders(market_i, open_orders_acc)?;
         invoke_settle_funds(dex_prog_acc, spot_market_acc, open_orders_acc, signer_acc, dex_base_acc,
                             dex_quote_acc, base_vault_acc, quote_vault_acc, dex_signer_acc,
                             token_prog_acc, &[&signers_seeds])?;
 
         let (post_base, post_quote) = {
-            let open_orders = load_open_orders(open_orders_acc)?;
-            (open_orders.native_coin_free, open_orders.native_pc_free)
+            (Account::unpack(&base_vault_acc.try_borrow_data()?)?.amount,
+             Account::unpack(&quote_vault_acc.try_borrow_data()?)?.amount)
+        };
+        let (pre_in, pre_out, post_in, post_out) = match side {
+            Side::Bid => (pre_base, pre_quote, post_base, post_quote),
+            Side::Ask => (pre_quote, pre_base, post_quote, post_base)
         };
 
-        check_default!(post_base <= pre_base)?;
-        check_default!(post_quote <= pre_quote)?;
-
-        let base_change = U64F64::from_num(pre_base - post_base) / mango_group.indexes[market_i].deposit;
-        let quote_change = U64F64::from_num(pre_quote - post_quote) / mango_group.indexes[NUM_MARKETS].deposit;
+        let out_index: MangoIndex = mango_group.indexes[out_token_i];
+        let in_index: MangoIndex = mango_group.indexes[in_token_i];
+        if post_out < pre_out {
+            let total_out = pre_out - post_out;
+            let native_deposit = liqee_margin_account.get_native_deposit(&out_index, out_token_i);
+            if native_deposit < total_out {  // only possible through rounding since the order is capped
+                let avail_deposit = liqee_margin_account.deposits[out_token_i];
+                checked_sub_deposit(&mut mango_group, &mut liqee_margin_account, out_token_i, avail_deposit)?;
+                let rem_spend = U64F64::from_num(total_out - native_deposit);
+                checked_add_borrow(&mut mango_group, &mut liqee_margin_account, out_token_i, rem_spend / out_index.borrow)?;
+            } else {
+                let mango_spent = U64F64::from_num(total_out) / out_index.deposit;
+                checked_sub_deposit(&mut mango_group, &mut liqee_margin_account, out_token_i, mango_spent)?;
+            }
+        } else {
+            let deposit = U64F64::from_num(post_out - pre_out) / out_index.deposit;
+            checked_add_deposit(&mut mango_group, &mut liqee_margin_account, out_token_i, deposit)?;
+        }
+        let total_in = U64F64::from_num(post_in.checked_sub(pre_in).ok_or(throw!())?) / in_index.deposit;
+        checked_add_deposit(&mut mango_group, &mut liqee_margin_account, in_token_i, total_in)?;
 
-        checked_add_deposit(&mut mango_group, &mut liqee_margin_account, market_i, base_change)?;
-        checked_add_deposit(&mut mango_group, &mut liqee_margin_account, NUM_MARKETS, quote_change)?;
+        settle_borrow_full_unchecked(&mut mango_group, &mut liqee_margin_account, out_token_i)?;
+        settle_borrow_full_unchecked(&mut mango_group, &mut liqee_margin_account, in_token_i)?;
 
+        let coll_ratio = liqee_margin_account.get_collateral_ratio(&mango_group, &prices, open_orders_accs, now)?;
+        if coll_ratio >= mango_group.init_coll_ratio {
+            liqee_margin_account.being_liquidated = false;
+        }
+        msg!("Force liquidation details: {{ \"market_index\": {}, \"in\": {}, \"out\": {}, \"coll_ratio\": {} }}",
+             market_i, post_in - pre_in, pre_out as i128 - post_out as i128, coll_ratio);
         Ok(())
     }
+
     #[inline(never)]
     fn partial_liquidate(
         program_id: &Pubkey,
@@ -1309,6 +3518,7 @@ impl Processor {
 
         const NUM_FIXED: usize = 10;
         // TODO make it so canceling orders feature is optional if no orders outstanding to cancel
+        let (accounts, secondary_oracle_accs) = accounts.split_at(NUM_FIXED + 2 * NUM_MARKETS);
         let accounts = array_ref![accounts, 0, NUM_FIXED + 2 * NUM_MARKETS];
         let (
             fixed_accs,
@@ -1330,6 +3540,7 @@ impl Processor {
         ] = fixed_accs;
         check!(token_prog_acc.key == &spl_token::ID, MangoErrorCode::InvalidProgramId)?;
         check!(liqor_acc.is_signer, MangoErrorCode::SignerNecessary)?;
+        check!(liqor_acc.key != liqee_margin_account_acc.key, MangoErrorCode::DuplicateAccounts)?;
         let mut mango_group = MangoGroup::load_mut_checked(
             mango_group_acc, program_id
         )?;
@@ -1348,6 +3559,7 @@ impl Processor {
             program_id, liqee_margin_account_acc, mango_group_acc.key
         )?;
 
+        check_open_orders_unique(open_orders_accs)?;
         for i in 0..NUM_MARKETS {
             check_eq!(open_orders_accs[i].key, &liqee_margin_account.open_orders[i],
                 MangoErrorCode::InvalidOpenOrdersAccount)?;
@@ -1355,12 +3567,15 @@ impl Processor {
         }
 
         let clock = Clock::from_account_info(clock_acc)?;
+        let now = clock.unix_timestamp as u64;
         mango_group.update_indexes(&clock)?;
-        let prices = get_prices(&mango_group, oracle_accs)?;
-        let coll_ratio = liqee_margin_account.get_collateral_ratio(
-            &mango_group, &prices, open_orders_accs)?;
-        
-        let starting_assets = liqee_margin_account.get_total_assets(&mango_group, open_orders_accs).unwrap();
+        // Nothing below trades on the dex, so prices and open orders are read once and reused
+        let prices = get_prices(&mut mango_group, oracle_accs, secondary_oracle_accs, &clock)?;
+        let open_orders_totals = load_open_orders_totals(open_orders_accs)?;
+        let (_, _, coll_ratio) = liqee_margin_account.get_health_from_totals(
+            &mango_group, &prices, &open_orders_totals, now)?;
+
+        let starting_assets = liqee_margin_account.get_total_assets_from_totals(&mango_group, &open_orders_totals).unwrap();
         let starting_liabs = liqee_margin_account.get_total_liabs(&mango_group).unwrap();
         msg!("Liquidation details: {{ \"assets\": {:?}, \"liabs\": {:?}, \"prices\": {:?}, \"coll_ratio\": {}, \"unused\": {} }}", starting_assets, starting_liabs, prices, coll_ratio, 0);
 
@@ -1373,6 +3588,7 @@ impl Processor {
         } else if coll_ratio >= mango_group.maint_coll_ratio {
             throw_err!(MangoErrorCode::NotLiquidatable)?;
         }
+        check_liquidation_cooldown(&mango_group, &liqee_margin_account, coll_ratio, now)?;
 
         // Settle borrows to increase coll ratio if possible
         for i in 0..NUM_TOKENS {
@@ -1380,8 +3596,8 @@ impl Processor {
         }
 
         // Check again to see if account still liquidatable
-        let coll_ratio = liqee_margin_account.get_collateral_ratio(
-            &mango_group, &prices, open_orders_accs)?;
+        let (_, _, coll_ratio) = liqee_margin_account.get_health_from_totals(
+            &mango_group, &prices, &open_orders_totals, now)?;
 
         if liqee_margin_account.being_liquidated {
             if coll_ratio >= mango_group.init_coll_ratio {
@@ -1397,8 +3613,8 @@ impl Processor {
 
         // Get how much to deposit and how much to withdraw
         let (in_quantity, out_quantity) = get_in_out_quantities(
-            &mut mango_group, &mut liqee_margin_account, open_orders_accs, &prices, in_token_index,
-            out_token_index, max_deposit
+            &mut mango_group, &mut liqee_margin_account, &open_orders_totals, &prices, in_token_index,
+            out_token_index, max_deposit, now
         )?;
         let signer_nonce = mango_group.signer_nonce;
         let signers_seeds = gen_signer_seeds(&signer_nonce, mango_group_acc.key);
@@ -1406,16 +3622,16 @@ impl Processor {
                         &[&signers_seeds], in_quantity)?;
         invoke_transfer(token_prog_acc, out_vault_acc, liqor_out_token_acc, signer_acc,
                         &[&signers_seeds], out_quantity)?;
-
+        liqee_margin_account.last_liquidation_ts = now;
 
         // Check if account valid now
-        let coll_ratio = liqee_margin_account.get_collateral_ratio(&mango_group, &prices, open_orders_accs)?;
+        let (assets_val, _, coll_ratio) = liqee_margin_account.get_health_from_totals(
+            &mango_group, &prices, &open_orders_totals, now)?;
         if coll_ratio >= mango_group.init_coll_ratio {
             // set margin account to no longer being liquidated
             liqee_margin_account.being_liquidated = false;
         } else {
             // if all asset vals is dust (less than 1 cent?) socialize loss on lenders
-            let assets_val = liqee_margin_account.get_assets_val(&mango_group, &prices, open_orders_accs)?;
             if assets_val < DUST_THRESHOLD {
                 for i in 0..NUM_TOKENS {
                     let native_borrow = liqee_margin_account.borrows[i] * mango_group.indexes[i].borrow;
@@ -1480,7 +3696,8 @@ impl Processor {
                 deposit_quantities
             } => {
                 // Either user takes the position
-                // Or the program can liquidate on the serum dex (in case no liquidator wants to take pos)
+                // Or the program can liquidate on the serum dex with ForceLiquidateOnDex
+                // (in case no liquidator wants to take pos)
                 msg!("Mango: Liquidate");
                 Self::liquidate(program_id, accounts, deposit_quantities)?;
             }
@@ -1527,16 +3744,18 @@ impl Processor {
                 Self::change_borrow_limit(program_id, accounts, token_index, borrow_limit)?;
             }
             MangoInstruction::PlaceAndSettle {
-                order
+                order,
+                worst_fill
             } => {
                 msg!("Mango: PlaceAndSettle");
-                Self::place_and_settle(program_id, accounts, order)?;
+                Self::place_and_settle(program_id, accounts, order, worst_fill)?;
             }
             MangoInstruction::ForceCancelOrders {
+                market_index,
                 limit
             } => {
                 msg!("Mango: ForceCancelOrders");
-                Self::force_cancel_orders(program_id, accounts, limit)?;
+                Self::force_cancel_orders(program_id, accounts, market_index, limit)?;
             }
             MangoInstruction::PartialLiquidate {
                 max_deposit
@@ -1544,12 +3763,315 @@ impl Processor {
                 msg!("Mango: PartialLiquidate");
                 Self::partial_liquidate(program_id, accounts, max_deposit)?;
             }
+            MangoInstruction::ChangeReserveFactor {
+                reserve_factor
+            } => {
+                msg!("Mango: ChangeReserveFactor");
+                Self::change_reserve_factor(program_id, accounts, reserve_factor)?;
+            }
+            MangoInstruction::WithdrawFees => {
+                msg!("Mango: WithdrawFees");
+                Self::withdraw_fees(program_id, accounts)?;
+            }
+            MangoInstruction::UpgradeMarginAccount => {
+                msg!("Mango: UpgradeMarginAccount");
+                Self::upgrade_margin_account(program_id, accounts)?;
+            }
+            MangoInstruction::ChangeCollWeight {
+                token_index,
+                coll_weight
+            } => {
+                msg!("Mango: ChangeCollWeight");
+                Self::change_coll_weight(program_id, accounts, token_index, coll_weight)?;
+            }
+            MangoInstruction::ChangeLiabWeight {
+                token_index,
+                liab_weight
+            } => {
+                msg!("Mango: ChangeLiabWeight");
+                Self::change_liab_weight(program_id, accounts, token_index, liab_weight)?;
+            }
+            MangoInstruction::ChangeDepositLimit {
+                token_index,
+                deposit_limit
+            } => {
+                msg!("Mango: ChangeDepositLimit");
+                Self::change_deposit_limit(program_id, accounts, token_index, deposit_limit)?;
+            }
+            MangoInstruction::ChangeLiquidationFee {
+                liquidation_fee
+            } => {
+                msg!("Mango: ChangeLiquidationFee");
+                Self::change_liquidation_fee(program_id, accounts, liquidation_fee)?;
+            }
+            MangoInstruction::SetCollRatios {
+                maint_coll_ratio,
+                init_coll_ratio
+            } => {
+                msg!("Mango: SetCollRatios");
+                Self::set_coll_ratios(program_id, accounts, maint_coll_ratio, init_coll_ratio)?;
+            }
+            MangoInstruction::SetMarketMode {
+                market_index,
+                reduce_only
+            } => {
+                msg!("Mango: SetMarketMode");
+                Self::set_market_mode(program_id, accounts, market_index, reduce_only)?;
+            }
+            MangoInstruction::SetHalt {
+                is_halted
+            } => {
+                msg!("Mango: SetHalt");
+                Self::set_halt(program_id, accounts, is_halted)?;
+            }
+            MangoInstruction::GetMarginAccountDetails => {
+                msg!("Mango: GetMarginAccountDetails");
+                Self::get_margin_account_details(program_id, accounts)?;
+            }
+            MangoInstruction::AuditVaults => {
+                msg!("Mango: AuditVaults");
+                Self::audit_vaults(program_id, accounts)?;
+            }
+            MangoInstruction::SetSecondaryOracle {
+                market_index,
+                max_divergence
+            } => {
+                msg!("Mango: SetSecondaryOracle");
+                Self::set_secondary_oracle(program_id, accounts, market_index, max_divergence)?;
+            }
+            MangoInstruction::SetAdmin => {
+                msg!("Mango: SetAdmin");
+                Self::set_admin(program_id, accounts)?;
+            }
+            MangoInstruction::AcceptAdmin => {
+                msg!("Mango: AcceptAdmin");
+                Self::accept_admin(program_id, accounts)?;
+            }
+            MangoInstruction::DepositWithReferrer {
+                quantity
+            } => {
+                msg!("Mango: DepositWithReferrer");
+                Self::deposit_with_referrer(program_id, accounts, quantity)?;
+            }
+            MangoInstruction::ForceLiquidateOnDex {
+                market_index,
+                limit
+            } => {
+                msg!("Mango: ForceLiquidateOnDex");
+                Self::force_liquidate_on_dex(program_id, accounts, market_index, limit)?;
+            }
+            MangoInstruction::ChangeDustThreshold {
+                token_index,
+                dust_threshold
+            } => {
+                msg!("Mango: ChangeDustThreshold");
+                Self::change_dust_threshold(program_id, accounts, token_index, dust_threshold)?;
+            }
+            MangoInstruction::AddSpotMarket {
+                market_index
+            } => {
+                msg!("Mango: AddSpotMarket");
+                Self::add_spot_market(program_id, accounts, market_index)?;
+            }
+            MangoInstruction::ChangeMinOrderNotional {
+                min_order_notional
+            } => {
+                msg!("Mango: ChangeMinOrderNotional");
+                Self::change_min_order_notional(program_id, accounts, min_order_notional)?;
+            }
+            MangoInstruction::CancelAllMyOrders {
+                market_index,
+                limit
+            } => {
+                msg!("Mango: CancelAllMyOrders");
+                Self::cancel_all_my_orders(program_id, accounts, market_index, limit)?;
+            }
+            MangoInstruction::DepositForAccount {
+                quantity
+            } => {
+                msg!("Mango: DepositForAccount");
+                Self::deposit_for_account(program_id, accounts, quantity)?;
+            }
+            MangoInstruction::SettleBorrowToRatio {
+                token_index,
+                target_ratio
+            } => {
+                msg!("Mango: SettleBorrowToRatio");
+                Self::settle_borrow_to_ratio(program_id, accounts, token_index, target_ratio)?;
+            }
+            MangoInstruction::ConsumeEvents {
+                limit
+            } => {
+                msg!("Mango: ConsumeEvents");
+                Self::consume_events(program_id, accounts, limit)?;
+            }
+            MangoInstruction::ChangeMaxBorrowPerAccount {
+                token_index,
+                max_borrow
+            } => {
+                msg!("Mango: ChangeMaxBorrowPerAccount");
+                Self::change_max_borrow_per_account(program_id, accounts, token_index, max_borrow)?;
+            }
+            MangoInstruction::CheckSolvency => {
+                msg!("Mango: CheckSolvency");
+                Self::check_solvency(program_id, accounts)?;
+            }
+            MangoInstruction::ChangeCollateralGracePeriod {
+                grace_period
+            } => {
+                msg!("Mango: ChangeCollateralGracePeriod");
+                Self::change_collateral_grace_period(program_id, accounts, grace_period)?;
+            }
+            MangoInstruction::MigrateMarginAccount => {
+                msg!("Mango: MigrateMarginAccount");
+                Self::migrate_margin_account(program_id, accounts)?;
+            }
+            MangoInstruction::ResolveBankruptcy => {
+                msg!("Mango: ResolveBankruptcy");
+                Self::resolve_bankruptcy(program_id, accounts)?;
+            }
+            MangoInstruction::SettleFundsPartial {
+                max_base,
+                max_quote
+            } => {
+                msg!("Mango: SettleFundsPartial");
+                Self::settle_funds_capped(program_id, accounts, max_base, max_quote)?;
+            }
+            MangoInstruction::InitMarginAccountPda {
+                bump
+            } => {
+                msg!("Mango: InitMarginAccountPda");
+                Self::init_margin_account_pda(program_id, accounts, bump)?;
+            }
+            MangoInstruction::SetBorrowLimitRamp {
+                targets,
+                ramp_seconds
+            } => {
+                msg!("Mango: SetBorrowLimitRamp");
+                Self::set_borrow_limit_ramp(program_id, accounts, targets, ramp_seconds)?;
+            }
+            MangoInstruction::ChangeMaxOrdersPerMarket {
+                max_orders
+            } => {
+                msg!("Mango: ChangeMaxOrdersPerMarket");
+                Self::change_max_orders_per_market(program_id, accounts, max_orders)?;
+            }
+            MangoInstruction::LogIndexes => {
+                msg!("Mango: LogIndexes");
+                Self::log_indexes(program_id, accounts)?;
+            }
+            MangoInstruction::ChangeAllBorrowLimits {
+                limits
+            } => {
+                msg!("Mango: ChangeAllBorrowLimits");
+                Self::change_all_borrow_limits(program_id, accounts, limits)?;
+            }
+            MangoInstruction::DepositMulti {
+                quantities
+            } => {
+                msg!("Mango: DepositMulti");
+                Self::deposit_multi(program_id, accounts, quantities)?;
+            }
+            MangoInstruction::RefreshOracleDecimals => {
+                msg!("Mango: RefreshOracleDecimals");
+                Self::refresh_oracle_decimals(program_id, accounts)?;
+            }
+            MangoInstruction::WithdrawExcessSrm {
+                quantity
+            } => {
+                msg!("Mango: WithdrawExcessSrm");
+                Self::withdraw_excess_srm(program_id, accounts, quantity)?;
+            }
+            MangoInstruction::CloseOpenOrders {
+                market_index
+            } => {
+                msg!("Mango: CloseOpenOrders");
+                Self::close_open_orders(program_id, accounts, market_index)?;
+            }
+            MangoInstruction::ChangeMaxAccrualInterval {
+                max_accrual_interval
+            } => {
+                msg!("Mango: ChangeMaxAccrualInterval");
+                Self::change_max_accrual_interval(program_id, accounts, max_accrual_interval)?;
+            }
+            MangoInstruction::InitOpenOrders {
+                market_index
+            } => {
+                msg!("Mango: InitOpenOrders");
+                Self::init_open_orders(program_id, accounts, market_index)?;
+            }
+            MangoInstruction::ChangeLiquidationCooldown {
+                liquidation_cooldown
+            } => {
+                msg!("Mango: ChangeLiquidationCooldown");
+                Self::change_liquidation_cooldown(program_id, accounts, liquidation_cooldown)?;
+            }
+            MangoInstruction::SetOracle {
+                market_index,
+                oracle_type
+            } => {
+                msg!("Mango: SetOracle");
+                Self::set_oracle(program_id, accounts, market_index, oracle_type)?;
+            }
+            MangoInstruction::ForceSettleFunds {
+                market_index
+            } => {
+                msg!("Mango: ForceSettleFunds");
+                Self::force_settle_funds(program_id, accounts, market_index)?;
+            }
+            MangoInstruction::VerifyGroupConfig => {
+                msg!("Mango: VerifyGroupConfig");
+                Self::verify_group_config(program_id, accounts)?;
+            }
+            MangoInstruction::ChangeOiLimit {
+                market_index,
+                oi_limit
+            } => {
+                msg!("Mango: ChangeOiLimit");
+                Self::change_oi_limit(program_id, accounts, market_index, oi_limit)?;
+            }
+            MangoInstruction::CancelOrderAndSettle {
+                order
+            } => {
+                msg!("Mango: CancelOrderAndSettle");
+                let data = serum_dex::instruction::MarketInstruction::CancelOrderV2(order).pack();
+                Self::cancel_order_and_settle(program_id, accounts, data)?;
+            }
+            MangoInstruction::ChangeMaxPriceMove {
+                max_price_move_bps,
+                price_band_timeout
+            } => {
+                msg!("Mango: ChangeMaxPriceMove");
+                Self::change_max_price_move(program_id, accounts, max_price_move_bps, price_band_timeout)?;
+            }
         }
         Ok(())
     }
 }
 
 
+/// Credit the base and quote that a serum SettleFunds moved from the open orders account of
+/// market_i into the Mango vaults to the deposits of margin_account. pre and post are the
+/// (native_coin_free, native_pc_free) of the open orders account before and after the settle
+pub fn credit_settled_funds(
+    mango_group: &mut MangoGroup,
+    margin_account: &mut MarginAccount,
+    market_i: usize,
+    pre: (u64, u64),
+    post: (u64, u64)
+) -> MangoResult<()> {
+    let (pre_base, pre_quote) = pre;
+    let (post_base, post_quote) = post;
+    check_default!(post_base <= pre_base)?;
+    check_default!(post_quote <= pre_quote)?;
+
+    let base_change = U64F64::from_num(pre_base - post_base) / mango_group.indexes[market_i].deposit;
+    let quote_change = U64F64::from_num(pre_quote - post_quote) / mango_group.indexes[QUOTE_INDEX].deposit;
+
+    checked_add_deposit(mango_group, margin_account, market_i, base_change)?;
+    checked_add_deposit(mango_group, margin_account, QUOTE_INDEX, quote_change)
+}
+
 fn settle_borrow_unchecked(
     mango_group: &mut MangoGroup,
     margin_account: &mut MarginAccount,
@@ -1563,6 +4085,7 @@ fn settle_borrow_unchecked(
     let quantity = U64F64::from_num(quantity);
 
     let quantity = min(quantity, native_deposit);
+    record_interest_paid(margin_account, &mango_group.indexes[token_index], token_index, min(quantity, native_borrow))?;
     if quantity >= native_borrow {  // Reduce borrows to 0 to prevent rounding related dust
         // NOTE: native_borrow / index.borrow is same as margin_account.borrows[token_index]
         checked_sub_deposit(mango_group, margin_account, token_index, native_borrow / deposit_index)?;
@@ -1577,6 +4100,218 @@ fn settle_borrow_unchecked(
 
 }
 
+/// Debit margin_account for the funds a new order moved out of the vault of token_i. The dex
+/// pulls the order's full cost whether it fills or rests, and what rests is locked in the open
+/// orders account where get_assets_val counts it, so the account's value doesn't change.
+/// Deposits are used first and the rest is borrowed
+pub fn debit_order_funds(
+    mango_group: &mut MangoGroup,
+    margin_account: &mut MarginAccount,
+    token_i: usize,
+    pre_amount: u64,
+    post_amount: u64,
+    reduce_only: bool,
+    now: u64
+) -> MangoResult<()> {
+    check_default!(post_amount <= pre_amount)?;
+    let spent = pre_amount - post_amount;
+    if spent == 0 {
+        // fully paid for by free funds already on the open orders account
+        return Ok(());
+    }
+    let index: MangoIndex = mango_group.indexes[token_i];
+    let native_deposit = margin_account.get_native_deposit(&index, token_i);
+
+    // user deposits will be used first.
+    // If user does not want that to happen, they must first issue a borrow command
+    if native_deposit >= spent {
+        let spent_deposit = U64F64::from_num(spent) / index.deposit;
+        checked_sub_deposit(mango_group, margin_account, token_i, spent_deposit)?;
+    } else {
+
+        let avail_deposit = margin_account.deposits[token_i];
+        checked_sub_deposit(mango_group, margin_account, token_i, avail_deposit)?;
+        let rem_spend = U64F64::from_num(spent - native_deposit);
+
+        check_default!(!reduce_only)?;  // Cannot borrow more in reduce only mode
+        checked_add_borrow(mango_group, margin_account, token_i, rem_spend / index.borrow)?;
+        check_max_borrow_per_account(mango_group, margin_account, token_i)?;
+        check_borrow_limit_ramp(mango_group, margin_account, token_i, now)?;
+    }
+    Ok(())
+}
+
+/// Credit the mango vault changes of a settled PlaceAndSettle to margin_account. in_token is the
+/// token the order buys and out_token the one it pays with. Returns whether out_token was borrowed
+pub fn credit_settled_fill(
+    mango_group: &mut MangoGroup,
+    margin_account: &mut MarginAccount,
+    in_token_i: usize,
+    out_token_i: usize,
+    pre_in: u64,
+    post_in: u64,
+    pre_out: u64,
+    post_out: u64,
+    reduce_only: bool,
+    now: u64
+) -> MangoResult<bool> {
+    // It's not possible for in_token to be negative
+    check_default!(post_in >= pre_in)?;
+    let out_index: MangoIndex = mango_group.indexes[out_token_i];
+    let in_index: MangoIndex = mango_group.indexes[in_token_i];
+
+    // if out token was net negative, then you may need to borrow more
+    let mut borrowed = false;
+    if post_out >= pre_out {
+        // Settling also pays out maker rebates and the free funds of earlier fills, so out_token can
+        // come back net positive as well. Both sides are then plain deposits and nothing is borrowed
+        if post_out > pre_out {
+            msg!("MANGO-REBATE token={} native={}", out_token_i, post_out - pre_out);
+        }
+        let deposit = U64F64::from_num(post_out - pre_out) / out_index.deposit;
+        checked_add_deposit(mango_group, margin_account, out_token_i, deposit)?;
+    } else {
+        let total_out = pre_out - post_out;
+        let native_deposit = margin_account.get_native_deposit(&out_index, out_token_i);
+        if native_deposit < total_out {  // need to borrow
+            borrowed = true;
+            let avail_deposit = margin_account.deposits[out_token_i];
+            checked_sub_deposit(mango_group, margin_account, out_token_i, avail_deposit)?;
+            let rem_spend = U64F64::from_num(total_out - native_deposit);
+
+            check_default!(!reduce_only)?;  // Cannot borrow more in reduce only mode
+            checked_add_borrow(mango_group, margin_account, out_token_i, rem_spend / out_index.borrow)?;
+            check_max_borrow_per_account(mango_group, margin_account, out_token_i)?;
+            check_borrow_limit_ramp(mango_group, margin_account, out_token_i, now)?;
+        } else {  // just spend user deposits
+            let mango_spent = U64F64::from_num(total_out) / out_index.deposit;
+            checked_sub_deposit(mango_group, margin_account, out_token_i, mango_spent)?;
+        }
+    }
+
+    let total_in = U64F64::from_num(post_in - pre_in) / in_index.deposit;
+    checked_add_deposit(mango_group, margin_account, in_token_i, total_in)?;
+
+    // Settle borrow
+    // TODO only do ops on tokens that have borrows and deposits
+    settle_borrow_full_unchecked(mango_group, margin_account, out_token_i)?;
+    settle_borrow_full_unchecked(mango_group, margin_account, in_token_i)?;
+    Ok(borrowed)
+}
+
+/// Fail if margin_account now borrows more of token_index than max_borrow_per_account allows
+fn check_max_borrow_per_account(
+    mango_group: &MangoGroup,
+    margin_account: &MarginAccount,
+    token_index: usize
+) -> MangoResult<()> {
+    let max_borrow = mango_group.max_borrow_per_account[token_index];
+    let native_borrow = margin_account.get_native_borrow(&mango_group.indexes[token_index], token_index);
+    check!(max_borrow == 0 || native_borrow <= max_borrow, MangoErrorCode::BorrowLimitExceeded)
+}
+
+/// Fail if margin_account now borrows more of token_index than the ramped borrow limit allows.
+/// borrow_limits is only enforced while a ramp is set with SetBorrowLimitRamp
+fn check_borrow_limit_ramp(
+    mango_group: &MangoGroup,
+    margin_account: &MarginAccount,
+    token_index: usize,
+    now: u64
+) -> MangoResult<()> {
+    if mango_group.borrow_limit_ramp_seconds == 0 {
+        return Ok(());
+    }
+    let native_borrow = margin_account.get_native_borrow(&mango_group.indexes[token_index], token_index);
+    check!(native_borrow <= mango_group.get_borrow_limit(token_index, now), MangoErrorCode::BorrowLimitExceeded)
+}
+
+/// Native quantity of token_index to settle so the collateral ratio rises to target_ratio, rounded
+/// up so the ratio lands at or just above it. Settling x of value removes x * coll_weight from
+/// assets and x * liab_weight from liabs, so the ratio only rises while it is above
+/// coll_weight / liab_weight; 0 if it can't rise or is already at the target.
+/// settle_borrow_unchecked caps the result at the native borrow and deposit
+pub fn get_settle_quantity_for_ratio(
+    mango_group: &MangoGroup,
+    margin_account: &MarginAccount,
+    prices: &[U64F64; NUM_TOKENS],
+    open_orders_accs: &[AccountInfo; NUM_MARKETS],
+    token_index: usize,
+    target_ratio: U64F64,
+    now: u64
+) -> MangoResult<u64> {
+    check_default!(target_ratio > ONE_U64F64)?;
+    let (assets_val, liabs_val, coll_ratio) = margin_account.get_health(mango_group, prices, open_orders_accs, now)?;
+    let coll_weight = mango_group.coll_weights[token_index];
+    let liab_weight = mango_group.liab_weights[token_index];
+    if coll_ratio >= target_ratio || coll_ratio.checked_mul(liab_weight).ok_or(throw!())? <= coll_weight {
+        return Ok(0);
+    }
+
+    // (assets_val - x * coll_weight) / (liabs_val - x * liab_weight) = target_ratio
+    let val_needed = target_ratio.checked_mul(liabs_val).ok_or(throw!())?
+        .checked_sub(assets_val).ok_or(throw!())?;
+    let weight = target_ratio.checked_mul(liab_weight).ok_or(throw!())?
+        .checked_sub(coll_weight).ok_or(throw!())?;
+    let quantity = val_needed
+        .checked_div(weight).ok_or(throw!())?
+        .checked_div(prices[token_index]).ok_or(throw!())?
+        .checked_ceil().ok_or(throw!())?;
+    Ok(quantity.checked_to_num().unwrap_or(u64::MAX))
+}
+
+/// Net every borrow of margin_account against its deposit in the same token. The smaller side is
+/// cleared in full, so no token is left both deposited and borrowed by the rounding of the native
+/// amounts. Group totals clamp at zero, see saturating_sub_deposit
+pub fn settle_all_borrows(
+    mango_group: &mut MangoGroup,
+    margin_account: &mut MarginAccount
+) -> MangoResult<()> {
+    for i in 0..NUM_TOKENS {
+        let index: MangoIndex = mango_group.indexes[i];
+        let native_borrow = margin_account.get_native_borrow(&index, i);
+        let native_deposit = margin_account.get_native_deposit(&index, i);
+        let quantity = U64F64::from_num(cmp::min(native_borrow, native_deposit));
+        record_interest_paid(margin_account, &index, i, quantity)?;
+
+        // the borrow rounds up and the deposit down, so what's left of the smaller side is dust
+        let (dep_settle, borr_settle) = if native_borrow <= native_deposit {
+            (quantity / index.deposit, margin_account.borrows[i])
+        } else {
+            (margin_account.deposits[i], quantity / index.borrow)
+        };
+        saturating_sub_deposit(mango_group, margin_account, i, dep_settle)?;
+        saturating_sub_borrow(mango_group, margin_account, i, borr_settle)?;
+    }
+    Ok(())
+}
+
+/// Forgive enough of an insolvent account's borrows, spread over its tokens by value, to bring it
+/// back to LIQ_MIN_COLL_RATIO. Lenders of each token take the loss through its deposit index.
+/// Borrows must already be settled with settle_all_borrows; otherwise the lowered deposit index
+/// also eats the liqee's own deposits in the tokens it borrowed and leaves it below 1 again
+pub fn socialize_liqee_loss(
+    mango_group: &mut MangoGroup,
+    margin_account: &mut MarginAccount,
+    assets_val: U64F64,
+    liabs_val: U64F64
+) -> MangoResult<()> {
+    let liabs = margin_account.get_total_liabs(mango_group)?;
+
+    // reduction_val = amount of quote currency value to reduce liabilities by to get coll_ratio = 1.01
+    let reduction_val = liabs_val
+        .checked_sub(assets_val / LIQ_MIN_COLL_RATIO).ok_or(throw!())?;
+
+    for i in 0..NUM_TOKENS {
+        let proportion = U64F64::from_num(liabs[i])
+            .checked_div(liabs_val).ok_or(throw!())?;
+
+        let token_reduce = proportion.checked_mul(reduction_val).ok_or(throw!())?;
+        socialize_loss(mango_group, margin_account, i, token_reduce)?;
+        // TODO Can socialize loss cause more liquidations? Perhaps other accounts then go below threshold
+    }
+    Ok(())
+}
+
 fn settle_borrow_full_unchecked(
     mango_group: &mut MangoGroup,
     margin_account: &mut MarginAccount,
@@ -1589,19 +4324,121 @@ fn settle_borrow_full_unchecked(
 
     let quantity = cmp::min(native_borrow, native_deposit);
 
-    let borr_settle = U64F64::from_num(quantity) / index.borrow;
+    // native_borrow is rounded up, so settling all of it can come to a bit more than borrows
+    let borr_settle = min(U64F64::from_num(quantity) / index.borrow, margin_account.borrows[token_index]);
     let dep_settle = U64F64::from_num(quantity) / index.deposit;
+    record_interest_paid(margin_account, index, token_index, U64F64::from_num(quantity))?;
 
     checked_sub_deposit(mango_group, margin_account, token_index, dep_settle)?;
     checked_sub_borrow(mango_group, margin_account, token_index, borr_settle)?;
 
-    // No need to check collateralization ratio or deposits/borrows validity
+    // No need to check collateralization ratio or deposits/borrows validity
+
+    Ok(())
+
+}
+
+/// Log line emitted by LogIndexes for each token. Fields are space separated key=value pairs in a
+/// fixed order; indexes have 12 decimals
+pub fn format_index_log(token_index: usize, index: &MangoIndex) -> String {
+    format!(
+        "MANGO-INDEX token={} borrow={} deposit={} last_update={}",
+        token_index, format_u64f64(index.borrow, 12), format_u64f64(index.deposit, 12), index.last_update
+    )
+}
+
+/// Within liquidation_cooldown of its last liquidation, only allow liquidating margin_account again
+/// while it's below maint_coll_ratio. This stops PartialLiquidate from continuing on an account
+/// that a price move just took back above maint before prices had time to settle
+pub fn check_liquidation_cooldown(
+    mango_group: &MangoGroup,
+    margin_account: &MarginAccount,
+    coll_ratio: U64F64,
+    now: u64
+) -> MangoResult<()> {
+    let cooldown_end = margin_account.last_liquidation_ts.saturating_add(mango_group.liquidation_cooldown);
+    if mango_group.liquidation_cooldown == 0 || now >= cooldown_end {
+        return Ok(());
+    }
+    check!(coll_ratio < mango_group.maint_coll_ratio, MangoErrorCode::LiquidationCooldown)
+}
+
+/// Reject liquidation deposits worth more than needed to bring the liqee to init_coll_ratio, so a
+/// liqor can't overpay by mistake. Deposits are valued as collateral after the liquidation fee is
+/// paid back out of them. LIQ_DEPOSIT_TOLERANCE of the deficit is allowed on top for price moves
+/// between sizing the deposits and execution, plus one native unit of each token deposited
+pub fn check_liquidation_deposits(
+    mango_group: &MangoGroup,
+    prices: &[U64F64; NUM_TOKENS],
+    deposit_quantities: &[u64; NUM_TOKENS],
+    deficit: u64
+) -> MangoResult<()> {
+    let kept = ONE_U64F64.saturating_sub(mango_group.liquidation_fee);
+    let mut value = ZERO_U64F64;
+    // deficit was rounded down to a whole native unit
+    let mut max_value = U64F64::from_num(deficit.checked_add(1).ok_or(throw!())?)
+        .checked_mul(ONE_U64F64 + LIQ_DEPOSIT_TOLERANCE).ok_or(throw!())?;
+    for i in 0..NUM_TOKENS {
+        if deposit_quantities[i] == 0 {
+            continue;
+        }
+        // the liqor's deposits aren't in the liqee's grace window, so they count at full weight
+        let unit_value = prices[i]
+            .checked_mul(mango_group.coll_weights[i]).ok_or(throw!())?
+            .checked_mul(kept).ok_or(throw!())?;
+        value = unit_value.checked_mul(U64F64::from_num(deposit_quantities[i])).ok_or(throw!())?
+            .checked_add(value).ok_or(throw!())?;
+        max_value = max_value.checked_add(unit_value).ok_or(throw!())?;
+    }
+    check!(value <= max_value, MangoErrorCode::ExcessLiquidationDeposit)
+}
 
-    Ok(())
+/// Check margin_account is at least at init_coll_ratio after a PlaceAndSettle and return its
+/// collateral ratio. A reduce only order that didn't borrow can only have reduced liabilities, so
+/// the open orders scan is skipped and None returned, unless need_ratio asks for the ratio anyway
+pub fn check_post_trade_health(
+    mango_group: &MangoGroup,
+    margin_account: &MarginAccount,
+    prices: &[U64F64; NUM_TOKENS],
+    open_orders_accs: &[AccountInfo; NUM_MARKETS],
+    reduce_only: bool,
+    borrowed: bool,
+    need_ratio: bool,
+    now: u64
+) -> MangoResult<Option<U64F64>> {
+    if reduce_only && !borrowed && !need_ratio {
+        return Ok(None);
+    }
+    let coll_ratio = margin_account.get_collateral_ratio(mango_group, prices, open_orders_accs, now)?;
+    check!(reduce_only || coll_ratio >= mango_group.init_coll_ratio, MangoErrorCode::CollateralRatioLimit)?;
+    Ok(Some(coll_ratio))
+}
 
+/// Log line emitted by PlaceAndSettle when the fill-log feature is on. Fields are space separated
+/// key=value pairs in a fixed order. native_out is the net native amount of out_token that left
+/// the vault and is negative if more came back than was spent
+#[cfg(feature = "fill-log")]
+pub fn format_fill_log(
+    market_index: usize,
+    side: Side,
+    in_token_index: usize,
+    native_in: u64,
+    out_token_index: usize,
+    native_out: i128,
+    borrowed: bool,
+    coll_ratio: U64F64
+) -> String {
+    let side = match side {
+        Side::Bid => "bid",
+        Side::Ask => "ask"
+    };
+    format!(
+        "MANGO-FILL market={} side={} in_token={} native_in={} out_token={} native_out={} borrowed={} ratio={}",
+        market_index, side, in_token_index, native_in, out_token_index, native_out, borrowed, coll_ratio
+    )
 }
 
-fn socialize_loss(
+pub fn socialize_loss(
     mango_group: &mut MangoGroup,
     margin_account: &mut MarginAccount,
     token_index: usize,
@@ -1610,17 +4447,31 @@ fn socialize_loss(
 
     // reduce borrow for this margin_account by appropriate amount
     // decrease MangoIndex.deposit by appropriate amount
+    let quantity: U64F64 = reduce_quantity_native
+        .checked_div(mango_group.indexes[token_index].borrow).ok_or(throw!())?;
+    saturating_sub_borrow(mango_group, margin_account, token_index, quantity)?;
+
+    // Lenders lose at most MAX_SOCIALIZED_LOSS of their deposits so the deposit index never
+    // reaches zero, which would break every later deposit and withdraw. The rest is written off
+    let total_deposits = U64F64::from_num(mango_group.get_total_native_deposit(token_index)?);
+    let max_lender_loss = total_deposits.checked_mul(MAX_SOCIALIZED_LOSS).ok_or(throw!())?;
+    let (percentage_loss, excess) = if total_deposits == ZERO_U64F64 {
+        (ZERO_U64F64, reduce_quantity_native)
+    } else if reduce_quantity_native <= max_lender_loss {
+        (reduce_quantity_native.checked_div(total_deposits).ok_or(throw!())?, ZERO_U64F64)
+    } else {
+        (MAX_SOCIALIZED_LOSS, reduce_quantity_native - max_lender_loss)
+    };
 
-    // TODO make sure there is enough funds to socialize losses
-    let quantity: U64F64 = reduce_quantity_native / mango_group.indexes[token_index].borrow;
-    checked_sub_borrow(mango_group, margin_account, token_index, quantity)?;
-
-    let total_deposits = U64F64::from_num(mango_group.get_total_native_deposit(token_index));
-    let percentage_loss = reduce_quantity_native.checked_div(total_deposits).unwrap();
     let index: &mut MangoIndex = &mut mango_group.indexes[token_index];
     index.deposit = index.deposit
-        .checked_sub(percentage_loss.checked_mul(index.deposit).unwrap()).unwrap();
+        .checked_sub(percentage_loss.checked_mul(index.deposit).ok_or(throw!())?).ok_or(throw!())?;
 
+    if excess > ZERO_U64F64 {
+        msg!("Socialized loss written off: {{ \"token_index\": {}, \"quantity\": {} }}",
+             token_index, excess);
+        mango_group.write_off(token_index, excess)?;
+    }
     Ok(())
 }
 
@@ -1631,7 +4482,63 @@ fn checked_sub_deposit(
     quantity: U64F64
 ) -> MangoResult<()> {
     margin_account.checked_sub_deposit(token_index, quantity)?;
-    mango_group.checked_sub_deposit(token_index, quantity)
+    mango_group.checked_sub_deposit(token_index, quantity)?;
+
+    // A dust deposit left behind is kept by the protocol; it stays in the vault as fees
+    let remaining = margin_account.deposits[token_index];
+    let native_remaining = remaining.checked_mul(mango_group.indexes[token_index].deposit).ok_or(throw!())?;
+    if remaining > ZERO_U64F64 && native_remaining < U64F64::from_num(mango_group.dust_thresholds[token_index]) {
+        margin_account.checked_sub_deposit(token_index, remaining)?;
+        mango_group.checked_sub_deposit(token_index, remaining)?;
+        mango_group.fees_accrued[token_index] = mango_group.fees_accrued[token_index]
+            .checked_add(remaining).ok_or(throw!())?;
+    }
+    Ok(())
+}
+
+/// Same as checked_sub_deposit, but quantity is capped at the account's deposit and the group
+/// total clamps at zero. After a socialized loss, rounding can leave the group total slightly
+/// below the sum of its accounts, and a liquidation must not fail over that
+fn saturating_sub_deposit(
+    mango_group: &mut MangoGroup,
+    margin_account: &mut MarginAccount,
+    token_index: usize,
+    quantity: U64F64
+) -> MangoResult<()> {
+    let quantity = min(quantity, margin_account.deposits[token_index]);
+    margin_account.checked_sub_deposit(token_index, quantity)?;
+    mango_group.total_deposits[token_index] = mango_group.total_deposits[token_index].saturating_sub(quantity);
+
+    let remaining = margin_account.deposits[token_index];
+    let native_remaining = remaining.checked_mul(mango_group.indexes[token_index].deposit).ok_or(throw!())?;
+    if remaining > ZERO_U64F64 && native_remaining < U64F64::from_num(mango_group.dust_thresholds[token_index]) {
+        margin_account.checked_sub_deposit(token_index, remaining)?;
+        mango_group.total_deposits[token_index] = mango_group.total_deposits[token_index].saturating_sub(remaining);
+        mango_group.fees_accrued[token_index] = mango_group.fees_accrued[token_index].saturating_add(remaining);
+    }
+    Ok(())
+}
+
+/// Same as checked_sub_borrow with the clamping of saturating_sub_deposit
+fn saturating_sub_borrow(
+    mango_group: &mut MangoGroup,
+    margin_account: &mut MarginAccount,
+    token_index: usize,
+    quantity: U64F64
+) -> MangoResult<()> {
+    let quantity = min(quantity, margin_account.borrows[token_index]);
+    margin_account.checked_sub_borrow(token_index, quantity)?;
+    mango_group.total_borrows[token_index] = mango_group.total_borrows[token_index].saturating_sub(quantity);
+
+    let remaining = margin_account.borrows[token_index];
+    let index: MangoIndex = mango_group.indexes[token_index];
+    let native_remaining = remaining.checked_mul(index.borrow).ok_or(throw!())?;
+    if remaining > ZERO_U64F64 && native_remaining < U64F64::from_num(mango_group.dust_thresholds[token_index]) {
+        margin_account.checked_sub_borrow(token_index, remaining)?;
+        mango_group.total_borrows[token_index] = mango_group.total_borrows[token_index].saturating_sub(remaining);
+        mango_group.write_off(token_index, native_remaining)?;
+    }
+    Ok(())
 }
 
 fn checked_sub_borrow(
@@ -1641,7 +4548,18 @@ fn checked_sub_borrow(
     quantity: U64F64
 ) -> MangoResult<()> {
     margin_account.checked_sub_borrow(token_index, quantity)?;
-    mango_group.checked_sub_borrow(token_index, quantity)
+    mango_group.checked_sub_borrow(token_index, quantity)?;
+
+    // A dust borrow left behind is forgiven; fees_accrued absorbs it and bad_debt records any shortfall
+    let remaining = margin_account.borrows[token_index];
+    let index: MangoIndex = mango_group.indexes[token_index];
+    let native_remaining = remaining.checked_mul(index.borrow).ok_or(throw!())?;
+    if remaining > ZERO_U64F64 && native_remaining < U64F64::from_num(mango_group.dust_thresholds[token_index]) {
+        margin_account.checked_sub_borrow(token_index, remaining)?;
+        mango_group.checked_sub_borrow(token_index, remaining)?;
+        mango_group.write_off(token_index, native_remaining)?;
+    }
+    Ok(())
 }
 
 fn checked_add_deposit(
@@ -1661,37 +4579,127 @@ fn checked_add_borrow(
     quantity: U64F64
 ) -> MangoResult<()> {
     margin_account.checked_add_borrow(token_index, quantity)?;
-    mango_group.checked_add_borrow(token_index, quantity)
+    mango_group.checked_add_borrow(token_index, quantity)?;
+
+    let native_quantity: u64 = quantity.checked_mul(mango_group.indexes[token_index].borrow).ok_or(throw!())?.to_num();
+    margin_account.borrow_principal[token_index] = margin_account.borrow_principal[token_index].saturating_add(native_quantity);
+    Ok(())
 }
 
-pub fn get_prices(
+/// Add the interest part of native_settled, the native borrow about to be repaid, to
+/// total_interest_paid. Accrued interest counts as repaid before the principal
+fn record_interest_paid(
+    margin_account: &mut MarginAccount,
+    index: &MangoIndex,
+    token_index: usize,
+    native_settled: U64F64
+) -> MangoResult<()> {
+    let native_borrow = margin_account.borrows[token_index].checked_mul(index.borrow).ok_or(throw!())?;
+    let accrued = native_borrow.saturating_sub(U64F64::from_num(margin_account.borrow_principal[token_index]));
+    let interest = min(native_settled, accrued);
+    margin_account.total_interest_paid[token_index] = margin_account.total_interest_paid[token_index]
+        .saturating_add(interest.to_num());
+    margin_account.borrow_principal[token_index] = margin_account.borrow_principal[token_index]
+        .saturating_sub((native_settled - interest).to_num());
+    Ok(())
+}
+
+/// Error if the live decimals of any added market's oracle differ from the cached oracle_decimals,
+/// in which case prices would be scaled wrong until RefreshOracleDecimals is run
+pub fn check_oracle_decimals(
     mango_group: &MangoGroup,
     oracle_accs: &[AccountInfo]
+) -> MangoResult<()> {
+    for i in 0..NUM_MARKETS {
+        check_eq!(&mango_group.oracles[i], oracle_accs[i].key, MangoErrorCode::InvalidOracle)?;
+        // Pyth values are rescaled to oracle_decimals, so only flux decimals can go stale
+        if mango_group.oracles[i] == Pubkey::default() || mango_group.oracle_types[i] == OracleType::Pyth as u8 {
+            continue;
+        }
+        let oracle = flux_aggregator::state::Aggregator::load_initialized(&oracle_accs[i])?;
+        check_eq!(oracle.config.decimals, mango_group.oracle_decimals[i], MangoErrorCode::OracleDecimalsMismatch)?;
+    }
+    Ok(())
+}
+
+/// secondary_oracle_accs holds the NUM_MARKETS secondary oracles passed after all other accounts of
+/// an instruction. It may be empty if no market has a secondary oracle configured. Each price must
+/// pass check_price_move at the clock's unix timestamp and is then recorded as the market's
+/// last_price. Pyth prices must have been published within PYTH_MAX_SLOT_AGE of the clock's slot
+pub fn get_prices(
+    mango_group: &mut MangoGroup,
+    oracle_accs: &[AccountInfo],
+    secondary_oracle_accs: &[AccountInfo],
+    clock: &Clock
 ) -> MangoResult<[U64F64; NUM_TOKENS]> {
+    let now = clock.unix_timestamp as u64;
     let mut prices = [ZERO_U64F64; NUM_TOKENS];
-    prices[NUM_MARKETS] = ONE_U64F64;  // quote currency is 1
-    let quote_decimals: u8 = mango_group.mint_decimals[NUM_MARKETS];
+    prices[QUOTE_INDEX] = ONE_U64F64;  // quote currency is 1
+    let quote_decimals: u8 = mango_group.mint_decimals[QUOTE_INDEX];
+
+    #[cfg(feature = "check-oracle-decimals")]
+    check_oracle_decimals(mango_group, oracle_accs)?;
 
     for i in 0..NUM_MARKETS {
-        check_eq_default!(&mango_group.oracles[i], oracle_accs[i].key)?;
+        check_eq!(&mango_group.oracles[i], oracle_accs[i].key, MangoErrorCode::InvalidOracle)?;
+        if mango_group.oracles[i] == Pubkey::default() {  // market not added yet; price stays 0
+            continue;
+        }
 
-        // TODO store this info in MangoGroup, first make sure it cannot be changed by solink
+        // decimals are cached when the oracle is added; RefreshOracleDecimals updates them if solink
+        // changes them
         let quote_adj = U64F64::from_num(
             10u64.pow(quote_decimals.checked_sub(mango_group.oracle_decimals[i]).unwrap() as u32)
         );
 
-        let answer = flux_aggregator::read_median(&oracle_accs[i])?; // this is in USD cents
-
-        let value = U64F64::from_num(answer.median);
+        let mut value = if mango_group.oracle_types[i] == OracleType::Pyth as u8 {
+            read_pyth_value(&oracle_accs[i], mango_group.oracle_decimals[i], clock.slot)?
+        } else {
+            let answer = flux_aggregator::read_median(&oracle_accs[i])?; // this is in USD cents
+            U64F64::from_num(answer.median)
+        };
+        if mango_group.oracles_secondary[i] != Pubkey::default() {
+            let secondary_acc = secondary_oracle_accs.get(i).ok_or(throw!())?;
+            check_eq!(&mango_group.oracles_secondary[i], secondary_acc.key, MangoErrorCode::InvalidOracle)?;
+            let secondary = flux_aggregator::read_median(secondary_acc)?;
+            value = median_oracle_price(
+                value,
+                U64F64::from_num(secondary.median),
+                mango_group.oracle_max_divergences[i]
+            )?;
+        }
 
         let base_adj = U64F64::from_num(10u64.pow(mango_group.mint_decimals[i] as u32));
         prices[i] = quote_adj
             .checked_div(base_adj).unwrap()
             .checked_mul(value).unwrap();
+        check_price_move(mango_group, i, prices[i], now)?;
+        // a price too large for last_price is recorded as 0, so the next read isn't banded
+        mango_group.last_price[i] = U32F32::checked_from_num(prices[i]).unwrap_or_default();
+        mango_group.last_price_ts[i] = now as u32;
     }
     Ok(prices)
 }
 
+/// Reject price as the new oracle price of market_i at unix timestamp now when it moved from
+/// last_price by more than max_price_move_bps basis points of last_price. Passes when the band is
+/// off or no price was recorded. Rejected prices aren't recorded, so once the market has gone
+/// price_band_timeout seconds without an accepted read the price is taken as a genuine move and
+/// accepted, instead of blocking liquidations until the admin resets the band
+pub fn check_price_move(mango_group: &MangoGroup, market_i: usize, price: U64F64, now: u64) -> MangoResult<()> {
+    let last_price = U64F64::from_num(mango_group.last_price[market_i]);
+    if mango_group.max_price_move_bps == 0 || last_price == ZERO_U64F64 {
+        return Ok(());
+    }
+    let max_gap = last_price
+        .checked_mul(U64F64::from_num(mango_group.max_price_move_bps)).ok_or(throw!())?
+        .checked_div(U64F64::from_num(10_000)).ok_or(throw!())?;
+    let gap = if price > last_price { price - last_price } else { last_price - price };
+    let elapsed = now.saturating_sub(mango_group.last_price_ts[market_i] as u64);
+    let timed_out = elapsed >= mango_group.price_band_timeout as u64;
+    check!(gap <= max_gap || timed_out, MangoErrorCode::PriceMoveTooLarge)
+}
+
 fn invoke_settle_funds<'a>(
     dex_prog_acc: &AccountInfo<'a>,
     spot_market_acc: &AccountInfo<'a>,
@@ -1737,6 +4745,316 @@ fn invoke_settle_funds<'a>(
     solana_program::program::invoke_signed(&instruction, &account_infos, signers_seeds)
 }
 
+fn invoke_close_open_orders<'a>(
+    dex_prog_acc: &AccountInfo<'a>,
+    open_orders_acc: &AccountInfo<'a>,
+    signer_acc: &AccountInfo<'a>,
+    destination_acc: &AccountInfo<'a>,
+    spot_market_acc: &AccountInfo<'a>,
+    signers_seeds: &[&[&[u8]]]
+) -> ProgramResult {
+    let data = serum_dex::instruction::MarketInstruction::CloseOpenOrders.pack();
+    let instruction = Instruction {
+        program_id: *dex_prog_acc.key,
+        data,
+        accounts: vec![
+            AccountMeta::new(*open_orders_acc.key, false),
+            AccountMeta::new_readonly(*signer_acc.key, true),
+            AccountMeta::new(*destination_acc.key, false),
+            AccountMeta::new_readonly(*spot_market_acc.key, false),
+        ],
+    };
+
+    let account_infos = [
+        dex_prog_acc.clone(),
+        open_orders_acc.clone(),
+        signer_acc.clone(),
+        destination_acc.clone(),
+        spot_market_acc.clone(),
+    ];
+    solana_program::program::invoke_signed(&instruction, &account_infos, signers_seeds)
+}
+
+fn invoke_init_open_orders<'a>(
+    dex_prog_acc: &AccountInfo<'a>,
+    open_orders_acc: &AccountInfo<'a>,
+    signer_acc: &AccountInfo<'a>,
+    spot_market_acc: &AccountInfo<'a>,
+    rent_acc: &AccountInfo<'a>,
+    signers_seeds: &[&[&[u8]]]
+) -> ProgramResult {
+    let data = serum_dex::instruction::MarketInstruction::InitOpenOrders.pack();
+    let instruction = Instruction {
+        program_id: *dex_prog_acc.key,
+        data,
+        accounts: vec![
+            AccountMeta::new(*open_orders_acc.key, false),
+            AccountMeta::new_readonly(*signer_acc.key, true),
+            AccountMeta::new_readonly(*spot_market_acc.key, false),
+            AccountMeta::new_readonly(*rent_acc.key, false),
+        ],
+    };
+
+    let account_infos = [
+        dex_prog_acc.clone(),
+        open_orders_acc.clone(),
+        signer_acc.clone(),
+        spot_market_acc.clone(),
+        rent_acc.clone(),
+    ];
+    solana_program::program::invoke_signed(&instruction, &account_infos, signers_seeds)
+}
+
+/// Check the open orders account passed for market_i of an order. The first order in a market
+/// takes any uninitialized account, which the dex initializes with the order, and records it on
+/// margin_account. Once the slot is set, by an earlier order or InitOpenOrders, only that
+/// account is accepted and it must be for spot_market_pk
+pub fn check_or_assign_open_orders(
+    margin_account: &mut MarginAccount,
+    market_i: usize,
+    open_orders_acc: &AccountInfo,
+    spot_market_pk: &Pubkey,
+    signer_key: &Pubkey
+) -> MangoResult<()> {
+    check_default!(*open_orders_acc.key != Pubkey::default())?;
+    if margin_account.has_open_orders(market_i) {
+        check_eq!(open_orders_acc.key, &margin_account.open_orders[market_i], MangoErrorCode::InvalidOpenOrdersAccount)?;
+        check_open_orders(open_orders_acc, signer_key)?;
+        let open_orders = load_open_orders(open_orders_acc)?;
+        let market = open_orders.market;
+        check_eq!(market, spot_market_pk.to_aligned_bytes(), MangoErrorCode::InvalidOpenOrdersAccount)
+    } else {
+        let open_orders = load_open_orders(open_orders_acc)?;
+        check_eq_default!(open_orders.account_flags, 0)?;
+        margin_account.open_orders[market_i] = *open_orders_acc.key;
+        Ok(())
+    }
+}
+
+/// Reject closing an open orders account that still has resting orders or any free or locked
+/// funds; those must be cancelled and settled first
+pub fn check_open_orders_empty(open_orders_acc: &AccountInfo) -> MangoResult<()> {
+    let open_orders = load_open_orders(open_orders_acc)?;
+    check!(
+        open_orders.free_slot_bits == u128::MAX
+            && open_orders.native_coin_total == 0
+            && open_orders.native_pc_total == 0,
+        MangoErrorCode::OpenOrdersNotEmpty
+    )
+}
+
+/// Reject new orders for max_coin_qty lots of market_i worth less than min_order_notional
+/// at the oracle price. Dust orders would otherwise hold open orders slots for nothing
+pub fn check_min_order_notional(
+    mango_group: &MangoGroup,
+    prices: &[U64F64; NUM_TOKENS],
+    market_i: usize,
+    max_coin_qty: u64,
+    coin_lot_size: u64
+) -> MangoResult<()> {
+    let native_base = U64F64::from_num(max_coin_qty).checked_mul(U64F64::from_num(coin_lot_size));
+    let notional = native_base.and_then(|v| v.checked_mul(prices[market_i])).unwrap_or(U64F64::MAX);
+    check!(notional >= U64F64::from_num(mango_group.min_order_notional), MangoErrorCode::OrderTooSmall)
+}
+
+/// Reject a new order for max_coin_qty lots of market_i when it would take the open interest of
+/// the market over oi_limits, valued in native quote at the oracle price. Positions aren't
+/// aggregated across accounts, so the open interest is approximated by the group's total native
+/// borrows of the base token: every short borrowed the base it sold. The part of the order that
+/// closes margin_account's position (an ask up to its base deposit, a bid up to its base borrow)
+/// is exempt; the rest counts on either side, since a bid can be filled by a new short. 0 means
+/// no cap
+pub fn check_open_interest(
+    mango_group: &MangoGroup,
+    margin_account: &MarginAccount,
+    prices: &[U64F64; NUM_TOKENS],
+    market_i: usize,
+    side: Side,
+    max_coin_qty: u64,
+    coin_lot_size: u64
+) -> MangoResult<()> {
+    let oi_limit = mango_group.oi_limits[market_i];
+    if oi_limit == 0 {
+        return Ok(());
+    }
+    let index = &mango_group.indexes[market_i];
+    let position = match side {
+        Side::Bid => margin_account.get_native_borrow(index, market_i),
+        Side::Ask => margin_account.get_native_deposit(index, market_i)
+    };
+    let order_base = max_coin_qty.saturating_mul(coin_lot_size).saturating_sub(position);
+    if order_base == 0 {
+        return Ok(());
+    }
+    let open_interest = mango_group.get_total_native_borrow(market_i).ok()
+        .and_then(|v| U64F64::from_num(order_base).checked_add(U64F64::from_num(v)))
+        .and_then(|v| v.checked_mul(prices[market_i]))
+        .unwrap_or(U64F64::MAX);
+    check!(open_interest <= U64F64::from_num(oi_limit), MangoErrorCode::OpenInterestLimitExceeded)
+}
+
+/// Compare the quote vault before and after a settled order against worst_fill: asks must have
+/// received at least worst_fill and bids spent at most worst_fill. 0 skips the check
+pub fn check_worst_fill(
+    side: Side,
+    pre_quote: u64,
+    post_quote: u64,
+    worst_fill: u64
+) -> MangoResult<()> {
+    if worst_fill == 0 {
+        return Ok(());
+    }
+    match side {
+        Side::Ask => check!(
+            post_quote.saturating_sub(pre_quote) >= worst_fill, MangoErrorCode::SlippageExceeded
+        ),
+        Side::Bid => check!(
+            pre_quote.saturating_sub(post_quote) <= worst_fill, MangoErrorCode::SlippageExceeded
+        )
+    }
+}
+
+/// Reject a new order in market_i if the account already has max_orders_per_market orders there.
+/// num_open_orders must be up to date; every open orders slot costs liquidators compute to cancel
+pub fn check_max_orders_per_market(
+    mango_group: &MangoGroup,
+    margin_account: &MarginAccount,
+    market_i: usize
+) -> MangoResult<()> {
+    let max_orders = mango_group.max_orders_per_market;
+    check!(
+        max_orders == 0 || (margin_account.num_open_orders[market_i] as u64) < max_orders,
+        MangoErrorCode::TooManyOpenOrders
+    )
+}
+
+/// Size the IOC order ForceLiquidateOnDex sends for market_i. If the base token is borrowed,
+/// bid for it with the quote deposits; if the quote token is borrowed, sell base deposits for it.
+/// The limit price is the oracle price moved by FORCE_LIQ_MAX_SLIPPAGE, in serum lots.
+/// Returns None if there is no position to unwind that this market can help with
+pub fn get_force_liquidation_order(
+    mango_group: &MangoGroup,
+    margin_account: &MarginAccount,
+    prices: &[U64F64; NUM_TOKENS],
+    market_i: usize,
+    coin_lot_size: u64,
+    pc_lot_size: u64
+) -> MangoResult<Option<serum_dex::instruction::NewOrderInstructionV3>> {
+    let base_index = &mango_group.indexes[market_i];
+    let quote_index = &mango_group.indexes[QUOTE_INDEX];
+    let native_base_borrow = margin_account.borrows[market_i].checked_mul(base_index.borrow).ok_or(throw!())?;
+    let native_base_deposit = margin_account.deposits[market_i].checked_mul(base_index.deposit).ok_or(throw!())?;
+    let native_quote_borrow = margin_account.borrows[QUOTE_INDEX].checked_mul(quote_index.borrow).ok_or(throw!())?;
+    let native_quote_deposit = margin_account.deposits[QUOTE_INDEX].checked_mul(quote_index.deposit).ok_or(throw!())?;
+
+    let coin_lot = U64F64::from_num(coin_lot_size);
+    let lot_adj = coin_lot.checked_div(U64F64::from_num(pc_lot_size)).ok_or(throw!())?;
+
+    let (side, limit_price, max_coin_qty, max_native_pc_qty) = if native_base_borrow > ZERO_U64F64
+        && native_quote_deposit >= ONE_U64F64 {
+        let limit_price = prices[market_i].checked_mul(ONE_U64F64 + FORCE_LIQ_MAX_SLIPPAGE).ok_or(throw!())?;
+        (
+            Side::Bid,
+            limit_price.checked_mul(lot_adj).ok_or(throw!())?.checked_ceil().ok_or(throw!())?.to_num::<u64>(),
+            native_base_borrow.checked_div(coin_lot).ok_or(throw!())?.checked_ceil().ok_or(throw!())?.to_num::<u64>(),
+            native_quote_deposit.to_num::<u64>()
+        )
+    } else if native_quote_borrow > ZERO_U64F64 && native_base_deposit > ZERO_U64F64 {
+        let limit_price = prices[market_i].checked_mul(ONE_U64F64 - FORCE_LIQ_MAX_SLIPPAGE).ok_or(throw!())?;
+        // Enough base to cover the quote borrow even at the worst allowed price
+        let base_needed = native_quote_borrow.checked_div(limit_price).ok_or(throw!())?;
+        let max_coin_qty = min(
+            base_needed.checked_div(coin_lot).ok_or(throw!())?.checked_ceil().ok_or(throw!())?,
+            native_base_deposit.checked_div(coin_lot).ok_or(throw!())?.checked_floor().ok_or(throw!())?
+        );
+        (
+            Side::Ask,
+            limit_price.checked_mul(lot_adj).ok_or(throw!())?.checked_floor().ok_or(throw!())?.to_num::<u64>(),
+            max_coin_qty.to_num::<u64>(),
+            u64::MAX
+        )
+    } else {
+        return Ok(None);
+    };
+
+    let (limit_price, max_coin_qty, max_native_pc_qty_including_fees) = match (
+        NonZeroU64::new(limit_price),
+        NonZeroU64::new(max_coin_qty),
+        NonZeroU64::new(max_native_pc_qty)
+    ) {
+        (Some(p), Some(c), Some(pc)) => (p, c, pc),
+        _ => { return Ok(None); }  // position is smaller than a lot
+    };
+
+    Ok(Some(serum_dex::instruction::NewOrderInstructionV3 {
+        side,
+        limit_price,
+        max_coin_qty,
+        max_native_pc_qty_including_fees,
+        self_trade_behavior: serum_dex::instruction::SelfTradeBehavior::DecrementTake,
+        order_type: serum_dex::matching::OrderType::ImmediateOrCancel,
+        client_order_id: 0,
+        limit: u16::MAX
+    }))
+}
+
+fn invoke_new_order<'a>(
+    dex_prog_acc: &AccountInfo<'a>,
+    spot_market_acc: &AccountInfo<'a>,
+    open_orders_acc: &AccountInfo<'a>,
+    dex_request_queue_acc: &AccountInfo<'a>,
+    dex_event_queue_acc: &AccountInfo<'a>,
+    bids_acc: &AccountInfo<'a>,
+    asks_acc: &AccountInfo<'a>,
+    vault_acc: &AccountInfo<'a>,
+    signer_acc: &AccountInfo<'a>,
+    dex_base_acc: &AccountInfo<'a>,
+    dex_quote_acc: &AccountInfo<'a>,
+    token_prog_acc: &AccountInfo<'a>,
+    rent_acc: &AccountInfo<'a>,
+    srm_vault_acc: &AccountInfo<'a>,
+    order: serum_dex::instruction::NewOrderInstructionV3,
+    signers_seeds: &[&[&[u8]]]
+) -> ProgramResult {
+    let data = serum_dex::instruction::MarketInstruction::NewOrderV3(order).pack();
+    let instruction = Instruction {
+        program_id: *dex_prog_acc.key,
+        data,
+        accounts: vec![
+            AccountMeta::new(*spot_market_acc.key, false),
+            AccountMeta::new(*open_orders_acc.key, false),
+            AccountMeta::new(*dex_request_queue_acc.key, false),
+            AccountMeta::new(*dex_event_queue_acc.key, false),
+            AccountMeta::new(*bids_acc.key, false),
+            AccountMeta::new(*asks_acc.key, false),
+            AccountMeta::new(*vault_acc.key, false),
+            AccountMeta::new_readonly(*signer_acc.key, true),
+            AccountMeta::new(*dex_base_acc.key, false),
+            AccountMeta::new(*dex_quote_acc.key, false),
+            AccountMeta::new_readonly(*token_prog_acc.key, false),
+            AccountMeta::new_readonly(*rent_acc.key, false),
+            AccountMeta::new(*srm_vault_acc.key, false),
+        ],
+    };
+    let account_infos = [
+        dex_prog_acc.clone(),  // Have to add account of the program id
+        spot_market_acc.clone(),
+        open_orders_acc.clone(),
+        dex_request_queue_acc.clone(),
+        dex_event_queue_acc.clone(),
+        bids_acc.clone(),
+        asks_acc.clone(),
+        vault_acc.clone(),
+        signer_acc.clone(),
+        dex_base_acc.clone(),
+        dex_quote_acc.clone(),
+        token_prog_acc.clone(),
+        rent_acc.clone(),
+        srm_vault_acc.clone(),
+    ];
+    solana_program::program::invoke_signed(&instruction, &account_infos, signers_seeds)
+}
+
 fn invoke_cancel_order<'a>(
     dex_prog_acc: &AccountInfo<'a>,
     spot_market_acc: &AccountInfo<'a>,
@@ -1774,6 +5092,38 @@ fn invoke_cancel_order<'a>(
     solana_program::program::invoke_signed(&instruction, &account_infos, signers_seeds)
 }
 
+fn invoke_consume_events<'a>(
+    dex_prog_acc: &AccountInfo<'a>,
+    open_orders_accs: &[AccountInfo<'a>],
+    spot_market_acc: &AccountInfo<'a>,
+    dex_event_queue_acc: &AccountInfo<'a>,
+    coin_fee_receivable_acc: &AccountInfo<'a>,
+    pc_fee_receivable_acc: &AccountInfo<'a>,
+    limit: u16
+) -> ProgramResult {
+    let data = serum_dex::instruction::MarketInstruction::ConsumeEvents(limit).pack();
+    let mut accounts: Vec<AccountMeta> = open_orders_accs.iter()
+        .map(|acc| AccountMeta::new(*acc.key, false))
+        .collect();
+    accounts.extend_from_slice(&[
+        AccountMeta::new(*spot_market_acc.key, false),
+        AccountMeta::new(*dex_event_queue_acc.key, false),
+        AccountMeta::new(*coin_fee_receivable_acc.key, false),
+        AccountMeta::new(*pc_fee_receivable_acc.key, false),
+    ]);
+    let instruction = Instruction { program_id: *dex_prog_acc.key, data, accounts };
+
+    let mut account_infos = vec![dex_prog_acc.clone()];
+    account_infos.extend(open_orders_accs.iter().cloned());
+    account_infos.extend_from_slice(&[
+        spot_market_acc.clone(),
+        dex_event_queue_acc.clone(),
+        coin_fee_receivable_acc.clone(),
+        pc_fee_receivable_acc.clone(),
+    ]);
+    solana_program::program::invoke(&instruction, &account_infos)
+}
+
 fn invoke_cancel_orders<'a>(
     open_orders_acc: &AccountInfo<'a>,
     dex_prog_acc: &AccountInfo<'a>,
@@ -1828,38 +5178,142 @@ fn invoke_cancel_orders<'a>(
         }
     }
 
-    let mut instruction = Instruction {
-        program_id: *dex_prog_acc.key,
-        data: vec![],
-        accounts: vec![
-            AccountMeta::new(*spot_market_acc.key, false),
-            AccountMeta::new(*bids_acc.key, false),
-            AccountMeta::new(*asks_acc.key, false),
-            AccountMeta::new(*open_orders_acc.key, false),
-            AccountMeta::new_readonly(*signer_acc.key, true),
-            AccountMeta::new(*dex_event_queue_acc.key, false),
-        ],
-    };
+    for cancel in cancels.into_iter() {
+        let data = serum_dex::instruction::MarketInstruction::CancelOrderV2(cancel).pack();
+        invoke_cancel_order(dex_prog_acc, spot_market_acc, bids_acc, asks_acc, open_orders_acc, signer_acc,
+                            dex_event_queue_acc, data, signers_seeds)?;
+    }
 
-    let account_infos = [
-        dex_prog_acc.clone(),
-        spot_market_acc.clone(),
-        bids_acc.clone(),
-        asks_acc.clone(),
-        open_orders_acc.clone(),
-        signer_acc.clone(),
-        dex_event_queue_acc.clone()
-    ];
+    Ok(())
+}
+
+/// Initialize an empty MangoSrmAccount for owner_acc and return it loaded
+fn init_mango_srm_account<'a>(
+    program_id: &Pubkey,
+    mango_srm_account_acc: &'a AccountInfo,
+    mango_group_pk: &Pubkey,
+    owner_acc: &AccountInfo,
+    rent_acc: &AccountInfo
+) -> MangoResult<RefMut<'a, MangoSrmAccount>> {
+    check_eq_default!(mango_srm_account_acc.owner, program_id)?;
+    check_eq_default!(mango_srm_account_acc.data_len(), size_of::<MangoSrmAccount>())?;
+    let rent = Rent::from_account_info(rent_acc)?;
+    check_default!(rent.is_exempt(mango_srm_account_acc.lamports(), size_of::<MangoSrmAccount>()))?;
+    check!(owner_acc.is_signer, MangoErrorCode::SignerNecessary)?;  // this is not necessary but whatever
+
+    let mut mango_srm_account = MangoSrmAccount::load_mut(mango_srm_account_acc)?;
+    check_eq_default!(mango_srm_account.account_flags, 0)?;
+    mango_srm_account.account_flags = (AccountFlag::Initialized | AccountFlag::MangoSrmAccount).bits();
+    mango_srm_account.mango_group = *mango_group_pk;
+    mango_srm_account.owner = *owner_acc.key;
+    mango_srm_account.amount = 0;
+    Ok(mango_srm_account)
+}
 
-    for cancel in cancels.iter() {
-        let cancel_instruction = serum_dex::instruction::MarketInstruction::CancelOrderV2(cancel.clone());
-        instruction.data = cancel_instruction.pack();
-        solana_program::program::invoke_signed(&instruction, &account_infos, signers_seeds)?;
+/// Number of extra accounts needed by deposit and withdraw when the token is native SOL:
+/// system program, native mint and rent sysvar
+const NUM_NATIVE_SOL_ACCS: usize = 3;
+
+/// Create new_acc at a program derived address signed for by seeds, with payer_acc topping it up
+/// to lamports. Anyone can send lamports to the address before it exists, which would make
+/// system_instruction::create_account fail, so the account is funded, allocated and assigned
+/// in separate steps instead
+fn invoke_create_pda_account<'a>(
+    payer_acc: &AccountInfo<'a>,
+    new_acc: &AccountInfo<'a>,
+    system_prog_acc: &AccountInfo<'a>,
+    lamports: u64,
+    space: u64,
+    owner: &Pubkey,
+    seeds: &[&[u8]]
+) -> MangoResult<()> {
+    let top_up = lamports.saturating_sub(new_acc.lamports());
+    if top_up > 0 {
+        let transfer_instruction = system_instruction::transfer(payer_acc.key, new_acc.key, top_up);
+        solana_program::program::invoke(
+            &transfer_instruction,
+            &[payer_acc.clone(), new_acc.clone(), system_prog_acc.clone()]
+        )?;
     }
 
+    let allocate_instruction = system_instruction::allocate(new_acc.key, space);
+    solana_program::program::invoke_signed(
+        &allocate_instruction,
+        &[new_acc.clone(), system_prog_acc.clone()],
+        &[seeds]
+    )?;
+    let assign_instruction = system_instruction::assign(new_acc.key, owner);
+    solana_program::program::invoke_signed(
+        &assign_instruction,
+        &[new_acc.clone(), system_prog_acc.clone()],
+        &[seeds]
+    )?;
+    Ok(())
+}
+
+/// Create the temporary wrapped SOL account of a margin account, funded by owner_acc with rent plus
+/// quantity lamports. owner_acc is the token account owner so it can transfer from and close it
+fn invoke_create_wrapped_sol<'a>(
+    program_id: &Pubkey,
+    margin_account_acc: &AccountInfo<'a>,
+    wrapped_acc: &AccountInfo<'a>,
+    owner_acc: &AccountInfo<'a>,
+    native_accs: &[AccountInfo<'a>],
+    token_prog_acc: &AccountInfo<'a>,
+    quantity: u64
+) -> MangoResult<()> {
+    let native_accs = array_ref![native_accs, 0, NUM_NATIVE_SOL_ACCS];
+    let [
+        system_prog_acc,
+        native_mint_acc,
+        rent_acc,
+    ] = native_accs;
+
+    check_eq_default!(system_prog_acc.key, &solana_program::system_program::id())?;
+    check_eq_default!(native_mint_acc.key, &spl_token::native_mint::id())?;
+    let (wrapped_pk, bump) = get_wrapped_sol_address(program_id, margin_account_acc.key);
+    check_eq_default!(wrapped_acc.key, &wrapped_pk)?;
+
+    let rent = Rent::from_account_info(rent_acc)?;
+    let lamports = rent.minimum_balance(Account::LEN).checked_add(quantity).ok_or(throw!())?;
+    let bump_seed = [bump];
+    let wrapped_seeds: [&[u8]; 3] = [margin_account_acc.key.as_ref(), WRAPPED_SOL_SEED, &bump_seed];
+    invoke_create_pda_account(
+        owner_acc, wrapped_acc, system_prog_acc, lamports, Account::LEN as u64, &spl_token::id(), &wrapped_seeds
+    )?;
+
+    let init_instruction = spl_token::instruction::initialize_account(
+        &spl_token::id(),
+        wrapped_acc.key,
+        native_mint_acc.key,
+        owner_acc.key
+    )?;
+    solana_program::program::invoke(
+        &init_instruction,
+        &[wrapped_acc.clone(), native_mint_acc.clone(), owner_acc.clone(), rent_acc.clone(), token_prog_acc.clone()]
+    )?;
     Ok(())
 }
 
+/// Close the temporary wrapped SOL account, returning all its lamports to owner_acc as native SOL
+fn invoke_close_wrapped_sol<'a>(
+    wrapped_acc: &AccountInfo<'a>,
+    owner_acc: &AccountInfo<'a>,
+    token_prog_acc: &AccountInfo<'a>
+) -> ProgramResult {
+    let close_instruction = spl_token::instruction::close_account(
+        &spl_token::id(),
+        wrapped_acc.key,
+        owner_acc.key,
+        owner_acc.key,
+        &[]
+    )?;
+    solana_program::program::invoke(
+        &close_instruction,
+        &[wrapped_acc.clone(), owner_acc.clone(), token_prog_acc.clone()]
+    )
+}
+
 fn invoke_transfer<'a>(
     token_prog_acc: &AccountInfo<'a>,
     source_acc: &AccountInfo<'a>,
@@ -1890,13 +5344,14 @@ fn invoke_transfer<'a>(
 fn get_in_out_quantities(
     mango_group: &mut MangoGroup,
     margin_account: &mut MarginAccount,
-    open_orders_accs: &[AccountInfo; NUM_MARKETS],
+    open_orders_totals: &OpenOrdersTotals,
     prices: &[U64F64; NUM_TOKENS],
     in_token_index: usize,
     out_token_index: usize,
-    liqor_max_in: u64
+    liqor_max_in: u64,
+    now: u64
 ) -> MangoResult<(u64, u64)> {
-    let deficit_val = margin_account.get_partial_liq_deficit(&mango_group, &prices, open_orders_accs)? + ONE_U64F64;
+    let deficit_val = margin_account.get_partial_liq_deficit(&mango_group, &prices, open_orders_totals, now)? + ONE_U64F64;
     let out_avail: U64F64 = margin_account.deposits[out_token_index].checked_mul(mango_group.indexes[out_token_index].deposit).unwrap();
     let out_avail_val = out_avail * prices[out_token_index];
 
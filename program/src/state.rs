@@ -1,24 +1,48 @@
 use std::cell::{Ref, RefMut};
+use std::cmp::min;
 use std::convert::identity;
 use std::mem::size_of;
 
 use bytemuck::{cast_slice, cast_slice_mut, from_bytes, from_bytes_mut, Pod, try_from_bytes, try_from_bytes_mut, Zeroable};
 use enumflags2::BitFlags;
-use fixed::types::U64F64;
+use fixed::types::{U32F32, U64F64};
+use serum_dex::matching::Side;
 use serum_dex::state::ToAlignedBytes;
 use solana_program::account_info::AccountInfo;
 use solana_program::clock::Clock;
 use solana_program::program_error::ProgramError;
+use solana_program::program_pack::Pack;
 use solana_program::pubkey::Pubkey;
 
 use fixed_macro::types::U64F64;
+use static_assertions::const_assert_eq;
 
 use crate::error::{check_assert, MangoResult, SourceFileId, MangoErrorCode, MangoError};
 
 /// Initially launching with BTC/USDT, ETH/USDT
 pub const NUM_TOKENS: usize = 3;
 pub const NUM_MARKETS: usize = NUM_TOKENS - 1;
-pub const MANGO_GROUP_PADDING: usize = 8 - (NUM_TOKENS + NUM_MARKETS) % 8;
+/// Index of the quote currency in every per token array. Each market trades token i against it
+pub const QUOTE_INDEX: usize = NUM_TOKENS - 1;
+const_assert_eq!(QUOTE_INDEX, NUM_MARKETS);
+/// Pads the u8 fields of MangoGroup so the two u16 fields after them end 8 byte aligned
+pub const MANGO_GROUP_PADDING: usize = 8 - (NUM_TOKENS + 3 * NUM_MARKETS + 2 + 4) % 8;
+/// Bytes at the end of MangoGroup set aside for new fields. Take new fields out of this so the
+/// account size stays at MANGO_GROUP_SIZE. Starts 8 byte aligned
+pub const MANGO_GROUP_RESERVED: usize = 0;
+/// Total size of MangoGroup in bytes: 1288 of fields and padding plus MANGO_GROUP_RESERVED
+pub const MANGO_GROUP_SIZE: usize = 1288;
+/// Version 2 grew MangoGroup for the borrow limit ramp; version 1 groups can't be loaded anymore
+/// and accounts are moved out of them with MigrateMarginAccount, see MangoGroupV1
+pub const MANGO_GROUP_VERSION: u8 = 2;
+/// Size in bytes of v1 MangoGroups; see MangoGroupV1
+pub const MANGO_GROUP_V1_SIZE: usize = 1176;
+pub const MANGO_GROUP_V1_PADDING: usize = 8 - (NUM_TOKENS + 2 * NUM_MARKETS + 2) % 8;
+/// Version 2 grew MarginAccount past MARGIN_ACCOUNT_V1_SIZE; older accounts are moved over
+/// with UpgradeMarginAccount
+pub const MARGIN_ACCOUNT_VERSION: u8 = 2;
+/// Size in bytes of v0 and v1 MarginAccounts, which end right after padding
+pub const MARGIN_ACCOUNT_V1_SIZE: usize = 240;
 pub const MINUTE: u64 = 60;
 pub const HOUR: u64 = 3600;
 pub const DAY: u64 = 86400;
@@ -30,8 +54,22 @@ const MAX_R: U64F64 = U64F64!(9.5129375951293759512937e-08); // max 300% APY ->
 pub const ONE_U64F64: U64F64 = U64F64!(1);
 pub const ZERO_U64F64: U64F64 = U64F64!(0);
 pub const PARTIAL_LIQ_INCENTIVE: U64F64 = U64F64!(1.05);
+pub const MAX_MAINT_COLL_RATIO_STEP: U64F64 = U64F64!(0.05);  // max increase of maint_coll_ratio per SetCollRatios
 pub const DUST_THRESHOLD: U64F64 = U64F64!(0.01);  // TODO make this part of MangoGroup state
 pub const EPSILON: U64F64 = U64F64!(1.0e-17);
+pub const INDEX_ROUNDING_TOLERANCE: U64F64 = U64F64!(1);  // in native units
+pub const GRACE_COLL_WEIGHT: U64F64 = U64F64!(0.5);  // scales coll_weights for deposits within collateral_grace_period
+pub const LIQ_DEPOSIT_TOLERANCE: U64F64 = U64F64!(0.1);  // portion of the collateral deficit a liqor may deposit on top
+pub const MAX_SOCIALIZED_LOSS: U64F64 = U64F64!(0.99);  // max portion of lenders' deposits one socialized loss takes; keeps the deposit index above zero
+
+/// Native SRM (6 decimals) needed for each serum dex fee discount tier above the base tier
+pub const SRM_FEE_TIER_THRESHOLDS: [u64; 5] = [
+    100_000_000,
+    1_000_000_000,
+    10_000_000_000,
+    100_000_000_000,
+    1_000_000_000_000
+];
 
 macro_rules! check_default {
     ($cond:expr) => {
@@ -43,6 +81,16 @@ macro_rules! check_eq_default {
         check_assert($x == $y, MangoErrorCode::Default, line!(), SourceFileId::State)
     }
 }
+macro_rules! check {
+    ($cond:expr, $err:expr) => {
+        check_assert($cond, $err, line!(), SourceFileId::State)
+    }
+}
+macro_rules! check_eq {
+    ($x:expr, $y:expr, $err:expr) => {
+        check_assert($x == $y, $err, line!(), SourceFileId::State)
+    }
+}
 
 macro_rules! throw {
     () => {
@@ -87,6 +135,12 @@ pub enum AccountFlag {
     MangoSrmAccount = 1u64 << 3
 }
 
+#[derive(Copy, Clone, BitFlags, Debug, Eq, PartialEq)]
+#[repr(u8)]
+pub enum MarketFlag {
+    ReduceOnly = 1u8 << 0
+}
+
 
 #[derive(Copy, Clone)]
 #[repr(C)]
@@ -131,9 +185,43 @@ pub struct MangoGroup {
     pub admin: Pubkey,
     pub borrow_limits: [u64; NUM_TOKENS],
 
+    pub reserve_factor: U64F64,  // portion of borrow interest kept by the protocol
+    pub fees_accrued: [U64F64; NUM_TOKENS],  // denominated in Mango index adjusted deposit terms
+
+    pub coll_weights: [U64F64; NUM_TOKENS],  // portion of deposit value counted as collateral; 1 for quote
+    pub liab_weights: [U64F64; NUM_TOKENS],  // multiplier on borrow value counted as liability; 1 for quote
+    pub liquidation_fee: U64F64,  // portion of a liquidator's deposits paid back to them out of the liqee's deposits
+    pub oracle_max_divergences: [U64F64; NUM_MARKETS],  // max relative gap between primary and secondary oracle
+    pub deposit_limits: [u64; NUM_TOKENS],  // max total native deposits per token; 0 means unlimited
+    pub dust_thresholds: [u64; NUM_TOKENS],  // native positions left below this are zeroed; 0 means off
+    pub oracles_secondary: [Pubkey; NUM_MARKETS],  // optional; default pubkey means only the primary oracle is used
+    pub pending_admin: Pubkey,  // proposed by SetAdmin, becomes admin on AcceptAdmin; default if none
+    pub min_order_notional: u64,  // min value in native quote of a new order at the oracle price; 0 means off
+    pub srm_vault_balance: u64,  // native SRM in srm_vault as of the last DepositSrm or WithdrawSrm
+    pub max_borrow_per_account: [u64; NUM_TOKENS],  // max native borrow of one MarginAccount; 0 means no cap
+    pub collateral_grace_period: u64,  // seconds after a deposit during which it counts at GRACE_COLL_WEIGHT; 0 means off
+    pub borrow_limit_target: [u64; NUM_TOKENS],  // borrow_limits moves linearly toward this; see get_borrow_limit
+    pub borrow_limit_start_ts: u64,  // unix timestamp the ramp of every token started at
+    pub borrow_limit_ramp_seconds: u64,  // length of the ramp shared by every token; 0 means no ramp
+    pub bad_debt: [u64; NUM_TOKENS],  // native losses written off that neither lenders nor fees_accrued covered; see write_off
+    pub last_price_ts: [u32; NUM_MARKETS],  // unix timestamp of the last accepted read of each market
+    pub max_orders_per_market: u64,  // max orders one MarginAccount may have resting in a market; 0 means no cap
+    pub total_user_srm: u64,  // native SRM in srm_vault owed to MangoSrmAccounts; the rest belongs to the protocol
+    pub max_accrual_interval: u64,  // max seconds of interest accrued by one update_indexes; 0 means no cap
+    pub liquidation_cooldown: u64,  // seconds after a liquidation during which the account must be below maint to be liquidated again; 0 means off
+    pub oi_limits: [u64; NUM_MARKETS],  // max open interest per market in native quote at the oracle price; 0 means no cap; see check_open_interest
+
     pub mint_decimals: [u8; NUM_TOKENS],
     pub oracle_decimals: [u8; NUM_MARKETS],
-    pub padding: [u8; MANGO_GROUP_PADDING]
+    pub oracle_types: [u8; NUM_MARKETS],  // OracleType of each market's primary oracle
+    pub market_flags: [u8; NUM_MARKETS],  // MarketFlag bits for each spot market
+    pub is_halted: bool,  // blocks deposits, withdrawals, borrows and new orders; unwinding is still allowed
+    pub version: u8,  // layout version, must equal MANGO_GROUP_VERSION
+    pub padding: [u8; MANGO_GROUP_PADDING],
+    pub max_price_move_bps: u16,  // max move of an oracle price from last_price in basis points; 0 means off; see check_price_move
+    pub price_band_timeout: u16,  // seconds without an accepted read after which any price is accepted again
+    pub last_price: [U32F32; NUM_MARKETS],  // oracle price of each market at the last accepted read; 0 before the first
+    pub reserved: [u8; MANGO_GROUP_RESERVED]
 }
 impl_loadable!(MangoGroup);
 
@@ -150,6 +238,7 @@ impl MangoGroup {
 
         let mango_group = Self::load_mut(account)?;
         check_eq_default!(mango_group.account_flags, (AccountFlag::Initialized | AccountFlag::MangoGroup).bits())?;
+        check_eq!(mango_group.version, MANGO_GROUP_VERSION, MangoErrorCode::InvalidAccountVersion)?;
 
         Ok(mango_group)
     }
@@ -162,6 +251,7 @@ impl MangoGroup {
 
         let mango_group = Self::load(account)?;
         check_eq_default!(mango_group.account_flags, (AccountFlag::Initialized | AccountFlag::MangoGroup).bits())?;
+        check_eq!(mango_group.version, MANGO_GROUP_VERSION, MangoErrorCode::InvalidAccountVersion)?;
 
         Ok(mango_group)
     }
@@ -171,16 +261,40 @@ impl MangoGroup {
     pub fn get_token_index_with_vault(&self, vault: &Pubkey) -> Option<usize> {
         self.vaults.iter().position(|pk| pk == vault)
     }
-    /// interest is in units per second (e.g. 0.01 => 1% interest per second)
-    pub fn get_interest_rate(&self, token_index: usize) -> U64F64 {
+    /// Write off native_quantity of token_i the protocol can't collect. It is taken out of
+    /// fees_accrued first and whatever fees_accrued can't cover is added to bad_debt, rounded up
+    pub fn write_off(&mut self, token_i: usize, native_quantity: U64F64) -> MangoResult<()> {
+        let deposit_index = self.indexes[token_i].deposit;
+        let quantity = native_quantity.checked_div(deposit_index).ok_or(throw!())?;
+        let covered = min(quantity, self.fees_accrued[token_i]);
+        self.fees_accrued[token_i] -= covered;
+
+        let native_uncovered: u64 = (quantity - covered).checked_mul(deposit_index).ok_or(throw!())?
+            .checked_ceil().ok_or(throw!())?.checked_to_num().ok_or(throw!())?;
+        self.bad_debt[token_i] = self.bad_debt[token_i].checked_add(native_uncovered).ok_or(throw!())?;
+        Ok(())
+    }
+    /// Serum dex fee tier the group's orders get from the SRM in srm_vault; see get_srm_fee_tier
+    pub fn get_group_fee_tier(&self) -> u8 {
+        get_srm_fee_tier(self.srm_vault_balance)
+    }
+    /// native borrows / native deposits; 0 when there are no deposits
+    pub fn get_utilization(&self, token_index: usize) -> U64F64 {
         let index: &MangoIndex = &self.indexes[token_index];
         let native_deposits = index.deposit.checked_mul(self.total_deposits[token_index]).unwrap();
+        if native_deposits == ZERO_U64F64 {
+            return ZERO_U64F64;
+        }
         let native_borrows = index.borrow.checked_mul(self.total_borrows[token_index]).unwrap();
-        if native_deposits <= native_borrows {  // if deps == 0, this is always true
+        native_borrows.checked_div(native_deposits).unwrap()
+    }
+    /// interest is in units per second (e.g. 0.01 => 1% interest per second)
+    pub fn get_interest_rate(&self, token_index: usize) -> U64F64 {
+        let utilization = self.get_utilization(token_index);
+        if self.total_deposits[token_index] == ZERO_U64F64 || utilization >= ONE_U64F64 {
             return MAX_R;  // kind of an error state
         }
 
-        let utilization = native_borrows.checked_div(native_deposits).unwrap();
         if utilization > OPTIMAL_UTIL {
             let extra_util = utilization - OPTIMAL_UTIL;
             let slope = (MAX_R - OPTIMAL_R) / (ONE_U64F64 - OPTIMAL_UTIL);
@@ -190,19 +304,58 @@ impl MangoGroup {
             slope * utilization
         }
     }
+    /// Yearly borrow rate at the current utilization, e.g. 0.2 for 20%. Not compounded
+    pub fn get_borrow_apy(&self, token_index: usize) -> U64F64 {
+        self.get_interest_rate(token_index).checked_mul(YEAR).unwrap()
+    }
+    /// Yearly rate depositors earn at the current utilization, after reserves. Not compounded
+    pub fn get_deposit_apy(&self, token_index: usize) -> U64F64 {
+        self.get_borrow_apy(token_index)
+            .checked_mul(self.get_utilization(token_index)).unwrap()
+            .checked_mul(ONE_U64F64 - self.reserve_factor).unwrap()
+    }
     pub fn update_indexes(&mut self, clock: &Clock) -> MangoResult<()> {
         // TODO verify what happens if total_deposits < total_borrows
-        // TODO verify what happens if total_deposits == 0 && total_borrows > 0
         // TODO What are cases where borrows is greater than deposits?
         // TODO total_borrows may be greater than total_deposits if rounding error
 
         let curr_ts = clock.unix_timestamp as u64;
+        let max_accrual_interval = self.max_accrual_interval;
 
         for i in 0..NUM_TOKENS {
             let interest_rate = self.get_interest_rate(i);
+            let utilization = self.get_utilization(i);
             let index: &mut MangoIndex = &mut self.indexes[i];
-            if index.last_update == curr_ts || self.total_deposits[i] == ZERO_U64F64 {
-                // TODO is skipping when total_deposits == 0 correct move?
+            if index.last_update == curr_ts {
+                continue;
+            }
+
+            // A clock jump or a long dormant token accrues at most max_accrual_interval per update
+            // so the index can't explode in one step. Later updates catch up on the rest
+            let mut elapsed = curr_ts - index.last_update;
+            if max_accrual_interval != 0 {
+                elapsed = min(elapsed, max_accrual_interval);
+            }
+
+            if self.total_deposits[i] == ZERO_U64F64 {
+                if self.total_borrows[i] == ZERO_U64F64 {
+                    continue;
+                }
+
+                // Borrows without deposits, e.g. after every lender withdrew.
+                // Borrowers keep paying interest at MAX_R. There are no depositors to credit so the
+                // deposit index stays where it is and the interest is kept as reserves in fees_accrued
+                let native_borrows: U64F64 = self.total_borrows[i].checked_mul(index.borrow).unwrap();
+                let borrow_interest = interest_rate
+                    .checked_mul(U64F64::from_num(elapsed)).unwrap();
+                index.last_update += elapsed;
+                index.borrow = index.borrow.checked_mul(borrow_interest).unwrap()
+                    .checked_add(index.borrow).unwrap();
+
+                let native_fees = native_borrows.checked_mul(borrow_interest).unwrap();
+                if let Some(fees) = native_fees.checked_div(index.deposit) {
+                    self.fees_accrued[i] = fees.checked_add(self.fees_accrued[i]).unwrap();
+                }
                 continue;
             }
 
@@ -211,33 +364,105 @@ impl MangoGroup {
             let native_borrows: U64F64 = self.total_borrows[i].checked_mul(index.borrow).unwrap();
             check_default!(native_borrows <= native_deposits + EPSILON)?;  // to account for rounding errors
 
-            let utilization = native_borrows.checked_div(native_deposits).unwrap();
             let borrow_interest = interest_rate
-                .checked_mul(U64F64::from_num(curr_ts - index.last_update)).unwrap();
+                .checked_mul(U64F64::from_num(elapsed)).unwrap();
 
+            // depositors only earn the part of borrow interest not kept as reserves
             let deposit_interest = borrow_interest
-                .checked_mul(utilization).unwrap();
+                .checked_mul(utilization).unwrap()
+                .checked_mul(ONE_U64F64 - self.reserve_factor).unwrap();
 
-            index.last_update = curr_ts;
+            let native_borrow_interest = native_borrows.checked_mul(borrow_interest).unwrap();
+            let native_deposit_interest = native_deposits.checked_mul(deposit_interest).unwrap();
+
+            index.last_update += elapsed;
             index.borrow = index.borrow.checked_mul(borrow_interest).unwrap()
                 .checked_add(index.borrow).unwrap();
 
             index.deposit = index.deposit.checked_mul(deposit_interest).unwrap()
                 .checked_add(index.deposit).unwrap();
+
+            let native_fees = native_borrow_interest.saturating_sub(native_deposit_interest);
+            self.fees_accrued[i] = native_fees.checked_div(index.deposit).unwrap()
+                .checked_add(self.fees_accrued[i]).unwrap();
+
+            // Interest credited to depositors must equal interest charged to borrowers less reserves.
+            // Multiply by (1 - reserve_factor) rather than divide so a reserve_factor of 1 is well defined
+            let deposit_increase = self.total_deposits[i].checked_mul(index.deposit).unwrap()
+                .checked_sub(native_deposits).unwrap();
+            let borrow_increase = self.total_borrows[i].checked_mul(index.borrow).unwrap()
+                .checked_sub(native_borrows).unwrap();
+            let expected_increase = borrow_increase.checked_mul(ONE_U64F64 - self.reserve_factor).unwrap();
+            let diff = if deposit_increase > expected_increase {
+                deposit_increase - expected_increase
+            } else {
+                expected_increase - deposit_increase
+            };
+            check_default!(diff <= INDEX_ROUNDING_TOLERANCE)?;
         }
         Ok(())
     }
 
-    pub fn has_valid_deposits_borrows(&self, token_i: usize) -> bool {
-        self.get_total_native_deposit(token_i) >= self.get_total_native_borrow(token_i)
+    pub fn has_valid_deposits_borrows(&self, token_i: usize) -> MangoResult<bool> {
+        Ok(self.get_total_native_deposit(token_i)? >= self.get_total_native_borrow(token_i)?)
     }
-    pub fn get_total_native_borrow(&self, token_i: usize) -> u64 {
-        let native: U64F64 = self.total_borrows[token_i] * self.indexes[token_i].borrow;
-        native.checked_ceil().unwrap().to_num()  // rounds toward +inf
+    /// Native total borrows of token_i, rounded up. Errors rather than truncating when the total
+    /// is above u64::MAX - 1, the largest native total that can be rounded up into a u64
+    pub fn get_total_native_borrow(&self, token_i: usize) -> MangoResult<u64> {
+        let native: U64F64 = self.total_borrows[token_i].checked_mul(self.indexes[token_i].borrow).ok_or(throw!())?;
+        native.checked_ceil().ok_or(throw!())?.checked_to_num().ok_or(throw!())  // rounds toward +inf
     }
-    pub fn get_total_native_deposit(&self, token_i: usize) -> u64 {
-        let native: U64F64 = self.total_deposits[token_i] * self.indexes[token_i].deposit;
-        native.checked_floor().unwrap().to_num()  // rounds toward -inf
+    /// Native total deposits of token_i, rounded down. Errors when the total doesn't fit in a
+    /// U64F64, i.e. is u64::MAX + 1 or more
+    pub fn get_total_native_deposit(&self, token_i: usize) -> MangoResult<u64> {
+        let native: U64F64 = self.total_deposits[token_i].checked_mul(self.indexes[token_i].deposit).ok_or(throw!())?;
+        native.checked_floor().ok_or(throw!())?.checked_to_num().ok_or(throw!())  // rounds toward -inf
+    }
+    /// Borrow limit of token_i at unix timestamp now. Without a ramp this is borrow_limits; during
+    /// a ramp it moves linearly from borrow_limits to borrow_limit_target and stays there after
+    pub fn get_borrow_limit(&self, token_i: usize, now: u64) -> u64 {
+        let ramp_seconds = self.borrow_limit_ramp_seconds;
+        let start = self.borrow_limits[token_i];
+        if ramp_seconds == 0 {
+            return start;
+        }
+        let target = self.borrow_limit_target[token_i];
+        let elapsed = now.saturating_sub(self.borrow_limit_start_ts);
+        if elapsed >= ramp_seconds {
+            return target;
+        }
+
+        // elapsed < ramp_seconds so the step is smaller than |target - start| and fits in a u64
+        let step = |diff: u64| (diff as u128 * elapsed as u128 / ramp_seconds as u128) as u64;
+        if target >= start {
+            start + step(target - start)
+        } else {
+            start - step(start - target)
+        }
+    }
+    /// Value in quote currency of all deposits and of all borrows in the group, as
+    /// (total_deposit_value, total_borrow_value). Borrows exceeding deposits means insolvency
+    pub fn get_solvency(&self, prices: &[U64F64; NUM_TOKENS]) -> (U64F64, U64F64) {
+        let mut deposits_val = ZERO_U64F64;
+        let mut borrows_val = ZERO_U64F64;
+        for i in 0..NUM_TOKENS {
+            deposits_val = deposits_val.saturating_add(
+                self.total_deposits[i].saturating_mul(self.indexes[i].deposit).saturating_mul(prices[i])
+            );
+            borrows_val = borrows_val.saturating_add(
+                self.total_borrows[i].saturating_mul(self.indexes[i].borrow).saturating_mul(prices[i])
+            );
+        }
+        (deposits_val, borrows_val)
+    }
+    /// Native amount by which the vault balance falls short of net deposits (deposits - borrows)
+    pub fn get_vault_shortfall(&self, token_i: usize, vault_amount: u64) -> MangoResult<u64> {
+        let net_deposits = self.get_total_native_deposit(token_i)?
+            .saturating_sub(self.get_total_native_borrow(token_i)?);
+        Ok(net_deposits.saturating_sub(vault_amount))
+    }
+    pub fn is_reduce_only(&self, market_i: usize) -> bool {
+        self.market_flags[market_i] & (MarketFlag::ReduceOnly as u8) != 0
     }
     pub fn get_market_index(&self, spot_market_pk: &Pubkey) -> Option<usize> {
         self.spot_markets.iter().position(|market| market == spot_market_pk)
@@ -257,6 +482,133 @@ impl MangoGroup {
 }
 
 
+/// Layout of v1 MangoGroups, which can't be loaded as a MangoGroup anymore. MigrateMarginAccount
+/// and UpgradeMarginAccount still read them so accounts can be moved out of such groups
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub struct MangoGroupV1 {
+    pub account_flags: u64,
+    pub tokens: [Pubkey; NUM_TOKENS],
+    pub vaults: [Pubkey; NUM_TOKENS],
+    pub indexes: [MangoIndex; NUM_TOKENS],
+    pub spot_markets: [Pubkey; NUM_MARKETS],
+    pub oracles: [Pubkey; NUM_MARKETS],
+    pub signer_nonce: u64,
+    pub signer_key: Pubkey,
+    pub dex_program_id: Pubkey,
+    pub total_deposits: [U64F64; NUM_TOKENS],
+    pub total_borrows: [U64F64; NUM_TOKENS],
+    pub maint_coll_ratio: U64F64,
+    pub init_coll_ratio: U64F64,
+    pub srm_vault: Pubkey,
+    pub admin: Pubkey,
+    pub borrow_limits: [u64; NUM_TOKENS],
+    pub reserve_factor: U64F64,
+    pub fees_accrued: [U64F64; NUM_TOKENS],
+    pub coll_weights: [U64F64; NUM_TOKENS],
+    pub liab_weights: [U64F64; NUM_TOKENS],
+    pub liquidation_fee: U64F64,
+    pub oracle_max_divergences: [U64F64; NUM_MARKETS],
+    pub deposit_limits: [u64; NUM_TOKENS],
+    pub dust_thresholds: [u64; NUM_TOKENS],
+    pub oracles_secondary: [Pubkey; NUM_MARKETS],
+    pub pending_admin: Pubkey,
+    pub min_order_notional: u64,
+    pub srm_vault_balance: u64,
+    pub max_borrow_per_account: [u64; NUM_TOKENS],
+    pub collateral_grace_period: u64,
+    pub mint_decimals: [u8; NUM_TOKENS],
+    pub oracle_decimals: [u8; NUM_MARKETS],
+    pub market_flags: [u8; NUM_MARKETS],
+    pub is_halted: bool,
+    pub version: u8,
+    pub padding: [u8; MANGO_GROUP_V1_PADDING],
+    pub reserved: [u8; 24]
+}
+impl_loadable!(MangoGroupV1);
+const_assert_eq!(size_of::<MangoGroupV1>(), MANGO_GROUP_V1_SIZE);
+
+impl MangoGroupV1 {
+    pub fn load_mut_checked<'a>(
+        account: &'a AccountInfo,
+        program_id: &Pubkey
+    ) -> MangoResult<RefMut<'a, Self>> {
+        check_eq_default!(account.data_len(), size_of::<Self>())?;
+        check_eq_default!(account.owner, program_id)?;
+
+        let mango_group = Self::load_mut(account)?;
+        check_eq_default!(mango_group.account_flags, (AccountFlag::Initialized | AccountFlag::MangoGroup).bits())?;
+        check_eq!(mango_group.version, 1, MangoErrorCode::InvalidAccountVersion)?;
+
+        Ok(mango_group)
+    }
+    pub fn load_checked<'a>(
+        account: &'a AccountInfo,
+        program_id: &Pubkey
+    ) -> MangoResult<Ref<'a, Self>> {
+        check_eq_default!(account.data_len(), size_of::<Self>())?;
+        check_eq_default!(account.owner, program_id)?;
+
+        let mango_group = Self::load(account)?;
+        check_eq_default!(mango_group.account_flags, (AccountFlag::Initialized | AccountFlag::MangoGroup).bits())?;
+        check_eq!(mango_group.version, 1, MangoErrorCode::InvalidAccountVersion)?;
+
+        Ok(mango_group)
+    }
+    /// The group as a MangoGroup. Fields v1 didn't have are zero, which turns off everything
+    /// they configure, and every oracle is a flux aggregator
+    pub fn to_mango_group(&self) -> MangoGroup {
+        let mut mango_group = MangoGroup::zeroed();
+        mango_group.account_flags = self.account_flags;
+        mango_group.tokens = self.tokens;
+        mango_group.vaults = self.vaults;
+        mango_group.indexes = self.indexes;
+        mango_group.spot_markets = self.spot_markets;
+        mango_group.oracles = self.oracles;
+        mango_group.signer_nonce = self.signer_nonce;
+        mango_group.signer_key = self.signer_key;
+        mango_group.dex_program_id = self.dex_program_id;
+        mango_group.total_deposits = self.total_deposits;
+        mango_group.total_borrows = self.total_borrows;
+        mango_group.maint_coll_ratio = self.maint_coll_ratio;
+        mango_group.init_coll_ratio = self.init_coll_ratio;
+        mango_group.srm_vault = self.srm_vault;
+        mango_group.admin = self.admin;
+        mango_group.borrow_limits = self.borrow_limits;
+        mango_group.reserve_factor = self.reserve_factor;
+        mango_group.fees_accrued = self.fees_accrued;
+        mango_group.coll_weights = self.coll_weights;
+        mango_group.liab_weights = self.liab_weights;
+        mango_group.liquidation_fee = self.liquidation_fee;
+        mango_group.oracle_max_divergences = self.oracle_max_divergences;
+        mango_group.deposit_limits = self.deposit_limits;
+        mango_group.dust_thresholds = self.dust_thresholds;
+        mango_group.oracles_secondary = self.oracles_secondary;
+        mango_group.pending_admin = self.pending_admin;
+        mango_group.min_order_notional = self.min_order_notional;
+        mango_group.srm_vault_balance = self.srm_vault_balance;
+        mango_group.max_borrow_per_account = self.max_borrow_per_account;
+        mango_group.collateral_grace_period = self.collateral_grace_period;
+        mango_group.mint_decimals = self.mint_decimals;
+        mango_group.oracle_decimals = self.oracle_decimals;
+        mango_group.market_flags = self.market_flags;
+        mango_group.is_halted = self.is_halted;
+        mango_group.version = self.version;
+        mango_group
+    }
+    /// Store the interest and totals of mango_group, a copy made by to_mango_group. Fails if
+    /// bad_debt was recorded, since v1 has nowhere to keep it
+    pub fn store_totals(&mut self, mango_group: &MangoGroup) -> MangoResult<()> {
+        check_eq_default!(mango_group.bad_debt, [0; NUM_TOKENS])?;
+        self.indexes = mango_group.indexes;
+        self.total_deposits = mango_group.total_deposits;
+        self.total_borrows = mango_group.total_borrows;
+        self.fees_accrued = mango_group.fees_accrued;
+        Ok(())
+    }
+}
+
+
 
 #[derive(Copy, Clone)]
 #[repr(C)]
@@ -274,7 +626,23 @@ pub struct MarginAccount {
     pub open_orders: [Pubkey; NUM_MARKETS],  // owned by Mango
 
     pub being_liquidated: bool,
-    pub padding: [u8; 7] // padding to make compatible with previous MarginAccount size
+
+    /// Layout version, must equal MARGIN_ACCOUNT_VERSION. Lives at byte offset 233, right after
+    /// being_liquidated at offset 232, in what used to be padding so v0 accounts read as version 0.
+    /// v0 and v1 accounts are MARGIN_ACCOUNT_V1_SIZE bytes; v2 added every field after padding
+    pub version: u8,
+
+    /// Number of orders resting in each market's open orders account as of the last instruction
+    /// that touched it. Fills cranked by the dex free slots out of band, so this is an upper bound
+    pub num_open_orders: [u8; NUM_MARKETS],
+    pub padding: [u8; 6 - NUM_MARKETS], // pads v0 and v1 accounts out to MARGIN_ACCOUNT_V1_SIZE
+
+    pub referrer: Pubkey,  // set by the first DepositWithReferrer; informational only
+    pub deposit_ts: [u64; NUM_TOKENS],  // unix timestamp of the last deposit of each token; starts its grace window
+    pub borrow_principal: [u64; NUM_TOKENS],  // native amount borrowed and not repaid yet, excluding interest
+    pub total_interest_paid: [u64; NUM_TOKENS],  // lifetime native interest repaid by settling borrows; informational only
+    pub last_liquidation_ts: u64,  // unix timestamp of the last Liquidate or PartialLiquidate that took deposits
+    pub grace_deposits: [u64; NUM_TOKENS],  // native amount deposited within the grace window; see record_grace_deposit
     // TODO add has_borrows field for easy memcmp fetching
 }
 impl_loadable!(MarginAccount);
@@ -286,10 +654,12 @@ impl MarginAccount {
         mango_group_pk: &Pubkey
     ) -> MangoResult<RefMut<'a, Self>> {
         check_eq_default!(account.owner, program_id)?;  // this is probably not necessary
+        check!(account.key != mango_group_pk, MangoErrorCode::DuplicateAccounts)?;
         check_eq_default!(account.data_len(), size_of::<MarginAccount>())?;
 
         let margin_account = Self::load_mut(account)?;
         check_eq_default!(margin_account.account_flags, (AccountFlag::Initialized | AccountFlag::MarginAccount).bits())?;
+        check_eq!(margin_account.version, MARGIN_ACCOUNT_VERSION, MangoErrorCode::InvalidAccountVersion)?;
         // prog_assert_eq!(&margin_account.owner, owner_pk)?; // not necessary
         check_eq_default!(&margin_account.mango_group, mango_group_pk)?;
 
@@ -301,25 +671,84 @@ impl MarginAccount {
         mango_group_pk: &Pubkey
     ) -> MangoResult<Ref<'a, Self>> {
         check_eq_default!(account.owner, program_id)?;  // This is probably not necessary
+        check!(account.key != mango_group_pk, MangoErrorCode::DuplicateAccounts)?;
         check_eq_default!(account.data_len(), size_of::<MarginAccount>())?;
 
         let margin_account = Self::load(account)?;
         check_eq_default!(margin_account.account_flags, (AccountFlag::Initialized | AccountFlag::MarginAccount).bits())?;
+        check_eq!(margin_account.version, MARGIN_ACCOUNT_VERSION, MangoErrorCode::InvalidAccountVersion)?;
         // prog_assert_eq!(&margin_account.owner, owner_pk)?;  // not necessary
         check_eq_default!(&margin_account.mango_group, mango_group_pk)?;
 
         Ok(margin_account)
     }
+    /// Whether an open orders account has been saved for market_index
+    pub fn has_open_orders(&self, market_index: usize) -> bool {
+        self.open_orders[market_index] != Pubkey::default()
+    }
+    /// Forget the open orders account of market_i after it was closed on the dex
+    pub fn clear_open_orders(&mut self, market_i: usize) {
+        self.open_orders[market_i] = Pubkey::default();
+        self.num_open_orders[market_i] = 0;
+    }
+    /// Set num_open_orders[market_i] from the used slots of the open orders account
+    pub fn update_num_open_orders(
+        &mut self,
+        market_i: usize,
+        open_orders_acc: &AccountInfo
+    ) -> MangoResult<()> {
+        self.num_open_orders[market_i] = if *open_orders_acc.key == Pubkey::default() {
+            0
+        } else {
+            let open_orders = load_open_orders(open_orders_acc)?;
+            if open_orders.account_flags == 0 {  // not yet initialized by the dex
+                0
+            } else {
+                count_open_orders(open_orders.free_slot_bits)
+            }
+        };
+        Ok(())
+    }
+
+    /// Return (assets_val, liabs_val, coll_ratio) at unix timestamp now, reading each open orders
+    /// account only once
+    pub fn get_health(
+        &self,
+        mango_group: &MangoGroup,
+        prices: &[U64F64; NUM_TOKENS],
+        open_orders_accs: &[AccountInfo; NUM_MARKETS],
+        now: u64
+    ) -> MangoResult<(U64F64, U64F64, U64F64)> {
+        self.get_health_from_totals(mango_group, prices, &load_open_orders_totals(open_orders_accs)?, now)
+    }
+
+    /// get_health for open orders already read with load_open_orders_totals
+    pub fn get_health_from_totals(
+        &self,
+        mango_group: &MangoGroup,
+        prices: &[U64F64; NUM_TOKENS],
+        open_orders_totals: &OpenOrdersTotals,
+        now: u64
+    ) -> MangoResult<(U64F64, U64F64, U64F64)> {
+        let assets = self.get_assets_val_from_totals(mango_group, prices, open_orders_totals, now)?;
+        let liabs = self.get_liabs_val(mango_group, prices)?;
+        let coll_ratio = if liabs == ZERO_U64F64 {
+            U64F64::MAX
+        } else {
+            assets.checked_div(liabs).unwrap()
+        };
+        Ok((assets, liabs, coll_ratio))
+    }
+
     pub fn get_equity(
         &self,
         mango_group: &MangoGroup,
         prices: &[U64F64; NUM_TOKENS],
-        open_orders_accs: &[AccountInfo; NUM_MARKETS]
+        open_orders_accs: &[AccountInfo; NUM_MARKETS],
+        now: u64
     ) -> MangoResult<U64F64> {
-        // TODO weight collateral differently
         // equity = val(deposits) + val(positions) + val(open_orders) - val(borrows)
-        let assets = self.get_assets_val(mango_group, prices, open_orders_accs)?;
-        let liabs = self.get_liabs_val(mango_group, prices)?;
+        let (assets, liabs, _) = self.get_health(mango_group, prices, open_orders_accs, now)?;
         if liabs > assets {
             Ok(ZERO_U64F64)
         } else {
@@ -331,21 +760,26 @@ impl MarginAccount {
         &self,
         mango_group: &MangoGroup,
         prices: &[U64F64; NUM_TOKENS],
-        open_orders_accs: &[AccountInfo; NUM_MARKETS]
+        open_orders_accs: &[AccountInfo; NUM_MARKETS],
+        now: u64
     ) -> MangoResult<U64F64> {
         // assets / liabs
-        let assets = self.get_assets_val(mango_group, prices, open_orders_accs)?;
-        let liabs = self.get_liabs_val(mango_group, prices)?;
-        if liabs == ZERO_U64F64 {
-            Ok(U64F64::MAX)
-        } else {
-            Ok(assets.checked_div( liabs).unwrap())
-        }
+        let (_, _, coll_ratio) = self.get_health(mango_group, prices, open_orders_accs, now)?;
+        Ok(coll_ratio)
     }
     pub fn get_total_assets(
         &self,
         mango_group: &MangoGroup,
         open_orders_accs: &[AccountInfo; NUM_MARKETS]
+    ) -> MangoResult<[u64; NUM_TOKENS]> {
+        self.get_total_assets_from_totals(mango_group, &load_open_orders_totals(open_orders_accs)?)
+    }
+
+    /// get_total_assets for open orders already read with load_open_orders_totals
+    pub fn get_total_assets_from_totals(
+        &self,
+        mango_group: &MangoGroup,
+        open_orders_totals: &OpenOrdersTotals
     ) -> MangoResult<[u64; NUM_TOKENS]> {
         let mut assets = [0u64; NUM_TOKENS];
 
@@ -354,13 +788,8 @@ impl MarginAccount {
                 .checked_add(assets[i]).unwrap();
         }
         for i in 0..NUM_MARKETS {
-            if *open_orders_accs[i].key == Pubkey::default() {
-                continue;
-            }
-            let open_orders = load_open_orders(&open_orders_accs[i])?;
-
-            assets[i] = open_orders.native_coin_total.checked_add(assets[i]).unwrap();
-            assets[NUM_TOKENS-1] = open_orders.native_pc_total.checked_add(assets[NUM_TOKENS-1]).unwrap();
+            assets[i] = open_orders_totals.coin[i].checked_add(assets[i]).unwrap();
+            assets[QUOTE_INDEX] = open_orders_totals.pc[i].checked_add(assets[QUOTE_INDEX]).unwrap();
         }
         Ok(assets)
     }
@@ -380,34 +809,82 @@ impl MarginAccount {
         &self,
         mango_group: &MangoGroup,
         prices: &[U64F64; NUM_TOKENS],
-        open_orders_accs: &[AccountInfo; NUM_MARKETS]
+        open_orders_accs: &[AccountInfo; NUM_MARKETS],
+        now: u64
+    ) -> MangoResult<U64F64> {
+        self.get_assets_val_from_totals(mango_group, prices, &load_open_orders_totals(open_orders_accs)?, now)
+    }
+
+    /// get_assets_val for open orders already read with load_open_orders_totals
+    pub fn get_assets_val_from_totals(
+        &self,
+        mango_group: &MangoGroup,
+        prices: &[U64F64; NUM_TOKENS],
+        open_orders_totals: &OpenOrdersTotals,
+        now: u64
     ) -> MangoResult<U64F64> {
-        // TODO weight collateral differently
         // equity = val(deposits) + val(positions) + val(open_orders) - val(borrows)
         let mut assets: U64F64 = ZERO_U64F64;
         for i in 0..NUM_MARKETS {  // Add up all the value in open orders
-            // TODO check open orders details
-            if *open_orders_accs[i].key == Pubkey::default() {
-                continue;
-            }
-
-            let open_orders = load_open_orders(&open_orders_accs[i])?;
-            assets = U64F64::from_num(open_orders.native_coin_total)
-                .checked_mul(prices[i]).unwrap()
-                .checked_add(U64F64::from_num(open_orders.native_pc_total)).unwrap()
-                .checked_add(assets).unwrap();
+            // open orders totals are not bounded by the margin account, so overflow is an error, not a panic
+            // funds in open orders count at the same coll weight as the deposits they came from
+            let coin_val = U64F64::from_num(open_orders_totals.coin[i])
+                .checked_mul(prices[i]).ok_or(throw!())?
+                .checked_mul(mango_group.coll_weights[i]).ok_or(throw!())?;
+            let pc_val = U64F64::from_num(open_orders_totals.pc[i])
+                .checked_mul(mango_group.coll_weights[QUOTE_INDEX]).ok_or(throw!())?;
+            assets = coin_val
+                .checked_add(pc_val).ok_or(throw!())?
+                .checked_add(assets).ok_or(throw!())?;
 
         }
         for i in 0..NUM_TOKENS {  // add up the value in margin account deposits and positions
-            let index: &MangoIndex = &mango_group.indexes[i];
-            let native_deposits = index.deposit.checked_mul(self.deposits[i]).unwrap();
-            assets = native_deposits
-                .checked_mul(prices[i]).unwrap()
-                .checked_add(assets).unwrap()
+            assets = self.get_deposit_coll_val(mango_group, prices, i, now)?
+                .checked_add(assets).ok_or(throw!())?
         }
         Ok(assets)
 
     }
+    /// Value of the deposits of token_i counted as collateral at unix timestamp now. Up to
+    /// grace_deposits of them count at GRACE_COLL_WEIGHT of the coll weight while now is within
+    /// collateral_grace_period of deposit_ts
+    pub fn get_deposit_coll_val(
+        &self,
+        mango_group: &MangoGroup,
+        prices: &[U64F64; NUM_TOKENS],
+        token_i: usize,
+        now: u64
+    ) -> MangoResult<U64F64> {
+        let index: &MangoIndex = &mango_group.indexes[token_i];
+        let native_deposits = index.deposit.checked_mul(self.deposits[token_i]).ok_or(throw!())?;
+        let unit_val = prices[token_i].checked_mul(mango_group.coll_weights[token_i]).ok_or(throw!())?;
+        let val = native_deposits.checked_mul(unit_val).ok_or(throw!())?;
+
+        if mango_group.collateral_grace_period == 0 ||
+            self.deposit_ts[token_i].saturating_add(mango_group.collateral_grace_period) <= now {
+            return Ok(val);
+        }
+        let grace = min(U64F64::from_num(self.grace_deposits[token_i]), native_deposits);
+        let haircut = grace
+            .checked_mul(unit_val).ok_or(throw!())?
+            .checked_mul(ONE_U64F64 - GRACE_COLL_WEIGHT).ok_or(throw!())?;
+        Ok(val - haircut)
+    }
+    /// Record a deposit of quantity native token_i at unix timestamp now for the collateral grace
+    /// period. Only the amount deposited counts at the reduced weight, so a deposit by anyone else
+    /// can't lower the weight of what's already there. A deposit within the window of the last
+    /// one joins it and restarts it
+    pub fn record_grace_deposit(&mut self, mango_group: &MangoGroup, token_i: usize, quantity: u64, now: u64) {
+        let grace_period = mango_group.collateral_grace_period;
+        if grace_period == 0 || quantity == 0 {
+            return;
+        }
+        if self.deposit_ts[token_i].saturating_add(grace_period) <= now {
+            self.grace_deposits[token_i] = 0;
+        }
+        self.grace_deposits[token_i] = self.grace_deposits[token_i].saturating_add(quantity);
+        self.deposit_ts[token_i] = now;
+    }
     pub fn get_liabs_val(
         &self,
         mango_group: &MangoGroup,
@@ -416,8 +893,11 @@ impl MarginAccount {
         let mut liabs: U64F64 = ZERO_U64F64;
         for i in 0..NUM_TOKENS {
             let index: &MangoIndex = &mango_group.indexes[i];
-            let native_borrows = index.borrow * self.borrows[i];
-            liabs += native_borrows * prices[i];
+            let native_borrows = index.borrow.checked_mul(self.borrows[i]).ok_or(throw!())?;
+            liabs = native_borrows
+                .checked_mul(prices[i]).ok_or(throw!())?
+                .checked_mul(mango_group.liab_weights[i]).ok_or(throw!())?
+                .checked_add(liabs).ok_or(throw!())?;
         }
         Ok(liabs)
     }
@@ -426,9 +906,20 @@ impl MarginAccount {
         &self,
         mango_group: &MangoGroup,
         prices: &[U64F64; NUM_TOKENS],
-        open_orders_accs: &[AccountInfo; NUM_MARKETS]
+        open_orders_accs: &[AccountInfo; NUM_MARKETS],
+        now: u64
+    ) -> MangoResult<u64> {
+        self.get_collateral_deficit_from_totals(mango_group, prices, &load_open_orders_totals(open_orders_accs)?, now)
+    }
+    /// get_collateral_deficit for open orders already read with load_open_orders_totals
+    pub fn get_collateral_deficit_from_totals(
+        &self,
+        mango_group: &MangoGroup,
+        prices: &[U64F64; NUM_TOKENS],
+        open_orders_totals: &OpenOrdersTotals,
+        now: u64
     ) -> MangoResult<u64> {
-        let assets = self.get_assets_val(mango_group, prices, open_orders_accs)?;
+        let assets = self.get_assets_val_from_totals(mango_group, prices, open_orders_totals, now)?;
         let liabs = self.get_liabs_val(mango_group, prices)?;
 
         if liabs == ZERO_U64F64 || assets >= liabs * mango_group.init_coll_ratio {
@@ -438,13 +929,68 @@ impl MarginAccount {
         }
     }
 
+    /// Return amount of quote currency to deposit to get account back to maint_coll_ratio; 0 if
+    /// the account can't be liquidated
+    pub fn get_maintenance_deficit(
+        &self,
+        mango_group: &MangoGroup,
+        prices: &[U64F64; NUM_TOKENS],
+        open_orders_accs: &[AccountInfo; NUM_MARKETS],
+        now: u64
+    ) -> MangoResult<u64> {
+        let (assets, liabs, _) = self.get_health(mango_group, prices, open_orders_accs, now)?;
+        let maint_liabs = liabs.checked_mul(mango_group.maint_coll_ratio).ok_or(throw!())?;
+
+        if liabs == ZERO_U64F64 || assets >= maint_liabs {
+            Ok(0)
+        } else {
+            Ok((maint_liabs - assets).checked_ceil().ok_or(throw!())?.to_num())
+        }
+    }
+
+    /// Return the largest native quantity of token_index that can be borrowed at unix timestamp now
+    /// while keeping the account at or above init_coll_ratio. Also clamped by the group borrow
+    /// limit for that token
+    pub fn get_max_borrowable(
+        &self,
+        mango_group: &MangoGroup,
+        prices: &[U64F64; NUM_TOKENS],
+        open_orders_accs: &[AccountInfo; NUM_MARKETS],
+        token_index: usize,
+        now: u64
+    ) -> MangoResult<u64> {
+        let assets = self.get_assets_val(mango_group, prices, open_orders_accs, now)?;
+        let liabs = self.get_liabs_val(mango_group, prices)?;
+        let init_liabs = liabs.checked_mul(mango_group.init_coll_ratio).ok_or(throw!())?;
+        if assets <= init_liabs {
+            return Ok(0);
+        }
+
+        // Borrowing adds a weighted value to both assets and liabs:
+        // (assets + q * p * cw) / (liabs + q * p * lw) >= init  =>  q <= (assets - init * liabs) / ((init * lw - cw) * p)
+        let denom = mango_group.init_coll_ratio.checked_mul(mango_group.liab_weights[token_index]).ok_or(throw!())?
+            .checked_sub(mango_group.coll_weights[token_index]).ok_or(throw!())?
+            .checked_mul(prices[token_index]).ok_or(throw!())?;
+        let coll_max: u64 = (assets - init_liabs).checked_div(denom).ok_or(throw!())?
+            .checked_floor().ok_or(throw!())?
+            .checked_to_num().unwrap_or(u64::MAX);
+
+        let native_borrow = self.get_native_borrow(&mango_group.indexes[token_index], token_index);
+        let borrow_limit = mango_group.get_borrow_limit(token_index, now);
+        let limit_max = borrow_limit.saturating_sub(native_borrow);
+        let account_cap = mango_group.max_borrow_per_account[token_index];
+        let account_max = if account_cap == 0 { u64::MAX } else { account_cap.saturating_sub(native_borrow) };
+        Ok(min(min(coll_max, limit_max), account_max))
+    }
+
     pub fn get_partial_liq_deficit(
         &self,
         mango_group: &MangoGroup,
         prices: &[U64F64; NUM_TOKENS],
-        open_orders_accs: &[AccountInfo; NUM_MARKETS]
+        open_orders_totals: &OpenOrdersTotals,
+        now: u64
     ) -> MangoResult<U64F64> {
-        let assets = self.get_assets_val(mango_group, prices, open_orders_accs)?;
+        let assets = self.get_assets_val_from_totals(mango_group, prices, open_orders_totals, now)?;
         let liabs = self.get_liabs_val(mango_group, prices)?;
 
         if liabs == ZERO_U64F64 || assets >= liabs * mango_group.init_coll_ratio {
@@ -455,17 +1001,46 @@ impl MarginAccount {
         }
 
     }
+    /// Rounded the same way as MangoGroup::get_total_native_borrow and get_total_native_deposit,
+    /// so an account never owes less or holds more than the group totals account for
     pub fn get_native_borrow(&self, index: &MangoIndex, token_i: usize) -> u64 {
-        (self.borrows[token_i] * index.borrow).to_num()
+        (self.borrows[token_i] * index.borrow).checked_ceil().unwrap().to_num()  // rounds toward +inf
     }
     pub fn get_native_deposit(&self, index: &MangoIndex, token_i: usize) -> u64 {
-        (self.deposits[token_i] * index.deposit).to_num()
+        (self.deposits[token_i] * index.deposit).checked_floor().unwrap().to_num()  // rounds toward -inf
+    }
+    /// get_native_deposit for every token, each with its own index
+    pub fn get_native_deposits(&self, mango_group: &MangoGroup) -> [u64; NUM_TOKENS] {
+        let mut native_deposits = [0u64; NUM_TOKENS];
+        for i in 0..NUM_TOKENS {
+            native_deposits[i] = self.get_native_deposit(&mango_group.indexes[i], i);
+        }
+        native_deposits
+    }
+    /// get_native_borrow for every token, each with its own index
+    pub fn get_native_borrows(&self, mango_group: &MangoGroup) -> [u64; NUM_TOKENS] {
+        let mut native_borrows = [0u64; NUM_TOKENS];
+        for i in 0..NUM_TOKENS {
+            native_borrows[i] = self.get_native_borrow(&mango_group.indexes[i], i);
+        }
+        native_borrows
+    }
+    /// Whether an order on market_i would only close out an existing position in the base token
+    pub fn is_reducing_order(&self, market_i: usize, side: Side) -> bool {
+        match side {
+            Side::Bid => self.borrows[market_i] > ZERO_U64F64,
+            Side::Ask => self.deposits[market_i] > ZERO_U64F64
+        }
     }
     pub fn checked_add_borrow(&mut self, token_i: usize, v: U64F64) -> MangoResult<()> {
         Ok(self.borrows[token_i] = self.borrows[token_i].checked_add(v).ok_or(throw!())?)
     }
     pub fn checked_sub_borrow(&mut self, token_i: usize, v: U64F64) -> MangoResult<()> {
-        Ok(self.borrows[token_i] = self.borrows[token_i].checked_sub(v).ok_or(throw!())?)
+        self.borrows[token_i] = self.borrows[token_i].checked_sub(v).ok_or(throw!())?;
+        if self.borrows[token_i] == ZERO_U64F64 {
+            self.borrow_principal[token_i] = 0;
+        }
+        Ok(())
     }
     pub fn checked_add_deposit(&mut self, token_i: usize, v: U64F64) -> MangoResult<()> {
         Ok(self.deposits[token_i] = self.deposits[token_i].checked_add(v).ok_or(throw!())?)
@@ -475,6 +1050,40 @@ impl MarginAccount {
     }
 }
 
+#[cfg(feature = "client")]
+impl MarginAccount {
+    /// Decode the data of a MarginAccount fetched over RPC. The data doesn't need to be aligned
+    pub fn unpack_from_slice(data: &[u8]) -> Result<MarginAccount, ProgramError> {
+        if data.len() != size_of::<MarginAccount>() {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        let mut margin_account = MarginAccount::zeroed();
+        bytemuck::bytes_of_mut(&mut margin_account).copy_from_slice(data);
+        margin_account.check_client_flags()?;
+        Ok(margin_account)
+    }
+
+    /// Encode into dst, which must be exactly the size of a MarginAccount
+    pub fn pack_into_slice(&self, dst: &mut [u8]) -> Result<(), ProgramError> {
+        if dst.len() != size_of::<MarginAccount>() {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        self.check_client_flags()?;
+        dst.copy_from_slice(bytemuck::bytes_of(self));
+        Ok(())
+    }
+
+    fn check_client_flags(&self) -> Result<(), ProgramError> {
+        if self.account_flags != (AccountFlag::Initialized | AccountFlag::MarginAccount).bits() {
+            return Err(ProgramError::UninitializedAccount);
+        }
+        if self.version != MARGIN_ACCOUNT_VERSION {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        Ok(())
+    }
+}
+
 // The SRM contributed to the pool by this user
 // These SRM are not at risk and have no effect on any margin calculations.
 // Depositing srm is a strictly altruistic act with no upside and no downside
@@ -502,6 +1111,28 @@ impl MangoSrmAccount {
 
         Ok(srm_account)
     }
+    pub fn load_checked<'a>(
+        program_id: &Pubkey,
+        account: &'a AccountInfo,
+        mango_group_pk: &Pubkey
+    ) -> MangoResult<Ref<'a, Self>> {
+        check_eq_default!(account.owner, program_id)?;
+        check_eq_default!(account.data_len(), size_of::<MangoSrmAccount>())?;
+        let srm_account = Self::load(account)?;
+        check_eq_default!(srm_account.account_flags, (AccountFlag::Initialized | AccountFlag::MangoSrmAccount).bits())?;
+        check_eq_default!(&srm_account.mango_group, mango_group_pk)?;
+
+        Ok(srm_account)
+    }
+    pub fn get_fee_tier(&self) -> u8 {
+        get_srm_fee_tier(self.amount)
+    }
+}
+
+/// Serum dex fee discount tier for holding srm_amount native SRM: 0 is the base tier (no discount)
+/// and 1 to 5 are the SRM2 to SRM6 tiers. MangoSrmAccount.amount is the source of truth for this
+pub fn get_srm_fee_tier(srm_amount: u64) -> u8 {
+    SRM_FEE_TIER_THRESHOLDS.iter().filter(|&&threshold| srm_amount >= threshold).count() as u8
 }
 
 
@@ -665,6 +1296,36 @@ pub fn load_open_orders<'a>(
     Ok(Ref::map(strip_dex_padding(acc)?, from_bytes))
 }
 
+/// native_coin_total and native_pc_total of the open orders account of each market, 0 where
+/// there is none. Instructions that don't touch the dex read these once with
+/// load_open_orders_totals and pass them to every health check
+#[derive(Copy, Clone, Default)]
+pub struct OpenOrdersTotals {
+    pub coin: [u64; NUM_MARKETS],
+    pub pc: [u64; NUM_MARKETS]
+}
+
+pub fn load_open_orders_totals(
+    open_orders_accs: &[AccountInfo; NUM_MARKETS]
+) -> MangoResult<OpenOrdersTotals> {
+    let mut totals = OpenOrdersTotals::default();
+    for i in 0..NUM_MARKETS {
+        // TODO check open orders details
+        if *open_orders_accs[i].key == Pubkey::default() {
+            continue;
+        }
+        let open_orders = load_open_orders(&open_orders_accs[i])?;
+        totals.coin[i] = open_orders.native_coin_total;
+        totals.pc[i] = open_orders.native_pc_total;
+    }
+    Ok(totals)
+}
+
+/// Number of slots in use given the free slot bitmap of an OpenOrders account
+pub fn count_open_orders(free_slot_bits: u128) -> u8 {
+    free_slot_bits.count_zeros() as u8
+}
+
 pub fn check_open_orders(
     acc: &AccountInfo,
     owner: &Pubkey
@@ -683,6 +1344,61 @@ pub fn check_open_orders(
     Ok(())
 }
 
+/// Check that the token account behind one of the group's vaults still has the mango signer as
+/// its authority, in case it was changed out of band after InitMangoGroup
+pub fn verify_vault_authority(
+    vault_acc: &AccountInfo,
+    signer_key: &Pubkey
+) -> MangoResult<()> {
+    check_eq!(vault_acc.owner, &spl_token::id(), MangoErrorCode::InvalidMangoVault)?;
+    let vault = spl_token::state::Account::unpack(&vault_acc.try_borrow_data()?)?;
+    check_eq!(&vault.owner, signer_key, MangoErrorCode::InvalidMangoVault)?;
+    Ok(())
+}
+
+/// Combine two oracle prices into their median (the mean for two values). Fails if they differ
+/// by more than max_divergence relative to the lower price
+pub fn median_oracle_price(
+    primary: U64F64,
+    secondary: U64F64,
+    max_divergence: U64F64
+) -> MangoResult<U64F64> {
+    let (low, high) = if primary < secondary { (primary, secondary) } else { (secondary, primary) };
+    let max_gap = low.checked_mul(max_divergence).ok_or(throw!())?;
+    check!(high - low <= max_gap, MangoErrorCode::OracleDivergence)?;
+    Ok(low + (high - low) / U64F64::from_num(2))
+}
+
+/// Fail if the same non-default open orders account is passed for more than one market,
+/// which would count its value multiple times in get_assets_val
+pub fn check_open_orders_unique(open_orders_accs: &[AccountInfo; NUM_MARKETS]) -> MangoResult<()> {
+    for i in 0..NUM_MARKETS {
+        let key = open_orders_accs[i].key;
+        if *key == Pubkey::default() {
+            continue;
+        }
+        for j in (i + 1)..NUM_MARKETS {
+            check!(key != open_orders_accs[j].key, MangoErrorCode::InvalidOpenOrdersAccount)?;
+        }
+    }
+    Ok(())
+}
+
+/// Read-only counterpart of load_market_state for when the spot market is only inspected, so the
+/// account can be passed as readonly
+pub fn load_market_state_checked<'a>(
+    market_account: &'a AccountInfo,
+    program_id: &Pubkey,
+) -> MangoResult<Ref<'a, serum_dex::state::MarketState>> {
+    check_eq_default!(market_account.owner, program_id)?;
+
+    let state: Ref<'a, serum_dex::state::MarketState> = Ref::map(
+        strip_dex_padding(market_account)?, from_bytes
+    );
+
+    state.check_flags()?;
+    Ok(state)
+}
 
 pub fn load_market_state<'a>(
     market_account: &'a AccountInfo,
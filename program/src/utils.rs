@@ -1,10 +1,13 @@
 use std::cell::RefMut;
 
 use bytemuck::{bytes_of, Contiguous};
+use fixed::types::U64F64;
 use serum_dex::critbit::SlabView;
 use solana_program::program_error::ProgramError;
 use solana_program::pubkey::Pubkey;
 
+pub const WRAPPED_SOL_SEED: &[u8] = b"wrapped_sol";
+
 pub fn gen_signer_seeds<'a>(nonce: &'a u64, acc_pk: &'a Pubkey) -> [&'a [u8]; 2] {
     [acc_pk.as_ref(), bytes_of(nonce)]
 }
@@ -30,6 +33,38 @@ pub fn create_signer_key_and_nonce(program_id: &Pubkey, acc_pk: &Pubkey) -> (Pub
 }
 
 
+/// Address of the temporary wrapped SOL account used to deposit or withdraw native SOL
+pub fn get_wrapped_sol_address(program_id: &Pubkey, margin_account_pk: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[margin_account_pk.as_ref(), WRAPPED_SOL_SEED], program_id)
+}
+
+
+/// Address of the MarginAccount of owner_pk in mango_group_pk created by InitMarginAccountPda
+pub fn get_margin_account_address(program_id: &Pubkey, mango_group_pk: &Pubkey, owner_pk: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[mango_group_pk.as_ref(), owner_pk.as_ref()], program_id)
+}
+
+
+/// v as a decimal string with decimals (at most 19) fraction digits, rounded to nearest, e.g.
+/// "1.200000" for 1.2 with 6 decimals. Display on U64F64 prints every binary fraction digit as
+/// decimals, which makes logged ratios and indexes like 1.19999999999999995559 hard to read
+pub fn format_u64f64(v: U64F64, decimals: u32) -> String {
+    let decimals = decimals.min(19);
+    let scale = 10u64.pow(decimals);
+    let mut int: u64 = v.int().to_num();
+    let mut frac: u64 = (v.frac() * U64F64::from_num(scale)).round().to_num();
+    if frac == scale {  // rounded up into the integer part
+        int = int.saturating_add(1);
+        frac = 0;
+    }
+    if decimals == 0 {
+        format!("{}", int)
+    } else {
+        format!("{}.{:0width$}", int, frac, width = decimals as usize)
+    }
+}
+
+
 pub fn get_dex_best_price(slab: RefMut<serum_dex::critbit::Slab>, is_bid: bool) -> Option<u64> {
     if slab.is_empty() {
         None
@@ -4,29 +4,33 @@ use std::mem::size_of;
 use std::convert::TryInto;
 use safe_transmute::{self, to_bytes::transmute_one_to_bytes};
 
+use bytemuck::{bytes_of, Zeroable};
 use fixed::types::U64F64;
 use common::create_signer_key_and_nonce;
 use flux_aggregator::borsh_utils;
 use flux_aggregator::borsh_state::BorshState;
 use flux_aggregator::state::{Aggregator, AggregatorConfig, Answer};
+use solana_program::entrypoint::ProgramResult;
 use solana_program::program_option::COption;
 use solana_program::program_pack::Pack;
 use solana_program::pubkey::Pubkey;
-use solana_program_test::{ProgramTest, BanksClient};
+use solana_program::rent::Rent;
+use solana_program_test::{processor, ProgramTest, BanksClient};
 
 use solana_sdk::{
-    account_info::IntoAccountInfo,
+    account_info::{AccountInfo, IntoAccountInfo},
     account::Account,
     instruction::Instruction,
     signature::{Keypair, Signer}
 };
 
 use spl_token::state::{Mint, Account as Token, AccountState};
-use serum_dex::state::{MarketState, AccountFlag, ToAlignedBytes};
+use serum_dex::state::{MarketState, AccountFlag, OpenOrders, ToAlignedBytes};
 
+use mango::oracle::{PYTH_ACCOUNT_TYPE_PRICE, PYTH_MAGIC, PYTH_STATUS_TRADING, PYTH_VERSION};
 use mango::processor::srm_token;
 use mango::instruction::init_mango_group;
-use mango::state::MangoGroup;
+use mango::state::{AccountFlag as MangoAccountFlag, MangoGroup, MangoGroupV1, MangoIndex, MARGIN_ACCOUNT_VERSION, MarginAccount, NUM_MARKETS, NUM_TOKENS, ONE_U64F64};
 
 pub const PRICE_BTC: u64 = 50000;
 pub const PRICE_ETH: u64 = 2000;
@@ -62,6 +66,16 @@ pub struct TestMint {
     pub decimals: u8,
 }
 
+impl Clone for TestMint {
+    fn clone(&self) -> Self {
+        TestMint {
+            pubkey: self.pubkey,
+            authority: Keypair::from_bytes(&self.authority.to_bytes()).unwrap(),
+            decimals: self.decimals,
+        }
+    }
+}
+
 
 pub fn add_mint(test: &mut ProgramTest, decimals: u8) -> TestMint {
     let authority = Keypair::new();
@@ -106,33 +120,76 @@ pub fn add_mint_srm(test: &mut ProgramTest) -> TestMint {
     }
 }
 
+#[allow(dead_code)]
+pub fn add_mint_native(test: &mut ProgramTest) -> TestMint {
+    let authority = Keypair::new();
+    let pubkey = spl_token::native_mint::ID;
+    let decimals = spl_token::native_mint::DECIMALS;
+    test.add_packable_account(
+        pubkey,
+        u32::MAX as u64,
+        &Mint {
+            is_initialized: true,
+            decimals,
+            ..Mint::default()
+        },
+        &spl_token::id(),
+    );
+    TestMint {
+        pubkey,
+        authority,
+        decimals,
+    }
+}
+
 pub struct TestDex {
     pub pubkey: Pubkey,
 }
 
 pub fn add_dex_empty(test: &mut ProgramTest, base_mint: Pubkey, quote_mint: Pubkey, dex_prog_id: Pubkey) -> TestDex {
     let pubkey = Pubkey::new_unique();
+    test.add_account(pubkey, dex_market_account(&pubkey, base_mint, quote_mint, dex_prog_id));
+    TestDex { pubkey }
+}
+
+// Serum spot market account with an empty book, owned by dex_prog_id
+pub fn dex_market_account(pubkey: &Pubkey, base_mint: Pubkey, quote_mint: Pubkey, dex_prog_id: Pubkey) -> Account {
+    let market = TestDexMarket {
+        pubkey: *pubkey,
+        req_q: Pubkey::new_unique(),
+        event_q: Pubkey::new_unique(),
+        bids: Pubkey::new_unique(),
+        asks: Pubkey::new_unique(),
+        coin_vault: Pubkey::new_unique(),
+        pc_vault: Pubkey::new_unique(),
+        vault_signer: Pubkey::new_unique(),
+        vault_signer_nonce: 0,
+    };
+    live_dex_market_account(&market, base_mint, quote_mint, dex_prog_id)
+}
+
+fn live_dex_market_account(market: &TestDexMarket, base_mint: Pubkey, quote_mint: Pubkey, dex_prog_id: Pubkey) -> Account {
     let mut acc = Account::new(u32::MAX as u64, 0, &dex_prog_id);
     let ms = MarketState {
         account_flags: (AccountFlag::Initialized | AccountFlag::Market).bits(),
-        own_address: pubkey.to_aligned_bytes(),
-        vault_signer_nonce: 0,
+        own_address: market.pubkey.to_aligned_bytes(),
+        vault_signer_nonce: market.vault_signer_nonce,
         coin_mint: base_mint.to_aligned_bytes(),
         pc_mint: quote_mint.to_aligned_bytes(),
 
-        coin_vault: Pubkey::new_unique().to_aligned_bytes(),
+        coin_vault: market.coin_vault.to_aligned_bytes(),
         coin_deposits_total: 0,
         coin_fees_accrued: 0,
 
-        pc_vault: Pubkey::new_unique().to_aligned_bytes(),
+        pc_vault: market.pc_vault.to_aligned_bytes(),
         pc_deposits_total: 0,
         pc_fees_accrued: 0,
         pc_dust_threshold: 0,
 
-        req_q: Pubkey::new_unique().to_aligned_bytes(),
-        event_q: Pubkey::new_unique().to_aligned_bytes(),
-        bids: Pubkey::new_unique().to_aligned_bytes(),
-        asks: Pubkey::new_unique().to_aligned_bytes(),
+        req_q: market.req_q.to_aligned_bytes(),
+        event_q: market.event_q.to_aligned_bytes(),
+        bids: market.bids.to_aligned_bytes(),
+        asks: market.asks.to_aligned_bytes(),
 
         coin_lot_size: 1,
         pc_lot_size: 1,
@@ -148,9 +205,71 @@ pub fn add_dex_empty(test: &mut ProgramTest, base_mint: Pubkey, quote_mint: Pubk
     accdata.extend(data);
     accdata.extend(tail);
     acc.data = accdata;
+    acc
+}
 
-    test.add_account(pubkey, acc);
-    TestDex { pubkey }
+// Serum dex accounts of a market whose book can take orders, see add_dex_market
+pub struct TestDexMarket {
+    pub pubkey: Pubkey,
+    pub req_q: Pubkey,
+    pub event_q: Pubkey,
+    pub bids: Pubkey,
+    pub asks: Pubkey,
+    pub coin_vault: Pubkey,
+    pub pc_vault: Pubkey,
+    pub vault_signer: Pubkey,
+    pub vault_signer_nonce: u64,
+}
+
+fn process_dex_instruction(program_id: &Pubkey, accounts: &[AccountInfo], input: &[u8]) -> ProgramResult {
+    Ok(serum_dex::state::State::process(program_id, accounts, input)?)
+}
+
+// Load the serum dex program so mango can CPI into it, e.g. to place and cancel real orders
+#[allow(dead_code)]
+pub fn add_dex_program(test: &mut ProgramTest) -> Pubkey {
+    let dex_prog_id = Pubkey::new_unique();
+    test.add_program("serum_dex", dex_prog_id, processor!(process_dex_instruction));
+    dex_prog_id
+}
+
+// Zeroed serum queue or slab account; a zeroed header past account_flags is an empty queue or book
+fn dex_book_account(account_flags: AccountFlag, len: usize, dex_prog_id: Pubkey) -> Account {
+    let mut data = vec![];
+    data.extend(b"serum");
+    data.extend(&(AccountFlag::Initialized | account_flags).bits().to_le_bytes());
+    data.extend(vec![0u8; len]);
+    data.extend(b"padding");
+    Account { lamports: u32::MAX as u64, data, owner: dex_prog_id, ..Account::default() }
+}
+
+// Serum spot market with an empty book that the dex program from add_dex_program can trade on
+#[allow(dead_code)]
+pub fn add_dex_market(test: &mut ProgramTest, base_mint: Pubkey, quote_mint: Pubkey, dex_prog_id: Pubkey) -> TestDexMarket {
+    let pubkey = Pubkey::new_unique();
+    let (vault_signer, vault_signer_nonce) = (0..).find_map(|nonce: u64| {
+        Pubkey::create_program_address(&[pubkey.as_ref(), &nonce.to_le_bytes()], &dex_prog_id).ok()
+            .map(|pk| (pk, nonce))
+    }).unwrap();
+
+    let market = TestDexMarket {
+        pubkey,
+        req_q: Pubkey::new_unique(),
+        event_q: Pubkey::new_unique(),
+        bids: Pubkey::new_unique(),
+        asks: Pubkey::new_unique(),
+        coin_vault: add_token_account(test, vault_signer, base_mint, 0).pubkey,
+        pc_vault: add_token_account(test, vault_signer, quote_mint, 0).pubkey,
+        vault_signer,
+        vault_signer_nonce,
+    };
+    // queue headers are 32 bytes including account_flags, requests 80, events 88; slab headers 32, nodes 72
+    test.add_account(market.req_q, dex_book_account(AccountFlag::RequestQueue, 24 + 80 * 16, dex_prog_id));
+    test.add_account(market.event_q, dex_book_account(AccountFlag::EventQueue, 24 + 88 * 128, dex_prog_id));
+    test.add_account(market.bids, dex_book_account(AccountFlag::Bids, 32 + 72 * 128, dex_prog_id));
+    test.add_account(market.asks, dex_book_account(AccountFlag::Asks, 32 + 72 * 128, dex_prog_id));
+    test.add_account(pubkey, live_dex_market_account(&market, base_mint, quote_mint, dex_prog_id));
+    market
 }
 
 pub struct TestTokenAccount {
@@ -174,15 +293,35 @@ pub fn add_token_account(test: &mut ProgramTest, owner: Pubkey, mint: Pubkey, in
     TestTokenAccount { pubkey }
 }
 
+// Add a wrapped SOL token account whose lamports are rent exempt plus initial_balance
+#[allow(dead_code)]
+pub fn add_native_token_account(test: &mut ProgramTest, owner: Pubkey, initial_balance: u64) -> TestTokenAccount {
+    let pubkey = Pubkey::new_unique();
+    let rent_exempt_reserve = Rent::default().minimum_balance(Token::LEN);
+    test.add_packable_account(
+        pubkey,
+        rent_exempt_reserve + initial_balance,
+        &Token {
+            mint: spl_token::native_mint::ID,
+            owner: owner,
+            amount: initial_balance,
+            state: AccountState::Initialized,
+            is_native: COption::Some(rent_exempt_reserve),
+            ..Token::default()
+        },
+        &spl_token::id(),
+    );
+    TestTokenAccount { pubkey }
+}
+
 pub struct TestAggregator {
     pub name: String,
     pub pubkey: Pubkey,
     pub price: u64,
 }
 
-pub fn add_aggregator(test: &mut ProgramTest, name: &str, decimals: u8, price: u64, owner: &Pubkey) -> TestAggregator {
-    let pubkey = Pubkey::new_unique();
-
+// Flux aggregator account with the given decimals and median answer
+pub fn aggregator_account(pubkey: &Pubkey, name: &str, decimals: u8, price: u64, owner: &Pubkey) -> Account {
     let mut description = [0u8; 32];
     let size = name.len().min(description.len());
     description[0..size].copy_from_slice(&name.as_bytes()[0..size]);
@@ -207,9 +346,30 @@ pub fn add_aggregator(test: &mut ProgramTest, name: &str, decimals: u8, price: u
         borsh_utils::get_packed_len::<Aggregator>(),
         &owner,
     );
-    let account_info = (&pubkey, false, &mut account).into_account_info();
+    let account_info = (pubkey, false, &mut account).into_account_info();
     aggregator.save(&account_info).unwrap();
-    test.add_account(pubkey, account);
+    account
+}
+
+// Pyth price account with only the header and aggregate price filled in, price * 10^expo +- conf * 10^expo
+#[allow(dead_code)]
+pub fn pyth_price_account(price: i64, expo: i32, conf: u64, owner: &Pubkey) -> Account {
+    let mut account = Account::new(u32::MAX as u64, 3312, owner);
+    let data = &mut account.data;
+    data[0..4].copy_from_slice(&PYTH_MAGIC.to_le_bytes());
+    data[4..8].copy_from_slice(&PYTH_VERSION.to_le_bytes());
+    data[8..12].copy_from_slice(&PYTH_ACCOUNT_TYPE_PRICE.to_le_bytes());
+    data[12..16].copy_from_slice(&3312u32.to_le_bytes());
+    data[20..24].copy_from_slice(&expo.to_le_bytes());
+    data[208..216].copy_from_slice(&price.to_le_bytes());
+    data[216..224].copy_from_slice(&conf.to_le_bytes());
+    data[224..228].copy_from_slice(&PYTH_STATUS_TRADING.to_le_bytes());
+    account
+}
+
+pub fn add_aggregator(test: &mut ProgramTest, name: &str, decimals: u8, price: u64, owner: &Pubkey) -> TestAggregator {
+    let pubkey = Pubkey::new_unique();
+    test.add_account(pubkey, aggregator_account(&pubkey, name, decimals, price, owner));
 
     TestAggregator {
         name: name.to_string(),
@@ -250,6 +410,16 @@ fn to_fixed_array<T, const N: usize>(v: Vec<T>) -> [T; N] {
 
 impl TestMangoGroup {
     pub fn init_mango_group(&self, payer: &Pubkey) -> Instruction {
+        self.init_mango_group_with_coll_ratios(payer, U64F64::from_num(1.1), U64F64::from_num(1.2))
+    }
+
+    #[allow(dead_code)]
+    pub fn init_mango_group_with_coll_ratios(
+        &self,
+        payer: &Pubkey,
+        maint_coll_ratio: U64F64,
+        init_coll_ratio: U64F64
+    ) -> Instruction {
         init_mango_group(
             &self.program_id,
             &self.mango_group_pk,
@@ -262,21 +432,29 @@ impl TestMangoGroup {
             self.dexes.iter().map(|m| m.pubkey).collect::<Vec<Pubkey>>().as_slice(),
             self.oracles.iter().map(|m| m.pubkey).collect::<Vec<Pubkey>>().as_slice(),
             self.signer_nonce,
-            U64F64::from_num(1.1),
-            U64F64::from_num(1.2),
+            maint_coll_ratio,
+            init_coll_ratio,
             to_fixed_array(self.borrow_limits.clone()),
         ).unwrap()
     }
 }
 
 pub fn add_mango_group_prodlike(test: &mut ProgramTest, program_id: Pubkey) -> TestMangoGroup {
+    let mints = vec![add_mint(test, 6), add_mint(test, 6), add_mint(test, 6)];
+    add_mango_group_prodlike_with_mints(test, program_id, mints)
+}
+
+// Same as add_mango_group_prodlike but with existing BTC, ETH and USDT mints, e.g. those of another group
+pub fn add_mango_group_prodlike_with_mints(
+    test: &mut ProgramTest,
+    program_id: Pubkey,
+    mints: Vec<TestMint>
+) -> TestMangoGroup {
     let mango_group_pk = Pubkey::new_unique();
     let (signer_pk, signer_nonce) = create_signer_key_and_nonce(&program_id, &mango_group_pk);
     test.add_account(mango_group_pk, Account::new(u32::MAX as u64, size_of::<MangoGroup>(), &program_id));
 
-    let btc_mint = add_mint(test, 6);
-    let eth_mint = add_mint(test, 6);
-    let usdt_mint = add_mint(test, 6);
+    let [btc_mint, eth_mint, usdt_mint]: [TestMint; NUM_TOKENS] = to_fixed_array(mints);
 
     let btc_vault = add_token_account(test, signer_pk, btc_mint.pubkey, 0);
     let eth_vault = add_token_account(test, signer_pk, eth_mint.pubkey, 0);
@@ -315,6 +493,133 @@ pub fn add_mango_group_prodlike(test: &mut ProgramTest, program_id: Pubkey) -> T
     }
 }
 
+// Initialized v1 MangoGroup with unit indexes whose vaults hold deposits, like a group created
+// before MangoGroup version 2. It has no oracles or spot markets; only enough to move accounts out
+#[allow(dead_code)]
+pub fn add_mango_group_v1(
+    test: &mut ProgramTest,
+    program_id: Pubkey,
+    mints: Vec<TestMint>,
+    deposits: [u64; NUM_TOKENS]
+) -> TestMangoGroup {
+    let mango_group_pk = Pubkey::new_unique();
+    let (signer_pk, signer_nonce) = create_signer_key_and_nonce(&program_id, &mango_group_pk);
+    let vaults: Vec<TestTokenAccount> = (0..NUM_TOKENS)
+        .map(|i| add_token_account(test, signer_pk, mints[i].pubkey, deposits[i]))
+        .collect();
+    let srm_mint = add_mint_srm(test);
+    let srm_vault = add_token_account(test, signer_pk, srm_mint.pubkey, 0);
+
+    let mut mango_group = MangoGroupV1::zeroed();
+    mango_group.account_flags = (MangoAccountFlag::Initialized | MangoAccountFlag::MangoGroup).bits();
+    mango_group.signer_key = signer_pk;
+    mango_group.signer_nonce = signer_nonce;
+    mango_group.srm_vault = srm_vault.pubkey;
+    mango_group.maint_coll_ratio = U64F64::from_num(1.1);
+    mango_group.init_coll_ratio = U64F64::from_num(1.2);
+    mango_group.version = 1;
+    for i in 0..NUM_TOKENS {
+        mango_group.tokens[i] = mints[i].pubkey;
+        mango_group.vaults[i] = vaults[i].pubkey;
+        mango_group.indexes[i] = MangoIndex { last_update: 0, borrow: ONE_U64F64, deposit: ONE_U64F64 };
+        mango_group.total_deposits[i] = U64F64::from_num(deposits[i]);
+        mango_group.coll_weights[i] = ONE_U64F64;
+        mango_group.liab_weights[i] = ONE_U64F64;
+    }
+
+    let mut account = Account::new(u32::MAX as u64, size_of::<MangoGroupV1>(), &program_id);
+    account.data.copy_from_slice(bytes_of(&mango_group));
+    test.add_account(mango_group_pk, account);
+
+    TestMangoGroup {
+        program_id,
+        mango_group_pk,
+        signer_pk,
+        signer_nonce,
+        mints,
+        vaults,
+        srm_mint,
+        srm_vault,
+        dex_prog_id: Pubkey::new_unique(),
+        dexes: vec![],
+        oracles: vec![],
+        borrow_limits: vec![0; NUM_TOKENS],
+    }
+}
+
+// Same as add_mango_group_prodlike but its spot markets trade on the serum dex from add_dex_program
+#[allow(dead_code)]
+pub fn add_mango_group_with_dex(test: &mut ProgramTest, program_id: Pubkey) -> (TestMangoGroup, Vec<TestDexMarket>) {
+    let mut mango_group = add_mango_group_prodlike(test, program_id);
+    let dex_prog_id = add_dex_program(test);
+    let quote_mint = mango_group.mints[NUM_MARKETS].pubkey;
+    let markets: Vec<TestDexMarket> = (0..NUM_MARKETS)
+        .map(|i| add_dex_market(test, mango_group.mints[i].pubkey, quote_mint, dex_prog_id))
+        .collect();
+    mango_group.dex_prog_id = dex_prog_id;
+    mango_group.dexes = markets.iter().map(|m| TestDex { pubkey: m.pubkey }).collect();
+    (mango_group, markets)
+}
+
+// Add a MarginAccount that is already initialized with the given native deposits and borrows
+// Useful to get an account into an unhealthy state without moving oracle prices
+#[allow(dead_code)]
+pub fn add_margin_account(
+    test: &mut ProgramTest,
+    program_id: Pubkey,
+    mango_group_pk: Pubkey,
+    owner: Pubkey,
+    deposits: [u64; NUM_TOKENS],
+    borrows: [u64; NUM_TOKENS],
+) -> Pubkey {
+    add_margin_account_at(test, Pubkey::new_unique(), program_id, mango_group_pk, owner, deposits, borrows)
+}
+
+// Same as add_margin_account but at a chosen address, e.g. the pubkey of a Keypair
+#[allow(dead_code)]
+pub fn add_margin_account_at(
+    test: &mut ProgramTest,
+    pubkey: Pubkey,
+    program_id: Pubkey,
+    mango_group_pk: Pubkey,
+    owner: Pubkey,
+    deposits: [u64; NUM_TOKENS],
+    borrows: [u64; NUM_TOKENS],
+) -> Pubkey {
+    add_margin_account_with_open_orders(
+        test, pubkey, program_id, mango_group_pk, owner, deposits, borrows, [Pubkey::default(); NUM_MARKETS]
+    )
+}
+
+// Same as add_margin_account_at with open orders accounts already set, e.g. from open_orders_account
+#[allow(dead_code)]
+pub fn add_margin_account_with_open_orders(
+    test: &mut ProgramTest,
+    pubkey: Pubkey,
+    program_id: Pubkey,
+    mango_group_pk: Pubkey,
+    owner: Pubkey,
+    deposits: [u64; NUM_TOKENS],
+    borrows: [u64; NUM_TOKENS],
+    open_orders: [Pubkey; NUM_MARKETS],
+) -> Pubkey {
+    let mut margin_account = MarginAccount::zeroed();
+    margin_account.account_flags = (MangoAccountFlag::Initialized | MangoAccountFlag::MarginAccount).bits();
+    margin_account.mango_group = mango_group_pk;
+    margin_account.owner = owner;
+    margin_account.version = MARGIN_ACCOUNT_VERSION;
+    margin_account.open_orders = open_orders;
+    for i in 0..NUM_TOKENS {
+        margin_account.deposits[i] = U64F64::from_num(deposits[i]);
+        margin_account.borrows[i] = U64F64::from_num(borrows[i]);
+    }
+
+    let mut account = Account::new(u32::MAX as u64, size_of::<MarginAccount>(), &program_id);
+    account.data.copy_from_slice(bytes_of(&margin_account));
+    test.add_account(pubkey, account);
+    pubkey
+}
+
 #[allow(dead_code)]  // Compiler complains about this even tho it is used
 pub async fn get_token_balance(banks_client: &mut BanksClient, pubkey: Pubkey) -> u64 {
     let token: Account = banks_client.get_account(pubkey).await.unwrap().unwrap();
@@ -322,4 +627,95 @@ pub async fn get_token_balance(banks_client: &mut BanksClient, pubkey: Pubkey) -
     spl_token::state::Account::unpack(&token.data[..])
         .unwrap()
         .amount
-}
\ No newline at end of file
+}
+
+// In memory MangoGroup with unit indexes; useful for testing the state helpers without a transaction
+#[allow(dead_code)]
+pub fn new_mango_group_in_memory(borrow_limits: [u64; NUM_TOKENS]) -> MangoGroup {
+    let mut mango_group = MangoGroup::zeroed();
+    for i in 0..NUM_TOKENS {
+        mango_group.indexes[i] = MangoIndex {
+            last_update: 0,
+            borrow: ONE_U64F64,
+            deposit: ONE_U64F64
+        };
+        mango_group.coll_weights[i] = ONE_U64F64;
+        mango_group.liab_weights[i] = ONE_U64F64;
+    }
+    mango_group.maint_coll_ratio = U64F64::from_num(1.1);
+    mango_group.init_coll_ratio = U64F64::from_num(1.2);
+    mango_group.borrow_limits = borrow_limits;
+    mango_group
+}
+
+// Prices per native unit matching the oracles of add_mango_group_prodlike
+#[allow(dead_code)]
+pub fn get_prodlike_prices() -> [U64F64; NUM_TOKENS] {
+    [U64F64::from_num(PRICE_BTC), U64F64::from_num(PRICE_ETH), ONE_U64F64]
+}
+
+// Open orders accounts for a MarginAccount that has not traded yet
+#[allow(dead_code)]
+pub fn empty_open_orders<'a>(
+    pk: &'a Pubkey,
+    accounts: &'a mut [Account; NUM_MARKETS]
+) -> [AccountInfo<'a>; NUM_MARKETS] {
+    let [btc_usdt, eth_usdt] = accounts;
+    [(pk, btc_usdt).into(), (pk, eth_usdt).into()]
+}
+
+/// Serum dex OpenOrders account owned by `owner` with the given free slot bitmap
+#[allow(dead_code)]
+pub fn open_orders_account(owner: &Pubkey, free_slot_bits: u128) -> Account {
+    let mut open_orders = OpenOrders::zeroed();
+    open_orders.account_flags = (AccountFlag::Initialized | AccountFlag::OpenOrders).bits();
+    open_orders.owner = owner.to_aligned_bytes();
+    open_orders.free_slot_bits = free_slot_bits;
+
+    let mut data = vec![];
+    data.extend(b"serum");
+    data.extend(bytes_of(&open_orders));
+    data.extend(b"padding");
+    Account { lamports: u32::MAX as u64, data, ..Account::default() }
+}
+
+/// Empty serum dex OpenOrders account of `owner` on a market from add_dex_market
+#[allow(dead_code)]
+pub fn dex_open_orders_account(owner: &Pubkey, market: &TestDexMarket, dex_prog_id: &Pubkey) -> Account {
+    let mut open_orders = OpenOrders::zeroed();
+    open_orders.account_flags = (AccountFlag::Initialized | AccountFlag::OpenOrders).bits();
+    open_orders.market = market.pubkey.to_aligned_bytes();
+    open_orders.owner = owner.to_aligned_bytes();
+    open_orders.free_slot_bits = u128::MAX;
+
+    let mut data = vec![];
+    data.extend(b"serum");
+    data.extend(bytes_of(&open_orders));
+    data.extend(b"padding");
+    Account { lamports: u32::MAX as u64, data, owner: *dex_prog_id, ..Account::default() }
+}
+
+#[allow(dead_code)]
+pub async fn get_margin_account(
+    banks_client: &mut BanksClient,
+    program_id: &Pubkey,
+    mango_group_pk: &Pubkey,
+    margin_account_pk: Pubkey
+) -> MarginAccount {
+    let mut account = banks_client.get_account(margin_account_pk).await.unwrap().unwrap();
+    let account_info: AccountInfo = (&margin_account_pk, &mut account).into();
+    let margin_account = MarginAccount::load_mut_checked(program_id, &account_info, mango_group_pk).unwrap();
+    *margin_account
+}
+
+#[allow(dead_code)]
+pub async fn get_mango_group(
+    banks_client: &mut BanksClient,
+    program_id: &Pubkey,
+    mango_group_pk: Pubkey
+) -> MangoGroup {
+    let mut account = banks_client.get_account(mango_group_pk).await.unwrap().unwrap();
+    let account_info: AccountInfo = (&mango_group_pk, &mut account).into();
+    let mango_group = MangoGroup::load_mut_checked(&account_info, program_id).unwrap();
+    *mango_group
+}
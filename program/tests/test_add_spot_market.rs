@@ -0,0 +1,77 @@
+// Tests related to adding a spot market to an existing MangoGroup
+#![cfg(feature="test-bpf")]
+
+mod helpers;
+
+use helpers::*;
+use solana_program::pubkey::Pubkey;
+use solana_program_test::*;
+use solana_sdk::{
+    signature::Signer,
+    transaction::Transaction,
+};
+
+use mango::{
+    entrypoint::process_instruction,
+    instruction::add_spot_market,
+};
+
+#[tokio::test]
+async fn test_add_spot_market_into_empty_slot() {
+    // Test that a market slot left empty at init can be filled once and never overwritten
+    let program_id = Pubkey::new_unique();
+
+    let mut test = ProgramTest::new(
+        "mango",
+        program_id,
+        processor!(process_instruction),
+    );
+
+    // limit to track compute unit increase
+    test.set_bpf_compute_max_units(50_000);
+
+    let mut mango_group = add_mango_group_prodlike(&mut test, program_id);
+
+    // reserve the ETH slot at init
+    let eth_market_pk = mango_group.dexes[1].pubkey;
+    let eth_oracle_pk = mango_group.oracles[1].pubkey;
+    mango_group.dexes[1].pubkey = Pubkey::default();
+    mango_group.oracles[1].pubkey = Pubkey::default();
+
+    let (mut banks_client, payer, recent_blockhash) = test.start().await;
+
+    let mut transaction = Transaction::new_with_payer(
+        &[mango_group.init_mango_group(&payer.pubkey())],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer], recent_blockhash);
+    assert!(banks_client.process_transaction(transaction).await.is_ok());
+
+    let group = get_mango_group(&mut banks_client, &program_id, mango_group.mango_group_pk).await;
+    assert_eq!(group.spot_markets[1], Pubkey::default());
+    assert_eq!(group.oracles[1], Pubkey::default());
+
+    let mut transaction = Transaction::new_with_payer(
+        &[add_spot_market(
+            &program_id, &mango_group.mango_group_pk, &payer.pubkey(), &eth_market_pk, &eth_oracle_pk, 1
+        ).unwrap()],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer], recent_blockhash);
+    assert!(banks_client.process_transaction(transaction).await.is_ok());
+
+    let group = get_mango_group(&mut banks_client, &program_id, mango_group.mango_group_pk).await;
+    assert_eq!(group.spot_markets[1], eth_market_pk);
+    assert_eq!(group.oracles[1], eth_oracle_pk);
+    assert_eq!(group.oracle_decimals[1], 6);
+
+    // Test the BTC slot which was set at init can't be overwritten
+    let mut transaction = Transaction::new_with_payer(
+        &[add_spot_market(
+            &program_id, &mango_group.mango_group_pk, &payer.pubkey(), &eth_market_pk, &eth_oracle_pk, 0
+        ).unwrap()],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer], recent_blockhash);
+    assert!(banks_client.process_transaction(transaction).await.is_err());
+}
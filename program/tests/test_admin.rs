@@ -0,0 +1,247 @@
+// Tests related to the admin of a MangoGroup and admin only instructions
+#![cfg(feature="test-bpf")]
+
+mod helpers;
+
+use helpers::*;
+use solana_program::pubkey::Pubkey;
+use solana_program_test::*;
+use solana_sdk::{
+    instruction::InstructionError,
+    signature::{Keypair, Signer},
+    transaction::{Transaction, TransactionError},
+};
+
+use mango::{
+    entrypoint::process_instruction,
+    error::MangoErrorCode,
+    instruction::{accept_admin, change_all_borrow_limits, change_borrow_limit, set_admin, set_halt},
+    state::NUM_TOKENS,
+};
+
+#[tokio::test]
+async fn test_set_admin_one_step() {
+    // Test that a signing new admin takes over immediately and the old admin loses its powers
+    let program_id = Pubkey::new_unique();
+
+    let mut test = ProgramTest::new(
+        "mango",
+        program_id,
+        processor!(process_instruction),
+    );
+
+    // limit to track compute unit increase
+    test.set_bpf_compute_max_units(50_000);
+
+    let mango_group = add_mango_group_prodlike(&mut test, program_id);
+    let new_admin = Keypair::new();
+
+    let (mut banks_client, payer, recent_blockhash) = test.start().await;
+
+    let mut transaction = Transaction::new_with_payer(
+        &[
+            mango_group.init_mango_group(&payer.pubkey()),
+            set_admin(
+                &program_id, &mango_group.mango_group_pk, &payer.pubkey(), &new_admin.pubkey(), true
+            ).unwrap(),
+        ],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer, &new_admin], recent_blockhash);
+    assert!(banks_client.process_transaction(transaction).await.is_ok());
+
+    let group = get_mango_group(&mut banks_client, &program_id, mango_group.mango_group_pk).await;
+    assert_eq!(group.admin, new_admin.pubkey());
+    assert_eq!(group.pending_admin, Pubkey::default());
+
+    // Test old admin can no longer use admin instructions
+    let mut transaction = Transaction::new_with_payer(
+        &[set_halt(&program_id, &mango_group.mango_group_pk, &payer.pubkey(), true).unwrap()],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer], recent_blockhash);
+    assert!(banks_client.process_transaction(transaction).await.is_err());
+
+    // Test new admin can
+    let mut transaction = Transaction::new_with_payer(
+        &[set_halt(&program_id, &mango_group.mango_group_pk, &new_admin.pubkey(), true).unwrap()],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer, &new_admin], recent_blockhash);
+    assert!(banks_client.process_transaction(transaction).await.is_ok());
+}
+
+#[tokio::test]
+async fn test_set_admin_two_step() {
+    // Test that a non signing new admin is only proposed until it accepts
+    let program_id = Pubkey::new_unique();
+
+    let mut test = ProgramTest::new(
+        "mango",
+        program_id,
+        processor!(process_instruction),
+    );
+
+    // limit to track compute unit increase
+    test.set_bpf_compute_max_units(50_000);
+
+    let mango_group = add_mango_group_prodlike(&mut test, program_id);
+    let new_admin = Keypair::new();
+    let other = Keypair::new();
+
+    let (mut banks_client, payer, recent_blockhash) = test.start().await;
+
+    let mut transaction = Transaction::new_with_payer(
+        &[
+            mango_group.init_mango_group(&payer.pubkey()),
+            set_admin(
+                &program_id, &mango_group.mango_group_pk, &payer.pubkey(), &new_admin.pubkey(), false
+            ).unwrap(),
+        ],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer], recent_blockhash);
+    assert!(banks_client.process_transaction(transaction).await.is_ok());
+
+    let group = get_mango_group(&mut banks_client, &program_id, mango_group.mango_group_pk).await;
+    assert_eq!(group.admin, payer.pubkey());
+    assert_eq!(group.pending_admin, new_admin.pubkey());
+
+    // Test only the proposed admin can accept
+    let mut transaction = Transaction::new_with_payer(
+        &[accept_admin(&program_id, &mango_group.mango_group_pk, &other.pubkey()).unwrap()],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer, &other], recent_blockhash);
+    assert!(banks_client.process_transaction(transaction).await.is_err());
+
+    let mut transaction = Transaction::new_with_payer(
+        &[accept_admin(&program_id, &mango_group.mango_group_pk, &new_admin.pubkey()).unwrap()],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer, &new_admin], recent_blockhash);
+    assert!(banks_client.process_transaction(transaction).await.is_ok());
+
+    let group = get_mango_group(&mut banks_client, &program_id, mango_group.mango_group_pk).await;
+    assert_eq!(group.admin, new_admin.pubkey());
+    assert_eq!(group.pending_admin, Pubkey::default());
+}
+
+#[tokio::test]
+async fn test_set_admin_not_admin_fails() {
+    // Test that only the current admin can change the admin
+    let program_id = Pubkey::new_unique();
+
+    let mut test = ProgramTest::new(
+        "mango",
+        program_id,
+        processor!(process_instruction),
+    );
+
+    // limit to track compute unit increase
+    test.set_bpf_compute_max_units(50_000);
+
+    let mango_group = add_mango_group_prodlike(&mut test, program_id);
+    let attacker = Keypair::new();
+
+    let (mut banks_client, payer, recent_blockhash) = test.start().await;
+
+    let mut transaction = Transaction::new_with_payer(
+        &[mango_group.init_mango_group(&payer.pubkey())],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer], recent_blockhash);
+    assert!(banks_client.process_transaction(transaction).await.is_ok());
+
+    let mut transaction = Transaction::new_with_payer(
+        &[set_admin(
+            &program_id, &mango_group.mango_group_pk, &attacker.pubkey(), &attacker.pubkey(), true
+        ).unwrap()],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer, &attacker], recent_blockhash);
+    assert!(banks_client.process_transaction(transaction).await.is_err());
+
+    let group = get_mango_group(&mut banks_client, &program_id, mango_group.mango_group_pk).await;
+    assert_eq!(group.admin, payer.pubkey());
+}
+
+#[tokio::test]
+async fn test_change_all_borrow_limits() {
+    // Test that the admin sets every token's borrow limit at once and anyone else is rejected
+    let program_id = Pubkey::new_unique();
+
+    let mut test = ProgramTest::new(
+        "mango",
+        program_id,
+        processor!(process_instruction),
+    );
+
+    // limit to track compute unit increase
+    test.set_bpf_compute_max_units(50_000);
+
+    let mango_group = add_mango_group_prodlike(&mut test, program_id);
+    let attacker = Keypair::new();
+
+    let (mut banks_client, payer, recent_blockhash) = test.start().await;
+
+    let limits = [7, 300, 1_000_000];
+    let mut transaction = Transaction::new_with_payer(
+        &[
+            mango_group.init_mango_group(&payer.pubkey()),
+            change_all_borrow_limits(&program_id, &mango_group.mango_group_pk, &payer.pubkey(), limits).unwrap(),
+        ],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer], recent_blockhash);
+    assert!(banks_client.process_transaction(transaction).await.is_ok());
+
+    let group = get_mango_group(&mut banks_client, &program_id, mango_group.mango_group_pk).await;
+    assert_eq!(group.borrow_limits, limits);
+
+    let mut transaction = Transaction::new_with_payer(
+        &[change_all_borrow_limits(
+            &program_id, &mango_group.mango_group_pk, &attacker.pubkey(), [0, 0, 0]
+        ).unwrap()],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer, &attacker], recent_blockhash);
+    assert!(banks_client.process_transaction(transaction).await.is_err());
+
+    let group = get_mango_group(&mut banks_client, &program_id, mango_group.mango_group_pk).await;
+    assert_eq!(group.borrow_limits, limits);
+}
+
+#[tokio::test]
+async fn test_change_borrow_limit_fails_token_index_out_of_range() {
+    // Test that a token_index past the last token fails with an error instead of panicking
+    let program_id = Pubkey::new_unique();
+
+    let mut test = ProgramTest::new(
+        "mango",
+        program_id,
+        processor!(process_instruction),
+    );
+
+    // limit to track compute unit increase
+    test.set_bpf_compute_max_units(50_000);
+
+    let mango_group = add_mango_group_prodlike(&mut test, program_id);
+
+    let (mut banks_client, payer, recent_blockhash) = test.start().await;
+
+    let mut transaction = Transaction::new_with_payer(
+        &[
+            mango_group.init_mango_group(&payer.pubkey()),
+            change_borrow_limit(
+                &program_id, &mango_group.mango_group_pk, &payer.pubkey(), NUM_TOKENS, 1
+            ).unwrap(),
+        ],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer], recent_blockhash);
+    assert_eq!(
+        banks_client.process_transaction(transaction).await.unwrap_err().unwrap(),
+        TransactionError::InstructionError(1, InstructionError::Custom(MangoErrorCode::Default.into()))
+    );
+}
@@ -0,0 +1,107 @@
+// Tests related to auditing the vault balances of a MangoGroup
+#![cfg(feature="test-bpf")]
+
+mod helpers;
+
+use std::mem::size_of;
+
+use fixed::types::U64F64;
+use helpers::*;
+use solana_program_test::*;
+use solana_sdk::{
+    account::Account,
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+    transaction::Transaction,
+};
+
+use mango::{
+    entrypoint::process_instruction,
+    instruction::{audit_vaults, deposit, init_margin_account},
+    state::MarginAccount,
+};
+
+#[test]
+fn test_vault_shortfall() {
+    // Test that a vault drained below net deposits reports the missing amount
+    let mut mango_group = new_mango_group_in_memory([0, 0, 0]);
+    mango_group.total_deposits[2] = U64F64::from_num(1000);
+    mango_group.total_borrows[2] = U64F64::from_num(400);
+
+    assert_eq!(mango_group.get_vault_shortfall(2, 600).unwrap(), 0);
+    assert_eq!(mango_group.get_vault_shortfall(2, 1000).unwrap(), 0);
+    assert_eq!(mango_group.get_vault_shortfall(2, 550).unwrap(), 50);
+    assert_eq!(mango_group.get_vault_shortfall(2, 0).unwrap(), 600);
+}
+
+#[tokio::test]
+async fn test_audit_vaults_succeeds() {
+    // Test that the audit passes after a deposit and leaves the group untouched
+    let program_id = Pubkey::new_unique();
+
+    let mut test = ProgramTest::new(
+        "mango",
+        program_id,
+        processor!(process_instruction),
+    );
+
+    // limit to track compute unit increase
+    test.set_bpf_compute_max_units(50_000);
+
+    let mango_group = add_mango_group_prodlike(&mut test, program_id);
+    let vault_pks = mango_group.vaults.iter().map(|m| m.pubkey).collect::<Vec<Pubkey>>();
+
+    let user = Keypair::new();
+    test.add_account(user.pubkey(), Account::new(u32::MAX as u64, 0, &user.pubkey()));
+    let user_account = add_token_account(&mut test, user.pubkey(), mango_group.mints[0].pubkey, 10);
+    let margin_account_pk = Pubkey::new_unique();
+    test.add_account(margin_account_pk, Account::new(u32::MAX as u64, size_of::<MarginAccount>(), &program_id));
+
+    let (mut banks_client, payer, recent_blockhash) = test.start().await;
+
+    let mut transaction = Transaction::new_with_payer(
+        &[
+            mango_group.init_mango_group(&payer.pubkey()),
+            init_margin_account(
+                &program_id,
+                &mango_group.mango_group_pk,
+                &margin_account_pk,
+                &user.pubkey(),
+            ).unwrap(),
+            deposit(
+                &program_id,
+                &mango_group.mango_group_pk,
+                &margin_account_pk,
+                &user.pubkey(),
+                &user_account.pubkey,
+                &mango_group.vaults[0].pubkey,
+                10,
+            ).unwrap(),
+        ],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer, &user], recent_blockhash);
+    assert!(banks_client.process_transaction(transaction).await.is_ok());
+
+    let mango_group_before = banks_client.get_account(mango_group.mango_group_pk).await.unwrap().unwrap();
+
+    let mut transaction = Transaction::new_with_payer(
+        &[audit_vaults(&program_id, &mango_group.mango_group_pk, vault_pks.as_slice()).unwrap()],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer], recent_blockhash);
+    assert!(banks_client.process_transaction(transaction).await.is_ok());
+
+    let mango_group_after = banks_client.get_account(mango_group.mango_group_pk).await.unwrap().unwrap();
+    assert_eq!(mango_group_before.data, mango_group_after.data);
+
+    // Passing the vaults in the wrong order fails
+    let mut reversed = vault_pks.clone();
+    reversed.reverse();
+    let mut transaction = Transaction::new_with_payer(
+        &[audit_vaults(&program_id, &mango_group.mango_group_pk, reversed.as_slice()).unwrap()],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer], recent_blockhash);
+    assert!(banks_client.process_transaction(transaction).await.is_err());
+}
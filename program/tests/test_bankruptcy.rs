@@ -0,0 +1,227 @@
+// Tests related to resolving bankrupt MarginAccounts
+#![cfg(feature="test-bpf")]
+
+mod helpers;
+
+use std::mem::size_of;
+
+use fixed::types::U64F64;
+use helpers::*;
+use solana_program_test::*;
+use solana_sdk::{
+    account::Account,
+    instruction::InstructionError,
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+    transaction::{Transaction, TransactionError},
+};
+
+use mango::{
+    entrypoint::process_instruction,
+    error::MangoErrorCode,
+    instruction::{deposit, init_margin_account, resolve_bankruptcy},
+    state::{MarginAccount, MAX_SOCIALIZED_LOSS, NUM_MARKETS, ONE_U64F64, ZERO_U64F64},
+};
+
+#[tokio::test]
+async fn test_resolve_bankruptcy_socializes_borrows() {
+    // Test that the borrows of an account with no deposits are written off against the lenders
+    let program_id = Pubkey::new_unique();
+
+    let mut test = ProgramTest::new(
+        "mango",
+        program_id,
+        processor!(process_instruction),
+    );
+
+    // limit to track compute unit increase
+    test.set_bpf_compute_max_units(50_000);
+
+    let mango_group = add_mango_group_prodlike(&mut test, program_id);
+
+    // a lender with 400 USDT deposited
+    let lender = Keypair::new();
+    test.add_account(lender.pubkey(), Account::new(u32::MAX as u64, 0, &lender.pubkey()));
+    let lender_usdt = add_token_account(&mut test, lender.pubkey(), mango_group.mints[2].pubkey, 400);
+    let lender_margin_account_pk = Pubkey::new_unique();
+    test.add_account(lender_margin_account_pk, Account::new(u32::MAX as u64, size_of::<MarginAccount>(), &program_id));
+
+    // 100 USDT borrowed with nothing left to seize
+    let bankrupt_pk = add_margin_account(
+        &mut test, program_id, mango_group.mango_group_pk, Pubkey::new_unique(), [0, 0, 0], [0, 0, 100]
+    );
+
+    let (mut banks_client, payer, recent_blockhash) = test.start().await;
+
+    let mut transaction = Transaction::new_with_payer(
+        &[
+            mango_group.init_mango_group(&payer.pubkey()),
+            init_margin_account(
+                &program_id,
+                &mango_group.mango_group_pk,
+                &lender_margin_account_pk,
+                &lender.pubkey(),
+            ).unwrap(),
+            deposit(
+                &program_id,
+                &mango_group.mango_group_pk,
+                &lender_margin_account_pk,
+                &lender.pubkey(),
+                &lender_usdt.pubkey,
+                &mango_group.vaults[2].pubkey,
+                400,
+            ).unwrap(),
+            resolve_bankruptcy(
+                &program_id,
+                &mango_group.mango_group_pk,
+                &bankrupt_pk,
+                &[Pubkey::default(); NUM_MARKETS],
+                mango_group.oracles.iter().map(|m| m.pubkey).collect::<Vec<Pubkey>>().as_slice(),
+            ).unwrap(),
+        ],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer, &lender], recent_blockhash);
+    assert!(banks_client.process_transaction(transaction).await.is_ok());
+
+    let bankrupt = get_margin_account(
+        &mut banks_client, &program_id, &mango_group.mango_group_pk, bankrupt_pk
+    ).await;
+    assert_eq!(bankrupt.borrows[2], ZERO_U64F64);
+
+    // lenders lose 100 / 400 of their deposits
+    let group = get_mango_group(&mut banks_client, &program_id, mango_group.mango_group_pk).await;
+    assert_eq!(group.indexes[2].deposit, U64F64::from_num(0.75));
+    assert_eq!(group.indexes[0].deposit, U64F64::from_num(1));
+}
+
+#[tokio::test]
+async fn test_resolve_bankruptcy_loss_exceeds_deposits() {
+    // Test that a loss larger than all lender deposits leaves a usable deposit index, so lenders
+    // can still deposit afterwards, and the part lenders didn't cover is recorded as bad debt
+    let program_id = Pubkey::new_unique();
+
+    let mut test = ProgramTest::new(
+        "mango",
+        program_id,
+        processor!(process_instruction),
+    );
+
+    // limit to track compute unit increase
+    test.set_bpf_compute_max_units(50_000);
+
+    let mango_group = add_mango_group_prodlike(&mut test, program_id);
+
+    let lender = Keypair::new();
+    test.add_account(lender.pubkey(), Account::new(u32::MAX as u64, 0, &lender.pubkey()));
+    let lender_usdt = add_token_account(&mut test, lender.pubkey(), mango_group.mints[2].pubkey, 400);
+    let lender_margin_account_pk = Pubkey::new_unique();
+    test.add_account(lender_margin_account_pk, Account::new(u32::MAX as u64, size_of::<MarginAccount>(), &program_id));
+
+    // 100 USDT borrowed against 50 USDT of lender deposits
+    let bankrupt_pk = add_margin_account(
+        &mut test, program_id, mango_group.mango_group_pk, Pubkey::new_unique(), [0, 0, 0], [0, 0, 100]
+    );
+
+    let (mut banks_client, payer, recent_blockhash) = test.start().await;
+
+    let mut transaction = Transaction::new_with_payer(
+        &[
+            mango_group.init_mango_group(&payer.pubkey()),
+            init_margin_account(
+                &program_id,
+                &mango_group.mango_group_pk,
+                &lender_margin_account_pk,
+                &lender.pubkey(),
+            ).unwrap(),
+            deposit(
+                &program_id,
+                &mango_group.mango_group_pk,
+                &lender_margin_account_pk,
+                &lender.pubkey(),
+                &lender_usdt.pubkey,
+                &mango_group.vaults[2].pubkey,
+                50,
+            ).unwrap(),
+            resolve_bankruptcy(
+                &program_id,
+                &mango_group.mango_group_pk,
+                &bankrupt_pk,
+                &[Pubkey::default(); NUM_MARKETS],
+                mango_group.oracles.iter().map(|m| m.pubkey).collect::<Vec<Pubkey>>().as_slice(),
+            ).unwrap(),
+        ],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer, &lender], recent_blockhash);
+    assert!(banks_client.process_transaction(transaction).await.is_ok());
+
+    let group = get_mango_group(&mut banks_client, &program_id, mango_group.mango_group_pk).await;
+    assert_eq!(group.indexes[2].deposit, ONE_U64F64 - MAX_SOCIALIZED_LOSS);
+    assert_eq!(group.bad_debt[2], 51);
+
+    let mut transaction = Transaction::new_with_payer(
+        &[
+            deposit(
+                &program_id,
+                &mango_group.mango_group_pk,
+                &lender_margin_account_pk,
+                &lender.pubkey(),
+                &lender_usdt.pubkey,
+                &mango_group.vaults[2].pubkey,
+                10,
+            ).unwrap(),
+        ],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer, &lender], recent_blockhash);
+    assert!(banks_client.process_transaction(transaction).await.is_ok());
+
+    // 0.5 USDT left of the first deposit plus the new 10
+    let lender_account = get_margin_account(
+        &mut banks_client, &program_id, &mango_group.mango_group_pk, lender_margin_account_pk
+    ).await;
+    let native_deposit = (lender_account.deposits[2] * group.indexes[2].deposit).to_num::<f64>();
+    assert!((native_deposit - 10.5).abs() < 1e-6);
+}
+
+#[tokio::test]
+async fn test_resolve_bankruptcy_fails_with_deposits() {
+    // Test that an account with any deposit left is not written off
+    let program_id = Pubkey::new_unique();
+
+    let mut test = ProgramTest::new(
+        "mango",
+        program_id,
+        processor!(process_instruction),
+    );
+
+    // limit to track compute unit increase
+    test.set_bpf_compute_max_units(50_000);
+
+    let mango_group = add_mango_group_prodlike(&mut test, program_id);
+    let margin_account_pk = add_margin_account(
+        &mut test, program_id, mango_group.mango_group_pk, Pubkey::new_unique(), [0, 0, 1], [0, 0, 100]
+    );
+
+    let (mut banks_client, payer, recent_blockhash) = test.start().await;
+
+    let mut transaction = Transaction::new_with_payer(
+        &[
+            mango_group.init_mango_group(&payer.pubkey()),
+            resolve_bankruptcy(
+                &program_id,
+                &mango_group.mango_group_pk,
+                &margin_account_pk,
+                &[Pubkey::default(); NUM_MARKETS],
+                mango_group.oracles.iter().map(|m| m.pubkey).collect::<Vec<Pubkey>>().as_slice(),
+            ).unwrap(),
+        ],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer], recent_blockhash);
+    assert_eq!(
+        banks_client.process_transaction(transaction).await.unwrap_err().unwrap(),
+        TransactionError::InstructionError(1, InstructionError::Custom(MangoErrorCode::NotBankrupt.into()))
+    );
+}
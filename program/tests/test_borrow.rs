@@ -8,16 +8,19 @@ use helpers::*;
 use solana_program::account_info::AccountInfo;
 use solana_program_test::*;
 use solana_sdk::{
+    instruction::InstructionError,
     pubkey::Pubkey,
     signature::{Signer, Keypair},
-    transaction::Transaction,
+    transaction::{Transaction, TransactionError},
     account::Account,
 };
 use mango::{
     entrypoint::process_instruction,
-    instruction::{deposit, borrow, init_margin_account},
+    error::MangoErrorCode,
+    instruction::{deposit, borrow, change_max_borrow_per_account, init_margin_account},
     state::MarginAccount,
     state::MangoGroup,
+    state::NUM_TOKENS,
 };
 
 #[tokio::test]
@@ -272,8 +275,11 @@ async fn test_borrow_fails_overleveraged() {
             recent_blockhash,
         );
 
-        // Test transaction failed
-        assert!(banks_client.process_transaction(transaction).await.is_err());
+        // Test transaction failed with the collateral ratio error
+        assert_eq!(
+            banks_client.process_transaction(transaction).await.unwrap_err().unwrap(),
+            TransactionError::InstructionError(0, InstructionError::Custom(MangoErrorCode::CollateralRatioLimit.into()))
+        );
         
         let mut margin_account = banks_client
             .get_account(margin_account_pk)
@@ -306,4 +312,120 @@ async fn test_borrow_fails_overleveraged() {
         // Test nothing is added to total in mango group
         assert_eq!(mango_group.total_borrows[borrow_token_index], 0);
     }
-}
\ No newline at end of file
+}
+
+#[tokio::test]
+async fn test_borrow_fails_over_max_borrow_per_account() {
+    // Test that a second borrow over the per account cap fails even though the borrow limit has headroom
+    let program_id = Pubkey::new_unique();
+
+    let mut test = ProgramTest::new(
+        "mango",
+        program_id,
+        processor!(process_instruction),
+    );
+
+    // limit to track compute unit increase
+    test.set_bpf_compute_max_units(50_000);
+
+    let borrow_token_index = 1;
+    let mango_group = add_mango_group_prodlike(&mut test, program_id);
+    let user = Keypair::new();
+    test.add_account(user.pubkey(), Account::new(u32::MAX as u64, 0, &user.pubkey()));
+    let margin_account_pk = add_margin_account(
+        &mut test, program_id, mango_group.mango_group_pk, user.pubkey(), [1, 0, 0], [0, 0, 0]
+    );
+
+    let (mut banks_client, payer, recent_blockhash) = test.start().await;
+
+    let mut transaction = Transaction::new_with_payer(
+        &[
+            mango_group.init_mango_group(&payer.pubkey()),
+            change_max_borrow_per_account(
+                &program_id,
+                &mango_group.mango_group_pk,
+                &payer.pubkey(),
+                borrow_token_index,
+                15,
+            ).unwrap(),
+        ],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer], recent_blockhash);
+    assert!(banks_client.process_transaction(transaction).await.is_ok());
+
+    let oracle_pks = mango_group.oracles.iter().map(|m| m.pubkey).collect::<Vec<Pubkey>>();
+    let borrow_instruction = |quantity: u64| borrow(
+        &program_id,
+        &mango_group.mango_group_pk,
+        &margin_account_pk,
+        &user.pubkey(),
+        &[Pubkey::default(), Pubkey::default()],
+        oracle_pks.as_slice(),
+        borrow_token_index,
+        quantity,
+    ).unwrap();
+
+    let mut transaction = Transaction::new_with_payer(&[borrow_instruction(10)], Some(&payer.pubkey()));
+    transaction.sign(&[&payer, &user], recent_blockhash);
+    assert!(banks_client.process_transaction(transaction).await.is_ok());
+
+    // 20 ETH is well under the borrow limit of 100 but over the cap of 15
+    let mut transaction = Transaction::new_with_payer(&[borrow_instruction(10)], Some(&payer.pubkey()));
+    transaction.sign(&[&payer, &user], recent_blockhash);
+    assert_eq!(
+        banks_client.process_transaction(transaction).await.unwrap_err().unwrap(),
+        TransactionError::InstructionError(0, InstructionError::Custom(MangoErrorCode::BorrowLimitExceeded.into()))
+    );
+
+    let margin_account = get_margin_account(
+        &mut banks_client, &program_id, &mango_group.mango_group_pk, margin_account_pk
+    ).await;
+    assert_eq!(margin_account.borrows[borrow_token_index], 10);
+}
+
+#[tokio::test]
+async fn test_borrow_fails_token_index_out_of_range() {
+    // Test that a token_index past the last token fails with an error instead of panicking
+    let program_id = Pubkey::new_unique();
+
+    let mut test = ProgramTest::new(
+        "mango",
+        program_id,
+        processor!(process_instruction),
+    );
+
+    // limit to track compute unit increase
+    test.set_bpf_compute_max_units(50_000);
+
+    let mango_group = add_mango_group_prodlike(&mut test, program_id);
+    let user = Keypair::new();
+    test.add_account(user.pubkey(), Account::new(u32::MAX as u64, 0, &user.pubkey()));
+    let margin_account_pk = add_margin_account(
+        &mut test, program_id, mango_group.mango_group_pk, user.pubkey(), [1, 0, 0], [0, 0, 0]
+    );
+
+    let (mut banks_client, payer, recent_blockhash) = test.start().await;
+
+    let mut transaction = Transaction::new_with_payer(
+        &[
+            mango_group.init_mango_group(&payer.pubkey()),
+            borrow(
+                &program_id,
+                &mango_group.mango_group_pk,
+                &margin_account_pk,
+                &user.pubkey(),
+                &[Pubkey::default(), Pubkey::default()],
+                mango_group.oracles.iter().map(|m| m.pubkey).collect::<Vec<Pubkey>>().as_slice(),
+                NUM_TOKENS,
+                1,
+            ).unwrap(),
+        ],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer, &user], recent_blockhash);
+    assert_eq!(
+        banks_client.process_transaction(transaction).await.unwrap_err().unwrap(),
+        TransactionError::InstructionError(1, InstructionError::Custom(MangoErrorCode::Default.into()))
+    );
+}
@@ -0,0 +1,94 @@
+// Tests related to the owner cancelling all of their orders in a market
+#![cfg(feature="test-bpf")]
+
+mod helpers;
+
+use helpers::*;
+use solana_program_test::*;
+use solana_sdk::{
+    account::Account,
+    instruction::{Instruction, InstructionError},
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+    transaction::{Transaction, TransactionError},
+};
+
+use mango::{
+    entrypoint::process_instruction,
+    error::MangoErrorCode,
+    instruction::cancel_all_my_orders,
+};
+
+fn cancel_all_my_orders_instruction(
+    mango_group: &TestMangoGroup,
+    owner_pk: &Pubkey,
+    margin_account_pk: &Pubkey,
+) -> Instruction {
+    cancel_all_my_orders(
+        &mango_group.program_id,
+        &mango_group.mango_group_pk,
+        owner_pk,
+        margin_account_pk,
+        &mango_group.dex_prog_id,
+        &mango_group.dexes[0].pubkey,
+        &Pubkey::new_unique(),
+        &Pubkey::new_unique(),
+        &Pubkey::new_unique(),
+        &mango_group.signer_pk,
+        &Pubkey::new_unique(),
+        0,
+        5,
+    ).unwrap()
+}
+
+#[tokio::test]
+async fn test_cancel_all_my_orders_requires_owner() {
+    // Test that only the MarginAccount owner can cancel all of its orders
+    let program_id = Pubkey::new_unique();
+
+    let mut test = ProgramTest::new(
+        "mango",
+        program_id,
+        processor!(process_instruction),
+    );
+
+    // limit to track compute unit increase
+    test.set_bpf_compute_max_units(50_000);
+
+    let mango_group = add_mango_group_prodlike(&mut test, program_id);
+    let owner = Keypair::new();
+    test.add_account(owner.pubkey(), Account::new(u32::MAX as u64, 0, &owner.pubkey()));
+    let margin_account_pk = add_margin_account(
+        &mut test, program_id, mango_group.mango_group_pk, owner.pubkey(), [0, 0, 0], [0, 0, 0]
+    );
+
+    let (mut banks_client, payer, recent_blockhash) = test.start().await;
+
+    let mut transaction = Transaction::new_with_payer(
+        &[mango_group.init_mango_group(&payer.pubkey())],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer], recent_blockhash);
+    assert!(banks_client.process_transaction(transaction).await.is_ok());
+
+    let mut transaction = Transaction::new_with_payer(
+        &[cancel_all_my_orders_instruction(&mango_group, &payer.pubkey(), &margin_account_pk)],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer], recent_blockhash);
+    assert_eq!(
+        banks_client.process_transaction(transaction).await.unwrap_err().unwrap(),
+        TransactionError::InstructionError(0, InstructionError::Custom(MangoErrorCode::InvalidMarginAccountOwner.into()))
+    );
+
+    // Test the owner gets past the owner check and fails on the open orders account it never created
+    let mut transaction = Transaction::new_with_payer(
+        &[cancel_all_my_orders_instruction(&mango_group, &owner.pubkey(), &margin_account_pk)],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer, &owner], recent_blockhash);
+    assert_eq!(
+        banks_client.process_transaction(transaction).await.unwrap_err().unwrap(),
+        TransactionError::InstructionError(0, InstructionError::Custom(MangoErrorCode::InvalidOpenOrdersAccount.into()))
+    );
+}
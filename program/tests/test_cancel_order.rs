@@ -0,0 +1,151 @@
+// Tests related to cancelling orders through a MangoGroup
+#![cfg(feature="test-bpf")]
+
+mod helpers;
+
+use bytemuck::Zeroable;
+use fixed::types::U64F64;
+use helpers::*;
+use serum_dex::{instruction::CancelOrderInstructionV2, matching::Side};
+use solana_program_test::*;
+use solana_sdk::{
+    account::Account,
+    instruction::InstructionError,
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+    transaction::{Transaction, TransactionError},
+};
+
+use mango::{
+    entrypoint::process_instruction,
+    error::MangoErrorCode,
+    instruction::{cancel_order, cancel_order_and_settle, MangoInstruction},
+    processor::credit_settled_funds,
+    state::{MarginAccount, QUOTE_INDEX},
+};
+
+#[tokio::test]
+async fn test_cancel_order_foreign_spot_market_fails() {
+    // Test that a spot market that is not in the group returns InvalidSpotMarket instead of panicking
+    let program_id = Pubkey::new_unique();
+
+    let mut test = ProgramTest::new(
+        "mango",
+        program_id,
+        processor!(process_instruction),
+    );
+
+    // limit to track compute unit increase
+    test.set_bpf_compute_max_units(50_000);
+
+    let mango_group = add_mango_group_prodlike(&mut test, program_id);
+    let owner = Keypair::new();
+    test.add_account(owner.pubkey(), Account::new(u32::MAX as u64, 0, &owner.pubkey()));
+    let margin_account_pk = add_margin_account(
+        &mut test, program_id, mango_group.mango_group_pk, owner.pubkey(), [0, 0, 0], [0, 0, 0]
+    );
+
+    let (mut banks_client, payer, recent_blockhash) = test.start().await;
+
+    let mut transaction = Transaction::new_with_payer(
+        &[mango_group.init_mango_group(&payer.pubkey())],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer], recent_blockhash);
+    assert!(banks_client.process_transaction(transaction).await.is_ok());
+
+    let mut transaction = Transaction::new_with_payer(
+        &[
+            cancel_order(
+                &program_id,
+                &mango_group.mango_group_pk,
+                &owner.pubkey(),
+                &margin_account_pk,
+                &mango_group.dex_prog_id,
+                &Pubkey::new_unique(),
+                &Pubkey::new_unique(),
+                &Pubkey::new_unique(),
+                &Pubkey::new_unique(),
+                &mango_group.signer_pk,
+                &Pubkey::new_unique(),
+                CancelOrderInstructionV2 { side: Side::Bid, order_id: 0 },
+            ).unwrap(),
+        ],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer, &owner], recent_blockhash);
+    assert_eq!(
+        banks_client.process_transaction(transaction).await.unwrap_err().unwrap(),
+        TransactionError::InstructionError(0, InstructionError::Custom(MangoErrorCode::InvalidSpotMarket.into()))
+    );
+}
+
+#[tokio::test]
+async fn test_cancel_order_and_settle_foreign_spot_market_fails() {
+    // Test that CancelOrderAndSettle checks the spot market like CancelOrder
+    let program_id = Pubkey::new_unique();
+
+    let mut test = ProgramTest::new(
+        "mango",
+        program_id,
+        processor!(process_instruction),
+    );
+
+    // limit to track compute unit increase
+    test.set_bpf_compute_max_units(50_000);
+
+    let mango_group = add_mango_group_prodlike(&mut test, program_id);
+    let owner = Keypair::new();
+    test.add_account(owner.pubkey(), Account::new(u32::MAX as u64, 0, &owner.pubkey()));
+    let margin_account_pk = add_margin_account(
+        &mut test, program_id, mango_group.mango_group_pk, owner.pubkey(), [0, 0, 0], [0, 0, 0]
+    );
+
+    let (mut banks_client, payer, recent_blockhash) = test.start().await;
+
+    let order = CancelOrderInstructionV2 { side: Side::Bid, order_id: 7 };
+    let instruction = cancel_order_and_settle(
+        &program_id,
+        &mango_group.mango_group_pk,
+        &owner.pubkey(),
+        &margin_account_pk,
+        &mango_group.dex_prog_id,
+        &Pubkey::new_unique(),
+        &Pubkey::new_unique(),
+        &Pubkey::new_unique(),
+        &Pubkey::new_unique(),
+        &mango_group.signer_pk,
+        &Pubkey::new_unique(),
+        &Pubkey::new_unique(),
+        &Pubkey::new_unique(),
+        &mango_group.vaults[0].pubkey,
+        &mango_group.vaults[2].pubkey,
+        &Pubkey::new_unique(),
+        order.clone(),
+    ).unwrap();
+    assert_eq!(MangoInstruction::unpack(&instruction.data), Some(MangoInstruction::CancelOrderAndSettle { order }));
+
+    let mut transaction = Transaction::new_with_payer(
+        &[mango_group.init_mango_group(&payer.pubkey()), instruction],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer, &owner], recent_blockhash);
+    assert_eq!(
+        banks_client.process_transaction(transaction).await.unwrap_err().unwrap(),
+        TransactionError::InstructionError(1, InstructionError::Custom(MangoErrorCode::InvalidSpotMarket.into()))
+    );
+}
+
+#[test]
+fn test_cancelled_bid_quote_returns_to_deposits() {
+    // Test that the quote a cancelled bid freed on the open orders account is credited to
+    // deposits once the settle moves it into the vault
+    let mut mango_group = new_mango_group_in_memory([0, 0, 0]);
+    let mut margin_account = MarginAccount::zeroed();
+
+    // a resting bid for 0.1 BTC at 50000 locked 5000 USDT, all free after the cancel
+    credit_settled_funds(&mut mango_group, &mut margin_account, 0, (0, 5000), (0, 0)).unwrap();
+    assert_eq!(margin_account.deposits[QUOTE_INDEX], U64F64::from_num(5000));
+    assert_eq!(margin_account.deposits[0], U64F64::from_num(0));
+    assert_eq!(mango_group.total_deposits[QUOTE_INDEX], U64F64::from_num(5000));
+}
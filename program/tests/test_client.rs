@@ -0,0 +1,269 @@
+// Tests related to the client instruction builders
+#![cfg(all(feature="test-bpf", feature="client"))]
+
+mod helpers;
+
+use std::mem::size_of;
+use std::num::NonZeroU64;
+
+use bytemuck::Zeroable;
+use fixed::types::U64F64;
+use helpers::*;
+use serum_dex::instruction::{NewOrderInstructionV3, SelfTradeBehavior};
+use serum_dex::matching::{OrderType, Side};
+use serum_dex::state::OpenOrders;
+use solana_program::account_info::AccountInfo;
+use solana_program::program_error::ProgramError;
+use solana_sdk::{
+    account::Account,
+    pubkey::Pubkey,
+};
+
+use mango::{
+    client::{borrow_ix, deposit_ix, get_withdrawable_with_free_funds, place_and_settle_ix, settle_and_withdraw_ixs,
+             simulate_liquidation, SpotMarketAccounts, withdraw_ix},
+    error::{MangoError, MangoErrorCode, SourceFileId},
+    state::{AccountFlag, MARGIN_ACCOUNT_VERSION, MarginAccount, NUM_MARKETS, QUOTE_INDEX},
+};
+
+fn spot_market_accounts() -> SpotMarketAccounts {
+    SpotMarketAccounts {
+        spot_market: Pubkey::new_unique(),
+        request_queue: Pubkey::new_unique(),
+        event_queue: Pubkey::new_unique(),
+        bids: Pubkey::new_unique(),
+        asks: Pubkey::new_unique(),
+        coin_vault: Pubkey::new_unique(),
+        pc_vault: Pubkey::new_unique(),
+        vault_signer: Pubkey::new_unique(),
+    }
+}
+
+fn new_order() -> NewOrderInstructionV3 {
+    NewOrderInstructionV3 {
+        side: Side::Bid,
+        limit_price: NonZeroU64::new(1).unwrap(),
+        max_coin_qty: NonZeroU64::new(1).unwrap(),
+        max_native_pc_qty_including_fees: NonZeroU64::new(1).unwrap(),
+        self_trade_behavior: SelfTradeBehavior::DecrementTake,
+        order_type: OrderType::Limit,
+        client_order_id: 0,
+        limit: 10,
+    }
+}
+
+#[test]
+fn test_client_account_counts() {
+    // Test that each builder produces the account count its processor function expects
+    let program_id = Pubkey::new_unique();
+    let mango_group_pk = Pubkey::new_unique();
+    let mut mango_group = new_mango_group_in_memory([0, 0, 0]);
+    for i in 0..mango_group.vaults.len() {
+        mango_group.vaults[i] = Pubkey::new_unique();
+    }
+    let margin_account_pk = Pubkey::new_unique();
+    let margin_account = MarginAccount::zeroed();
+    let owner_pk = Pubkey::new_unique();
+    let token_account_pk = Pubkey::new_unique();
+
+    let ix = deposit_ix(
+        &program_id, &mango_group_pk, &mango_group, &margin_account_pk, &owner_pk, &token_account_pk, 1, 1
+    ).unwrap();
+    assert_eq!(ix.accounts.len(), 7);
+    assert_eq!(ix.accounts[4].pubkey, mango_group.vaults[1]);
+
+    let ix = withdraw_ix(
+        &program_id, &mango_group_pk, &mango_group, &margin_account_pk, &margin_account, &owner_pk,
+        &token_account_pk, 1, 1
+    ).unwrap();
+    assert_eq!(ix.accounts.len(), 8 + 2 * NUM_MARKETS);
+    assert_eq!(ix.accounts[4].pubkey, mango_group.vaults[1]);
+
+    let ix = borrow_ix(
+        &program_id, &mango_group_pk, &mango_group, &margin_account_pk, &margin_account, &owner_pk, 1, 1
+    ).unwrap();
+    assert_eq!(ix.accounts.len(), 4 + 2 * NUM_MARKETS);
+
+    let open_orders_pk = Pubkey::new_unique();
+    let ix = place_and_settle_ix(
+        &program_id, &mango_group_pk, &mango_group, &margin_account_pk, &margin_account, &owner_pk, 1,
+        &spot_market_accounts(), &open_orders_pk, new_order(), 0
+    ).unwrap();
+    assert_eq!(ix.accounts.len(), 19 + 2 * NUM_MARKETS);
+    assert_eq!(ix.accounts[10].pubkey, mango_group.vaults[1]);
+    assert_eq!(ix.accounts[11].pubkey, mango_group.vaults[NUM_MARKETS]);
+    assert_eq!(ix.accounts[19 + 1].pubkey, open_orders_pk);
+}
+
+#[test]
+fn test_settle_and_withdraw() {
+    // Test that quote freed on the dex counts toward what can be withdrawn and that settling and
+    // withdrawing it go out as one transaction, settle first
+    let program_id = Pubkey::new_unique();
+    let mango_group_pk = Pubkey::new_unique();
+    let mut mango_group = new_mango_group_in_memory([0, 0, 0]);
+    for i in 0..mango_group.vaults.len() {
+        mango_group.vaults[i] = Pubkey::new_unique();
+    }
+    let margin_account_pk = Pubkey::new_unique();
+    let owner_pk = Pubkey::new_unique();
+    let token_account_pk = Pubkey::new_unique();
+    let open_orders_pk = Pubkey::new_unique();
+    let mut margin_account = MarginAccount::zeroed();
+    margin_account.deposits[QUOTE_INDEX] = U64F64::from_num(1000);
+    margin_account.open_orders[0] = open_orders_pk;
+
+    // a cancelled bid left 500 quote free on the dex
+    let mut btc_usdt = open_orders_account(&mango_group.signer_key, u128::MAX);
+    {
+        let open_orders: &mut OpenOrders = bytemuck::from_bytes_mut(&mut btc_usdt.data[5..5 + size_of::<OpenOrders>()]);
+        open_orders.native_pc_free = 500;
+        open_orders.native_pc_total = 500;
+    }
+    let pk = Pubkey::default();
+    let mut eth_usdt = Account::default();
+    let open_orders_accs: [AccountInfo; 2] = [(&open_orders_pk, &mut btc_usdt).into(), (&pk, &mut eth_usdt).into()];
+    let available = get_withdrawable_with_free_funds(&mango_group, &margin_account, &open_orders_accs, QUOTE_INDEX).unwrap();
+    assert_eq!(available, 1500);
+    assert_eq!(get_withdrawable_with_free_funds(&mango_group, &margin_account, &open_orders_accs, 0).unwrap(), 0);
+
+    let market = spot_market_accounts();
+    let ixs = settle_and_withdraw_ixs(
+        &program_id, &mango_group_pk, &mango_group, &margin_account_pk, &margin_account, &owner_pk, 0,
+        &market, &token_account_pk, QUOTE_INDEX, available
+    ).unwrap();
+    assert_eq!(ixs.len(), 2);
+    assert_eq!(ixs[0].accounts.len(), 14);
+    assert_eq!(ixs[0].accounts[6].pubkey, open_orders_pk);
+    assert_eq!(ixs[0].accounts[10].pubkey, mango_group.vaults[0]);
+    assert_eq!(ixs[0].accounts[11].pubkey, mango_group.vaults[QUOTE_INDEX]);
+    assert_eq!(ixs[1].accounts.len(), 8 + 2 * NUM_MARKETS);
+    assert_eq!(ixs[1].accounts[4].pubkey, mango_group.vaults[QUOTE_INDEX]);
+    assert_eq!(ixs[1].accounts[8].pubkey, open_orders_pk);
+}
+
+#[test]
+fn test_client_appends_secondary_oracles() {
+    // Test that the secondary oracles are appended once any market has one configured
+    let program_id = Pubkey::new_unique();
+    let mango_group_pk = Pubkey::new_unique();
+    let mut mango_group = new_mango_group_in_memory([0, 0, 0]);
+    mango_group.oracles_secondary[0] = Pubkey::new_unique();
+    let margin_account = MarginAccount::zeroed();
+
+    let ix = borrow_ix(
+        &program_id, &mango_group_pk, &mango_group, &Pubkey::new_unique(), &margin_account,
+        &Pubkey::new_unique(), 1, 1
+    ).unwrap();
+    assert_eq!(ix.accounts.len(), 4 + 3 * NUM_MARKETS);
+    assert_eq!(ix.accounts[4 + 2 * NUM_MARKETS].pubkey, mango_group.oracles_secondary[0]);
+}
+
+#[test]
+fn test_simulate_liquidation() {
+    // Test the quote deposit that brings underwater accounts back to init_coll_ratio
+    let mut mango_group = new_mango_group_in_memory([0, 0, 0]);
+    let prices = get_prodlike_prices();
+    let pk = Pubkey::default();
+    let mut accounts = [Account::default(), Account::default()];
+    let open_orders_accs = empty_open_orders(&pk, &mut accounts);
+
+    // above maint: nothing to do
+    let mut margin_account = MarginAccount::zeroed();
+    margin_account.deposits[0] = U64F64::from_num(1);
+    margin_account.borrows[2] = U64F64::from_num(45000);
+    let quantities = simulate_liquidation(&mango_group, &margin_account, &prices, &open_orders_accs, 0).unwrap();
+    assert_eq!(quantities, [0, 0, 0]);
+
+    // 50000 / 46000 < 1.1: 1.2 * 46000 - 50000 = 5200 plus one for rounding
+    margin_account.borrows[2] = U64F64::from_num(46000);
+    let quantities = simulate_liquidation(&mango_group, &margin_account, &prices, &open_orders_accs, 0).unwrap();
+    assert_eq!(quantities, [0, 0, 5201]);
+
+    let mut liquidated = margin_account;
+    liquidated.deposits[2] = U64F64::from_num(quantities[2]);
+    let coll_ratio = liquidated.get_collateral_ratio(&mango_group, &prices, &open_orders_accs, 0).unwrap();
+    assert!(coll_ratio >= mango_group.init_coll_ratio);
+
+    // the liqor gets 5% back out of the account, so it has to deposit 5200 / 0.95
+    mango_group.liquidation_fee = U64F64::from_num(0.05);
+    let quantities = simulate_liquidation(&mango_group, &margin_account, &prices, &open_orders_accs, 0).unwrap();
+    assert_eq!(quantities, [0, 0, 5475]);
+
+    // insolvent: after settling USDT and socializing, 20000 of ETH backs 19802 of BTC borrows
+    mango_group.liquidation_fee = U64F64::from_num(0);
+    mango_group.total_deposits[1] = U64F64::from_num(10);
+    mango_group.total_deposits[2] = U64F64::from_num(200000);
+    mango_group.total_borrows[0] = U64F64::from_num(1);
+    mango_group.total_borrows[2] = U64F64::from_num(100000);
+    let mut margin_account = MarginAccount::zeroed();
+    margin_account.deposits[1] = U64F64::from_num(10);
+    margin_account.deposits[2] = U64F64::from_num(100000);
+    margin_account.borrows[0] = U64F64::from_num(1);
+    margin_account.borrows[2] = U64F64::from_num(100000);
+    let quantities = simulate_liquidation(&mango_group, &margin_account, &prices, &open_orders_accs, 0).unwrap();
+    assert_eq!(quantities[0], 0);
+    assert_eq!(quantities[1], 0);
+    assert!((quantities[2] as i64 - 3764).abs() <= 1);
+
+    // the simulation doesn't touch the passed in state
+    assert_eq!(margin_account.borrows[2], U64F64::from_num(100000));
+    assert_eq!(mango_group.total_borrows[2], U64F64::from_num(100000));
+}
+
+#[test]
+fn test_client_errors_report_client_file() {
+    // Test that a failed check in the client module is attributed to src/client.rs
+    let mut mango_group = new_mango_group_in_memory([0, 0, 0]);
+    let prices = get_prodlike_prices();
+    let pk = Pubkey::default();
+    let mut accounts = [Account::default(), Account::default()];
+    let open_orders_accs = empty_open_orders(&pk, &mut accounts);
+
+    // a liquidation_fee of 1 leaves nothing of the deposit to size it with
+    mango_group.liquidation_fee = U64F64::from_num(1);
+    let mut margin_account = MarginAccount::zeroed();
+    margin_account.deposits[0] = U64F64::from_num(1);
+    margin_account.borrows[2] = U64F64::from_num(46000);
+    let r = simulate_liquidation(&mango_group, &margin_account, &prices, &open_orders_accs, 0);
+    assert!(matches!(r, Err(MangoError::MangoErrorCode {
+        mango_error_code: MangoErrorCode::Default,
+        source_file_id: SourceFileId::Client,
+        ..
+    })));
+    assert!(r.unwrap_err().to_string().contains("src/client.rs"));
+}
+
+#[test]
+fn test_margin_account_pack_round_trip() {
+    // Test that a populated MarginAccount survives packing and unpacking from unaligned data
+    let mut margin_account = MarginAccount::zeroed();
+    margin_account.account_flags = (AccountFlag::Initialized | AccountFlag::MarginAccount).bits();
+    margin_account.version = MARGIN_ACCOUNT_VERSION;
+    margin_account.mango_group = Pubkey::new_unique();
+    margin_account.owner = Pubkey::new_unique();
+    margin_account.deposits = [U64F64::from_num(1.5), U64F64::from_num(0), U64F64::from_num(2000)];
+    margin_account.borrows[1] = U64F64::from_num(3);
+    margin_account.open_orders[0] = Pubkey::new_unique();
+    margin_account.num_open_orders[0] = 4;
+    margin_account.being_liquidated = true;
+
+    let mut buffer = vec![0u8; size_of::<MarginAccount>() + 1];
+    margin_account.pack_into_slice(&mut buffer[1..]).unwrap();
+    let unpacked = MarginAccount::unpack_from_slice(&buffer[1..]).unwrap();
+
+    assert_eq!(unpacked.mango_group, margin_account.mango_group);
+    assert_eq!(unpacked.owner, margin_account.owner);
+    assert_eq!(unpacked.deposits, margin_account.deposits);
+    assert_eq!(unpacked.borrows, margin_account.borrows);
+    assert_eq!(unpacked.open_orders, margin_account.open_orders);
+    assert_eq!(unpacked.num_open_orders, margin_account.num_open_orders);
+    assert!(unpacked.being_liquidated);
+    assert_eq!(bytemuck::bytes_of(&unpacked), &buffer[1..]);
+
+    // wrong length and uninitialized accounts are rejected both ways
+    assert_eq!(MarginAccount::unpack_from_slice(&buffer).err(), Some(ProgramError::InvalidAccountData));
+    assert_eq!(margin_account.pack_into_slice(&mut buffer).err(), Some(ProgramError::InvalidAccountData));
+    let empty = vec![0u8; size_of::<MarginAccount>()];
+    assert_eq!(MarginAccount::unpack_from_slice(&empty).err(), Some(ProgramError::UninitializedAccount));
+}
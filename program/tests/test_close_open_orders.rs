@@ -0,0 +1,179 @@
+// Tests related to closing the open orders account of a MarginAccount
+#![cfg(feature="test-bpf")]
+
+mod helpers;
+
+use std::mem::size_of;
+
+use bytemuck::Zeroable;
+use helpers::*;
+use serum_dex::state::OpenOrders;
+use solana_program::account_info::AccountInfo;
+use solana_program_test::*;
+use solana_sdk::{
+    account::Account,
+    instruction::InstructionError,
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+    transaction::{Transaction, TransactionError},
+};
+
+use mango::{
+    entrypoint::process_instruction,
+    error::{MangoError, MangoErrorCode},
+    instruction::close_open_orders,
+    processor::check_open_orders_empty,
+    state::MarginAccount,
+};
+
+#[test]
+fn test_close_emptied_open_orders() {
+    // Test that an open orders account with no orders and no funds can be closed and is then
+    // cleared from the MarginAccount
+    let owner_pk = Pubkey::new_unique();
+    let open_orders_pk = Pubkey::new_unique();
+    let mut margin_account = MarginAccount::zeroed();
+    margin_account.open_orders[1] = open_orders_pk;
+    margin_account.num_open_orders[1] = 2;
+
+    let mut account = open_orders_account(&owner_pk, u128::MAX);
+    let open_orders_acc: AccountInfo = (&open_orders_pk, &mut account).into();
+    assert!(check_open_orders_empty(&open_orders_acc).is_ok());
+
+    margin_account.clear_open_orders(1);
+    assert!(!margin_account.has_open_orders(1));
+    assert_eq!(margin_account.num_open_orders[1], 0);
+}
+
+#[test]
+fn test_check_open_orders_empty_rejects_orders_and_funds() {
+    // Test that resting orders, free funds and locked funds each block closing
+    let owner_pk = Pubkey::new_unique();
+    let open_orders_pk = Pubkey::new_unique();
+
+    let mut account = open_orders_account(&owner_pk, u128::MAX << 1);
+    let open_orders_acc: AccountInfo = (&open_orders_pk, &mut account).into();
+    let r = check_open_orders_empty(&open_orders_acc);
+    assert!(matches!(r, Err(MangoError::MangoErrorCode { mango_error_code: MangoErrorCode::OpenOrdersNotEmpty, .. })));
+
+    for (coin_total, pc_total) in [(1, 0), (0, 1)].iter() {
+        let mut account = open_orders_account(&owner_pk, u128::MAX);
+        {
+            let open_orders: &mut OpenOrders = bytemuck::from_bytes_mut(
+                &mut account.data[5..5 + size_of::<OpenOrders>()]
+            );
+            open_orders.native_coin_total = *coin_total;
+            open_orders.native_pc_total = *pc_total;
+        }
+        let open_orders_acc: AccountInfo = (&open_orders_pk, &mut account).into();
+        let r = check_open_orders_empty(&open_orders_acc);
+        assert!(matches!(r, Err(MangoError::MangoErrorCode { mango_error_code: MangoErrorCode::OpenOrdersNotEmpty, .. })));
+    }
+}
+
+#[test]
+fn test_slot_reset_after_full_unwind() {
+    // Test that once orders are cancelled and funds settled, the slot is reset to the default
+    // pubkey and the account drops out of health scans
+    let owner_pk = Pubkey::new_unique();
+    let open_orders_pk = Pubkey::new_unique();
+    let mango_group = new_mango_group_in_memory([0, 0, 0]);
+    let prices = get_prodlike_prices();
+    let mut margin_account = MarginAccount::zeroed();
+    margin_account.open_orders[0] = open_orders_pk;
+    margin_account.num_open_orders[0] = 1;
+
+    // one bid resting with 1000 USDT locked
+    let mut account = open_orders_account(&owner_pk, u128::MAX << 1);
+    {
+        let open_orders: &mut OpenOrders = bytemuck::from_bytes_mut(
+            &mut account.data[5..5 + size_of::<OpenOrders>()]
+        );
+        open_orders.native_pc_total = 1000;
+    }
+    {
+        let open_orders_acc: AccountInfo = (&open_orders_pk, &mut account).into();
+        assert!(check_open_orders_empty(&open_orders_acc).is_err());
+    }
+
+    // cancelling the order frees the slot and settling moves the freed funds back to the vault
+    {
+        let open_orders: &mut OpenOrders = bytemuck::from_bytes_mut(
+            &mut account.data[5..5 + size_of::<OpenOrders>()]
+        );
+        open_orders.free_slot_bits = u128::MAX;
+        open_orders.native_pc_total = 0;
+    }
+    let open_orders_acc: AccountInfo = (&open_orders_pk, &mut account).into();
+    assert!(check_open_orders_empty(&open_orders_acc).is_ok());
+
+    margin_account.clear_open_orders(0);
+    assert_eq!(margin_account.open_orders[0], Pubkey::default());
+    assert_eq!(margin_account.num_open_orders[0], 0);
+
+    // health scans take the default pubkey and skip the slot
+    let pk = Pubkey::default();
+    let mut accounts = [Account::default(), Account::default()];
+    let open_orders_accs = empty_open_orders(&pk, &mut accounts);
+    assert!(margin_account.get_assets_val(&mango_group, &prices, &open_orders_accs, 0).is_ok());
+}
+
+#[tokio::test]
+async fn test_close_open_orders_with_funds_fails() {
+    // Test that an open orders account still holding funds is not closed and stays on the account
+    let program_id = Pubkey::new_unique();
+
+    let mut test = ProgramTest::new(
+        "mango",
+        program_id,
+        processor!(process_instruction),
+    );
+
+    // limit to track compute unit increase
+    test.set_bpf_compute_max_units(50_000);
+
+    let mango_group = add_mango_group_prodlike(&mut test, program_id);
+
+    let open_orders_pk = Pubkey::new_unique();
+    let mut open_orders_acc = open_orders_account(&mango_group.signer_pk, u128::MAX);
+    {
+        let open_orders: &mut OpenOrders = bytemuck::from_bytes_mut(
+            &mut open_orders_acc.data[5..5 + size_of::<OpenOrders>()]
+        );
+        open_orders.native_pc_free = 10;
+        open_orders.native_pc_total = 10;
+    }
+    test.add_account(open_orders_pk, open_orders_acc);
+
+    let owner = Keypair::new();
+    test.add_account(owner.pubkey(), Account::new(u32::MAX as u64, 0, &owner.pubkey()));
+    let margin_account_pk = add_margin_account_with_open_orders(
+        &mut test, Pubkey::new_unique(), program_id, mango_group.mango_group_pk, owner.pubkey(),
+        [0, 0, 0], [0, 0, 0], [open_orders_pk, Pubkey::default()]
+    );
+
+    let (mut banks_client, payer, recent_blockhash) = test.start().await;
+
+    let mut transaction = Transaction::new_with_payer(
+        &[
+            mango_group.init_mango_group(&payer.pubkey()),
+            close_open_orders(
+                &program_id,
+                &mango_group.mango_group_pk,
+                &owner.pubkey(),
+                &margin_account_pk,
+                &mango_group.dex_prog_id,
+                &mango_group.dexes[0].pubkey,
+                &open_orders_pk,
+                &mango_group.signer_pk,
+                0,
+            ).unwrap(),
+        ],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer, &owner], recent_blockhash);
+    assert_eq!(
+        banks_client.process_transaction(transaction).await.unwrap_err().unwrap(),
+        TransactionError::InstructionError(1, InstructionError::Custom(MangoErrorCode::OpenOrdersNotEmpty.into()))
+    );
+}
@@ -0,0 +1,78 @@
+// Tests related to changing the collateral ratios of a MangoGroup
+#![cfg(feature="test-bpf")]
+
+mod helpers;
+
+use fixed::types::U64F64;
+use helpers::*;
+use solana_program_test::*;
+use solana_sdk::{
+    instruction::Instruction,
+    pubkey::Pubkey,
+    signature::Signer,
+    transaction::Transaction,
+};
+
+use mango::{
+    entrypoint::process_instruction,
+    instruction::set_coll_ratios,
+};
+
+// Runs SetCollRatios on a fresh prodlike group (maint 1.1, init 1.2) and returns whether it succeeded
+async fn try_set_coll_ratios(maint_coll_ratio: f64, init_coll_ratio: f64) -> bool {
+    let program_id = Pubkey::new_unique();
+
+    let mut test = ProgramTest::new(
+        "mango",
+        program_id,
+        processor!(process_instruction),
+    );
+
+    // limit to track compute unit increase
+    test.set_bpf_compute_max_units(50_000);
+
+    let mango_group = add_mango_group_prodlike(&mut test, program_id);
+    let (mut banks_client, payer, recent_blockhash) = test.start().await;
+
+    let mut transaction = Transaction::new_with_payer(
+        &[mango_group.init_mango_group(&payer.pubkey())],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer], recent_blockhash);
+    assert!(banks_client.process_transaction(transaction).await.is_ok());
+
+    let instruction: Instruction = set_coll_ratios(
+        &program_id,
+        &mango_group.mango_group_pk,
+        &payer.pubkey(),
+        U64F64::from_num(maint_coll_ratio),
+        U64F64::from_num(init_coll_ratio),
+    ).unwrap();
+    let mut transaction = Transaction::new_with_payer(&[instruction], Some(&payer.pubkey()));
+    transaction.sign(&[&payer], recent_blockhash);
+    let succeeded = banks_client.process_transaction(transaction).await.is_ok();
+
+    if succeeded {
+        let group = get_mango_group(&mut banks_client, &program_id, mango_group.mango_group_pk).await;
+        assert_eq!(group.maint_coll_ratio, U64F64::from_num(maint_coll_ratio));
+        assert_eq!(group.init_coll_ratio, U64F64::from_num(init_coll_ratio));
+    }
+    succeeded
+}
+
+#[tokio::test]
+async fn test_set_coll_ratios_ordering() {
+    // Test that init_coll_ratio must be at least maint_coll_ratio and both must be above 1
+    assert!(try_set_coll_ratios(1.12, 1.25).await);
+    assert!(try_set_coll_ratios(1.12, 1.12).await);
+    assert!(!try_set_coll_ratios(1.12, 1.11).await);
+    assert!(!try_set_coll_ratios(1.0, 1.2).await);
+}
+
+#[tokio::test]
+async fn test_set_coll_ratios_maint_step() {
+    // Test that maint_coll_ratio can be loosened freely but only tightened by a bounded step
+    assert!(try_set_coll_ratios(1.05, 1.2).await);
+    assert!(try_set_coll_ratios(1.14, 1.2).await);
+    assert!(!try_set_coll_ratios(1.2, 1.3).await);
+}
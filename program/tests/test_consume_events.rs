@@ -0,0 +1,86 @@
+// Tests related to cranking the serum event queue through mango
+#![cfg(feature="test-bpf")]
+
+mod helpers;
+
+use helpers::*;
+use solana_program_test::*;
+use solana_sdk::{
+    instruction::InstructionError,
+    pubkey::Pubkey,
+    signature::Signer,
+    transaction::{Transaction, TransactionError},
+};
+
+use mango::{
+    entrypoint::process_instruction,
+    error::MangoErrorCode,
+    instruction::consume_events,
+};
+
+#[tokio::test]
+async fn test_consume_events_checks_accounts() {
+    // Test that ConsumeEvents only cranks mango's dex and open orders owned by the mango signer
+    // There is no serum dex in these tests, so a fill and the crank itself can't be simulated
+    let program_id = Pubkey::new_unique();
+
+    let mut test = ProgramTest::new(
+        "mango",
+        program_id,
+        processor!(process_instruction),
+    );
+
+    // limit to track compute unit increase
+    test.set_bpf_compute_max_units(50_000);
+
+    let mango_group = add_mango_group_prodlike(&mut test, program_id);
+    let mango_open_orders_pk = Pubkey::new_unique();
+    test.add_account(mango_open_orders_pk, open_orders_account(&mango_group.signer_pk, u128::MAX));
+    let other_open_orders_pk = Pubkey::new_unique();
+    test.add_account(other_open_orders_pk, open_orders_account(&Pubkey::new_unique(), u128::MAX));
+
+    let (mut banks_client, payer, recent_blockhash) = test.start().await;
+
+    let mut transaction = Transaction::new_with_payer(
+        &[mango_group.init_mango_group(&payer.pubkey())],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer], recent_blockhash);
+    assert!(banks_client.process_transaction(transaction).await.is_ok());
+
+    let consume_events_instruction = |dex_prog_id: &Pubkey, open_orders_pks: &[Pubkey]| {
+        consume_events(
+            &program_id,
+            &mango_group.mango_group_pk,
+            dex_prog_id,
+            &mango_group.dexes[0].pubkey,
+            &Pubkey::new_unique(),
+            &Pubkey::new_unique(),
+            &Pubkey::new_unique(),
+            open_orders_pks,
+            10,
+        ).unwrap()
+    };
+
+    // Not the group's dex program
+    let mut transaction = Transaction::new_with_payer(
+        &[consume_events_instruction(&Pubkey::new_unique(), &[mango_open_orders_pk])],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer], recent_blockhash);
+    assert_eq!(
+        banks_client.process_transaction(transaction).await.unwrap_err().unwrap(),
+        TransactionError::InstructionError(0, InstructionError::Custom(MangoErrorCode::InvalidProgramId.into()))
+    );
+
+    // Open orders that the mango signer doesn't own
+    let mut transaction = Transaction::new_with_payer(
+        &[consume_events_instruction(&mango_group.dex_prog_id, &[mango_open_orders_pk, other_open_orders_pk])],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer], recent_blockhash);
+    assert_eq!(
+        banks_client.process_transaction(transaction).await.unwrap_err().unwrap(),
+        TransactionError::InstructionError(0, InstructionError::Custom(MangoErrorCode::Default.into()))
+    );
+}
@@ -4,19 +4,23 @@
 mod helpers;
 
 use std::mem::size_of;
+
+use fixed::types::U64F64;
 use helpers::*;
 use solana_program::account_info::AccountInfo;
 use solana_program_test::*;
 use solana_sdk::{
+    instruction::InstructionError,
     pubkey::Pubkey,
     signature::{Signer, Keypair},
-    transaction::Transaction,
+    transaction::{Transaction, TransactionError},
     account::Account,
 };
 
 use mango::{
     entrypoint::process_instruction,
-    instruction::{deposit, init_margin_account},
+    error::MangoErrorCode,
+    instruction::{change_deposit_limit, deposit, deposit_for_account, deposit_multi, deposit_with_referrer, init_margin_account},
     state::MarginAccount,
 };
 
@@ -228,4 +232,397 @@ async fn test_deposit_fails_invalid_margin_account_owner() {
         .unwrap();
         assert_eq!(margin_account.deposits[0], 0);
     }
-}
\ No newline at end of file
+}
+#[tokio::test]
+async fn test_deposit_limit() {
+    // Test that a deposit up to the deposit limit succeeds and one beyond it is rejected
+    let program_id = Pubkey::new_unique();
+
+    let mut test = ProgramTest::new(
+        "mango",
+        program_id,
+        processor!(process_instruction),
+    );
+
+    // limit to track compute unit increase
+    test.set_bpf_compute_max_units(50_000);
+
+    let initial_amount = 5;
+    let deposit_limit = 3;
+
+    // setup mango group
+    let mango_group = add_mango_group_prodlike(&mut test, program_id);
+
+    // setup user account
+    let user = Keypair::new();
+    test.add_account(user.pubkey(), Account::new(u32::MAX as u64, 0, &user.pubkey()));
+
+    // setup user token accounts
+    let user_account = add_token_account(
+        &mut test,
+        user.pubkey(),
+        mango_group.mints[0].pubkey,
+        initial_amount,
+    );
+
+    // setup marginaccount account
+    let margin_account_pk = Pubkey::new_unique();
+    test.add_account(margin_account_pk, Account::new(u32::MAX as u64, size_of::<MarginAccount>(), &program_id));
+
+    // setup test harness
+    let (mut banks_client, payer, recent_blockhash) = test.start().await;
+
+    {
+        let mut transaction = Transaction::new_with_payer(
+            &[
+                mango_group.init_mango_group(&payer.pubkey()),
+                change_deposit_limit(
+                    &program_id,
+                    &mango_group.mango_group_pk,
+                    &payer.pubkey(),
+                    0,
+                    deposit_limit,
+                ).unwrap(),
+                init_margin_account(
+                    &program_id,
+                    &mango_group.mango_group_pk,
+                    &margin_account_pk,
+                    &user.pubkey(),
+                ).unwrap(),
+                deposit(
+                    &program_id,
+                    &mango_group.mango_group_pk,
+                    &margin_account_pk,
+                    &user.pubkey(),
+                    &user_account.pubkey,
+                    &mango_group.vaults[0].pubkey,
+                    deposit_limit,
+                ).unwrap(),
+            ],
+            Some(&payer.pubkey()),
+        );
+
+        transaction.sign(
+            &[&payer, &user],
+            recent_blockhash,
+        );
+
+        // Test deposit exactly at the limit succeeded
+        assert!(banks_client.process_transaction(transaction).await.is_ok());
+    }
+
+    {
+        let mut transaction = Transaction::new_with_payer(
+            &[
+                deposit(
+                    &program_id,
+                    &mango_group.mango_group_pk,
+                    &margin_account_pk,
+                    &user.pubkey(),
+                    &user_account.pubkey,
+                    &mango_group.vaults[0].pubkey,
+                    1,
+                ).unwrap(),
+            ],
+            Some(&payer.pubkey()),
+        );
+
+        transaction.sign(
+            &[&payer, &user],
+            recent_blockhash,
+        );
+
+        // Test deposit over the limit failed
+        assert!(banks_client.process_transaction(transaction).await.is_err());
+
+        // Test nothing beyond the limit reached the vault
+        let mango_vault_balance = get_token_balance(&mut banks_client, mango_group.vaults[0].pubkey).await;
+        assert_eq!(mango_vault_balance, deposit_limit);
+    }
+}
+
+#[tokio::test]
+async fn test_deposit_with_referrer_recorded_once() {
+    // Test that the first referrer is recorded and later referrers don't overwrite it
+    let program_id = Pubkey::new_unique();
+
+    let mut test = ProgramTest::new(
+        "mango",
+        program_id,
+        processor!(process_instruction),
+    );
+
+    // limit to track compute unit increase
+    test.set_bpf_compute_max_units(50_000);
+
+    let initial_amount = 10;
+    let mango_group = add_mango_group_prodlike(&mut test, program_id);
+
+    let user = Keypair::new();
+    test.add_account(user.pubkey(), Account::new(u32::MAX as u64, 0, &user.pubkey()));
+    let user_account = add_token_account(
+        &mut test,
+        user.pubkey(),
+        mango_group.mints[0].pubkey,
+        initial_amount,
+    );
+
+    let margin_account_pk = Pubkey::new_unique();
+    test.add_account(margin_account_pk, Account::new(u32::MAX as u64, size_of::<MarginAccount>(), &program_id));
+
+    let first_referrer_pk = Pubkey::new_unique();
+    let second_referrer_pk = Pubkey::new_unique();
+
+    let (mut banks_client, payer, recent_blockhash) = test.start().await;
+
+    let mut transaction = Transaction::new_with_payer(
+        &[
+            mango_group.init_mango_group(&payer.pubkey()),
+            init_margin_account(
+                &program_id,
+                &mango_group.mango_group_pk,
+                &margin_account_pk,
+                &user.pubkey(),
+            ).unwrap(),
+            deposit_with_referrer(
+                &program_id,
+                &mango_group.mango_group_pk,
+                &margin_account_pk,
+                &user.pubkey(),
+                &user_account.pubkey,
+                &mango_group.vaults[0].pubkey,
+                &first_referrer_pk,
+                1,
+            ).unwrap(),
+            deposit_with_referrer(
+                &program_id,
+                &mango_group.mango_group_pk,
+                &margin_account_pk,
+                &user.pubkey(),
+                &user_account.pubkey,
+                &mango_group.vaults[0].pubkey,
+                &second_referrer_pk,
+                2,
+            ).unwrap(),
+        ],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer, &user], recent_blockhash);
+    assert!(banks_client.process_transaction(transaction).await.is_ok());
+
+    let margin_account = get_margin_account(
+        &mut banks_client, &program_id, &mango_group.mango_group_pk, margin_account_pk
+    ).await;
+    assert_eq!(margin_account.referrer, first_referrer_pk);
+    assert_eq!(margin_account.deposits[0], 3);
+}
+
+#[tokio::test]
+async fn test_deposit_for_account_funds_other_owner() {
+    // Test that A can fund B's margin account with DepositForAccount, while Deposit rejects it
+    let program_id = Pubkey::new_unique();
+
+    let mut test = ProgramTest::new(
+        "mango",
+        program_id,
+        processor!(process_instruction),
+    );
+
+    // limit to track compute unit increase
+    test.set_bpf_compute_max_units(50_000);
+
+    let initial_amount = 10;
+    let mango_group = add_mango_group_prodlike(&mut test, program_id);
+
+    let funder = Keypair::new();
+    test.add_account(funder.pubkey(), Account::new(u32::MAX as u64, 0, &funder.pubkey()));
+    let funder_account = add_token_account(
+        &mut test,
+        funder.pubkey(),
+        mango_group.mints[0].pubkey,
+        initial_amount,
+    );
+
+    let client = Keypair::new();
+    let margin_account_pk = Pubkey::new_unique();
+    test.add_account(margin_account_pk, Account::new(u32::MAX as u64, size_of::<MarginAccount>(), &program_id));
+
+    let (mut banks_client, payer, recent_blockhash) = test.start().await;
+
+    let mut transaction = Transaction::new_with_payer(
+        &[
+            mango_group.init_mango_group(&payer.pubkey()),
+            init_margin_account(
+                &program_id,
+                &mango_group.mango_group_pk,
+                &margin_account_pk,
+                &client.pubkey(),
+            ).unwrap(),
+        ],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer, &client], recent_blockhash);
+    assert!(banks_client.process_transaction(transaction).await.is_ok());
+
+    // Plain Deposit still requires the margin account owner
+    let mut transaction = Transaction::new_with_payer(
+        &[
+            deposit(
+                &program_id,
+                &mango_group.mango_group_pk,
+                &margin_account_pk,
+                &funder.pubkey(),
+                &funder_account.pubkey,
+                &mango_group.vaults[0].pubkey,
+                1,
+            ).unwrap(),
+        ],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer, &funder], recent_blockhash);
+    assert!(banks_client.process_transaction(transaction).await.is_err());
+
+    let mut transaction = Transaction::new_with_payer(
+        &[
+            deposit_for_account(
+                &program_id,
+                &mango_group.mango_group_pk,
+                &margin_account_pk,
+                &funder.pubkey(),
+                &funder_account.pubkey,
+                &mango_group.vaults[0].pubkey,
+                4,
+            ).unwrap(),
+        ],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer, &funder], recent_blockhash);
+    assert!(banks_client.process_transaction(transaction).await.is_ok());
+
+    assert_eq!(get_token_balance(&mut banks_client, funder_account.pubkey).await, initial_amount - 4);
+    assert_eq!(get_token_balance(&mut banks_client, mango_group.vaults[0].pubkey).await, 4);
+
+    let margin_account = get_margin_account(
+        &mut banks_client, &program_id, &mango_group.mango_group_pk, margin_account_pk
+    ).await;
+    assert_eq!(margin_account.owner, client.pubkey());
+    assert_eq!(margin_account.deposits[0], 4);
+}
+
+#[tokio::test]
+async fn test_deposit_into_foreign_vault_fails() {
+    // Test that a vault that is not one of the group's vaults returns InvalidMangoVault instead of panicking
+    let program_id = Pubkey::new_unique();
+
+    let mut test = ProgramTest::new(
+        "mango",
+        program_id,
+        processor!(process_instruction),
+    );
+
+    // limit to track compute unit increase
+    test.set_bpf_compute_max_units(50_000);
+
+    let mango_group = add_mango_group_prodlike(&mut test, program_id);
+    let user = Keypair::new();
+    test.add_account(user.pubkey(), Account::new(u32::MAX as u64, 0, &user.pubkey()));
+    let user_account = add_token_account(&mut test, user.pubkey(), mango_group.mints[0].pubkey, 10);
+    let foreign_vault = add_token_account(&mut test, mango_group.signer_pk, mango_group.mints[0].pubkey, 0);
+    let margin_account_pk = add_margin_account(
+        &mut test, program_id, mango_group.mango_group_pk, user.pubkey(), [0, 0, 0], [0, 0, 0]
+    );
+
+    let (mut banks_client, payer, recent_blockhash) = test.start().await;
+
+    let mut transaction = Transaction::new_with_payer(
+        &[mango_group.init_mango_group(&payer.pubkey())],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer], recent_blockhash);
+    assert!(banks_client.process_transaction(transaction).await.is_ok());
+
+    let mut transaction = Transaction::new_with_payer(
+        &[
+            deposit(
+                &program_id,
+                &mango_group.mango_group_pk,
+                &margin_account_pk,
+                &user.pubkey(),
+                &user_account.pubkey,
+                &foreign_vault.pubkey,
+                1,
+            ).unwrap(),
+        ],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer, &user], recent_blockhash);
+    assert_eq!(
+        banks_client.process_transaction(transaction).await.unwrap_err().unwrap(),
+        TransactionError::InstructionError(0, InstructionError::Custom(MangoErrorCode::InvalidMangoVault.into()))
+    );
+    assert_eq!(get_token_balance(&mut banks_client, user_account.pubkey).await, 10);
+}
+
+#[tokio::test]
+async fn test_deposit_multi_all_tokens() {
+    // Test that one DepositMulti credits every token at its deposit index
+    let program_id = Pubkey::new_unique();
+
+    let mut test = ProgramTest::new(
+        "mango",
+        program_id,
+        processor!(process_instruction),
+    );
+
+    // limit to track compute unit increase
+    test.set_bpf_compute_max_units(50_000);
+
+    let mango_group = add_mango_group_prodlike(&mut test, program_id);
+
+    let user = Keypair::new();
+    test.add_account(user.pubkey(), Account::new(u32::MAX as u64, 0, &user.pubkey()));
+    let quantities = [2, 30, 5000];
+    let user_accounts: Vec<Pubkey> = (0..3).map(|i| {
+        add_token_account(&mut test, user.pubkey(), mango_group.mints[i].pubkey, quantities[i] + 1).pubkey
+    }).collect();
+
+    let margin_account_pk = Pubkey::new_unique();
+    test.add_account(margin_account_pk, Account::new(u32::MAX as u64, size_of::<MarginAccount>(), &program_id));
+
+    let (mut banks_client, payer, recent_blockhash) = test.start().await;
+
+    let mut transaction = Transaction::new_with_payer(
+        &[
+            mango_group.init_mango_group(&payer.pubkey()),
+            init_margin_account(
+                &program_id,
+                &mango_group.mango_group_pk,
+                &margin_account_pk,
+                &user.pubkey(),
+            ).unwrap(),
+            deposit_multi(
+                &program_id,
+                &mango_group.mango_group_pk,
+                &margin_account_pk,
+                &user.pubkey(),
+                &user_accounts,
+                mango_group.vaults.iter().map(|m| m.pubkey).collect::<Vec<Pubkey>>().as_slice(),
+                quantities,
+            ).unwrap(),
+        ],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer, &user], recent_blockhash);
+    assert!(banks_client.process_transaction(transaction).await.is_ok());
+
+    let group = get_mango_group(&mut banks_client, &program_id, mango_group.mango_group_pk).await;
+    let margin_account = get_margin_account(
+        &mut banks_client, &program_id, &mango_group.mango_group_pk, margin_account_pk
+    ).await;
+    for i in 0..3 {
+        assert_eq!(margin_account.deposits[i], U64F64::from_num(quantities[i]) / group.indexes[i].deposit);
+        assert_eq!(group.total_deposits[i], margin_account.deposits[i]);
+        assert_eq!(get_token_balance(&mut banks_client, user_accounts[i]).await, 1);
+        assert_eq!(get_token_balance(&mut banks_client, mango_group.vaults[i].pubkey).await, quantities[i]);
+    }
+}
@@ -0,0 +1,21 @@
+// Tests related to the PlaceAndSettle fill log
+#![cfg(all(feature="test-bpf", feature="fill-log"))]
+
+use fixed::types::U64F64;
+use serum_dex::matching::Side;
+
+use mango::processor::format_fill_log;
+
+#[test]
+fn test_format_fill_log() {
+    // Test that the fill log has a stable key=value layout that bots can parse
+    let line = format_fill_log(0, Side::Bid, 0, 2, 2, 100_000, true, U64F64::from_num(1.5));
+    assert_eq!(
+        line,
+        "MANGO-FILL market=0 side=bid in_token=0 native_in=2 out_token=2 native_out=100000 borrowed=true ratio=1.5"
+    );
+
+    let line = format_fill_log(1, Side::Ask, 2, 3000, 1, -5, false, U64F64::from_num(2));
+    assert!(line.starts_with("MANGO-FILL market=1 side=ask in_token=2 native_in=3000 out_token=1 native_out=-5 "));
+    assert!(line.ends_with("borrowed=false ratio=2"));
+}
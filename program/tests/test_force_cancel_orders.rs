@@ -0,0 +1,487 @@
+// Tests related to force cancelling orders of a MarginAccount
+#![cfg(feature="test-bpf")]
+
+mod helpers;
+
+use std::num::NonZeroU64;
+
+use fixed::types::U64F64;
+use helpers::*;
+use serum_dex::instruction::{NewOrderInstructionV3, SelfTradeBehavior};
+use serum_dex::matching::{OrderType, Side};
+use solana_program::account_info::AccountInfo;
+use solana_program_test::*;
+use solana_sdk::{
+    account::Account,
+    instruction::{Instruction, InstructionError},
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+    transaction::{Transaction, TransactionError},
+};
+
+use mango::{
+    entrypoint::process_instruction,
+    error::MangoErrorCode,
+    instruction::{deposit, force_cancel_orders, place_order, set_coll_ratios},
+    state::{load_asks_mut, load_market_state, load_open_orders, NUM_MARKETS},
+};
+
+fn force_cancel_orders_instruction(
+    mango_group: &TestMangoGroup,
+    liqor_pk: &Pubkey,
+    margin_account_pk: &Pubkey,
+    market_index: usize,
+) -> Instruction {
+    force_cancel_orders(
+        &mango_group.program_id,
+        &mango_group.mango_group_pk,
+        liqor_pk,
+        margin_account_pk,
+        &mango_group.vaults[market_index].pubkey,
+        &mango_group.vaults[NUM_MARKETS].pubkey,
+        &mango_group.dexes[market_index].pubkey,
+        &Pubkey::new_unique(),
+        &Pubkey::new_unique(),
+        &mango_group.signer_pk,
+        &Pubkey::new_unique(),
+        &Pubkey::new_unique(),
+        &Pubkey::new_unique(),
+        &Pubkey::new_unique(),
+        &mango_group.dex_prog_id,
+        &[Pubkey::default(); NUM_MARKETS],
+        mango_group.oracles.iter().map(|m| m.pubkey).collect::<Vec<Pubkey>>().as_slice(),
+        0,
+        5,
+    ).unwrap()
+}
+
+fn force_cancel_resting_orders_instruction(
+    mango_group: &TestMangoGroup,
+    market: &TestDexMarket,
+    liqor_pk: &Pubkey,
+    margin_account_pk: &Pubkey,
+    open_orders_pk: &Pubkey,
+    limit: u8,
+) -> Instruction {
+    force_cancel_orders(
+        &mango_group.program_id,
+        &mango_group.mango_group_pk,
+        liqor_pk,
+        margin_account_pk,
+        &mango_group.vaults[0].pubkey,
+        &mango_group.vaults[NUM_MARKETS].pubkey,
+        &market.pubkey,
+        &market.bids,
+        &market.asks,
+        &mango_group.signer_pk,
+        &market.event_q,
+        &market.coin_vault,
+        &market.pc_vault,
+        &market.vault_signer,
+        &mango_group.dex_prog_id,
+        &[*open_orders_pk, Pubkey::default()],
+        mango_group.oracles.iter().map(|m| m.pubkey).collect::<Vec<Pubkey>>().as_slice(),
+        0,
+        limit,
+    ).unwrap()
+}
+
+// Ids of the orders in the used slots of an open orders account
+async fn get_order_ids(banks_client: &mut BanksClient, open_orders_pk: Pubkey) -> Vec<u128> {
+    let mut account = banks_client.get_account(open_orders_pk).await.unwrap().unwrap();
+    let account_info: AccountInfo = (&open_orders_pk, &mut account).into();
+    let open_orders = load_open_orders(&account_info).unwrap();
+    (0..128)
+        .filter(|j| open_orders.free_slot_bits & (1u128 << j) == 0)
+        .map(|j| open_orders.orders[j])
+        .collect()
+}
+
+// Whether each of order_ids is still resting on the asks of market
+async fn asks_resting(
+    banks_client: &mut BanksClient,
+    mango_group: &TestMangoGroup,
+    market: &TestDexMarket,
+    order_ids: &[u128],
+) -> Vec<bool> {
+    let mut market_acc = banks_client.get_account(market.pubkey).await.unwrap().unwrap();
+    let mut asks_acc = banks_client.get_account(market.asks).await.unwrap().unwrap();
+    let market_info: AccountInfo = (&market.pubkey, &mut market_acc).into();
+    let asks_info: AccountInfo = (&market.asks, &mut asks_acc).into();
+    let market_state = load_market_state(&market_info, &mango_group.dex_prog_id).unwrap();
+    let asks = load_asks_mut(&market_state, &asks_info).unwrap();
+    order_ids.iter().map(|id| asks.find_by_key(*id).is_some()).collect()
+}
+
+#[tokio::test]
+async fn test_force_cancel_orders_healthy_fails() {
+    // Test that the orders of an account above maint_coll_ratio cannot be force cancelled
+    let program_id = Pubkey::new_unique();
+
+    let mut test = ProgramTest::new(
+        "mango",
+        program_id,
+        processor!(process_instruction),
+    );
+
+    // limit to track compute unit increase
+    test.set_bpf_compute_max_units(50_000);
+
+    let mango_group = add_mango_group_prodlike(&mut test, program_id);
+    let owner_pk = Pubkey::new_unique();
+    let margin_account_pk = add_margin_account(
+        &mut test, program_id, mango_group.mango_group_pk, owner_pk, [1, 0, 0], [0, 0, 0]
+    );
+
+    let (mut banks_client, payer, recent_blockhash) = test.start().await;
+
+    let mut transaction = Transaction::new_with_payer(
+        &[mango_group.init_mango_group(&payer.pubkey())],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer], recent_blockhash);
+    assert!(banks_client.process_transaction(transaction).await.is_ok());
+
+    let mut transaction = Transaction::new_with_payer(
+        &[force_cancel_orders_instruction(&mango_group, &payer.pubkey(), &margin_account_pk, 0)],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer], recent_blockhash);
+    assert!(banks_client.process_transaction(transaction).await.is_err());
+
+    let margin_account = get_margin_account(
+        &mut banks_client, &program_id, &mango_group.mango_group_pk, margin_account_pk
+    ).await;
+    assert!(!margin_account.being_liquidated);
+}
+
+#[tokio::test]
+async fn test_force_cancel_orders_unhealthy_succeeds() {
+    // Test that anyone can force cancel the orders of an account below maint_coll_ratio
+    let program_id = Pubkey::new_unique();
+
+    let mut test = ProgramTest::new(
+        "mango",
+        program_id,
+        processor!(process_instruction),
+    );
+
+    // limit to track compute unit increase
+    test.set_bpf_compute_max_units(50_000);
+
+    let mango_group = add_mango_group_prodlike(&mut test, program_id);
+    let owner_pk = Pubkey::new_unique();
+
+    // 1 BTC of assets against 50000 USDT of liabs -> coll ratio of 1.0
+    let margin_account_pk = add_margin_account(
+        &mut test, program_id, mango_group.mango_group_pk, owner_pk, [1, 0, 0], [0, 0, PRICE_BTC]
+    );
+
+    let (mut banks_client, payer, recent_blockhash) = test.start().await;
+
+    let mut transaction = Transaction::new_with_payer(
+        &[
+            mango_group.init_mango_group(&payer.pubkey()),
+            force_cancel_orders_instruction(&mango_group, &payer.pubkey(), &margin_account_pk, 0),
+        ],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer], recent_blockhash);
+    assert!(banks_client.process_transaction(transaction).await.is_ok());
+
+    let margin_account = get_margin_account(
+        &mut banks_client, &program_id, &mango_group.mango_group_pk, margin_account_pk
+    ).await;
+    assert!(margin_account.being_liquidated);
+}
+
+#[tokio::test]
+async fn test_force_cancel_orders_being_liquidated_between_maint_and_init() {
+    // Test that an account already being liquidated can still be force cancelled between maint
+    // and init, and that the flag is only cleared once it is back at init
+    let program_id = Pubkey::new_unique();
+
+    let mut test = ProgramTest::new(
+        "mango",
+        program_id,
+        processor!(process_instruction),
+    );
+
+    // limit to track compute unit increase
+    test.set_bpf_compute_max_units(50_000);
+
+    let mango_group = add_mango_group_prodlike(&mut test, program_id);
+    let owner_pk = Pubkey::new_unique();
+
+    // 1 BTC of assets against 42800 USDT of liabs -> coll ratio of about 1.168
+    let margin_account_pk = add_margin_account(
+        &mut test, program_id, mango_group.mango_group_pk, owner_pk, [1, 0, 0], [0, 0, 42_800]
+    );
+
+    let (mut banks_client, payer, recent_blockhash) = test.start().await;
+
+    // Below maint the account is marked as being liquidated
+    let mut transaction = Transaction::new_with_payer(
+        &[
+            mango_group.init_mango_group_with_coll_ratios(
+                &payer.pubkey(), U64F64::from_num(1.18), U64F64::from_num(1.25)
+            ),
+            force_cancel_orders_instruction(&mango_group, &payer.pubkey(), &margin_account_pk, 0),
+        ],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer], recent_blockhash);
+    assert!(banks_client.process_transaction(transaction).await.is_ok());
+
+    let margin_account = get_margin_account(
+        &mut banks_client, &program_id, &mango_group.mango_group_pk, margin_account_pk
+    ).await;
+    assert!(margin_account.being_liquidated);
+
+    // Above maint but below init it stays cancellable and keeps the flag
+    let mut transaction = Transaction::new_with_payer(
+        &[
+            set_coll_ratios(
+                &program_id, &mango_group.mango_group_pk, &payer.pubkey(),
+                U64F64::from_num(1.14), U64F64::from_num(1.25)
+            ).unwrap(),
+            force_cancel_orders_instruction(&mango_group, &payer.pubkey(), &margin_account_pk, 0),
+        ],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer], recent_blockhash);
+    assert!(banks_client.process_transaction(transaction).await.is_ok());
+
+    let margin_account = get_margin_account(
+        &mut banks_client, &program_id, &mango_group.mango_group_pk, margin_account_pk
+    ).await;
+    assert!(margin_account.being_liquidated);
+
+    // At init the call succeeds and clears the flag
+    let mut transaction = Transaction::new_with_payer(
+        &[
+            set_coll_ratios(
+                &program_id, &mango_group.mango_group_pk, &payer.pubkey(),
+                U64F64::from_num(1.1), U64F64::from_num(1.15)
+            ).unwrap(),
+            force_cancel_orders_instruction(&mango_group, &payer.pubkey(), &margin_account_pk, 0),
+        ],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer], recent_blockhash);
+    assert!(banks_client.process_transaction(transaction).await.is_ok());
+
+    let margin_account = get_margin_account(
+        &mut banks_client, &program_id, &mango_group.mango_group_pk, margin_account_pk
+    ).await;
+    assert!(!margin_account.being_liquidated);
+
+    // Once cleared, the account is not liquidatable between maint and init again
+    let mut transaction = Transaction::new_with_payer(
+        &[
+            set_coll_ratios(
+                &program_id, &mango_group.mango_group_pk, &payer.pubkey(),
+                U64F64::from_num(1.15), U64F64::from_num(1.2)
+            ).unwrap(),
+            force_cancel_orders_instruction(&mango_group, &payer.pubkey(), &margin_account_pk, 0),
+        ],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer], recent_blockhash);
+    assert_eq!(
+        banks_client.process_transaction(transaction).await.unwrap_err().unwrap(),
+        TransactionError::InstructionError(1, InstructionError::Custom(MangoErrorCode::NotLiquidatable.into()))
+    );
+}
+
+#[tokio::test]
+async fn test_force_cancel_orders_being_liquidated() {
+    // Test that resting orders on the dex are force cancelled once the account drops below maint,
+    // stay cancellable while it is being liquidated and below init, and that the flag is cleared
+    // once it is back at init
+    let program_id = Pubkey::new_unique();
+
+    let mut test = ProgramTest::new(
+        "mango",
+        program_id,
+        processor!(process_instruction),
+    );
+
+    // limit to track compute unit increase
+    test.set_bpf_compute_max_units(50_000);
+
+    let (mango_group, markets) = add_mango_group_with_dex(&mut test, program_id);
+    let market = &markets[0];
+    let oracle_pks = mango_group.oracles.iter().map(|m| m.pubkey).collect::<Vec<Pubkey>>();
+
+    let user = Keypair::new();
+    test.add_account(user.pubkey(), Account::new(u32::MAX as u64, 0, &user.pubkey()));
+    let user_btc = add_token_account(&mut test, user.pubkey(), mango_group.mints[0].pubkey, 10);
+
+    let open_orders_pk = Pubkey::new_unique();
+    test.add_account(
+        open_orders_pk, dex_open_orders_account(&mango_group.signer_pk, market, &mango_group.dex_prog_id)
+    );
+
+    // 10 BTC of assets once deposited against 428000 USDT of liabs -> coll ratio of about 1.168
+    let margin_account_pk = add_margin_account_with_open_orders(
+        &mut test, Pubkey::new_unique(), program_id, mango_group.mango_group_pk, user.pubkey(),
+        [0, 0, 0], [0, 0, 428_000], [open_orders_pk, Pubkey::default()]
+    );
+
+    let (mut banks_client, payer, recent_blockhash) = test.start().await;
+
+    let mut transaction = Transaction::new_with_payer(
+        &[
+            mango_group.init_mango_group_with_coll_ratios(
+                &payer.pubkey(), U64F64::from_num(1.1), U64F64::from_num(1.15)
+            ),
+            deposit(
+                &program_id,
+                &mango_group.mango_group_pk,
+                &margin_account_pk,
+                &user.pubkey(),
+                &user_btc.pubkey,
+                &mango_group.vaults[0].pubkey,
+                10,
+            ).unwrap(),
+        ],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer, &user], recent_blockhash);
+    assert!(banks_client.process_transaction(transaction).await.is_ok());
+
+    // Two asks far above the oracle price rest on the book; the BTC they lock still counts as assets
+    let place_ask = |client_order_id: u64| {
+        place_order(
+            &program_id,
+            &mango_group.mango_group_pk,
+            &user.pubkey(),
+            &margin_account_pk,
+            &mango_group.dex_prog_id,
+            &market.pubkey,
+            &market.req_q,
+            &market.event_q,
+            &market.bids,
+            &market.asks,
+            &mango_group.vaults[0].pubkey,
+            &mango_group.signer_pk,
+            &market.coin_vault,
+            &market.pc_vault,
+            &mango_group.srm_vault.pubkey,
+            &[open_orders_pk, Pubkey::default()],
+            oracle_pks.as_slice(),
+            NewOrderInstructionV3 {
+                side: Side::Ask,
+                limit_price: NonZeroU64::new(2 * PRICE_BTC).unwrap(),
+                max_coin_qty: NonZeroU64::new(1).unwrap(),
+                max_native_pc_qty_including_fees: NonZeroU64::new(2 * PRICE_BTC).unwrap(),
+                self_trade_behavior: SelfTradeBehavior::DecrementTake,
+                order_type: OrderType::Limit,
+                client_order_id,
+                limit: 5,
+            },
+        ).unwrap()
+    };
+    let mut transaction = Transaction::new_with_payer(
+        &[place_ask(1), place_ask(2)],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer, &user], recent_blockhash);
+    assert!(banks_client.process_transaction(transaction).await.is_ok());
+
+    let order_ids = get_order_ids(&mut banks_client, open_orders_pk).await;
+    assert_eq!(order_ids.len(), 2);
+    assert_eq!(asks_resting(&mut banks_client, &mango_group, market, &order_ids).await, vec![true, true]);
+
+    // Between maint and init an account that is not being liquidated keeps its orders
+    let mut transaction = Transaction::new_with_payer(
+        &[
+            set_coll_ratios(
+                &program_id, &mango_group.mango_group_pk, &payer.pubkey(),
+                U64F64::from_num(1.14), U64F64::from_num(1.25)
+            ).unwrap(),
+        ],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer], recent_blockhash);
+    assert!(banks_client.process_transaction(transaction).await.is_ok());
+
+    let mut transaction = Transaction::new_with_payer(
+        &[force_cancel_resting_orders_instruction(
+            &mango_group, market, &payer.pubkey(), &margin_account_pk, &open_orders_pk, 5
+        )],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer], recent_blockhash);
+    assert_eq!(
+        banks_client.process_transaction(transaction).await.unwrap_err().unwrap(),
+        TransactionError::InstructionError(0, InstructionError::Custom(MangoErrorCode::NotLiquidatable.into()))
+    );
+    assert_eq!(asks_resting(&mut banks_client, &mango_group, market, &order_ids).await, vec![true, true]);
+
+    // Below maint the first order is cancelled and the account is marked as being liquidated
+    let mut transaction = Transaction::new_with_payer(
+        &[
+            set_coll_ratios(
+                &program_id, &mango_group.mango_group_pk, &payer.pubkey(),
+                U64F64::from_num(1.18), U64F64::from_num(1.25)
+            ).unwrap(),
+            force_cancel_resting_orders_instruction(
+                &mango_group, market, &payer.pubkey(), &margin_account_pk, &open_orders_pk, 1
+            ),
+        ],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer], recent_blockhash);
+    assert!(banks_client.process_transaction(transaction).await.is_ok());
+
+    assert_eq!(asks_resting(&mut banks_client, &mango_group, market, &order_ids).await, vec![false, true]);
+    let margin_account = get_margin_account(
+        &mut banks_client, &program_id, &mango_group.mango_group_pk, margin_account_pk
+    ).await;
+    assert!(margin_account.being_liquidated);
+
+    // Back between maint and init, the account being liquidated can still have its orders cancelled
+    let mut transaction = Transaction::new_with_payer(
+        &[
+            set_coll_ratios(
+                &program_id, &mango_group.mango_group_pk, &payer.pubkey(),
+                U64F64::from_num(1.14), U64F64::from_num(1.25)
+            ).unwrap(),
+            force_cancel_resting_orders_instruction(
+                &mango_group, market, &payer.pubkey(), &margin_account_pk, &open_orders_pk, 5
+            ),
+        ],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer], recent_blockhash);
+    assert!(banks_client.process_transaction(transaction).await.is_ok());
+
+    assert_eq!(asks_resting(&mut banks_client, &mango_group, market, &order_ids).await, vec![false, false]);
+    let margin_account = get_margin_account(
+        &mut banks_client, &program_id, &mango_group.mango_group_pk, margin_account_pk
+    ).await;
+    assert!(margin_account.being_liquidated);
+
+    // At init the account is no longer being liquidated
+    let mut transaction = Transaction::new_with_payer(
+        &[
+            set_coll_ratios(
+                &program_id, &mango_group.mango_group_pk, &payer.pubkey(),
+                U64F64::from_num(1.1), U64F64::from_num(1.15)
+            ).unwrap(),
+            force_cancel_resting_orders_instruction(
+                &mango_group, market, &payer.pubkey(), &margin_account_pk, &open_orders_pk, 5
+            ),
+        ],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer], recent_blockhash);
+    assert!(banks_client.process_transaction(transaction).await.is_ok());
+
+    let margin_account = get_margin_account(
+        &mut banks_client, &program_id, &mango_group.mango_group_pk, margin_account_pk
+    ).await;
+    assert!(!margin_account.being_liquidated);
+}
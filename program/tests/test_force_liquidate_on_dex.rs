@@ -0,0 +1,139 @@
+// Tests related to unwinding a MarginAccount on the serum dex without a liquidator
+#![cfg(feature="test-bpf")]
+
+mod helpers;
+
+use bytemuck::Zeroable;
+use fixed::types::U64F64;
+use helpers::*;
+use serum_dex::matching::{OrderType, Side};
+use solana_program_test::*;
+use solana_sdk::{
+    instruction::InstructionError,
+    pubkey::Pubkey,
+    signature::Signer,
+    transaction::{Transaction, TransactionError},
+};
+
+use mango::{
+    entrypoint::process_instruction,
+    error::MangoErrorCode,
+    instruction::force_liquidate_on_dex,
+    processor::get_force_liquidation_order,
+    state::{MarginAccount, NUM_MARKETS},
+};
+
+#[test]
+fn test_force_liquidation_order_short() {
+    // Test that a short base position is unwound by bidding for the borrowed base with quote
+    let mango_group = new_mango_group_in_memory([0, 0, 0]);
+    let prices = get_prodlike_prices();
+    let mut margin_account = MarginAccount::zeroed();
+    margin_account.borrows[0] = U64F64::from_num(500_000);  // 0.5 BTC
+    margin_account.deposits[2] = U64F64::from_num(40_000_000_000u64);  // 40000 USDT
+
+    let order = get_force_liquidation_order(&mango_group, &margin_account, &prices, 0, 100, 10)
+        .unwrap().unwrap();
+    assert_eq!(order.side, Side::Bid);
+    assert_eq!(order.order_type, OrderType::ImmediateOrCancel);
+    // 50000 * 1.05 in lots of 100 base per 10 quote
+    assert!((order.limit_price.get() as i64 - 525_000).abs() <= 1);
+    assert_eq!(order.max_coin_qty.get(), 5_000);
+    assert_eq!(order.max_native_pc_qty_including_fees.get(), 40_000_000_000);
+}
+
+#[test]
+fn test_force_liquidation_order_long() {
+    // Test that a long base position is unwound by selling only as much base as repays the quote
+    let mango_group = new_mango_group_in_memory([0, 0, 0]);
+    let prices = get_prodlike_prices();
+    let mut margin_account = MarginAccount::zeroed();
+    margin_account.deposits[0] = U64F64::from_num(1_000_000);  // 1 BTC
+    margin_account.borrows[2] = U64F64::from_num(30_000_000_000u64);  // 30000 USDT
+
+    let order = get_force_liquidation_order(&mango_group, &margin_account, &prices, 0, 100, 10)
+        .unwrap().unwrap();
+    assert_eq!(order.side, Side::Ask);
+    assert!((order.limit_price.get() as i64 - 475_000).abs() <= 1);
+    // 30000 USDT at 47500 is 0.6316 BTC, rounded up to a lot
+    assert_eq!(order.max_coin_qty.get(), 6_316);
+
+    // Never sell more base than deposited
+    margin_account.deposits[0] = U64F64::from_num(500_000);
+    let order = get_force_liquidation_order(&mango_group, &margin_account, &prices, 0, 100, 10)
+        .unwrap().unwrap();
+    assert_eq!(order.max_coin_qty.get(), 5_000);
+}
+
+#[test]
+fn test_force_liquidation_order_nothing_to_unwind() {
+    // Test that no order is made when the market can't help the position
+    let mango_group = new_mango_group_in_memory([0, 0, 0]);
+    let prices = get_prodlike_prices();
+    let mut margin_account = MarginAccount::zeroed();
+    margin_account.deposits[0] = U64F64::from_num(1_000_000);
+    margin_account.borrows[1] = U64F64::from_num(1_000_000);  // ETH borrow can't be unwound on BTC/USDT
+
+    assert!(get_force_liquidation_order(&mango_group, &margin_account, &prices, 0, 100, 10)
+        .unwrap().is_none());
+}
+
+#[tokio::test]
+async fn test_force_liquidate_on_dex_healthy_fails() {
+    // Test that an account above maint_coll_ratio can't be unwound on the dex
+    let program_id = Pubkey::new_unique();
+
+    let mut test = ProgramTest::new(
+        "mango",
+        program_id,
+        processor!(process_instruction),
+    );
+
+    // limit to track compute unit increase
+    test.set_bpf_compute_max_units(50_000);
+
+    let mango_group = add_mango_group_prodlike(&mut test, program_id);
+    let owner_pk = Pubkey::new_unique();
+    let margin_account_pk = add_margin_account(
+        &mut test, program_id, mango_group.mango_group_pk, owner_pk, [1, 0, 0], [0, 0, 0]
+    );
+
+    let (mut banks_client, payer, recent_blockhash) = test.start().await;
+
+    let mut transaction = Transaction::new_with_payer(
+        &[mango_group.init_mango_group(&payer.pubkey())],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer], recent_blockhash);
+    assert!(banks_client.process_transaction(transaction).await.is_ok());
+
+    let instruction = force_liquidate_on_dex(
+        &program_id,
+        &mango_group.mango_group_pk,
+        &payer.pubkey(),
+        &margin_account_pk,
+        &mango_group.dex_prog_id,
+        &mango_group.dexes[0].pubkey,
+        &Pubkey::new_unique(),
+        &Pubkey::new_unique(),
+        &Pubkey::new_unique(),
+        &Pubkey::new_unique(),
+        &mango_group.vaults[0].pubkey,
+        &mango_group.vaults[NUM_MARKETS].pubkey,
+        &mango_group.signer_pk,
+        &Pubkey::new_unique(),
+        &Pubkey::new_unique(),
+        &mango_group.srm_vault.pubkey,
+        &Pubkey::new_unique(),
+        &[Pubkey::default(); NUM_MARKETS],
+        mango_group.oracles.iter().map(|m| m.pubkey).collect::<Vec<Pubkey>>().as_slice(),
+        0,
+        5,
+    ).unwrap();
+    let mut transaction = Transaction::new_with_payer(&[instruction], Some(&payer.pubkey()));
+    transaction.sign(&[&payer], recent_blockhash);
+    assert_eq!(
+        banks_client.process_transaction(transaction).await.unwrap_err().unwrap(),
+        TransactionError::InstructionError(0, InstructionError::Custom(MangoErrorCode::NotLiquidatable.into()))
+    );
+}
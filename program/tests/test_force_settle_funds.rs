@@ -0,0 +1,144 @@
+// Tests related to force settling the funds of a MarginAccount on the dex
+#![cfg(feature="test-bpf")]
+
+mod helpers;
+
+use bytemuck::Zeroable;
+use fixed::types::U64F64;
+use helpers::*;
+use solana_program_test::*;
+use solana_sdk::{
+    instruction::{Instruction, InstructionError},
+    pubkey::Pubkey,
+    signature::Signer,
+    transaction::{Transaction, TransactionError},
+};
+
+use mango::{
+    entrypoint::process_instruction,
+    error::MangoErrorCode,
+    instruction::force_settle_funds,
+    processor::credit_settled_funds,
+    state::{MarginAccount, NUM_MARKETS, QUOTE_INDEX},
+};
+
+fn force_settle_funds_instruction(
+    mango_group: &TestMangoGroup,
+    liqor_pk: &Pubkey,
+    margin_account_pk: &Pubkey,
+    market_index: usize,
+) -> Instruction {
+    force_settle_funds(
+        &mango_group.program_id,
+        &mango_group.mango_group_pk,
+        liqor_pk,
+        margin_account_pk,
+        &mango_group.vaults[market_index].pubkey,
+        &mango_group.vaults[NUM_MARKETS].pubkey,
+        &mango_group.dexes[market_index].pubkey,
+        &mango_group.signer_pk,
+        &Pubkey::new_unique(),
+        &Pubkey::new_unique(),
+        &Pubkey::new_unique(),
+        &mango_group.dex_prog_id,
+        &[Pubkey::default(); NUM_MARKETS],
+        mango_group.oracles.iter().map(|m| m.pubkey).collect::<Vec<Pubkey>>().as_slice(),
+        market_index,
+    ).unwrap()
+}
+
+#[test]
+fn test_settled_funds_credited_to_deposits() {
+    // Test that the base and quote leaving the open orders account are added to the deposits of
+    // the account and the group
+    let mut mango_group = new_mango_group_in_memory([0, 0, 0]);
+    mango_group.indexes[QUOTE_INDEX].deposit = U64F64::from_num(2);
+    let mut margin_account = MarginAccount::zeroed();
+    margin_account.deposits[QUOTE_INDEX] = U64F64::from_num(100);
+    mango_group.total_deposits[QUOTE_INDEX] = U64F64::from_num(100);
+
+    // a filled ask freed 3 ETH of which 1 was settled; 500 USDT freed were all settled
+    credit_settled_funds(&mut mango_group, &mut margin_account, 1, (3, 500), (2, 0)).unwrap();
+    assert_eq!(margin_account.deposits, [U64F64::from_num(0), U64F64::from_num(1), U64F64::from_num(350)]);
+    assert_eq!(mango_group.total_deposits, margin_account.deposits);
+
+    // the free funds can't grow through a settle
+    assert!(credit_settled_funds(&mut mango_group, &mut margin_account, 1, (0, 0), (1, 0)).is_err());
+}
+
+#[tokio::test]
+async fn test_force_settle_funds_healthy_fails() {
+    // Test that the funds of an account above maint_coll_ratio cannot be force settled
+    let program_id = Pubkey::new_unique();
+
+    let mut test = ProgramTest::new(
+        "mango",
+        program_id,
+        processor!(process_instruction),
+    );
+
+    // limit to track compute unit increase
+    test.set_bpf_compute_max_units(50_000);
+
+    let mango_group = add_mango_group_prodlike(&mut test, program_id);
+    let owner_pk = Pubkey::new_unique();
+    let margin_account_pk = add_margin_account(
+        &mut test, program_id, mango_group.mango_group_pk, owner_pk, [1, 0, 0], [0, 0, 0]
+    );
+
+    let (mut banks_client, payer, recent_blockhash) = test.start().await;
+
+    let mut transaction = Transaction::new_with_payer(
+        &[
+            mango_group.init_mango_group(&payer.pubkey()),
+            force_settle_funds_instruction(&mango_group, &payer.pubkey(), &margin_account_pk, 0),
+        ],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer], recent_blockhash);
+    assert_eq!(
+        banks_client.process_transaction(transaction).await.unwrap_err().unwrap(),
+        TransactionError::InstructionError(1, InstructionError::Custom(MangoErrorCode::NotLiquidatable.into()))
+    );
+}
+
+#[tokio::test]
+async fn test_force_settle_funds_unhealthy_succeeds() {
+    // Test that anyone can force settle the funds of an account below maint_coll_ratio
+    let program_id = Pubkey::new_unique();
+
+    let mut test = ProgramTest::new(
+        "mango",
+        program_id,
+        processor!(process_instruction),
+    );
+
+    // limit to track compute unit increase
+    test.set_bpf_compute_max_units(50_000);
+
+    let mango_group = add_mango_group_prodlike(&mut test, program_id);
+    let owner_pk = Pubkey::new_unique();
+
+    // 1 BTC of assets against 50000 USDT of liabs -> coll ratio of 1.0
+    let margin_account_pk = add_margin_account(
+        &mut test, program_id, mango_group.mango_group_pk, owner_pk, [1, 0, 0], [0, 0, PRICE_BTC]
+    );
+
+    let (mut banks_client, payer, recent_blockhash) = test.start().await;
+
+    let mut transaction = Transaction::new_with_payer(
+        &[
+            mango_group.init_mango_group(&payer.pubkey()),
+            force_settle_funds_instruction(&mango_group, &payer.pubkey(), &margin_account_pk, 0),
+        ],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer], recent_blockhash);
+    assert!(banks_client.process_transaction(transaction).await.is_ok());
+
+    // settling doesn't start a liquidation
+    let margin_account = get_margin_account(
+        &mut banks_client, &program_id, &mango_group.mango_group_pk, margin_account_pk
+    ).await;
+    assert!(!margin_account.being_liquidated);
+}
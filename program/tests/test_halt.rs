@@ -0,0 +1,140 @@
+// Tests related to halting a MangoGroup
+#![cfg(feature="test-bpf")]
+
+mod helpers;
+
+use std::mem::size_of;
+
+use fixed::types::U64F64;
+use helpers::*;
+use solana_program_test::*;
+use solana_sdk::{
+    account::Account,
+    instruction::{Instruction, InstructionError},
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+    transaction::{Transaction, TransactionError},
+};
+
+use mango::{
+    entrypoint::process_instruction,
+    error::MangoErrorCode,
+    instruction::{borrow, deposit, init_margin_account, set_halt, settle_borrow},
+    state::{MarginAccount, NUM_MARKETS},
+};
+
+#[tokio::test]
+async fn test_halt_blocks_new_positions_allows_unwinding() {
+    // Test that deposits and borrows fail while halted but borrows can still be settled
+    let program_id = Pubkey::new_unique();
+
+    let mut test = ProgramTest::new(
+        "mango",
+        program_id,
+        processor!(process_instruction),
+    );
+
+    // limit to track compute unit increase
+    test.set_bpf_compute_max_units(50_000);
+
+    let mango_group = add_mango_group_prodlike(&mut test, program_id);
+    let oracle_pks = mango_group.oracles.iter().map(|m| m.pubkey).collect::<Vec<Pubkey>>();
+
+    let user = Keypair::new();
+    test.add_account(user.pubkey(), Account::new(u32::MAX as u64, 0, &user.pubkey()));
+    let user_account = add_token_account(&mut test, user.pubkey(), mango_group.mints[2].pubkey, 10);
+    let margin_account_pk = Pubkey::new_unique();
+    test.add_account(margin_account_pk, Account::new(u32::MAX as u64, size_of::<MarginAccount>(), &program_id));
+
+    let (mut banks_client, payer, recent_blockhash) = test.start().await;
+
+    // Open a USDT borrow backed by a USDT deposit, then halt the group
+    let mut transaction = Transaction::new_with_payer(
+        &[
+            mango_group.init_mango_group(&payer.pubkey()),
+            init_margin_account(
+                &program_id,
+                &mango_group.mango_group_pk,
+                &margin_account_pk,
+                &user.pubkey(),
+            ).unwrap(),
+            deposit(
+                &program_id,
+                &mango_group.mango_group_pk,
+                &margin_account_pk,
+                &user.pubkey(),
+                &user_account.pubkey,
+                &mango_group.vaults[2].pubkey,
+                5,
+            ).unwrap(),
+            borrow(
+                &program_id,
+                &mango_group.mango_group_pk,
+                &margin_account_pk,
+                &user.pubkey(),
+                &[Pubkey::default(); NUM_MARKETS],
+                oracle_pks.as_slice(),
+                2,
+                5,
+            ).unwrap(),
+            set_halt(&program_id, &mango_group.mango_group_pk, &payer.pubkey(), true).unwrap(),
+        ],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer, &user], recent_blockhash);
+    assert!(banks_client.process_transaction(transaction).await.is_ok());
+
+    let blocked: Vec<Instruction> = vec![
+        deposit(
+            &program_id,
+            &mango_group.mango_group_pk,
+            &margin_account_pk,
+            &user.pubkey(),
+            &user_account.pubkey,
+            &mango_group.vaults[2].pubkey,
+            1,
+        ).unwrap(),
+        borrow(
+            &program_id,
+            &mango_group.mango_group_pk,
+            &margin_account_pk,
+            &user.pubkey(),
+            &[Pubkey::default(); NUM_MARKETS],
+            oracle_pks.as_slice(),
+            2,
+            1,
+        ).unwrap(),
+    ];
+    for instruction in blocked {
+        let mut transaction = Transaction::new_with_payer(&[instruction], Some(&payer.pubkey()));
+        transaction.sign(&[&payer, &user], recent_blockhash);
+        let err = banks_client.process_transaction(transaction).await.unwrap_err().unwrap();
+        assert_eq!(
+            err,
+            TransactionError::InstructionError(0, InstructionError::Custom(MangoErrorCode::GroupHalted.into()))
+        );
+    }
+
+    let mut transaction = Transaction::new_with_payer(
+        &[
+            settle_borrow(
+                &program_id,
+                &mango_group.mango_group_pk,
+                &margin_account_pk,
+                &user.pubkey(),
+                2,
+                5,
+            ).unwrap(),
+        ],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer, &user], recent_blockhash);
+    assert!(banks_client.process_transaction(transaction).await.is_ok());
+
+    let margin_account = get_margin_account(
+        &mut banks_client, &program_id, &mango_group.mango_group_pk, margin_account_pk
+    ).await;
+    // Allow for interest accrued between transactions
+    assert!(margin_account.borrows[2] < U64F64::from_num(0.001));
+    assert!(margin_account.deposits[2] > U64F64::from_num(4.999));
+}
@@ -0,0 +1,70 @@
+// Tests related to reporting the health of a MarginAccount
+#![cfg(feature="test-bpf")]
+
+mod helpers;
+
+use helpers::*;
+use solana_program_test::*;
+use solana_sdk::{
+    pubkey::Pubkey,
+    signature::Signer,
+    transaction::Transaction,
+};
+
+use mango::{
+    entrypoint::process_instruction,
+    instruction::get_margin_account_details,
+    state::NUM_MARKETS,
+};
+
+#[tokio::test]
+async fn test_get_margin_account_details() {
+    // Test that the health of a known account can be logged without changing any state
+    let program_id = Pubkey::new_unique();
+
+    let mut test = ProgramTest::new(
+        "mango",
+        program_id,
+        processor!(process_instruction),
+    );
+
+    // limit to track compute unit increase
+    test.set_bpf_compute_max_units(50_000);
+
+    let mango_group = add_mango_group_prodlike(&mut test, program_id);
+    let margin_account_pk = add_margin_account(
+        &mut test, program_id, mango_group.mango_group_pk, Pubkey::new_unique(), [1, 0, 0], [0, 0, 10_000]
+    );
+
+    let (mut banks_client, payer, recent_blockhash) = test.start().await;
+
+    let mut transaction = Transaction::new_with_payer(
+        &[mango_group.init_mango_group(&payer.pubkey())],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer], recent_blockhash);
+    assert!(banks_client.process_transaction(transaction).await.is_ok());
+
+    let margin_account_before = banks_client.get_account(margin_account_pk).await.unwrap().unwrap();
+    let mango_group_before = banks_client.get_account(mango_group.mango_group_pk).await.unwrap().unwrap();
+
+    let mut transaction = Transaction::new_with_payer(
+        &[
+            get_margin_account_details(
+                &program_id,
+                &mango_group.mango_group_pk,
+                &margin_account_pk,
+                &[Pubkey::default(); NUM_MARKETS],
+                mango_group.oracles.iter().map(|m| m.pubkey).collect::<Vec<Pubkey>>().as_slice(),
+            ).unwrap(),
+        ],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer], recent_blockhash);
+    assert!(banks_client.process_transaction(transaction).await.is_ok());
+
+    let margin_account_after = banks_client.get_account(margin_account_pk).await.unwrap().unwrap();
+    let mango_group_after = banks_client.get_account(mango_group.mango_group_pk).await.unwrap().unwrap();
+    assert_eq!(margin_account_before.data, margin_account_after.data);
+    assert_eq!(mango_group_before.data, mango_group_after.data);
+}
@@ -4,22 +4,41 @@
 mod helpers;
 
 use std::mem::size_of;
+use bytemuck::{bytes_of, Zeroable};
+use fixed::types::U64F64;
 use helpers::*;
 use solana_program_test::*;
 use solana_sdk::{
+    instruction::InstructionError,
     pubkey::Pubkey,
     signature::{Signer, Keypair},
-    transaction::Transaction,
+    transaction::{Transaction, TransactionError},
     account::Account,
 };
 use solana_program::account_info::AccountInfo;
 
 use mango::{
     entrypoint::process_instruction,
-    instruction::init_margin_account,
-    state::MarginAccount,
+    error::{MangoError, MangoErrorCode},
+    instruction::{init_margin_account, init_margin_account_pda, init_margin_account_pda_at, upgrade_margin_account},
+    state::{AccountFlag, MARGIN_ACCOUNT_V1_SIZE, MARGIN_ACCOUNT_VERSION, MarginAccount},
+    utils::get_margin_account_address,
 };
 
+// MarginAccount as it was laid out before the fields after padding were added
+fn margin_account_v1(program_id: &Pubkey, mango_group_pk: &Pubkey, owner_pk: &Pubkey, version: u8) -> Account {
+    let mut margin_account = MarginAccount::zeroed();
+    margin_account.account_flags = (AccountFlag::Initialized | AccountFlag::MarginAccount).bits();
+    margin_account.mango_group = *mango_group_pk;
+    margin_account.owner = *owner_pk;
+    margin_account.version = version;
+    margin_account.deposits[0] = U64F64::from_num(7);
+
+    let mut account = Account::new(u32::MAX as u64, MARGIN_ACCOUNT_V1_SIZE, program_id);
+    account.data.copy_from_slice(&bytes_of(&margin_account)[..MARGIN_ACCOUNT_V1_SIZE]);
+    account
+}
+
 #[tokio::test]
 async fn test_init_mango_group() {
     // Mostly a test to ensure we can successfully create the testing harness
@@ -53,6 +72,49 @@ async fn test_init_mango_group() {
     assert!(banks_client.process_transaction(transaction).await.is_ok());
 }
 
+#[tokio::test]
+async fn test_init_mango_group_invalid_coll_ratios() {
+    // Test that coll ratios at or below 1 or with maint above init are rejected
+    let program_id = Pubkey::new_unique();
+
+    let mut test = ProgramTest::new(
+        "mango",
+        program_id,
+        processor!(process_instruction),
+    );
+
+    // limit to track compute unit increase
+    test.set_bpf_compute_max_units(20_000);
+
+    let mango_group = add_mango_group_prodlike(&mut test, program_id);
+
+    let (mut banks_client, payer, recent_blockhash) = test.start().await;
+
+    let invalid_ratios = [(1.2, 1.1), (1.1, 1.1), (1.0, 1.2), (0.9, 0.95)];
+    for (maint_coll_ratio, init_coll_ratio) in invalid_ratios.iter() {
+        let mut transaction = Transaction::new_with_payer(
+            &[
+                mango_group.init_mango_group_with_coll_ratios(
+                    &payer.pubkey(), U64F64::from_num(*maint_coll_ratio), U64F64::from_num(*init_coll_ratio)
+                ),
+            ],
+            Some(&payer.pubkey()),
+        );
+        transaction.sign(&[&payer], recent_blockhash);
+        assert_eq!(
+            banks_client.process_transaction(transaction).await.unwrap_err().unwrap(),
+            TransactionError::InstructionError(0, InstructionError::Custom(MangoErrorCode::Default.into()))
+        );
+    }
+
+    let mut transaction = Transaction::new_with_payer(
+        &[mango_group.init_mango_group(&payer.pubkey())],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer], recent_blockhash);
+    assert!(banks_client.process_transaction(transaction).await.is_ok());
+}
+
 #[tokio::test]
 async fn test_init_margin_account() {
     // Test that we can create a MarginAccount
@@ -109,3 +171,238 @@ async fn test_init_margin_account() {
         assert_eq!(borrow.to_bits(), 0);
     }
 }
+
+
+#[test]
+fn test_load_margin_account_wrong_version() {
+    // Test that loading an account with an old layout fails, with a clear error code if the size
+    // happens to match
+    let program_id = Pubkey::new_unique();
+    let mango_group_pk = Pubkey::new_unique();
+    let margin_account_pk = Pubkey::new_unique();
+    let mut account = margin_account_v1(&program_id, &mango_group_pk, &Pubkey::new_unique(), 1);
+    let account_info: AccountInfo = (&margin_account_pk, &mut account).into();
+    assert!(MarginAccount::load_checked(&program_id, &account_info, &mango_group_pk).is_err());
+
+    let mut margin_account = MarginAccount::zeroed();
+    margin_account.account_flags = (AccountFlag::Initialized | AccountFlag::MarginAccount).bits();
+    margin_account.mango_group = mango_group_pk;
+    margin_account.version = 1;
+    let mut account = Account::new(u32::MAX as u64, size_of::<MarginAccount>(), &program_id);
+    account.data.copy_from_slice(bytes_of(&margin_account));
+    let account_info: AccountInfo = (&margin_account_pk, &mut account).into();
+
+    let result = MarginAccount::load_checked(&program_id, &account_info, &mango_group_pk);
+    assert!(matches!(
+        result,
+        Err(MangoError::MangoErrorCode { mango_error_code: MangoErrorCode::InvalidAccountVersion, .. })
+    ));
+}
+
+#[tokio::test]
+async fn test_upgrade_margin_account() {
+    // Test that a v0 and a v1 MarginAccount can be moved to a new account of the current size,
+    // keeping their balances and closing the old account
+    let program_id = Pubkey::new_unique();
+
+    let mut test = ProgramTest::new(
+        "mango",
+        program_id,
+        processor!(process_instruction),
+    );
+
+    // limit to track compute unit increase
+    test.set_bpf_compute_max_units(20_000);
+
+    let mango_group = add_mango_group_prodlike(&mut test, program_id);
+    let user = Keypair::new();
+    let old_pks = [Pubkey::new_unique(), Pubkey::new_unique()];
+    let new_pks = [Pubkey::new_unique(), Pubkey::new_unique()];
+    for (version, (old_pk, new_pk)) in old_pks.iter().zip(new_pks.iter()).enumerate() {
+        test.add_account(*old_pk, margin_account_v1(&program_id, &mango_group.mango_group_pk, &user.pubkey(), version as u8));
+        test.add_account(*new_pk, Account::new(u32::MAX as u64, size_of::<MarginAccount>(), &program_id));
+    }
+
+    let (mut banks_client, payer, recent_blockhash) = test.start().await;
+
+    let mut transaction = Transaction::new_with_payer(
+        &[
+            mango_group.init_mango_group(&payer.pubkey()),
+            upgrade_margin_account(
+                &program_id, &mango_group.mango_group_pk, &old_pks[0], &new_pks[0], &user.pubkey(),
+            ).unwrap(),
+            upgrade_margin_account(
+                &program_id, &mango_group.mango_group_pk, &old_pks[1], &new_pks[1], &user.pubkey(),
+            ).unwrap(),
+        ],
+        Some(&payer.pubkey()),
+    );
+
+    transaction.sign(
+        &[&payer, &user],
+        recent_blockhash,
+    );
+    assert!(banks_client.process_transaction(transaction).await.is_ok());
+
+    for (old_pk, new_pk) in old_pks.iter().zip(new_pks.iter()) {
+        let margin_account = get_margin_account(
+            &mut banks_client, &program_id, &mango_group.mango_group_pk, *new_pk
+        ).await;
+        assert_eq!(margin_account.version, MARGIN_ACCOUNT_VERSION);
+        assert_eq!(margin_account.owner, user.pubkey());
+        assert_eq!(margin_account.deposits[0], U64F64::from_num(7));
+        assert_eq!(margin_account.deposit_ts, [0; 3]);
+        assert!(banks_client.get_account(*old_pk).await.unwrap().is_none());
+    }
+    assert_eq!(banks_client.get_balance(user.pubkey()).await.unwrap(), 2 * u32::MAX as u64);
+}
+
+#[tokio::test]
+async fn test_upgrade_margin_account_wrong_owner() {
+    // Test that only the owner of the margin account can move it
+    let program_id = Pubkey::new_unique();
+
+    let mut test = ProgramTest::new(
+        "mango",
+        program_id,
+        processor!(process_instruction),
+    );
+
+    // limit to track compute unit increase
+    test.set_bpf_compute_max_units(20_000);
+
+    let mango_group = add_mango_group_prodlike(&mut test, program_id);
+    let user = Keypair::new();
+    let old_pk = Pubkey::new_unique();
+    let new_pk = Pubkey::new_unique();
+    test.add_account(old_pk, margin_account_v1(&program_id, &mango_group.mango_group_pk, &Pubkey::new_unique(), 1));
+    test.add_account(new_pk, Account::new(u32::MAX as u64, size_of::<MarginAccount>(), &program_id));
+
+    let (mut banks_client, payer, recent_blockhash) = test.start().await;
+
+    let mut transaction = Transaction::new_with_payer(
+        &[
+            mango_group.init_mango_group(&payer.pubkey()),
+            upgrade_margin_account(
+                &program_id, &mango_group.mango_group_pk, &old_pk, &new_pk, &user.pubkey(),
+            ).unwrap(),
+        ],
+        Some(&payer.pubkey()),
+    );
+
+    transaction.sign(
+        &[&payer, &user],
+        recent_blockhash,
+    );
+    assert_eq!(
+        banks_client.process_transaction(transaction).await.unwrap_err().unwrap(),
+        TransactionError::InstructionError(1, InstructionError::Custom(MangoErrorCode::InvalidMarginAccountOwner.into()))
+    );
+}
+
+#[tokio::test]
+async fn test_init_margin_account_pda() {
+    // Test that the margin account is created at its derived address, and that any other address
+    // or a non-canonical bump fails
+    let program_id = Pubkey::new_unique();
+
+    let mut test = ProgramTest::new(
+        "mango",
+        program_id,
+        processor!(process_instruction),
+    );
+
+    // limit to track compute unit increase
+    test.set_bpf_compute_max_units(50_000);
+
+    let mango_group = add_mango_group_prodlike(&mut test, program_id);
+    let (mut banks_client, payer, recent_blockhash) = test.start().await;
+
+    let mut transaction = Transaction::new_with_payer(
+        &[mango_group.init_mango_group(&payer.pubkey())],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer], recent_blockhash);
+    assert!(banks_client.process_transaction(transaction).await.is_ok());
+
+    let (margin_account_pk, bump) = get_margin_account_address(
+        &program_id, &mango_group.mango_group_pk, &payer.pubkey()
+    );
+    let invalid_address = TransactionError::InstructionError(
+        0, InstructionError::Custom(MangoErrorCode::InvalidAccountAddress.into())
+    );
+
+    // derived from another owner
+    let (other_pk, other_bump) = get_margin_account_address(
+        &program_id, &mango_group.mango_group_pk, &Pubkey::new_unique()
+    );
+    let mut transaction = Transaction::new_with_payer(
+        &[init_margin_account_pda_at(&program_id, &mango_group.mango_group_pk, &other_pk, &payer.pubkey(), other_bump).unwrap()],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer], recent_blockhash);
+    assert_eq!(banks_client.process_transaction(transaction).await.unwrap_err().unwrap(), invalid_address);
+
+    // right address, wrong bump
+    let mut transaction = Transaction::new_with_payer(
+        &[init_margin_account_pda_at(&program_id, &mango_group.mango_group_pk, &margin_account_pk, &payer.pubkey(), bump.wrapping_sub(1)).unwrap()],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer], recent_blockhash);
+    assert_eq!(banks_client.process_transaction(transaction).await.unwrap_err().unwrap(), invalid_address);
+
+    let mut transaction = Transaction::new_with_payer(
+        &[init_margin_account_pda(&program_id, &mango_group.mango_group_pk, &payer.pubkey()).unwrap()],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer], recent_blockhash);
+    assert!(banks_client.process_transaction(transaction).await.is_ok());
+
+    let margin_account = get_margin_account(
+        &mut banks_client, &program_id, &mango_group.mango_group_pk, margin_account_pk
+    ).await;
+    assert_eq!(margin_account.owner, payer.pubkey());
+    assert_eq!(margin_account.version, MARGIN_ACCOUNT_VERSION);
+}
+
+#[tokio::test]
+async fn test_init_margin_account_pda_prefunded() {
+    // Test that lamports sent to the derived address ahead of time don't block creating the
+    // margin account there
+    let program_id = Pubkey::new_unique();
+
+    let mut test = ProgramTest::new(
+        "mango",
+        program_id,
+        processor!(process_instruction),
+    );
+
+    // limit to track compute unit increase
+    test.set_bpf_compute_max_units(50_000);
+
+    let mango_group = add_mango_group_prodlike(&mut test, program_id);
+    let user = Keypair::new();
+    test.add_account(user.pubkey(), Account::new(u32::MAX as u64, 0, &solana_program::system_program::id()));
+    let (margin_account_pk, _) = get_margin_account_address(
+        &program_id, &mango_group.mango_group_pk, &user.pubkey()
+    );
+    test.add_account(margin_account_pk, Account::new(1_000, 0, &solana_program::system_program::id()));
+
+    let (mut banks_client, payer, recent_blockhash) = test.start().await;
+
+    let mut transaction = Transaction::new_with_payer(
+        &[
+            mango_group.init_mango_group(&payer.pubkey()),
+            init_margin_account_pda(&program_id, &mango_group.mango_group_pk, &user.pubkey()).unwrap(),
+        ],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer, &user], recent_blockhash);
+    assert!(banks_client.process_transaction(transaction).await.is_ok());
+
+    let margin_account = get_margin_account(
+        &mut banks_client, &program_id, &mango_group.mango_group_pk, margin_account_pk
+    ).await;
+    assert_eq!(margin_account.owner, user.pubkey());
+    assert_eq!(margin_account.version, MARGIN_ACCOUNT_VERSION);
+}
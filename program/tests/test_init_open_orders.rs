@@ -0,0 +1,158 @@
+// Tests related to assigning open orders accounts to the market slots of a MarginAccount
+#![cfg(feature="test-bpf")]
+
+mod helpers;
+
+use std::mem::size_of;
+
+use bytemuck::Zeroable;
+use helpers::*;
+use serum_dex::state::{OpenOrders, ToAlignedBytes};
+use solana_program::account_info::AccountInfo;
+use solana_program_test::*;
+use solana_sdk::{
+    account::Account,
+    instruction::InstructionError,
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+    transaction::{Transaction, TransactionError},
+};
+
+use mango::{
+    entrypoint::process_instruction,
+    error::{MangoError, MangoErrorCode},
+    instruction::init_open_orders,
+    processor::check_or_assign_open_orders,
+    state::MarginAccount,
+};
+
+// Initialized open orders account of signer_pk for the spot market spot_market_pk
+fn market_open_orders_account(signer_pk: &Pubkey, spot_market_pk: &Pubkey) -> Account {
+    let mut account = open_orders_account(signer_pk, u128::MAX);
+    let open_orders: &mut OpenOrders = bytemuck::from_bytes_mut(&mut account.data[5..5 + size_of::<OpenOrders>()]);
+    open_orders.market = spot_market_pk.to_aligned_bytes();
+    account
+}
+
+#[test]
+fn test_pre_initialized_open_orders_used_unchanged() {
+    // Test that an order in a market whose slot was set ahead of time, e.g. by InitOpenOrders,
+    // uses that open orders account and leaves the slot as it is
+    let signer_pk = Pubkey::new_unique();
+    let spot_market_pk = Pubkey::new_unique();
+    let open_orders_pk = Pubkey::new_unique();
+    let mut margin_account = MarginAccount::zeroed();
+    margin_account.open_orders[0] = open_orders_pk;
+
+    let mut account = market_open_orders_account(&signer_pk, &spot_market_pk);
+    let open_orders_acc: AccountInfo = (&open_orders_pk, &mut account).into();
+    check_or_assign_open_orders(&mut margin_account, 0, &open_orders_acc, &spot_market_pk, &signer_pk).unwrap();
+    assert_eq!(margin_account.open_orders[0], open_orders_pk);
+
+    // any other open orders account is rejected, even if it's owned by the signer
+    let other_pk = Pubkey::new_unique();
+    let mut account = market_open_orders_account(&signer_pk, &spot_market_pk);
+    let other_acc: AccountInfo = (&other_pk, &mut account).into();
+    let r = check_or_assign_open_orders(&mut margin_account, 0, &other_acc, &spot_market_pk, &signer_pk);
+    assert!(matches!(r, Err(MangoError::MangoErrorCode { mango_error_code: MangoErrorCode::InvalidOpenOrdersAccount, .. })));
+    assert_eq!(margin_account.open_orders[0], open_orders_pk);
+}
+
+#[test]
+fn test_first_order_assigns_open_orders() {
+    // Test that an empty slot takes an uninitialized open orders account and records it
+    let signer_pk = Pubkey::new_unique();
+    let open_orders_pk = Pubkey::new_unique();
+    let mut margin_account = MarginAccount::zeroed();
+
+    let mut account = open_orders_account(&signer_pk, u128::MAX);
+    account.data[5..5 + size_of::<OpenOrders>()].copy_from_slice(bytemuck::bytes_of(&OpenOrders::zeroed()));
+    let open_orders_acc: AccountInfo = (&open_orders_pk, &mut account).into();
+    check_or_assign_open_orders(&mut margin_account, 1, &open_orders_acc, &Pubkey::new_unique(), &signer_pk).unwrap();
+    assert_eq!(margin_account.open_orders[1], open_orders_pk);
+    assert!(!margin_account.has_open_orders(0));
+
+    // an initialized open orders account can't be taken over
+    let other_pk = Pubkey::new_unique();
+    let mut account = open_orders_account(&signer_pk, u128::MAX);
+    let other_acc: AccountInfo = (&other_pk, &mut account).into();
+    assert!(check_or_assign_open_orders(&mut margin_account, 0, &other_acc, &Pubkey::new_unique(), &signer_pk).is_err());
+}
+
+#[test]
+fn test_open_orders_of_other_market_rejected() {
+    // Test that an order in one market can't go to an open orders account of another market,
+    // even if that account sits in the slot of the market being traded
+    let signer_pk = Pubkey::new_unique();
+    let btc_usdt_pk = Pubkey::new_unique();
+    let eth_usdt_pk = Pubkey::new_unique();
+    let open_orders_pk = Pubkey::new_unique();
+    let mut margin_account = MarginAccount::zeroed();
+    margin_account.open_orders[0] = open_orders_pk;
+
+    let mut account = market_open_orders_account(&signer_pk, &eth_usdt_pk);
+    let open_orders_acc: AccountInfo = (&open_orders_pk, &mut account).into();
+    let r = check_or_assign_open_orders(&mut margin_account, 0, &open_orders_acc, &btc_usdt_pk, &signer_pk);
+    assert!(matches!(r, Err(MangoError::MangoErrorCode { mango_error_code: MangoErrorCode::InvalidOpenOrdersAccount, .. })));
+
+    assert!(check_or_assign_open_orders(&mut margin_account, 0, &open_orders_acc, &eth_usdt_pk, &signer_pk).is_ok());
+}
+
+#[tokio::test]
+async fn test_init_open_orders_slot_taken_fails() {
+    // Test that InitOpenOrders doesn't replace the open orders account already in the slot
+    let program_id = Pubkey::new_unique();
+
+    let mut test = ProgramTest::new(
+        "mango",
+        program_id,
+        processor!(process_instruction),
+    );
+
+    // limit to track compute unit increase
+    test.set_bpf_compute_max_units(50_000);
+
+    let mango_group = add_mango_group_prodlike(&mut test, program_id);
+
+    let open_orders_pk = Pubkey::new_unique();
+    test.add_account(open_orders_pk, open_orders_account(&mango_group.signer_pk, u128::MAX));
+
+    let owner = Keypair::new();
+    let margin_account_pk = add_margin_account_with_open_orders(
+        &mut test, Pubkey::new_unique(), program_id, mango_group.mango_group_pk, owner.pubkey(),
+        [0, 0, 0], [0, 0, 0], [open_orders_pk, Pubkey::default()]
+    );
+
+    let new_open_orders_pk = Pubkey::new_unique();
+    test.add_account(new_open_orders_pk, Account::new(u32::MAX as u64, size_of::<OpenOrders>() + 12, &mango_group.dex_prog_id));
+
+    let (mut banks_client, payer, recent_blockhash) = test.start().await;
+
+    let mut transaction = Transaction::new_with_payer(
+        &[
+            mango_group.init_mango_group(&payer.pubkey()),
+            init_open_orders(
+                &program_id,
+                &mango_group.mango_group_pk,
+                &owner.pubkey(),
+                &margin_account_pk,
+                &mango_group.dex_prog_id,
+                &mango_group.dexes[0].pubkey,
+                &new_open_orders_pk,
+                &mango_group.signer_pk,
+                0,
+            ).unwrap(),
+        ],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer, &owner], recent_blockhash);
+    assert_eq!(
+        banks_client.process_transaction(transaction).await.unwrap_err().unwrap(),
+        TransactionError::InstructionError(1, InstructionError::Custom(MangoErrorCode::InvalidOpenOrdersAccount.into()))
+    );
+
+    let margin_account = get_margin_account(
+        &mut banks_client, &program_id, &mango_group.mango_group_pk, margin_account_pk
+    ).await;
+    assert_eq!(margin_account.open_orders[0], open_orders_pk);
+}
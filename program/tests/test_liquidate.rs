@@ -0,0 +1,402 @@
+// Tests related to liquidating a MarginAccount
+#![cfg(feature="test-bpf")]
+
+mod helpers;
+
+use bytemuck::Zeroable;
+use fixed::types::U64F64;
+use helpers::*;
+use solana_program_test::*;
+use solana_sdk::{
+    account::Account,
+    instruction::InstructionError,
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+    transaction::{Transaction, TransactionError},
+};
+
+use mango::{
+    entrypoint::process_instruction,
+    error::{MangoError, MangoErrorCode},
+    instruction::{change_liquidation_fee, liquidate},
+    processor::{check_liquidation_cooldown, check_liquidation_deposits},
+    state::{MarginAccount, NUM_MARKETS, NUM_TOKENS},
+};
+
+#[tokio::test]
+async fn test_liquidate_pays_liquidation_fee() {
+    // Test that the liqor is paid liquidation_fee of their deposits out of the liqee's deposits
+    let program_id = Pubkey::new_unique();
+
+    let mut test = ProgramTest::new(
+        "mango",
+        program_id,
+        processor!(process_instruction),
+    );
+
+    // limit to track compute unit increase
+    test.set_bpf_compute_max_units(50_000);
+
+    let mango_group = add_mango_group_prodlike(&mut test, program_id);
+
+    // 1 BTC of assets against 50000 USDT of liabs -> coll ratio of 1.0
+    let liqee_pk = Pubkey::new_unique();
+    let margin_account_pk = add_margin_account(
+        &mut test, program_id, mango_group.mango_group_pk, liqee_pk, [1, 0, 0], [0, 0, PRICE_BTC]
+    );
+
+    // 11000 USDT brings the account to (50000 + 10450) / 50000 > 1.2 after paying the fee
+    let deposit_amount = 11000;
+    let liqor = Keypair::new();
+    test.add_account(liqor.pubkey(), Account::new(u32::MAX as u64, 0, &liqor.pubkey()));
+    let liqor_token_accounts: Vec<TestTokenAccount> = mango_group.mints.iter().map(
+        |mint| add_token_account(&mut test, liqor.pubkey(), mint.pubkey, deposit_amount)
+    ).collect();
+
+    let (mut banks_client, payer, recent_blockhash) = test.start().await;
+
+    let mut transaction = Transaction::new_with_payer(
+        &[
+            mango_group.init_mango_group(&payer.pubkey()),
+            change_liquidation_fee(
+                &program_id,
+                &mango_group.mango_group_pk,
+                &payer.pubkey(),
+                U64F64::from_num(0.05),
+            ).unwrap(),
+            liquidate(
+                &program_id,
+                &mango_group.mango_group_pk,
+                &liqor.pubkey(),
+                &margin_account_pk,
+                &mango_group.signer_pk,
+                &[Pubkey::default(); NUM_MARKETS],
+                mango_group.oracles.iter().map(|m| m.pubkey).collect::<Vec<Pubkey>>().as_slice(),
+                mango_group.vaults.iter().map(|m| m.pubkey).collect::<Vec<Pubkey>>().as_slice(),
+                liqor_token_accounts.iter().map(|m| m.pubkey).collect::<Vec<Pubkey>>().as_slice(),
+                [0, 0, deposit_amount],
+            ).unwrap(),
+        ],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer, &liqor], recent_blockhash);
+    assert!(banks_client.process_transaction(transaction).await.is_ok());
+
+    let bonus = deposit_amount / 20;
+    let margin_account = get_margin_account(
+        &mut banks_client, &program_id, &mango_group.mango_group_pk, margin_account_pk
+    ).await;
+    assert_eq!(margin_account.owner, liqor.pubkey());
+    // what's left of the liqor's USDT after the fee is netted against the USDT borrow
+    assert_eq!(margin_account.deposits[2], 0);
+    assert_eq!(margin_account.borrows[2], U64F64::from_num(PRICE_BTC - (deposit_amount - bonus)));
+
+    let liqor_balance = get_token_balance(&mut banks_client, liqor_token_accounts[2].pubkey).await;
+    assert_eq!(liqor_balance, bonus);
+    let vault_balance = get_token_balance(&mut banks_client, mango_group.vaults[2].pubkey).await;
+    assert_eq!(vault_balance, deposit_amount - bonus);
+}
+
+#[tokio::test]
+async fn test_liquidate_liqee_as_liqor_fails() {
+    // Test that signing with the liqee MarginAccount's own key as the liqor is rejected cleanly
+    let program_id = Pubkey::new_unique();
+
+    let mut test = ProgramTest::new(
+        "mango",
+        program_id,
+        processor!(process_instruction),
+    );
+
+    // limit to track compute unit increase
+    test.set_bpf_compute_max_units(50_000);
+
+    let mango_group = add_mango_group_prodlike(&mut test, program_id);
+
+    // 1 BTC of assets against 50000 USDT of liabs -> coll ratio of 1.0
+    let margin_account = Keypair::new();
+    add_margin_account_at(
+        &mut test, margin_account.pubkey(), program_id, mango_group.mango_group_pk, Pubkey::new_unique(),
+        [1, 0, 0], [0, 0, PRICE_BTC]
+    );
+    let liqor_token_accounts: Vec<TestTokenAccount> = mango_group.mints.iter().map(
+        |mint| add_token_account(&mut test, margin_account.pubkey(), mint.pubkey, 11000)
+    ).collect();
+
+    let (mut banks_client, payer, recent_blockhash) = test.start().await;
+
+    let mut transaction = Transaction::new_with_payer(
+        &[mango_group.init_mango_group(&payer.pubkey())],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer], recent_blockhash);
+    assert!(banks_client.process_transaction(transaction).await.is_ok());
+
+    let mut transaction = Transaction::new_with_payer(
+        &[
+            liquidate(
+                &program_id,
+                &mango_group.mango_group_pk,
+                &margin_account.pubkey(),
+                &margin_account.pubkey(),
+                &mango_group.signer_pk,
+                &[Pubkey::default(); NUM_MARKETS],
+                mango_group.oracles.iter().map(|m| m.pubkey).collect::<Vec<Pubkey>>().as_slice(),
+                mango_group.vaults.iter().map(|m| m.pubkey).collect::<Vec<Pubkey>>().as_slice(),
+                liqor_token_accounts.iter().map(|m| m.pubkey).collect::<Vec<Pubkey>>().as_slice(),
+                [0, 0, 11000],
+            ).unwrap(),
+        ],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer, &margin_account], recent_blockhash);
+    assert_eq!(
+        banks_client.process_transaction(transaction).await.unwrap_err().unwrap(),
+        TransactionError::InstructionError(0, InstructionError::Custom(MangoErrorCode::DuplicateAccounts.into()))
+    );
+}
+
+#[tokio::test]
+async fn test_liquidate_nets_acquired_account() {
+    // Test that the liqor takes over an account with no token both deposited and borrowed
+    let program_id = Pubkey::new_unique();
+
+    let mut test = ProgramTest::new(
+        "mango",
+        program_id,
+        processor!(process_instruction),
+    );
+
+    // limit to track compute unit increase
+    test.set_bpf_compute_max_units(50_000);
+
+    let mango_group = add_mango_group_prodlike(&mut test, program_id);
+
+    // 1 BTC of assets against 50000 USDT of liabs -> coll ratio of 1.0
+    let liqee_pk = Pubkey::new_unique();
+    let margin_account_pk = add_margin_account(
+        &mut test, program_id, mango_group.mango_group_pk, liqee_pk, [1, 0, 0], [0, 0, PRICE_BTC]
+    );
+
+    let deposit_amount = 11000;
+    let liqor = Keypair::new();
+    test.add_account(liqor.pubkey(), Account::new(u32::MAX as u64, 0, &liqor.pubkey()));
+    let liqor_token_accounts: Vec<TestTokenAccount> = mango_group.mints.iter().map(
+        |mint| add_token_account(&mut test, liqor.pubkey(), mint.pubkey, deposit_amount)
+    ).collect();
+
+    let (mut banks_client, payer, recent_blockhash) = test.start().await;
+
+    let mut transaction = Transaction::new_with_payer(
+        &[
+            mango_group.init_mango_group(&payer.pubkey()),
+            liquidate(
+                &program_id,
+                &mango_group.mango_group_pk,
+                &liqor.pubkey(),
+                &margin_account_pk,
+                &mango_group.signer_pk,
+                &[Pubkey::default(); NUM_MARKETS],
+                mango_group.oracles.iter().map(|m| m.pubkey).collect::<Vec<Pubkey>>().as_slice(),
+                mango_group.vaults.iter().map(|m| m.pubkey).collect::<Vec<Pubkey>>().as_slice(),
+                liqor_token_accounts.iter().map(|m| m.pubkey).collect::<Vec<Pubkey>>().as_slice(),
+                [0, 0, deposit_amount],
+            ).unwrap(),
+        ],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer, &liqor], recent_blockhash);
+    assert!(banks_client.process_transaction(transaction).await.is_ok());
+
+    let margin_account = get_margin_account(
+        &mut banks_client, &program_id, &mango_group.mango_group_pk, margin_account_pk
+    ).await;
+    assert_eq!(margin_account.owner, liqor.pubkey());
+    for i in 0..NUM_TOKENS {
+        assert!(margin_account.deposits[i] == 0 || margin_account.borrows[i] == 0);
+    }
+    // the liqor's 11000 USDT went straight to paying down the borrow
+    assert_eq!(margin_account.deposits[2], 0);
+    assert_eq!(margin_account.borrows[2], U64F64::from_num(PRICE_BTC - deposit_amount));
+}
+
+#[tokio::test]
+async fn test_liquidate_over_deposit_fails() {
+    // Test that deposits worth far more than the collateral deficit are rejected
+    let program_id = Pubkey::new_unique();
+
+    let mut test = ProgramTest::new(
+        "mango",
+        program_id,
+        processor!(process_instruction),
+    );
+
+    // limit to track compute unit increase
+    test.set_bpf_compute_max_units(50_000);
+
+    let mango_group = add_mango_group_prodlike(&mut test, program_id);
+
+    // 1 BTC of assets against 50000 USDT of liabs -> 10000 USDT short of init_coll_ratio
+    let liqee_pk = Pubkey::new_unique();
+    let margin_account_pk = add_margin_account(
+        &mut test, program_id, mango_group.mango_group_pk, liqee_pk, [1, 0, 0], [0, 0, PRICE_BTC]
+    );
+
+    let deposit_amount = 20000;
+    let liqor = Keypair::new();
+    test.add_account(liqor.pubkey(), Account::new(u32::MAX as u64, 0, &liqor.pubkey()));
+    let liqor_token_accounts: Vec<TestTokenAccount> = mango_group.mints.iter().map(
+        |mint| add_token_account(&mut test, liqor.pubkey(), mint.pubkey, deposit_amount)
+    ).collect();
+
+    let (mut banks_client, payer, recent_blockhash) = test.start().await;
+
+    let mut transaction = Transaction::new_with_payer(
+        &[
+            mango_group.init_mango_group(&payer.pubkey()),
+            liquidate(
+                &program_id,
+                &mango_group.mango_group_pk,
+                &liqor.pubkey(),
+                &margin_account_pk,
+                &mango_group.signer_pk,
+                &[Pubkey::default(); NUM_MARKETS],
+                mango_group.oracles.iter().map(|m| m.pubkey).collect::<Vec<Pubkey>>().as_slice(),
+                mango_group.vaults.iter().map(|m| m.pubkey).collect::<Vec<Pubkey>>().as_slice(),
+                liqor_token_accounts.iter().map(|m| m.pubkey).collect::<Vec<Pubkey>>().as_slice(),
+                [0, 0, deposit_amount],
+            ).unwrap(),
+        ],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer, &liqor], recent_blockhash);
+    assert_eq!(
+        banks_client.process_transaction(transaction).await.unwrap_err().unwrap(),
+        TransactionError::InstructionError(1, InstructionError::Custom(MangoErrorCode::ExcessLiquidationDeposit.into()))
+    );
+}
+
+#[test]
+fn test_check_liquidation_deposits_tolerance() {
+    // Test that deposits up to the deficit plus the tolerance pass and anything more fails
+    let mut mango_group = new_mango_group_in_memory([0, 0, 0]);
+    let prices = get_prodlike_prices();
+
+    assert!(check_liquidation_deposits(&mango_group, &prices, &[0, 0, 10000], 10000).is_ok());
+    assert!(check_liquidation_deposits(&mango_group, &prices, &[0, 0, 11000], 10000).is_ok());
+    let r = check_liquidation_deposits(&mango_group, &prices, &[0, 0, 11100], 10000);
+    assert!(matches!(r, Err(MangoError::MangoErrorCode { mango_error_code: MangoErrorCode::ExcessLiquidationDeposit, .. })));
+
+    // deposits are valued at the oracle price, with one native unit of slack per token
+    let r = check_liquidation_deposits(&mango_group, &prices, &[0, 7, 0], 10000);
+    assert!(matches!(r, Err(MangoError::MangoErrorCode { mango_error_code: MangoErrorCode::ExcessLiquidationDeposit, .. })));
+    assert!(check_liquidation_deposits(&mango_group, &prices, &[0, 6, 0], 10000).is_ok());
+
+    // the part paid back as the liquidation fee doesn't count
+    mango_group.liquidation_fee = U64F64::from_num(0.5);
+    assert!(check_liquidation_deposits(&mango_group, &prices, &[0, 0, 20000], 10000).is_ok());
+}
+
+#[test]
+fn test_liquidation_cooldown() {
+    // Test that an account liquidated moments ago and now back above maint_coll_ratio can't be
+    // liquidated again until liquidation_cooldown has passed
+    let mut mango_group = new_mango_group_in_memory([0, 0, 0]);
+    mango_group.liquidation_cooldown = 60;
+    let mut margin_account = MarginAccount::zeroed();
+    margin_account.last_liquidation_ts = 1000;
+
+    // between maint and init, where PartialLiquidate continues on accounts being liquidated
+    let healthy = U64F64::from_num(1.15);
+    let r = check_liquidation_cooldown(&mango_group, &margin_account, healthy, 1000);
+    assert!(matches!(r, Err(MangoError::MangoErrorCode { mango_error_code: MangoErrorCode::LiquidationCooldown, .. })));
+    let r = check_liquidation_cooldown(&mango_group, &margin_account, healthy, 1059);
+    assert!(matches!(r, Err(MangoError::MangoErrorCode { mango_error_code: MangoErrorCode::LiquidationCooldown, .. })));
+    assert!(check_liquidation_cooldown(&mango_group, &margin_account, healthy, 1060).is_ok());
+
+    // still below maint after the first liquidation
+    assert!(check_liquidation_cooldown(&mango_group, &margin_account, U64F64::from_num(1.05), 1000).is_ok());
+
+    mango_group.liquidation_cooldown = 0;
+    assert!(check_liquidation_cooldown(&mango_group, &margin_account, healthy, 1000).is_ok());
+}
+
+#[tokio::test]
+async fn test_liquidate_swapped_accounts_fail() {
+    // Test that vaults or liqor token wallets passed out of token order are rejected instead of
+    // crediting the deposit to the wrong token
+    let program_id = Pubkey::new_unique();
+
+    let mut test = ProgramTest::new(
+        "mango",
+        program_id,
+        processor!(process_instruction),
+    );
+
+    // limit to track compute unit increase
+    test.set_bpf_compute_max_units(50_000);
+
+    let mango_group = add_mango_group_prodlike(&mut test, program_id);
+
+    // 1 BTC of assets against 50000 USDT of liabs -> coll ratio of 1.0
+    let liqee_pk = Pubkey::new_unique();
+    let margin_account_pk = add_margin_account(
+        &mut test, program_id, mango_group.mango_group_pk, liqee_pk, [1, 0, 0], [0, 0, PRICE_BTC]
+    );
+
+    let deposit_amount = 11000;
+    let liqor = Keypair::new();
+    test.add_account(liqor.pubkey(), Account::new(u32::MAX as u64, 0, &liqor.pubkey()));
+    let liqor_token_accounts: Vec<TestTokenAccount> = mango_group.mints.iter().map(
+        |mint| add_token_account(&mut test, liqor.pubkey(), mint.pubkey, deposit_amount)
+    ).collect();
+
+    let (mut banks_client, payer, recent_blockhash) = test.start().await;
+
+    let mut transaction = Transaction::new_with_payer(
+        &[mango_group.init_mango_group(&payer.pubkey())],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer], recent_blockhash);
+    assert!(banks_client.process_transaction(transaction).await.is_ok());
+
+    let vault_pks = mango_group.vaults.iter().map(|m| m.pubkey).collect::<Vec<Pubkey>>();
+    let token_account_pks = liqor_token_accounts.iter().map(|m| m.pubkey).collect::<Vec<Pubkey>>();
+    let mut swapped_vault_pks = vault_pks.clone();
+    swapped_vault_pks.swap(1, 2);
+    let mut swapped_token_account_pks = token_account_pks.clone();
+    swapped_token_account_pks.swap(1, 2);
+
+    for (vaults, token_accounts, error_code) in [
+        (&swapped_vault_pks, &token_account_pks, MangoErrorCode::InvalidMangoVault),
+        (&vault_pks, &swapped_token_account_pks, MangoErrorCode::InvalidTokenAccount),
+    ].iter() {
+        let mut transaction = Transaction::new_with_payer(
+            &[
+                liquidate(
+                    &program_id,
+                    &mango_group.mango_group_pk,
+                    &liqor.pubkey(),
+                    &margin_account_pk,
+                    &mango_group.signer_pk,
+                    &[Pubkey::default(); NUM_MARKETS],
+                    mango_group.oracles.iter().map(|m| m.pubkey).collect::<Vec<Pubkey>>().as_slice(),
+                    vaults.as_slice(),
+                    token_accounts.as_slice(),
+                    [0, 0, deposit_amount],
+                ).unwrap(),
+            ],
+            Some(&payer.pubkey()),
+        );
+        transaction.sign(&[&payer, &liqor], recent_blockhash);
+        assert_eq!(
+            banks_client.process_transaction(transaction).await.unwrap_err().unwrap(),
+            TransactionError::InstructionError(0, InstructionError::Custom((*error_code).into()))
+        );
+    }
+
+    let margin_account = get_margin_account(
+        &mut banks_client, &program_id, &mango_group.mango_group_pk, margin_account_pk
+    ).await;
+    assert_eq!(margin_account.owner, liqee_pk);
+}
@@ -0,0 +1,40 @@
+// Tests related to the LogIndexes log lines
+#![cfg(feature="test-bpf")]
+
+mod helpers;
+
+use fixed::types::U64F64;
+use helpers::*;
+use solana_program::clock::Clock;
+
+use mango::processor::format_index_log;
+
+// Pulls the deposit index out of a MANGO-INDEX log line
+fn parse_deposit(line: &str) -> U64F64 {
+    let field = line.split(' ').find(|f| f.starts_with("deposit=")).unwrap();
+    field["deposit=".len()..].parse().unwrap()
+}
+
+#[test]
+fn test_log_indexes_deposit_index_increases() {
+    // Test that the logged deposit index grows after a time step while some deposits are borrowed
+    let mut mango_group = new_mango_group_in_memory([0, 0, 0]);
+    mango_group.total_deposits[2] = U64F64::from_num(1000);
+    mango_group.total_borrows[2] = U64F64::from_num(500);
+
+    let mut clock = Clock::default();
+    clock.unix_timestamp = 1;
+    mango_group.update_indexes(&clock).unwrap();
+    let before = format_index_log(2, &mango_group.indexes[2]);
+    assert!(before.starts_with("MANGO-INDEX token=2 borrow="));
+    assert!(before.ends_with(" last_update=1"));
+
+    clock.unix_timestamp += 86400;
+    mango_group.update_indexes(&clock).unwrap();
+    let after = format_index_log(2, &mango_group.indexes[2]);
+    assert!(after.ends_with(" last_update=86401"));
+    assert!(parse_deposit(&after) > parse_deposit(&before));
+
+    // no utilization, no interest
+    assert_eq!(parse_deposit(&format_index_log(0, &mango_group.indexes[0])), U64F64::from_num(1));
+}
@@ -0,0 +1,163 @@
+// Tests related to putting a spot market in reduce only mode
+#![cfg(feature="test-bpf")]
+
+mod helpers;
+
+use std::num::NonZeroU64;
+
+use bytemuck::Zeroable;
+use fixed::types::U64F64;
+use helpers::*;
+use serum_dex::instruction::{NewOrderInstructionV3, SelfTradeBehavior};
+use serum_dex::matching::{OrderType, Side};
+use solana_program_test::*;
+use solana_sdk::{
+    account::Account,
+    instruction::InstructionError,
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+    transaction::{Transaction, TransactionError},
+};
+
+use mango::{
+    entrypoint::process_instruction,
+    error::MangoErrorCode,
+    instruction::{force_cancel_orders, place_order, set_market_mode},
+    state::{MarginAccount, MarketFlag, NUM_MARKETS},
+};
+
+#[test]
+fn test_reducing_order_side() {
+    // Test that only orders closing out an existing base position count as reducing
+    let mut mango_group = new_mango_group_in_memory([0, 0, 0]);
+    assert!(!mango_group.is_reduce_only(0));
+    mango_group.market_flags[0] |= MarketFlag::ReduceOnly as u8;
+    assert!(mango_group.is_reduce_only(0));
+    assert!(!mango_group.is_reduce_only(1));
+
+    let mut margin_account = MarginAccount::zeroed();
+    margin_account.deposits[2] = U64F64::from_num(1000);
+    assert!(!margin_account.is_reducing_order(0, Side::Bid));
+    assert!(!margin_account.is_reducing_order(0, Side::Ask));
+
+    margin_account.borrows[0] = U64F64::from_num(1);
+    assert!(margin_account.is_reducing_order(0, Side::Bid));
+    assert!(!margin_account.is_reducing_order(0, Side::Ask));
+
+    margin_account.borrows[0] = U64F64::from_num(0);
+    margin_account.deposits[0] = U64F64::from_num(1);
+    assert!(!margin_account.is_reducing_order(0, Side::Bid));
+    assert!(margin_account.is_reducing_order(0, Side::Ask));
+}
+
+#[tokio::test]
+async fn test_reduce_only_market_blocks_bid_allows_cancel() {
+    // Test that a fresh bid on a reduce only market fails while cancelling orders on it still works
+    let program_id = Pubkey::new_unique();
+
+    let mut test = ProgramTest::new(
+        "mango",
+        program_id,
+        processor!(process_instruction),
+    );
+
+    // limit to track compute unit increase
+    test.set_bpf_compute_max_units(50_000);
+
+    let mango_group = add_mango_group_prodlike(&mut test, program_id);
+    let oracle_pks = mango_group.oracles.iter().map(|m| m.pubkey).collect::<Vec<Pubkey>>();
+
+    let user = Keypair::new();
+    test.add_account(user.pubkey(), Account::new(u32::MAX as u64, 0, &user.pubkey()));
+    let margin_account_pk = add_margin_account(
+        &mut test, program_id, mango_group.mango_group_pk, user.pubkey(), [0, 0, PRICE_BTC], [0, 0, 0]
+    );
+
+    // 1 BTC of assets against 50000 USDT of liabs -> coll ratio of 1.0 so orders can be force cancelled
+    let unhealthy_pk = add_margin_account(
+        &mut test, program_id, mango_group.mango_group_pk, Pubkey::new_unique(), [1, 0, 0], [0, 0, PRICE_BTC]
+    );
+
+    let (mut banks_client, payer, recent_blockhash) = test.start().await;
+
+    let mut transaction = Transaction::new_with_payer(
+        &[
+            mango_group.init_mango_group(&payer.pubkey()),
+            set_market_mode(&program_id, &mango_group.mango_group_pk, &payer.pubkey(), 0, true).unwrap(),
+        ],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer], recent_blockhash);
+    assert!(banks_client.process_transaction(transaction).await.is_ok());
+
+    let order = NewOrderInstructionV3 {
+        side: Side::Bid,
+        limit_price: NonZeroU64::new(PRICE_BTC).unwrap(),
+        max_coin_qty: NonZeroU64::new(1).unwrap(),
+        max_native_pc_qty_including_fees: NonZeroU64::new(PRICE_BTC).unwrap(),
+        self_trade_behavior: SelfTradeBehavior::DecrementTake,
+        order_type: OrderType::Limit,
+        client_order_id: 0,
+        limit: 5,
+    };
+    let mut transaction = Transaction::new_with_payer(
+        &[
+            place_order(
+                &program_id,
+                &mango_group.mango_group_pk,
+                &user.pubkey(),
+                &margin_account_pk,
+                &mango_group.dex_prog_id,
+                &mango_group.dexes[0].pubkey,
+                &Pubkey::new_unique(),
+                &Pubkey::new_unique(),
+                &Pubkey::new_unique(),
+                &Pubkey::new_unique(),
+                &mango_group.vaults[NUM_MARKETS].pubkey,
+                &mango_group.signer_pk,
+                &Pubkey::new_unique(),
+                &Pubkey::new_unique(),
+                &mango_group.srm_vault.pubkey,
+                &[Pubkey::new_unique(), Pubkey::default()],
+                oracle_pks.as_slice(),
+                order,
+            ).unwrap(),
+        ],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer, &user], recent_blockhash);
+    let err = banks_client.process_transaction(transaction).await.unwrap_err().unwrap();
+    assert_eq!(
+        err,
+        TransactionError::InstructionError(0, InstructionError::Custom(MangoErrorCode::ReduceOnlyMarket.into()))
+    );
+
+    let mut transaction = Transaction::new_with_payer(
+        &[
+            force_cancel_orders(
+                &program_id,
+                &mango_group.mango_group_pk,
+                &payer.pubkey(),
+                &unhealthy_pk,
+                &mango_group.vaults[0].pubkey,
+                &mango_group.vaults[NUM_MARKETS].pubkey,
+                &mango_group.dexes[0].pubkey,
+                &Pubkey::new_unique(),
+                &Pubkey::new_unique(),
+                &mango_group.signer_pk,
+                &Pubkey::new_unique(),
+                &Pubkey::new_unique(),
+                &Pubkey::new_unique(),
+                &Pubkey::new_unique(),
+                &mango_group.dex_prog_id,
+                &[Pubkey::default(); NUM_MARKETS],
+                oracle_pks.as_slice(),
+                0,
+                5,
+            ).unwrap(),
+        ],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer], recent_blockhash);
+    assert!(banks_client.process_transaction(transaction).await.is_ok());
+}
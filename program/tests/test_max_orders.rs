@@ -0,0 +1,47 @@
+// Tests related to the max orders per market enforced by PlaceOrder and PlaceAndSettle
+#![cfg(feature="test-bpf")]
+
+mod helpers;
+
+use bytemuck::Zeroable;
+use helpers::*;
+use solana_program::account_info::AccountInfo;
+use solana_sdk::pubkey::Pubkey;
+
+use mango::{
+    error::{MangoError, MangoErrorCode},
+    processor::check_max_orders_per_market,
+    state::MarginAccount,
+};
+
+#[test]
+fn test_max_orders_per_market() {
+    // Test that orders are accepted until the account has max_orders_per_market resting in a market
+    let mut mango_group = new_mango_group_in_memory([0, 0, 0]);
+    let mut margin_account = MarginAccount::zeroed();
+    let owner_pk = Pubkey::new_unique();
+    let open_orders_pk = Pubkey::new_unique();
+
+    // off by default
+    margin_account.num_open_orders[0] = 128;
+    assert!(check_max_orders_per_market(&mango_group, &margin_account, 0).is_ok());
+
+    mango_group.max_orders_per_market = 4;
+    for used_slots in 0..=4u32 {
+        // each placed order takes one more free slot of the open orders account
+        let free_slot_bits = u128::MAX << used_slots;
+        let mut account = open_orders_account(&owner_pk, free_slot_bits);
+        let open_orders_acc: AccountInfo = (&open_orders_pk, &mut account).into();
+        margin_account.update_num_open_orders(0, &open_orders_acc).unwrap();
+
+        let r = check_max_orders_per_market(&mango_group, &margin_account, 0);
+        if used_slots < 4 {
+            assert!(r.is_ok());
+        } else {
+            assert!(matches!(r, Err(MangoError::MangoErrorCode { mango_error_code: MangoErrorCode::TooManyOpenOrders, .. })));
+        }
+    }
+
+    // the cap is per market
+    assert!(check_max_orders_per_market(&mango_group, &margin_account, 1).is_ok());
+}
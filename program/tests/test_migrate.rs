@@ -0,0 +1,356 @@
+// Tests related to migrating a MarginAccount to a new MangoGroup
+#![cfg(feature="test-bpf")]
+
+mod helpers;
+
+use std::mem::size_of;
+
+use fixed::types::U64F64;
+use helpers::*;
+use solana_program::account_info::AccountInfo;
+use solana_program_test::*;
+use solana_sdk::{
+    account::Account,
+    instruction::{Instruction, InstructionError},
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+    transaction::{Transaction, TransactionError},
+};
+
+use mango::{
+    entrypoint::process_instruction,
+    error::MangoErrorCode,
+    instruction::{
+        change_deposit_limit, change_max_borrow_per_account, deposit, init_margin_account, migrate_margin_account
+    },
+    state::{MangoGroupV1, MarginAccount, NUM_TOKENS},
+};
+
+fn migrate_margin_account_instruction(
+    old_group: &TestMangoGroup,
+    new_group: &TestMangoGroup,
+    margin_account_pk: &Pubkey,
+    owner_pk: &Pubkey,
+) -> Instruction {
+    migrate_margin_account(
+        &old_group.program_id,
+        &old_group.mango_group_pk,
+        &new_group.mango_group_pk,
+        margin_account_pk,
+        owner_pk,
+        &old_group.signer_pk,
+        &new_group.signer_pk,
+        old_group.vaults.iter().map(|m| m.pubkey).collect::<Vec<Pubkey>>().as_slice(),
+        new_group.vaults.iter().map(|m| m.pubkey).collect::<Vec<Pubkey>>().as_slice(),
+        new_group.oracles.iter().map(|m| m.pubkey).collect::<Vec<Pubkey>>().as_slice(),
+    ).unwrap()
+}
+
+#[tokio::test]
+async fn test_migrate_margin_account_moves_deposits() {
+    // Test that deposits in two tokens move to the new group along with the tokens in the vaults
+    let program_id = Pubkey::new_unique();
+
+    let mut test = ProgramTest::new(
+        "mango",
+        program_id,
+        processor!(process_instruction),
+    );
+
+    // limit to track compute unit increase
+    test.set_bpf_compute_max_units(50_000);
+
+    let old_group = add_mango_group_prodlike(&mut test, program_id);
+    let new_group = add_mango_group_prodlike_with_mints(&mut test, program_id, old_group.mints.clone());
+
+    let user = Keypair::new();
+    test.add_account(user.pubkey(), Account::new(u32::MAX as u64, 0, &user.pubkey()));
+    let btc_amount = 3;
+    let usdt_amount = 5000;
+    let btc_account = add_token_account(&mut test, user.pubkey(), old_group.mints[0].pubkey, btc_amount);
+    let usdt_account = add_token_account(&mut test, user.pubkey(), old_group.mints[2].pubkey, usdt_amount);
+
+    let margin_account_pk = Pubkey::new_unique();
+    test.add_account(margin_account_pk, Account::new(u32::MAX as u64, size_of::<MarginAccount>(), &program_id));
+
+    let (mut banks_client, payer, recent_blockhash) = test.start().await;
+
+    let mut transaction = Transaction::new_with_payer(
+        &[
+            old_group.init_mango_group(&payer.pubkey()),
+            new_group.init_mango_group(&payer.pubkey()),
+            init_margin_account(
+                &program_id,
+                &old_group.mango_group_pk,
+                &margin_account_pk,
+                &user.pubkey(),
+            ).unwrap(),
+            deposit(
+                &program_id,
+                &old_group.mango_group_pk,
+                &margin_account_pk,
+                &user.pubkey(),
+                &btc_account.pubkey,
+                &old_group.vaults[0].pubkey,
+                btc_amount,
+            ).unwrap(),
+            deposit(
+                &program_id,
+                &old_group.mango_group_pk,
+                &margin_account_pk,
+                &user.pubkey(),
+                &usdt_account.pubkey,
+                &old_group.vaults[2].pubkey,
+                usdt_amount,
+            ).unwrap(),
+        ],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer, &user], recent_blockhash);
+    assert!(banks_client.process_transaction(transaction).await.is_ok());
+
+    let mut transaction = Transaction::new_with_payer(
+        &[migrate_margin_account_instruction(&old_group, &new_group, &margin_account_pk, &user.pubkey())],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer, &user], recent_blockhash);
+    assert!(banks_client.process_transaction(transaction).await.is_ok());
+
+    let margin_account = get_margin_account(
+        &mut banks_client, &program_id, &new_group.mango_group_pk, margin_account_pk
+    ).await;
+    assert_eq!(margin_account.mango_group, new_group.mango_group_pk);
+    assert_eq!(margin_account.deposits, [U64F64::from_num(btc_amount), U64F64::from_num(0), U64F64::from_num(usdt_amount)]);
+
+    let old = get_mango_group(&mut banks_client, &program_id, old_group.mango_group_pk).await;
+    let new = get_mango_group(&mut banks_client, &program_id, new_group.mango_group_pk).await;
+    assert_eq!(old.total_deposits[0], U64F64::from_num(0));
+    assert_eq!(old.total_deposits[2], U64F64::from_num(0));
+    assert_eq!(new.total_deposits[0], U64F64::from_num(btc_amount));
+    assert_eq!(new.total_deposits[2], U64F64::from_num(usdt_amount));
+
+    assert_eq!(get_token_balance(&mut banks_client, old_group.vaults[0].pubkey).await, 0);
+    assert_eq!(get_token_balance(&mut banks_client, old_group.vaults[2].pubkey).await, 0);
+    assert_eq!(get_token_balance(&mut banks_client, new_group.vaults[0].pubkey).await, btc_amount);
+    assert_eq!(get_token_balance(&mut banks_client, new_group.vaults[2].pubkey).await, usdt_amount);
+}
+
+#[tokio::test]
+async fn test_migrate_margin_account_over_deposit_limit_fails() {
+    // Test that an account can't bring more deposits into the new group than its deposit limit allows
+    let program_id = Pubkey::new_unique();
+
+    let mut test = ProgramTest::new(
+        "mango",
+        program_id,
+        processor!(process_instruction),
+    );
+
+    // limit to track compute unit increase
+    test.set_bpf_compute_max_units(50_000);
+
+    let old_group = add_mango_group_prodlike(&mut test, program_id);
+    let new_group = add_mango_group_prodlike_with_mints(&mut test, program_id, old_group.mints.clone());
+
+    let user = Keypair::new();
+    test.add_account(user.pubkey(), Account::new(u32::MAX as u64, 0, &user.pubkey()));
+    let btc_amount = 3;
+    let btc_account = add_token_account(&mut test, user.pubkey(), old_group.mints[0].pubkey, btc_amount);
+
+    let margin_account_pk = Pubkey::new_unique();
+    test.add_account(margin_account_pk, Account::new(u32::MAX as u64, size_of::<MarginAccount>(), &program_id));
+
+    let (mut banks_client, payer, recent_blockhash) = test.start().await;
+
+    let mut transaction = Transaction::new_with_payer(
+        &[
+            old_group.init_mango_group(&payer.pubkey()),
+            new_group.init_mango_group(&payer.pubkey()),
+            change_deposit_limit(&program_id, &new_group.mango_group_pk, &payer.pubkey(), 0, btc_amount - 1).unwrap(),
+            init_margin_account(
+                &program_id,
+                &old_group.mango_group_pk,
+                &margin_account_pk,
+                &user.pubkey(),
+            ).unwrap(),
+            deposit(
+                &program_id,
+                &old_group.mango_group_pk,
+                &margin_account_pk,
+                &user.pubkey(),
+                &btc_account.pubkey,
+                &old_group.vaults[0].pubkey,
+                btc_amount,
+            ).unwrap(),
+        ],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer, &user], recent_blockhash);
+    assert!(banks_client.process_transaction(transaction).await.is_ok());
+
+    let mut transaction = Transaction::new_with_payer(
+        &[migrate_margin_account_instruction(&old_group, &new_group, &margin_account_pk, &user.pubkey())],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer, &user], recent_blockhash);
+    assert_eq!(
+        banks_client.process_transaction(transaction).await.unwrap_err().unwrap(),
+        TransactionError::InstructionError(0, InstructionError::Custom(MangoErrorCode::DepositLimitExceeded.into()))
+    );
+
+    let margin_account = get_margin_account(
+        &mut banks_client, &program_id, &old_group.mango_group_pk, margin_account_pk
+    ).await;
+    assert_eq!(margin_account.mango_group, old_group.mango_group_pk);
+    assert_eq!(get_token_balance(&mut banks_client, old_group.vaults[0].pubkey).await, btc_amount);
+}
+
+#[tokio::test]
+async fn test_migrate_margin_account_over_max_borrow_fails() {
+    // Test that an account can't bring more borrows into the new group than max_borrow_per_account
+    let program_id = Pubkey::new_unique();
+
+    let mut test = ProgramTest::new(
+        "mango",
+        program_id,
+        processor!(process_instruction),
+    );
+
+    // limit to track compute unit increase
+    test.set_bpf_compute_max_units(50_000);
+
+    let old_group = add_mango_group_prodlike(&mut test, program_id);
+    let new_group = add_mango_group_prodlike_with_mints(&mut test, program_id, old_group.mints.clone());
+
+    // deposits and borrows of each token cancel out, so no tokens move between the vaults
+    let user = Keypair::new();
+    let margin_account_pk = add_margin_account(
+        &mut test, program_id, old_group.mango_group_pk, user.pubkey(), [0, 0, 40000], [0, 0, 40000]
+    );
+
+    let (mut banks_client, payer, recent_blockhash) = test.start().await;
+
+    let mut transaction = Transaction::new_with_payer(
+        &[
+            old_group.init_mango_group(&payer.pubkey()),
+            new_group.init_mango_group(&payer.pubkey()),
+            change_max_borrow_per_account(&program_id, &new_group.mango_group_pk, &payer.pubkey(), 2, 30000).unwrap(),
+        ],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer], recent_blockhash);
+    assert!(banks_client.process_transaction(transaction).await.is_ok());
+
+    let mut transaction = Transaction::new_with_payer(
+        &[migrate_margin_account_instruction(&old_group, &new_group, &margin_account_pk, &user.pubkey())],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer, &user], recent_blockhash);
+    assert_eq!(
+        banks_client.process_transaction(transaction).await.unwrap_err().unwrap(),
+        TransactionError::InstructionError(0, InstructionError::Custom(MangoErrorCode::BorrowLimitExceeded.into()))
+    );
+}
+
+#[tokio::test]
+async fn test_migrate_margin_account_below_init_fails() {
+    // Test that an account below the new group's init_coll_ratio can't move there
+    let program_id = Pubkey::new_unique();
+
+    let mut test = ProgramTest::new(
+        "mango",
+        program_id,
+        processor!(process_instruction),
+    );
+
+    // limit to track compute unit increase
+    test.set_bpf_compute_max_units(50_000);
+
+    let old_group = add_mango_group_prodlike(&mut test, program_id);
+    let new_group = add_mango_group_prodlike_with_mints(&mut test, program_id, old_group.mints.clone());
+
+    // 40000 USDT of assets against 40000 USDT of liabs -> coll ratio of 1.0
+    let user = Keypair::new();
+    let margin_account_pk = add_margin_account(
+        &mut test, program_id, old_group.mango_group_pk, user.pubkey(), [0, 0, 40000], [0, 0, 40000]
+    );
+
+    let (mut banks_client, payer, recent_blockhash) = test.start().await;
+
+    let mut transaction = Transaction::new_with_payer(
+        &[
+            old_group.init_mango_group(&payer.pubkey()),
+            new_group.init_mango_group(&payer.pubkey()),
+        ],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer], recent_blockhash);
+    assert!(banks_client.process_transaction(transaction).await.is_ok());
+
+    let mut transaction = Transaction::new_with_payer(
+        &[migrate_margin_account_instruction(&old_group, &new_group, &margin_account_pk, &user.pubkey())],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer, &user], recent_blockhash);
+    assert_eq!(
+        banks_client.process_transaction(transaction).await.unwrap_err().unwrap(),
+        TransactionError::InstructionError(0, InstructionError::Custom(MangoErrorCode::CollateralRatioLimit.into()))
+    );
+
+    let margin_account = get_margin_account(
+        &mut banks_client, &program_id, &old_group.mango_group_pk, margin_account_pk
+    ).await;
+    assert_eq!(margin_account.mango_group, old_group.mango_group_pk);
+}
+
+#[tokio::test]
+async fn test_migrate_margin_account_from_v1_group() {
+    // Test that an account can move out of a group that still has the v1 layout
+    let program_id = Pubkey::new_unique();
+
+    let mut test = ProgramTest::new(
+        "mango",
+        program_id,
+        processor!(process_instruction),
+    );
+
+    // limit to track compute unit increase
+    test.set_bpf_compute_max_units(50_000);
+
+    let new_group = add_mango_group_prodlike(&mut test, program_id);
+    let btc_amount = 3;
+    let usdt_amount = 5000;
+    let old_group = add_mango_group_v1(&mut test, program_id, new_group.mints.clone(), [btc_amount, 0, usdt_amount]);
+
+    let user = Keypair::new();
+    let margin_account_pk = add_margin_account(
+        &mut test, program_id, old_group.mango_group_pk, user.pubkey(), [btc_amount, 0, usdt_amount], [0, 0, 0]
+    );
+
+    let (mut banks_client, payer, recent_blockhash) = test.start().await;
+
+    let mut transaction = Transaction::new_with_payer(
+        &[
+            new_group.init_mango_group(&payer.pubkey()),
+            migrate_margin_account_instruction(&old_group, &new_group, &margin_account_pk, &user.pubkey()),
+        ],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer, &user], recent_blockhash);
+    assert!(banks_client.process_transaction(transaction).await.is_ok());
+
+    let margin_account = get_margin_account(
+        &mut banks_client, &program_id, &new_group.mango_group_pk, margin_account_pk
+    ).await;
+    assert_eq!(margin_account.deposits, [U64F64::from_num(btc_amount), U64F64::from_num(0), U64F64::from_num(usdt_amount)]);
+
+    let mut account = banks_client.get_account(old_group.mango_group_pk).await.unwrap().unwrap();
+    let account_info: AccountInfo = (&old_group.mango_group_pk, &mut account).into();
+    let old = MangoGroupV1::load_checked(&account_info, &program_id).unwrap();
+    assert_eq!(old.total_deposits, [U64F64::from_num(0); NUM_TOKENS]);
+
+    assert_eq!(get_token_balance(&mut banks_client, old_group.vaults[0].pubkey).await, 0);
+    assert_eq!(get_token_balance(&mut banks_client, old_group.vaults[2].pubkey).await, 0);
+    assert_eq!(get_token_balance(&mut banks_client, new_group.vaults[0].pubkey).await, btc_amount);
+    assert_eq!(get_token_balance(&mut banks_client, new_group.vaults[2].pubkey).await, usdt_amount);
+}
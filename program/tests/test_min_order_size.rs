@@ -0,0 +1,30 @@
+// Tests related to the min order notional enforced by PlaceOrder and PlaceAndSettle
+#![cfg(feature="test-bpf")]
+
+mod helpers;
+
+use helpers::*;
+
+use mango::{
+    error::{MangoError, MangoErrorCode},
+    processor::check_min_order_notional,
+};
+
+#[test]
+fn test_min_order_notional() {
+    // Test that orders below min_order_notional at the oracle price are rejected
+    let mut mango_group = new_mango_group_in_memory([0, 0, 0]);
+    let prices = get_prodlike_prices();
+
+    // off by default
+    assert!(check_min_order_notional(&mango_group, &prices, 0, 1, 1).is_ok());
+
+    // 10 lots of 100 native ETH at 2000 is 2_000_000 native quote
+    mango_group.min_order_notional = 2_000_000;
+    assert!(check_min_order_notional(&mango_group, &prices, 1, 10, 100).is_ok());
+
+    assert!(matches!(
+        check_min_order_notional(&mango_group, &prices, 1, 9, 100),
+        Err(MangoError::MangoErrorCode { mango_error_code: MangoErrorCode::OrderTooSmall, .. })
+    ));
+}
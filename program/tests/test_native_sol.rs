@@ -0,0 +1,171 @@
+// Tests related to depositing native SOL into a MangoGroup with a wrapped SOL vault
+#![cfg(feature="test-bpf")]
+
+mod helpers;
+
+use std::mem::size_of;
+use helpers::*;
+use solana_program::system_program;
+use solana_program_test::*;
+use solana_sdk::{
+    pubkey::Pubkey,
+    signature::{Signer, Keypair},
+    transaction::Transaction,
+    account::Account,
+};
+
+use mango::{
+    entrypoint::process_instruction,
+    instruction::{deposit_native_sol, init_margin_account},
+    state::MarginAccount,
+    utils::get_wrapped_sol_address,
+};
+
+#[tokio::test]
+async fn test_deposit_native_sol_succeeds() {
+    // Test that native SOL is wrapped into the vault and credited to the margin account
+    let program_id = Pubkey::new_unique();
+
+    let mut test = ProgramTest::new(
+        "mango",
+        program_id,
+        processor!(process_instruction),
+    );
+
+    // limit to track compute unit increase
+    test.set_bpf_compute_max_units(50_000);
+
+    let initial_lamports = 10_000_000_000;
+    let deposit_amount = 1_000_000_000;
+
+    // setup mango group with wrapped SOL as the first token
+    let mut mango_group = add_mango_group_prodlike(&mut test, program_id);
+    let sol_mint = add_mint_native(&mut test);
+    let sol_vault = add_native_token_account(&mut test, mango_group.signer_pk, 0);
+    let sol_usdt_dex = add_dex_empty(
+        &mut test, sol_mint.pubkey, mango_group.mints[2].pubkey, mango_group.dex_prog_id
+    );
+    mango_group.mints[0] = sol_mint;
+    mango_group.vaults[0] = sol_vault;
+    mango_group.dexes[0] = sol_usdt_dex;
+
+    // setup user account holding native SOL
+    let user = Keypair::new();
+    test.add_account(user.pubkey(), Account::new(initial_lamports, 0, &system_program::id()));
+
+    // setup marginaccount account
+    let margin_account_pk = Pubkey::new_unique();
+    test.add_account(margin_account_pk, Account::new(u32::MAX as u64, size_of::<MarginAccount>(), &program_id));
+
+    // setup test harness
+    let (mut banks_client, payer, recent_blockhash) = test.start().await;
+
+    let mut transaction = Transaction::new_with_payer(
+        &[
+            mango_group.init_mango_group(&payer.pubkey()),
+            init_margin_account(
+                &program_id,
+                &mango_group.mango_group_pk,
+                &margin_account_pk,
+                &user.pubkey(),
+            ).unwrap(),
+            deposit_native_sol(
+                &program_id,
+                &mango_group.mango_group_pk,
+                &margin_account_pk,
+                &user.pubkey(),
+                &mango_group.vaults[0].pubkey,
+                deposit_amount,
+            ).unwrap(),
+        ],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer, &user], recent_blockhash);
+    assert!(banks_client.process_transaction(transaction).await.is_ok());
+
+    // Test the user paid exactly the deposit, the temporary account rent was refunded
+    let user_account = banks_client.get_account(user.pubkey()).await.unwrap().unwrap();
+    assert_eq!(user_account.lamports, initial_lamports - deposit_amount);
+
+    // Test expected amount is added to the vault
+    let mango_vault_balance = get_token_balance(&mut banks_client, mango_group.vaults[0].pubkey).await;
+    assert_eq!(mango_vault_balance, deposit_amount);
+
+    // Test expected amount is in margin account
+    let margin_account = get_margin_account(
+        &mut banks_client, &program_id, &mango_group.mango_group_pk, margin_account_pk
+    ).await;
+    assert_eq!(margin_account.deposits[0], deposit_amount);
+}
+
+#[tokio::test]
+async fn test_deposit_native_sol_prefunded_wrapped_account() {
+    // Test that lamports sent to the wrapped SOL address ahead of time don't block the deposit,
+    // and go back to the user when the temporary account is closed
+    let program_id = Pubkey::new_unique();
+
+    let mut test = ProgramTest::new(
+        "mango",
+        program_id,
+        processor!(process_instruction),
+    );
+
+    // limit to track compute unit increase
+    test.set_bpf_compute_max_units(50_000);
+
+    let initial_lamports = 10_000_000_000;
+    let deposit_amount = 1_000_000_000;
+    let prefunded_lamports = 1_000;
+
+    let mut mango_group = add_mango_group_prodlike(&mut test, program_id);
+    let sol_mint = add_mint_native(&mut test);
+    let sol_vault = add_native_token_account(&mut test, mango_group.signer_pk, 0);
+    let sol_usdt_dex = add_dex_empty(
+        &mut test, sol_mint.pubkey, mango_group.mints[2].pubkey, mango_group.dex_prog_id
+    );
+    mango_group.mints[0] = sol_mint;
+    mango_group.vaults[0] = sol_vault;
+    mango_group.dexes[0] = sol_usdt_dex;
+
+    let user = Keypair::new();
+    test.add_account(user.pubkey(), Account::new(initial_lamports, 0, &system_program::id()));
+
+    let margin_account_pk = Pubkey::new_unique();
+    test.add_account(margin_account_pk, Account::new(u32::MAX as u64, size_of::<MarginAccount>(), &program_id));
+
+    // someone else sends lamports to the wrapped SOL address first
+    let (wrapped_pk, _) = get_wrapped_sol_address(&program_id, &margin_account_pk);
+    test.add_account(wrapped_pk, Account::new(prefunded_lamports, 0, &system_program::id()));
+
+    let (mut banks_client, payer, recent_blockhash) = test.start().await;
+
+    let mut transaction = Transaction::new_with_payer(
+        &[
+            mango_group.init_mango_group(&payer.pubkey()),
+            init_margin_account(
+                &program_id,
+                &mango_group.mango_group_pk,
+                &margin_account_pk,
+                &user.pubkey(),
+            ).unwrap(),
+            deposit_native_sol(
+                &program_id,
+                &mango_group.mango_group_pk,
+                &margin_account_pk,
+                &user.pubkey(),
+                &mango_group.vaults[0].pubkey,
+                deposit_amount,
+            ).unwrap(),
+        ],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer, &user], recent_blockhash);
+    assert!(banks_client.process_transaction(transaction).await.is_ok());
+
+    let user_account = banks_client.get_account(user.pubkey()).await.unwrap().unwrap();
+    assert_eq!(user_account.lamports, initial_lamports - deposit_amount + prefunded_lamports);
+
+    let mango_vault_balance = get_token_balance(&mut banks_client, mango_group.vaults[0].pubkey).await;
+    assert_eq!(mango_vault_balance, deposit_amount);
+    assert!(banks_client.get_account(wrapped_pk).await.unwrap().is_none());
+}
@@ -0,0 +1,107 @@
+// Tests related to the open interest limit of a spot market
+#![cfg(feature="test-bpf")]
+
+mod helpers;
+
+use bytemuck::Zeroable;
+use fixed::types::U64F64;
+use helpers::*;
+use serum_dex::matching::Side;
+use solana_program_test::*;
+use solana_sdk::{
+    pubkey::Pubkey,
+    signature::Signer,
+    transaction::Transaction,
+};
+
+use mango::{
+    entrypoint::process_instruction,
+    error::{MangoError, MangoErrorCode},
+    instruction::change_oi_limit,
+    processor::check_open_interest,
+    state::MarginAccount,
+};
+
+#[test]
+fn test_order_over_oi_limit_rejected() {
+    // Test that an order that would take BTC open interest over the limit is rejected on either
+    // side, unless it reduces a position
+    let mut mango_group = new_mango_group_in_memory([0, 0, 0]);
+    let prices = get_prodlike_prices();
+    mango_group.total_borrows[0] = U64F64::from_num(1);
+    mango_group.oi_limits[0] = 3 * PRICE_BTC;
+    let margin_account = MarginAccount::zeroed();
+
+    // 1 BTC shorted plus 2 more in the order is just at the limit
+    assert!(check_open_interest(&mango_group, &margin_account, &prices, 0, Side::Bid, 2, 1).is_ok());
+    for side in [Side::Bid, Side::Ask].iter() {
+        let r = check_open_interest(&mango_group, &margin_account, &prices, 0, *side, 3, 1);
+        assert!(matches!(r, Err(MangoError::MangoErrorCode { mango_error_code: MangoErrorCode::OpenInterestLimitExceeded, .. })));
+    }
+
+    // buying back borrowed BTC closes a short
+    let mut short = MarginAccount::zeroed();
+    short.borrows[0] = U64F64::from_num(1);
+    assert!(check_open_interest(&mango_group, &short, &prices, 0, Side::Bid, 3, 1).is_ok());
+    let r = check_open_interest(&mango_group, &short, &prices, 0, Side::Bid, 4, 1);
+    assert!(matches!(r, Err(MangoError::MangoErrorCode { mango_error_code: MangoErrorCode::OpenInterestLimitExceeded, .. })));
+
+    // other markets and a limit of 0 are not capped
+    assert!(check_open_interest(&mango_group, &margin_account, &prices, 1, Side::Bid, 1000, 1).is_ok());
+    mango_group.oi_limits[0] = 0;
+    assert!(check_open_interest(&mango_group, &margin_account, &prices, 0, Side::Bid, 3, 1).is_ok());
+}
+
+#[test]
+fn test_small_deposit_does_not_exempt_oversized_ask() {
+    // Test that only the part of an ask covered by the base deposit is exempt from the limit
+    let mut mango_group = new_mango_group_in_memory([0, 0, 0]);
+    let prices = get_prodlike_prices();
+    mango_group.total_borrows[0] = U64F64::from_num(1);
+    mango_group.oi_limits[0] = 3 * PRICE_BTC;
+    let mut margin_account = MarginAccount::zeroed();
+    margin_account.deposits[0] = U64F64::from_num(1);
+
+    // selling the 1 BTC deposit plus 2 more is just at the limit
+    assert!(check_open_interest(&mango_group, &margin_account, &prices, 0, Side::Ask, 3, 1).is_ok());
+
+    // a dust deposit doesn't let a large short through
+    let r = check_open_interest(&mango_group, &margin_account, &prices, 0, Side::Ask, 1000, 1);
+    assert!(matches!(r, Err(MangoError::MangoErrorCode { mango_error_code: MangoErrorCode::OpenInterestLimitExceeded, .. })));
+
+    // an ask within the deposit is exempt even past the limit
+    mango_group.total_borrows[0] = U64F64::from_num(10);
+    assert!(check_open_interest(&mango_group, &margin_account, &prices, 0, Side::Ask, 1, 1).is_ok());
+}
+
+#[tokio::test]
+async fn test_change_oi_limit() {
+    // Test that the admin can set the open interest limit of a market
+    let program_id = Pubkey::new_unique();
+
+    let mut test = ProgramTest::new(
+        "mango",
+        program_id,
+        processor!(process_instruction),
+    );
+
+    // limit to track compute unit increase
+    test.set_bpf_compute_max_units(50_000);
+
+    let mango_group = add_mango_group_prodlike(&mut test, program_id);
+
+    let (mut banks_client, payer, recent_blockhash) = test.start().await;
+
+    let mut transaction = Transaction::new_with_payer(
+        &[
+            mango_group.init_mango_group(&payer.pubkey()),
+            change_oi_limit(&program_id, &mango_group.mango_group_pk, &payer.pubkey(), 1, 1_000_000).unwrap(),
+        ],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer], recent_blockhash);
+    assert!(banks_client.process_transaction(transaction).await.is_ok());
+
+    let group = get_mango_group(&mut banks_client, &program_id, mango_group.mango_group_pk).await;
+    assert_eq!(group.oi_limits, [0, 1_000_000]);
+}
@@ -0,0 +1,98 @@
+// Tests related to the oracle decimals cached in the MangoGroup
+#![cfg(feature="test-bpf")]
+
+mod helpers;
+
+use helpers::*;
+use solana_program::account_info::AccountInfo;
+use solana_program_test::*;
+use solana_sdk::{
+    account::Account,
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+    transaction::Transaction,
+};
+
+use mango::{
+    entrypoint::process_instruction,
+    error::{MangoError, MangoErrorCode},
+    instruction::refresh_oracle_decimals,
+    processor::check_oracle_decimals,
+};
+
+#[test]
+fn test_check_oracle_decimals_mismatch() {
+    // Test that an oracle whose decimals changed after it was added is rejected
+    let mut mango_group = new_mango_group_in_memory([0, 0, 0]);
+    let owner_pk = Pubkey::new_unique();
+    let btc_pk = Pubkey::new_unique();
+    let eth_pk = Pubkey::new_unique();
+    mango_group.oracles = [btc_pk, eth_pk];
+    mango_group.oracle_decimals = [6, 6];
+
+    let mut btc_acc = aggregator_account(&btc_pk, "BTC:USDT", 6, 50000, &owner_pk);
+    let mut eth_acc = aggregator_account(&eth_pk, "ETH:USDT", 6, 2000, &owner_pk);
+    let oracle_accs: [AccountInfo; 2] = [(&btc_pk, &mut btc_acc).into(), (&eth_pk, &mut eth_acc).into()];
+    assert!(check_oracle_decimals(&mango_group, &oracle_accs).is_ok());
+
+    let mut btc_acc = aggregator_account(&btc_pk, "BTC:USDT", 6, 50000, &owner_pk);
+    let mut eth_acc = aggregator_account(&eth_pk, "ETH:USDT", 8, 200000, &owner_pk);
+    let oracle_accs: [AccountInfo; 2] = [(&btc_pk, &mut btc_acc).into(), (&eth_pk, &mut eth_acc).into()];
+    let r = check_oracle_decimals(&mango_group, &oracle_accs);
+    assert!(matches!(r, Err(MangoError::MangoErrorCode { mango_error_code: MangoErrorCode::OracleDecimalsMismatch, .. })));
+
+    // markets that are not added yet are skipped
+    mango_group.oracles[1] = Pubkey::default();
+    let mut btc_acc = aggregator_account(&btc_pk, "BTC:USDT", 6, 50000, &owner_pk);
+    let mut empty_acc = Account::default();
+    let default_pk = Pubkey::default();
+    let oracle_accs: [AccountInfo; 2] = [(&btc_pk, &mut btc_acc).into(), (&default_pk, &mut empty_acc).into()];
+    assert!(check_oracle_decimals(&mango_group, &oracle_accs).is_ok());
+}
+
+#[tokio::test]
+async fn test_refresh_oracle_decimals_admin_only() {
+    // Test that only the admin can refresh and an unchanged aggregator keeps its decimals
+    let program_id = Pubkey::new_unique();
+
+    let mut test = ProgramTest::new(
+        "mango",
+        program_id,
+        processor!(process_instruction),
+    );
+
+    // limit to track compute unit increase
+    test.set_bpf_compute_max_units(50_000);
+
+    let mango_group = add_mango_group_prodlike(&mut test, program_id);
+    let oracle_pks = mango_group.oracles.iter().map(|m| m.pubkey).collect::<Vec<Pubkey>>();
+    let user = Keypair::new();
+
+    let (mut banks_client, payer, recent_blockhash) = test.start().await;
+
+    let mut transaction = Transaction::new_with_payer(
+        &[
+            mango_group.init_mango_group(&payer.pubkey()),
+            refresh_oracle_decimals(
+                &program_id, &mango_group.mango_group_pk, &payer.pubkey(), oracle_pks.as_slice()
+            ).unwrap(),
+        ],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer], recent_blockhash);
+    assert!(banks_client.process_transaction(transaction).await.is_ok());
+
+    let group = get_mango_group(&mut banks_client, &program_id, mango_group.mango_group_pk).await;
+    assert_eq!(group.oracle_decimals, [6, 6]);
+
+    let mut transaction = Transaction::new_with_payer(
+        &[
+            refresh_oracle_decimals(
+                &program_id, &mango_group.mango_group_pk, &user.pubkey(), oracle_pks.as_slice()
+            ).unwrap(),
+        ],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer, &user], recent_blockhash);
+    assert!(banks_client.process_transaction(transaction).await.is_err());
+}
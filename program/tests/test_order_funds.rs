@@ -0,0 +1,53 @@
+// Tests related to debiting a MarginAccount for the funds a new order takes from the vault
+#![cfg(feature="test-bpf")]
+
+mod helpers;
+
+use std::mem::size_of;
+
+use bytemuck::Zeroable;
+use fixed::types::U64F64;
+use helpers::*;
+use serum_dex::state::OpenOrders;
+use solana_program::account_info::AccountInfo;
+use solana_sdk::{account::Account, pubkey::Pubkey};
+
+use mango::{
+    processor::debit_order_funds,
+    state::{MarginAccount, QUOTE_INDEX, ZERO_U64F64},
+};
+
+#[test]
+fn test_debit_order_funds_resting_order() {
+    // Test that a bid resting far from the market moves deposits into open orders without changing
+    // the value of the account
+    let mut mango_group = new_mango_group_in_memory([0, 0, 0]);
+    let prices = get_prodlike_prices();
+    let pk = Pubkey::default();
+    let mut margin_account = MarginAccount::zeroed();
+    margin_account.deposits[QUOTE_INDEX] = U64F64::from_num(5000);
+    mango_group.total_deposits[QUOTE_INDEX] = U64F64::from_num(5000);
+
+    let mut accounts = [Account::default(), Account::default()];
+    let before = margin_account.get_assets_val(&mango_group, &prices, &empty_open_orders(&pk, &mut accounts), 0).unwrap();
+
+    // 1000 quote locked in the open orders account, nothing filled
+    debit_order_funds(&mut mango_group, &mut margin_account, QUOTE_INDEX, 10_000, 9000, false, 0).unwrap();
+    assert_eq!(margin_account.deposits[QUOTE_INDEX], U64F64::from_num(4000));
+    assert_eq!(margin_account.borrows, [ZERO_U64F64; 3]);
+
+    let owner_pk = Pubkey::new_unique();
+    let open_orders_pk = Pubkey::new_unique();
+    let mut btc_usdt = open_orders_account(&owner_pk, u128::MAX << 1);
+    let open_orders: &mut OpenOrders = bytemuck::from_bytes_mut(&mut btc_usdt.data[5..5 + size_of::<OpenOrders>()]);
+    open_orders.native_pc_total = 1000;
+    let mut eth_usdt = Account::default();
+    let open_orders_accs: [AccountInfo; 2] = [(&open_orders_pk, &mut btc_usdt).into(), (&pk, &mut eth_usdt).into()];
+    let after = margin_account.get_assets_val(&mango_group, &prices, &open_orders_accs, 0).unwrap();
+    assert_eq!(before, after);
+
+    // an order paid for by free funds already on the open orders account takes nothing, even in
+    // reduce only mode
+    debit_order_funds(&mut mango_group, &mut margin_account, QUOTE_INDEX, 9000, 9000, true, 0).unwrap();
+    assert_eq!(margin_account.deposits[QUOTE_INDEX], U64F64::from_num(4000));
+}
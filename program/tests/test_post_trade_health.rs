@@ -0,0 +1,70 @@
+// Tests related to the health check at the end of PlaceAndSettle
+#![cfg(feature="test-bpf")]
+
+mod helpers;
+
+use bytemuck::Zeroable;
+use fixed::types::U64F64;
+use helpers::*;
+use solana_program::account_info::AccountInfo;
+use solana_sdk::{
+    account::Account,
+    pubkey::Pubkey,
+};
+
+use mango::{
+    error::{MangoError, MangoErrorCode},
+    processor::check_post_trade_health,
+    state::MarginAccount,
+};
+
+#[test]
+fn test_reduce_only_skips_health_scan() {
+    // Test that a reduce only order that didn't borrow never reads the open orders accounts
+    let mango_group = new_mango_group_in_memory([0, 0, 0]);
+    let prices = get_prodlike_prices();
+
+    // 1 BTC against 45000 USDT of liabs -> coll ratio of 1.11, below init_coll_ratio
+    let mut margin_account = MarginAccount::zeroed();
+    margin_account.deposits[0] = U64F64::from_num(1);
+    margin_account.borrows[2] = U64F64::from_num(45000);
+
+    // open orders accounts that fail to load, so any scan errors
+    let pk = Pubkey::new_unique();
+    let mut accounts = [Account::default(), Account::default()];
+    let [btc_usdt, eth_usdt] = &mut accounts;
+    let open_orders_accs: [AccountInfo; 2] = [(&pk, btc_usdt).into(), (&pk, eth_usdt).into()];
+
+    let r = check_post_trade_health(&mango_group, &margin_account, &prices, &open_orders_accs, true, false, false, 0);
+    assert!(matches!(r, Ok(None)));
+
+    // the scan still runs when the ratio is needed, e.g. for the fill log
+    let r = check_post_trade_health(&mango_group, &margin_account, &prices, &open_orders_accs, true, false, true, 0);
+    assert!(r.is_err());
+    let r = check_post_trade_health(&mango_group, &margin_account, &prices, &open_orders_accs, false, false, false, 0);
+    assert!(r.is_err());
+}
+
+#[test]
+fn test_post_trade_health_below_init() {
+    // Test that orders that aren't reduce only must leave the account at init_coll_ratio
+    let mango_group = new_mango_group_in_memory([0, 0, 0]);
+    let prices = get_prodlike_prices();
+    let pk = Pubkey::default();
+    let mut accounts = [Account::default(), Account::default()];
+    let open_orders_accs = empty_open_orders(&pk, &mut accounts);
+
+    let mut margin_account = MarginAccount::zeroed();
+    margin_account.deposits[0] = U64F64::from_num(1);
+    margin_account.borrows[2] = U64F64::from_num(40000);
+    let r = check_post_trade_health(&mango_group, &margin_account, &prices, &open_orders_accs, false, true, false, 0);
+    assert_eq!(r.unwrap(), Some(U64F64::from_num(1.25)));
+
+    margin_account.borrows[2] = U64F64::from_num(45000);
+    let r = check_post_trade_health(&mango_group, &margin_account, &prices, &open_orders_accs, false, true, false, 0);
+    assert!(matches!(r, Err(MangoError::MangoErrorCode { mango_error_code: MangoErrorCode::CollateralRatioLimit, .. })));
+
+    // reduce only orders are allowed to end below init_coll_ratio
+    let r = check_post_trade_health(&mango_group, &margin_account, &prices, &open_orders_accs, true, false, true, 0);
+    assert!(r.unwrap().is_some());
+}
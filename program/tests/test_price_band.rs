@@ -0,0 +1,139 @@
+// Tests related to the band oracle prices may move in between reads
+#![cfg(feature="test-bpf")]
+
+mod helpers;
+
+use fixed::types::{U32F32, U64F64};
+use helpers::*;
+use solana_program::account_info::AccountInfo;
+use solana_program::clock::Clock;
+use solana_program_test::*;
+use solana_sdk::{
+    pubkey::Pubkey,
+    signature::Signer,
+    transaction::Transaction,
+};
+
+use mango::{
+    entrypoint::process_instruction,
+    error::{MangoError, MangoErrorCode, MangoResult},
+    instruction::change_max_price_move,
+    processor::get_prices,
+    state::{MangoGroup, NUM_TOKENS},
+};
+
+fn read_prices(mango_group: &mut MangoGroup, btc_price: u64, now: u64) -> MangoResult<[U64F64; NUM_TOKENS]> {
+    let owner_pk = Pubkey::new_unique();
+    let [btc_pk, eth_pk] = mango_group.oracles;
+    let mut btc_acc = aggregator_account(&btc_pk, "BTC:USDT", 0, btc_price, &owner_pk);
+    let mut eth_acc = aggregator_account(&eth_pk, "ETH:USDT", 0, PRICE_ETH, &owner_pk);
+    let oracle_accs: [AccountInfo; 2] = [(&btc_pk, &mut btc_acc).into(), (&eth_pk, &mut eth_acc).into()];
+    get_prices(mango_group, &oracle_accs, &[], &Clock { unix_timestamp: now as i64, ..Clock::default() })
+}
+
+#[test]
+fn test_price_move_band() {
+    // Test that a price within max_price_move_bps of the last one is accepted and recorded, while a
+    // jump past it is rejected and leaves last_price alone
+    let mut mango_group = new_mango_group_in_memory([0, 0, 0]);
+    mango_group.mint_decimals = [0, 0, 0];
+    mango_group.oracle_decimals = [0, 0];
+    mango_group.oracles = [Pubkey::new_unique(), Pubkey::new_unique()];
+    mango_group.max_price_move_bps = 1250;
+    mango_group.price_band_timeout = 60;
+
+    // the first read has nothing to compare against
+    assert!(read_prices(&mut mango_group, PRICE_BTC, 1000).is_ok());
+    assert_eq!(mango_group.last_price, [U32F32::from_num(PRICE_BTC), U32F32::from_num(PRICE_ETH)]);
+    assert_eq!(mango_group.last_price_ts, [1000, 1000]);
+
+    // 12.5% up is just in the band
+    let prices = read_prices(&mut mango_group, PRICE_BTC * 9 / 8, 1010).unwrap();
+    assert_eq!(prices[0], U64F64::from_num(PRICE_BTC * 9 / 8));
+    assert_eq!(mango_group.last_price[0], U32F32::from_num(PRICE_BTC * 9 / 8));
+
+    // a third down from there is not
+    let r = read_prices(&mut mango_group, PRICE_BTC * 3 / 4, 1020);
+    assert!(matches!(r, Err(MangoError::MangoErrorCode { mango_error_code: MangoErrorCode::PriceMoveTooLarge, .. })));
+    assert_eq!(mango_group.last_price[0], U32F32::from_num(PRICE_BTC * 9 / 8));
+    assert_eq!(mango_group.last_price_ts[0], 1010);
+
+    // without a band any move goes
+    mango_group.max_price_move_bps = 0;
+    assert!(read_prices(&mut mango_group, PRICE_BTC * 3 / 4, 1020).is_ok());
+    assert_eq!(mango_group.last_price[0], U32F32::from_num(PRICE_BTC * 3 / 4));
+}
+
+#[test]
+fn test_price_move_band_times_out() {
+    // Test that a price staying out of the band is accepted once the market went
+    // price_band_timeout seconds without an accepted read, so a real crash can't block the market
+    let mut mango_group = new_mango_group_in_memory([0, 0, 0]);
+    mango_group.mint_decimals = [0, 0, 0];
+    mango_group.oracle_decimals = [0, 0];
+    mango_group.oracles = [Pubkey::new_unique(), Pubkey::new_unique()];
+    mango_group.max_price_move_bps = 1250;
+    mango_group.price_band_timeout = 60;
+    assert!(read_prices(&mut mango_group, PRICE_BTC, 1000).is_ok());
+
+    let r = read_prices(&mut mango_group, PRICE_BTC / 2, 1059);
+    assert!(matches!(r, Err(MangoError::MangoErrorCode { mango_error_code: MangoErrorCode::PriceMoveTooLarge, .. })));
+
+    let prices = read_prices(&mut mango_group, PRICE_BTC / 2, 1060).unwrap();
+    assert_eq!(prices[0], U64F64::from_num(PRICE_BTC / 2));
+    assert_eq!(mango_group.last_price[0], U32F32::from_num(PRICE_BTC / 2));
+    assert_eq!(mango_group.last_price_ts[0], 1060);
+}
+
+#[test]
+fn test_price_move_band_skips_prices_past_last_price_range() {
+    // Test that a price too large for last_price is recorded as 0, which leaves the next read unbanded
+    let mut mango_group = new_mango_group_in_memory([0, 0, 0]);
+    mango_group.mint_decimals = [0, 0, 0];
+    mango_group.oracle_decimals = [0, 0];
+    mango_group.oracles = [Pubkey::new_unique(), Pubkey::new_unique()];
+    mango_group.max_price_move_bps = 1250;
+    mango_group.price_band_timeout = 60;
+
+    assert!(read_prices(&mut mango_group, 1 << 33, 1000).is_ok());
+    assert_eq!(mango_group.last_price[0], U32F32::from_num(0));
+
+    assert!(read_prices(&mut mango_group, PRICE_BTC, 1010).is_ok());
+    assert_eq!(mango_group.last_price[0], U32F32::from_num(PRICE_BTC));
+}
+
+#[tokio::test]
+async fn test_change_max_price_move() {
+    // Test that the admin can set max_price_move_bps
+    let program_id = Pubkey::new_unique();
+
+    let mut test = ProgramTest::new(
+        "mango",
+        program_id,
+        processor!(process_instruction),
+    );
+
+    // limit to track compute unit increase
+    test.set_bpf_compute_max_units(50_000);
+
+    let mango_group = add_mango_group_prodlike(&mut test, program_id);
+
+    let (mut banks_client, payer, recent_blockhash) = test.start().await;
+
+    let mut transaction = Transaction::new_with_payer(
+        &[
+            mango_group.init_mango_group(&payer.pubkey()),
+            change_max_price_move(
+                &program_id, &mango_group.mango_group_pk, &payer.pubkey(), 2500, 300
+            ).unwrap(),
+        ],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer], recent_blockhash);
+    assert!(banks_client.process_transaction(transaction).await.is_ok());
+
+    let group = get_mango_group(&mut banks_client, &program_id, mango_group.mango_group_pk).await;
+    assert_eq!(group.max_price_move_bps, 2500);
+    assert_eq!(group.price_band_timeout, 300);
+    assert_eq!(group.last_price, [U32F32::from_num(0); 2]);
+}
@@ -0,0 +1,149 @@
+// Tests related to reading Pyth price accounts as oracles
+#![cfg(feature="test-bpf")]
+
+mod helpers;
+
+use helpers::*;
+use solana_program::account_info::AccountInfo;
+use solana_program::clock::Clock;
+use solana_program_test::*;
+use solana_sdk::{
+    pubkey::Pubkey,
+    signature::Signer,
+    transaction::Transaction,
+};
+
+use mango::{
+    entrypoint::process_instruction,
+    error::{MangoError, MangoErrorCode, SourceFileId},
+    instruction::set_oracle,
+    oracle::{get_pyth_value, load_pyth_price, OracleType, PYTH_MAX_SLOT_AGE, PythPrice},
+    processor::get_prices,
+};
+
+#[test]
+fn test_pyth_price_matches_flux() {
+    // Test that a Pyth feed gives the same prices as a flux aggregator for the same ETH price
+    let mut mango_group = new_mango_group_in_memory([0, 0, 0]);
+    mango_group.mint_decimals = [6, 6, 6];
+    mango_group.oracle_decimals = [6, 6];
+    let owner_pk = Pubkey::new_unique();
+    let btc_pk = Pubkey::new_unique();
+    let eth_pk = Pubkey::new_unique();
+    let eth_pyth_pk = Pubkey::new_unique();
+
+    mango_group.oracles = [btc_pk, eth_pk];
+    let mut btc_acc = aggregator_account(&btc_pk, "BTC:USDT", 6, PRICE_BTC * 1_000_000, &owner_pk);
+    let mut eth_acc = aggregator_account(&eth_pk, "ETH:USDT", 6, PRICE_ETH * 1_000_000, &owner_pk);
+    let oracle_accs: [AccountInfo; 2] = [(&btc_pk, &mut btc_acc).into(), (&eth_pk, &mut eth_acc).into()];
+    let flux_prices = get_prices(&mut mango_group, &oracle_accs, &[], &Clock::default()).unwrap();
+
+    // Pyth quotes ETH with 8 decimals, which are rescaled to the 6 of USDT
+    mango_group.oracles = [btc_pk, eth_pyth_pk];
+    mango_group.oracle_types = [OracleType::Flux as u8, OracleType::Pyth as u8];
+    let mut btc_acc = aggregator_account(&btc_pk, "BTC:USDT", 6, PRICE_BTC * 1_000_000, &owner_pk);
+    let mut eth_pyth_acc = pyth_price_account(PRICE_ETH as i64 * 100_000_000, -8, 100_000_000, &owner_pk);
+    let oracle_accs: [AccountInfo; 2] = [(&btc_pk, &mut btc_acc).into(), (&eth_pyth_pk, &mut eth_pyth_acc).into()];
+    let pyth_prices = get_prices(&mut mango_group, &oracle_accs, &[], &Clock::default()).unwrap();
+    assert_eq!(pyth_prices, flux_prices);
+}
+
+#[test]
+fn test_pyth_price_rejected() {
+    // Test that wide confidence intervals, halted feeds and other accounts are rejected
+    let owner_pk = Pubkey::new_unique();
+    let pk = Pubkey::new_unique();
+    let mut account = pyth_price_account(2000_00000000, -8, 39_00000000, &owner_pk);
+    let acc: AccountInfo = (&pk, &mut account).into();
+    let pyth_price = load_pyth_price(&acc).unwrap();
+    assert_eq!(pyth_price, PythPrice { price: 2000_00000000, conf: 39_00000000, expo: -8, status: 1, pub_slot: 0 });
+    assert!(get_pyth_value(&pyth_price, 6, 0).is_ok());
+
+    let wide = PythPrice { conf: 41_00000000, ..pyth_price };
+    let r = get_pyth_value(&wide, 6, 0);
+    assert!(matches!(r, Err(MangoError::MangoErrorCode {
+        mango_error_code: MangoErrorCode::OracleConfidenceTooWide,
+        source_file_id: SourceFileId::Oracle,
+        ..
+    })));
+
+    let halted = PythPrice { status: 0, ..pyth_price };
+    let r = get_pyth_value(&halted, 6, 0);
+    assert!(matches!(r, Err(MangoError::MangoErrorCode { mango_error_code: MangoErrorCode::InvalidOracle, .. })));
+
+    let negative = PythPrice { price: -1, conf: 0, ..pyth_price };
+    assert!(get_pyth_value(&negative, 6, 0).is_err());
+
+    // a flux aggregator is not a Pyth price account
+    let mut account = aggregator_account(&pk, "ETH:USDT", 6, 2000, &owner_pk);
+    let acc: AccountInfo = (&pk, &mut account).into();
+    let r = load_pyth_price(&acc);
+    assert!(matches!(r, Err(MangoError::MangoErrorCode { mango_error_code: MangoErrorCode::InvalidOracle, .. })));
+}
+
+#[test]
+fn test_pyth_price_stale() {
+    // Test that a price published more than PYTH_MAX_SLOT_AGE slots ago is rejected, both on its
+    // own and when get_prices reads the feed
+    let owner_pk = Pubkey::new_unique();
+    let pk = Pubkey::new_unique();
+    let mut account = pyth_price_account(2000_00000000, -8, 0, &owner_pk);
+    account.data[232..240].copy_from_slice(&100u64.to_le_bytes());
+    let acc: AccountInfo = (&pk, &mut account).into();
+    let pyth_price = load_pyth_price(&acc).unwrap();
+    assert_eq!(pyth_price.pub_slot, 100);
+
+    assert!(get_pyth_value(&pyth_price, 6, 100 + PYTH_MAX_SLOT_AGE).is_ok());
+    let r = get_pyth_value(&pyth_price, 6, 100 + PYTH_MAX_SLOT_AGE + 1);
+    assert!(matches!(r, Err(MangoError::MangoErrorCode { mango_error_code: MangoErrorCode::OracleStale, .. })));
+
+    let mut mango_group = new_mango_group_in_memory([0, 0, 0]);
+    mango_group.mint_decimals = [6, 6, 6];
+    mango_group.oracle_decimals = [6, 6];
+    let btc_pk = Pubkey::new_unique();
+    mango_group.oracles = [btc_pk, pk];
+    mango_group.oracle_types = [OracleType::Flux as u8, OracleType::Pyth as u8];
+    let mut btc_acc = aggregator_account(&btc_pk, "BTC:USDT", 6, PRICE_BTC * 1_000_000, &owner_pk);
+    let oracle_accs: [AccountInfo; 2] = [(&btc_pk, &mut btc_acc).into(), acc];
+    let clock = Clock { slot: 100 + PYTH_MAX_SLOT_AGE + 1, ..Clock::default() };
+    let r = get_prices(&mut mango_group, &oracle_accs, &[], &clock);
+    assert!(matches!(r, Err(MangoError::MangoErrorCode { mango_error_code: MangoErrorCode::OracleStale, .. })));
+}
+
+#[tokio::test]
+async fn test_set_pyth_oracle() {
+    // Test that the admin can switch a market to a Pyth oracle
+    let program_id = Pubkey::new_unique();
+
+    let mut test = ProgramTest::new(
+        "mango",
+        program_id,
+        processor!(process_instruction),
+    );
+
+    // limit to track compute unit increase
+    test.set_bpf_compute_max_units(50_000);
+
+    let mango_group = add_mango_group_prodlike(&mut test, program_id);
+    let pyth_pk = Pubkey::new_unique();
+    test.add_account(pyth_pk, pyth_price_account(PRICE_ETH as i64 * 100_000_000, -8, 0, &Pubkey::new_unique()));
+
+    let (mut banks_client, payer, recent_blockhash) = test.start().await;
+
+    let mut transaction = Transaction::new_with_payer(
+        &[
+            mango_group.init_mango_group(&payer.pubkey()),
+            set_oracle(
+                &program_id, &mango_group.mango_group_pk, &payer.pubkey(), &pyth_pk, 1, OracleType::Pyth as u8
+            ).unwrap(),
+        ],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer], recent_blockhash);
+    assert!(banks_client.process_transaction(transaction).await.is_ok());
+
+    let group = get_mango_group(&mut banks_client, &program_id, mango_group.mango_group_pk).await;
+    assert_eq!(group.oracles[1], pyth_pk);
+    assert_eq!(group.oracle_types, [OracleType::Flux as u8, OracleType::Pyth as u8]);
+    assert_eq!(group.oracle_decimals, [6, 6]);
+}
@@ -4,20 +4,27 @@
 mod helpers;
 
 use std::mem::size_of;
+use bytemuck::Zeroable;
+use fixed::types::U64F64;
 use helpers::*;
 use solana_program::account_info::AccountInfo;
 use solana_program_test::*;
 use solana_sdk::{
+    instruction::InstructionError,
     pubkey::Pubkey,
     signature::{Signer, Keypair},
-    transaction::Transaction,
+    transaction::{Transaction, TransactionError},
     account::Account,
 };
 use mango::{
     entrypoint::process_instruction,
+    error::MangoErrorCode,
     instruction::{deposit, borrow, settle_borrow, init_margin_account},
+    processor::{debit_order_funds, get_settle_quantity_for_ratio, settle_all_borrows},
     state::MarginAccount,
     state::MangoGroup,
+    state::ONE_U64F64,
+    state::NUM_TOKENS,
 };
 
 #[tokio::test]
@@ -188,3 +195,143 @@ async fn test_settle_borrow_succeeds() {
         assert_eq!(mango_group.total_borrows[borrow_token_index], 0);
     }
 }
+
+#[test]
+fn test_settle_quantity_for_ratio() {
+    // Test that settling the computed quantity lands the coll ratio within one native unit of the target
+    let prices = get_prodlike_prices();
+    let pk = Pubkey::default();
+    let mut accounts = [Account::default(), Account::default()];
+    let open_orders_accs = empty_open_orders(&pk, &mut accounts);
+
+    // 1 BTC and 70000 USDT against 40000 USDT borrowed -> coll ratio of 3
+    let mut mango_group = new_mango_group_in_memory([0, 0, 0]);
+    let mut margin_account = MarginAccount::zeroed();
+    margin_account.deposits[0] = U64F64::from_num(1);
+    margin_account.deposits[2] = U64F64::from_num(70000);
+    margin_account.borrows[2] = U64F64::from_num(40000);
+    mango_group.total_deposits = margin_account.deposits;
+    mango_group.total_borrows = margin_account.borrows;
+
+    let target = U64F64::from_num(4);
+    let quantity = get_settle_quantity_for_ratio(
+        &mango_group, &margin_account, &prices, &open_orders_accs, 2, target, 0
+    ).unwrap();
+    assert_eq!(quantity, 13334);
+
+    margin_account.deposits[2] -= U64F64::from_num(quantity);
+    margin_account.borrows[2] -= U64F64::from_num(quantity);
+    let ratio = margin_account.get_collateral_ratio(&mango_group, &prices, &open_orders_accs, 0).unwrap();
+    assert!(ratio >= target);
+
+    // One native unit less would have fallen short
+    margin_account.deposits[2] += ONE_U64F64;
+    margin_account.borrows[2] += ONE_U64F64;
+    let ratio = margin_account.get_collateral_ratio(&mango_group, &prices, &open_orders_accs, 0).unwrap();
+    assert!(ratio < target);
+
+    // Already above the target, nothing to settle
+    let quantity = get_settle_quantity_for_ratio(
+        &mango_group, &margin_account, &prices, &open_orders_accs, 2, U64F64::from_num(2), 0
+    ).unwrap();
+    assert_eq!(quantity, 0);
+}
+
+#[tokio::test]
+async fn test_settle_borrow_fails_token_index_out_of_range() {
+    // Test that a token_index past the last token fails with an error instead of panicking
+    let program_id = Pubkey::new_unique();
+
+    let mut test = ProgramTest::new(
+        "mango",
+        program_id,
+        processor!(process_instruction),
+    );
+
+    // limit to track compute unit increase
+    test.set_bpf_compute_max_units(50_000);
+
+    let mango_group = add_mango_group_prodlike(&mut test, program_id);
+    let user = Keypair::new();
+    test.add_account(user.pubkey(), Account::new(u32::MAX as u64, 0, &user.pubkey()));
+    let margin_account_pk = add_margin_account(
+        &mut test, program_id, mango_group.mango_group_pk, user.pubkey(), [1, 0, 0], [0, 0, 0]
+    );
+
+    let (mut banks_client, payer, recent_blockhash) = test.start().await;
+
+    let mut transaction = Transaction::new_with_payer(
+        &[
+            mango_group.init_mango_group(&payer.pubkey()),
+            settle_borrow(
+                &program_id,
+                &mango_group.mango_group_pk,
+                &margin_account_pk,
+                &user.pubkey(),
+                NUM_TOKENS,
+                1,
+            ).unwrap(),
+        ],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer, &user], recent_blockhash);
+    assert_eq!(
+        banks_client.process_transaction(transaction).await.unwrap_err().unwrap(),
+        TransactionError::InstructionError(1, InstructionError::Custom(MangoErrorCode::Default.into()))
+    );
+}
+
+#[test]
+fn test_settle_borrow_records_interest_paid() {
+    // Test that settling a borrow after interest accrued adds only the interest to total_interest_paid
+    let mut mango_group = new_mango_group_in_memory([0, 0, 0]);
+    let mut margin_account = MarginAccount::zeroed();
+
+    // spend 1000 more USDT than deposited, borrowing the rest
+    margin_account.deposits[2] = U64F64::from_num(1000);
+    mango_group.total_deposits[2] = U64F64::from_num(1000);
+    debit_order_funds(&mut mango_group, &mut margin_account, 2, 2000, 0, false, 0).unwrap();
+    assert_eq!(margin_account.borrow_principal[2], 1000);
+
+    // 10% interest accrues, then 2000 USDT comes back as a deposit
+    mango_group.indexes[2].borrow = U64F64::from_num(1.1);
+    margin_account.deposits[2] = U64F64::from_num(2000);
+    mango_group.total_deposits[2] = U64F64::from_num(2000);
+
+    settle_all_borrows(&mut mango_group, &mut margin_account).unwrap();
+    assert_eq!(margin_account.borrows[2], U64F64::from_num(0));
+    assert_eq!(margin_account.total_interest_paid[2], 100);
+    assert_eq!(margin_account.borrow_principal[2], 0);
+    assert_eq!(margin_account.total_interest_paid[0], 0);
+}
+
+#[test]
+fn test_settle_all_borrows_leaves_one_side() {
+    // Test that netting clears the smaller side in full even when the native amounts round,
+    // so no token is left both deposited and borrowed
+    let mut mango_group = new_mango_group_in_memory([0, 0, 0]);
+    mango_group.indexes[0].borrow = U64F64::from_num(1.3);
+    mango_group.indexes[1].deposit = U64F64::from_num(1.3);
+    mango_group.indexes[1].borrow = U64F64::from_num(1.3);
+    let mut margin_account = MarginAccount::zeroed();
+
+    // 1.3 native deposit against a 1.3 native borrow: the deposit rounds down to 1, the borrow up to 2
+    margin_account.deposits[0] = U64F64::from_num(1.3);
+    margin_account.borrows[0] = U64F64::from_num(1);
+    // about 13 native deposit against about 6.5 native borrow
+    margin_account.deposits[1] = U64F64::from_num(10);
+    margin_account.borrows[1] = U64F64::from_num(5);
+    mango_group.total_deposits = margin_account.deposits;
+    mango_group.total_borrows = margin_account.borrows;
+
+    settle_all_borrows(&mut mango_group, &mut margin_account).unwrap();
+    for i in 0..NUM_TOKENS {
+        assert!(margin_account.deposits[i] == 0 || margin_account.borrows[i] == 0);
+    }
+    assert_eq!(margin_account.deposits[0], 0);
+    assert!(margin_account.borrows[0] > 0);
+    assert!(margin_account.deposits[1] > 0);
+    assert_eq!(margin_account.borrows[1], 0);
+    assert_eq!(mango_group.total_deposits, margin_account.deposits);
+    assert_eq!(mango_group.total_borrows, margin_account.borrows);
+}
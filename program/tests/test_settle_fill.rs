@@ -0,0 +1,57 @@
+// Tests related to crediting the settled fill of PlaceAndSettle to a MarginAccount
+#![cfg(feature="test-bpf")]
+
+mod helpers;
+
+use bytemuck::Zeroable;
+use fixed::types::U64F64;
+use helpers::*;
+
+use mango::{
+    processor::credit_settled_fill,
+    state::{MarginAccount, QUOTE_INDEX, ZERO_U64F64},
+};
+
+#[test]
+fn test_credit_settled_fill_maker_rebate() {
+    // Test that a bid whose settle returns more quote than it spent credits both tokens and borrows nothing
+    let mut mango_group = new_mango_group_in_memory([0, 0, 0]);
+    let mut margin_account = MarginAccount::zeroed();
+
+    let borrowed = credit_settled_fill(
+        &mut mango_group, &mut margin_account, 0, QUOTE_INDEX, 10, 12, 1000, 1200, false, 0
+    ).unwrap();
+    assert!(!borrowed);
+    assert_eq!(margin_account.deposits, [U64F64::from_num(2), ZERO_U64F64, U64F64::from_num(200)]);
+    assert_eq!(margin_account.borrows, [ZERO_U64F64; 3]);
+    assert_eq!(mango_group.total_deposits[QUOTE_INDEX], U64F64::from_num(200));
+    assert_eq!(mango_group.total_borrows, [ZERO_U64F64; 3]);
+
+    // same in reduce only mode, where borrowing would fail
+    let borrowed = credit_settled_fill(
+        &mut mango_group, &mut margin_account, 0, QUOTE_INDEX, 12, 13, 1200, 1200, true, 0
+    ).unwrap();
+    assert!(!borrowed);
+    assert_eq!(margin_account.deposits[0], U64F64::from_num(3));
+}
+
+#[test]
+fn test_credit_settled_fill_borrows_shortfall() {
+    // Test that spending more out token than deposited borrows the difference
+    let mut mango_group = new_mango_group_in_memory([0, 0, 0]);
+    let mut margin_account = MarginAccount::zeroed();
+    margin_account.deposits[QUOTE_INDEX] = U64F64::from_num(100);
+    mango_group.total_deposits[QUOTE_INDEX] = U64F64::from_num(1000);
+
+    let borrowed = credit_settled_fill(
+        &mut mango_group, &mut margin_account, 0, QUOTE_INDEX, 0, 1, 1000, 700, false, 0
+    ).unwrap();
+    assert!(borrowed);
+    assert_eq!(margin_account.deposits, [U64F64::from_num(1), ZERO_U64F64, ZERO_U64F64]);
+    assert_eq!(margin_account.borrows[QUOTE_INDEX], U64F64::from_num(200));
+
+    // borrowing is not allowed in reduce only mode
+    assert!(credit_settled_fill(
+        &mut mango_group, &mut margin_account, 0, QUOTE_INDEX, 1, 2, 700, 600, true, 0
+    ).is_err());
+}
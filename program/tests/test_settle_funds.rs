@@ -0,0 +1,96 @@
+// Tests related to settling funds from serum dex open orders into a MarginAccount
+#![cfg(feature="test-bpf")]
+
+mod helpers;
+
+use std::mem::size_of;
+
+use helpers::*;
+use serum_dex::state::OpenOrders;
+use solana_program_test::*;
+use solana_sdk::{
+    account::Account,
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+    transaction::Transaction,
+};
+
+use mango::{
+    entrypoint::process_instruction,
+    instruction::settle_funds_partial,
+};
+
+#[tokio::test]
+async fn test_settle_funds_partial_over_cap_leaves_funds() {
+    // Test that free funds over the caps don't fail the instruction and stay on the open orders
+    // account
+    let program_id = Pubkey::new_unique();
+
+    let mut test = ProgramTest::new(
+        "mango",
+        program_id,
+        processor!(process_instruction),
+    );
+
+    // limit to track compute unit increase
+    test.set_bpf_compute_max_units(50_000);
+
+    let mango_group = add_mango_group_prodlike(&mut test, program_id);
+
+    let base_free = 5;
+    let quote_free = 1000;
+    let open_orders_pk = Pubkey::new_unique();
+    let mut open_orders_acc = open_orders_account(&mango_group.signer_pk, u128::MAX);
+    let open_orders: &mut OpenOrders = bytemuck::from_bytes_mut(
+        &mut open_orders_acc.data[5..5 + size_of::<OpenOrders>()]
+    );
+    open_orders.native_coin_free = base_free;
+    open_orders.native_coin_total = base_free;
+    open_orders.native_pc_free = quote_free;
+    open_orders.native_pc_total = quote_free;
+    test.add_account(open_orders_pk, open_orders_acc);
+
+    let owner = Keypair::new();
+    test.add_account(owner.pubkey(), Account::new(u32::MAX as u64, 0, &owner.pubkey()));
+    let margin_account_pk = add_margin_account_with_open_orders(
+        &mut test, Pubkey::new_unique(), program_id, mango_group.mango_group_pk, owner.pubkey(),
+        [0, 0, 0], [0, 0, 0], [open_orders_pk, Pubkey::default()]
+    );
+
+    let (mut banks_client, payer, recent_blockhash) = test.start().await;
+
+    // base is within its cap but quote isn't, and serum can only settle both in full, so neither
+    // is settled
+    let mut transaction = Transaction::new_with_payer(
+        &[
+            mango_group.init_mango_group(&payer.pubkey()),
+            settle_funds_partial(
+                &program_id,
+                &mango_group.mango_group_pk,
+                &owner.pubkey(),
+                &margin_account_pk,
+                &mango_group.dex_prog_id,
+                &mango_group.dexes[0].pubkey,
+                &open_orders_pk,
+                &mango_group.signer_pk,
+                &Pubkey::new_unique(),
+                &Pubkey::new_unique(),
+                &mango_group.vaults[0].pubkey,
+                &mango_group.vaults[2].pubkey,
+                &Pubkey::new_unique(),
+                base_free,
+                quote_free - 1,
+            ).unwrap(),
+        ],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer, &owner], recent_blockhash);
+    assert!(banks_client.process_transaction(transaction).await.is_ok());
+
+    let open_orders_acc = banks_client.get_account(open_orders_pk).await.unwrap().unwrap();
+    let open_orders: &OpenOrders = bytemuck::from_bytes(
+        &open_orders_acc.data[5..5 + size_of::<OpenOrders>()]
+    );
+    assert_eq!(open_orders.native_coin_free, base_free);
+    assert_eq!(open_orders.native_pc_free, quote_free);
+}
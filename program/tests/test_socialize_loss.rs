@@ -0,0 +1,193 @@
+// Tests related to socializing losses of bankrupt MarginAccounts on lenders
+#![cfg(feature="test-bpf")]
+
+mod helpers;
+
+use bytemuck::Zeroable;
+use fixed::types::U64F64;
+use helpers::*;
+use solana_sdk::{
+    account::Account,
+    pubkey::Pubkey,
+};
+
+use mango::{
+    processor::{settle_all_borrows, socialize_liqee_loss, socialize_loss},
+    state::{MangoGroup, MarginAccount, MAX_SOCIALIZED_LOSS, ONE_U64F64, ZERO_U64F64},
+};
+
+#[test]
+fn test_socialize_loss_no_deposits() {
+    // Test that a loss with no lenders is written off against fees and bad debt without touching
+    // the deposit index
+    let mut mango_group = new_mango_group_in_memory([0, 0, 0]);
+    mango_group.total_borrows[2] = U64F64::from_num(100);
+    mango_group.fees_accrued[2] = U64F64::from_num(30);
+    let mut margin_account = MarginAccount::zeroed();
+    margin_account.borrows[2] = U64F64::from_num(100);
+
+    socialize_loss(&mut mango_group, &mut margin_account, 2, U64F64::from_num(100)).unwrap();
+
+    assert_eq!(margin_account.borrows[2], ZERO_U64F64);
+    assert_eq!(mango_group.total_borrows[2], ZERO_U64F64);
+    assert_eq!(mango_group.indexes[2].deposit, ONE_U64F64);
+    assert_eq!(mango_group.fees_accrued[2], ZERO_U64F64);
+    assert_eq!(mango_group.bad_debt[2], 70);
+}
+
+#[test]
+fn test_socialize_loss_exceeds_deposits() {
+    // Test that a loss larger than all deposits takes MAX_SOCIALIZED_LOSS of the lenders' deposits
+    // and records the rest as bad debt instead of zeroing the index
+    let mut mango_group = new_mango_group_in_memory([0, 0, 0]);
+    mango_group.total_deposits[2] = U64F64::from_num(50);
+    mango_group.total_borrows[2] = U64F64::from_num(100);
+    let mut margin_account = MarginAccount::zeroed();
+    margin_account.borrows[2] = U64F64::from_num(100);
+
+    socialize_loss(&mut mango_group, &mut margin_account, 2, U64F64::from_num(100)).unwrap();
+
+    assert_eq!(margin_account.borrows[2], ZERO_U64F64);
+    assert_eq!(mango_group.indexes[2].deposit, ONE_U64F64 - MAX_SOCIALIZED_LOSS);
+    // 100 - 50 * 0.99 = 50.5, rounded up
+    assert_eq!(mango_group.bad_debt[2], 51);
+}
+
+#[test]
+fn test_socialize_loss_partial() {
+    // Test that lenders lose the fraction of their deposits equal to the loss
+    let mut mango_group = new_mango_group_in_memory([0, 0, 0]);
+    mango_group.total_deposits[2] = U64F64::from_num(400);
+    mango_group.total_borrows[2] = U64F64::from_num(100);
+    let mut margin_account = MarginAccount::zeroed();
+    margin_account.borrows[2] = U64F64::from_num(100);
+
+    socialize_loss(&mut mango_group, &mut margin_account, 2, U64F64::from_num(100)).unwrap();
+
+    assert_eq!(margin_account.borrows[2], ZERO_U64F64);
+    assert_eq!(mango_group.indexes[2].deposit, U64F64::from_num(0.75));
+}
+
+#[test]
+fn test_socialize_loss_zeroes_dust_borrow() {
+    // Test that a borrow left below the dust threshold is zeroed and written off against fees
+    let mut mango_group = new_mango_group_in_memory([0, 0, 0]);
+    mango_group.dust_thresholds[2] = 10;
+    mango_group.total_deposits[2] = U64F64::from_num(400);
+    mango_group.total_borrows[2] = U64F64::from_num(100);
+    mango_group.fees_accrued[2] = U64F64::from_num(5);
+    let mut margin_account = MarginAccount::zeroed();
+    margin_account.borrows[2] = U64F64::from_num(100);
+
+    socialize_loss(&mut mango_group, &mut margin_account, 2, U64F64::from_num(97)).unwrap();
+
+    assert_eq!(margin_account.borrows[2], ZERO_U64F64);
+    assert_eq!(mango_group.total_borrows[2], ZERO_U64F64);
+    assert_eq!(mango_group.fees_accrued[2], U64F64::from_num(2));
+}
+
+#[test]
+fn test_socialize_loss_dust_borrow_over_fees() {
+    // Test that the part of a forgiven dust borrow that fees_accrued can't cover is recorded as bad debt
+    let mut mango_group = new_mango_group_in_memory([0, 0, 0]);
+    mango_group.dust_thresholds[2] = 10;
+    mango_group.total_deposits[2] = U64F64::from_num(400);
+    mango_group.total_borrows[2] = U64F64::from_num(100);
+    mango_group.fees_accrued[2] = U64F64::from_num(1);
+    let mut margin_account = MarginAccount::zeroed();
+    margin_account.borrows[2] = U64F64::from_num(100);
+
+    socialize_loss(&mut mango_group, &mut margin_account, 2, U64F64::from_num(97)).unwrap();
+
+    assert_eq!(margin_account.borrows[2], ZERO_U64F64);
+    assert_eq!(mango_group.total_borrows[2], ZERO_U64F64);
+    assert_eq!(mango_group.fees_accrued[2], ZERO_U64F64);
+    assert_eq!(mango_group.bad_debt[2], 2);
+}
+
+#[test]
+fn test_socialize_loss_keeps_borrow_without_dust_threshold() {
+    // Test that the remaining 3 native units stay borrowed when the dust threshold is off
+    let mut mango_group = new_mango_group_in_memory([0, 0, 0]);
+    mango_group.total_deposits[2] = U64F64::from_num(400);
+    mango_group.total_borrows[2] = U64F64::from_num(100);
+    let mut margin_account = MarginAccount::zeroed();
+    margin_account.borrows[2] = U64F64::from_num(100);
+
+    socialize_loss(&mut mango_group, &mut margin_account, 2, U64F64::from_num(97)).unwrap();
+
+    assert_eq!(margin_account.borrows[2], U64F64::from_num(3));
+    assert_eq!(mango_group.total_borrows[2], U64F64::from_num(3));
+}
+
+fn insolvent_liqee() -> (MangoGroup, MarginAccount) {
+    // 10 ETH and 100000 USDT of deposits against 1 BTC and 100000 USDT of borrows -> coll ratio of 0.8
+    let mut mango_group = new_mango_group_in_memory([0, 0, 0]);
+    mango_group.total_deposits[1] = U64F64::from_num(10);
+    mango_group.total_deposits[2] = U64F64::from_num(200000);
+    mango_group.total_borrows[0] = U64F64::from_num(1);
+    mango_group.total_borrows[2] = U64F64::from_num(100000);
+    let mut margin_account = MarginAccount::zeroed();
+    margin_account.deposits[1] = U64F64::from_num(10);
+    margin_account.deposits[2] = U64F64::from_num(100000);
+    margin_account.borrows[0] = U64F64::from_num(1);
+    margin_account.borrows[2] = U64F64::from_num(100000);
+    (mango_group, margin_account)
+}
+
+#[test]
+fn test_socialize_liqee_loss_after_settling_borrows() {
+    // Test that settling borrows before socializing leaves the liqee at LIQ_MIN_COLL_RATIO with equity,
+    // while socializing first also cuts its own USDT deposit and leaves it insolvent
+    let prices = get_prodlike_prices();
+    let pk = Pubkey::default();
+    let mut accounts = [Account::default(), Account::default()];
+    let open_orders_accs = empty_open_orders(&pk, &mut accounts);
+
+    let (mut mango_group, mut margin_account) = insolvent_liqee();
+    settle_all_borrows(&mut mango_group, &mut margin_account).unwrap();
+    assert_eq!(margin_account.deposits[2], ZERO_U64F64);
+    assert_eq!(margin_account.borrows[2], ZERO_U64F64);
+    let (assets_val, liabs_val, _) = margin_account.get_health(&mango_group, &prices, &open_orders_accs, 0).unwrap();
+    socialize_liqee_loss(&mut mango_group, &mut margin_account, assets_val, liabs_val).unwrap();
+    let settled_ratio = margin_account.get_collateral_ratio(&mango_group, &prices, &open_orders_accs, 0).unwrap();
+    let settled_equity = margin_account.get_equity(&mango_group, &prices, &open_orders_accs, 0).unwrap();
+
+    let (mut mango_group, mut margin_account) = insolvent_liqee();
+    let (assets_val, liabs_val, _) = margin_account.get_health(&mango_group, &prices, &open_orders_accs, 0).unwrap();
+    socialize_liqee_loss(&mut mango_group, &mut margin_account, assets_val, liabs_val).unwrap();
+    let unsettled_ratio = margin_account.get_collateral_ratio(&mango_group, &prices, &open_orders_accs, 0).unwrap();
+    let unsettled_equity = margin_account.get_equity(&mango_group, &prices, &open_orders_accs, 0).unwrap();
+
+    // (20000 - 20000 / 1.01) = 198 of equity left
+    assert!((settled_ratio.to_num::<f64>() - 1.01).abs() < 0.001);
+    assert!((settled_equity.to_num::<f64>() - 198.02).abs() < 1.0);
+    assert!(unsettled_ratio < ONE_U64F64);
+    assert!(settled_equity > unsettled_equity);
+}
+
+#[test]
+fn test_liquidation_math_clamps_drifted_group_totals() {
+    // Test that group totals which drifted below the liqee's own balances clamp at zero
+    // instead of underflowing, and the liqee still ends up at LIQ_MIN_COLL_RATIO
+    let prices = get_prodlike_prices();
+    let pk = Pubkey::default();
+    let mut accounts = [Account::default(), Account::default()];
+    let open_orders_accs = empty_open_orders(&pk, &mut accounts);
+
+    let (mut mango_group, mut margin_account) = insolvent_liqee();
+    mango_group.total_deposits[2] = U64F64::from_num(50000);
+    mango_group.total_borrows[0] = U64F64::from_num(0.5);
+    mango_group.total_borrows[2] = U64F64::from_num(50000);
+
+    settle_all_borrows(&mut mango_group, &mut margin_account).unwrap();
+    assert_eq!(mango_group.total_deposits[2], ZERO_U64F64);
+    assert_eq!(mango_group.total_borrows[2], ZERO_U64F64);
+
+    let (assets_val, liabs_val, _) = margin_account.get_health(&mango_group, &prices, &open_orders_accs, 0).unwrap();
+    socialize_liqee_loss(&mut mango_group, &mut margin_account, assets_val, liabs_val).unwrap();
+    assert_eq!(mango_group.total_borrows[0], ZERO_U64F64);
+
+    let ratio = margin_account.get_collateral_ratio(&mango_group, &prices, &open_orders_accs, 0).unwrap();
+    assert!((ratio.to_num::<f64>() - 1.01).abs() < 0.001);
+}
@@ -8,15 +8,17 @@ use solana_program::account_info::AccountInfo;
 use solana_program_test::*;
 use solana_sdk::{
     account::Account,
+    instruction::InstructionError,
     pubkey::Pubkey,
     signature::{Keypair, Signer},
-    transaction::Transaction,
+    transaction::{Transaction, TransactionError},
 };
 use std::mem::size_of;
 
 use mango::{
     entrypoint::process_instruction,
-    instruction::{deposit_srm, withdraw_srm},
+    error::MangoErrorCode,
+    instruction::{deposit_srm, withdraw_excess_srm, withdraw_srm},
     state::MangoSrmAccount,
 };
 
@@ -160,3 +162,484 @@ async fn test_deposit_and_withdraw_srm() {
         assert!(banks_client.process_transaction(transaction).await.is_err());
     }
 }
+
+#[tokio::test]
+async fn test_deposit_srm_initializes_then_accumulates() {
+    // Test that the first deposit initializes the MangoSrmAccount for its owner
+    // Test that later deposits, even from someone else, add to it without changing the owner
+    let program_id = Pubkey::new_unique();
+
+    let mut test = ProgramTest::new("mango", program_id, processor!(process_instruction));
+
+    // limit to track compute unit increase
+    test.set_bpf_compute_max_units(50_000);
+
+    let initial_amount = 500;
+    let first_deposit_amount = 100;
+    let second_deposit_amount = 40;
+
+    let user = Keypair::new();
+    let other_user = Keypair::new();
+    let mango_group = add_mango_group_prodlike(&mut test, program_id);
+    let mango_srm_account_pk = Pubkey::new_unique();
+    test.add_account(
+        mango_srm_account_pk,
+        Account::new(u32::MAX as u64, size_of::<MangoSrmAccount>(), &program_id),
+    );
+    let user_srm_account = add_token_account(
+        &mut test,
+        user.pubkey(),
+        mango_group.srm_mint.pubkey,
+        initial_amount,
+    );
+    let other_user_srm_account = add_token_account(
+        &mut test,
+        other_user.pubkey(),
+        mango_group.srm_mint.pubkey,
+        initial_amount,
+    );
+
+    let (mut banks_client, payer, recent_blockhash) = test.start().await;
+
+    let mut transaction = Transaction::new_with_payer(
+        &[
+            mango_group.init_mango_group(&payer.pubkey()),
+            deposit_srm(
+                &program_id,
+                &mango_group.mango_group_pk,
+                &mango_srm_account_pk,
+                &user.pubkey(),
+                &user_srm_account.pubkey,
+                &mango_group.srm_vault.pubkey,
+                first_deposit_amount,
+            )
+            .unwrap(),
+        ],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer, &user], recent_blockhash);
+    assert!(banks_client.process_transaction(transaction).await.is_ok());
+
+    {
+        let mut mango_srm_account = banks_client
+            .get_account(mango_srm_account_pk)
+            .await
+            .unwrap()
+            .unwrap();
+        let account_info: AccountInfo = (&mango_srm_account_pk, &mut mango_srm_account).into();
+        let mango_srm_account = MangoSrmAccount::load_checked(
+            &program_id,
+            &account_info,
+            &mango_group.mango_group_pk,
+        )
+        .unwrap();
+        assert_eq!(mango_srm_account.owner, user.pubkey());
+        assert_eq!(mango_srm_account.amount, first_deposit_amount);
+    }
+
+    let mut transaction = Transaction::new_with_payer(
+        &[deposit_srm(
+            &program_id,
+            &mango_group.mango_group_pk,
+            &mango_srm_account_pk,
+            &other_user.pubkey(),
+            &other_user_srm_account.pubkey,
+            &mango_group.srm_vault.pubkey,
+            second_deposit_amount,
+        )
+        .unwrap()],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer, &other_user], recent_blockhash);
+    assert!(banks_client.process_transaction(transaction).await.is_ok());
+
+    let mut mango_srm_account = banks_client
+        .get_account(mango_srm_account_pk)
+        .await
+        .unwrap()
+        .unwrap();
+    let account_info: AccountInfo = (&mango_srm_account_pk, &mut mango_srm_account).into();
+    let mango_srm_account = MangoSrmAccount::load_checked(
+        &program_id,
+        &account_info,
+        &mango_group.mango_group_pk,
+    )
+    .unwrap();
+    assert_eq!(mango_srm_account.owner, user.pubkey());
+    assert_eq!(mango_srm_account.amount, first_deposit_amount + second_deposit_amount);
+
+    let mango_vault_srm_balance =
+        get_token_balance(&mut banks_client, mango_group.srm_vault.pubkey).await;
+    assert_eq!(mango_vault_srm_balance, first_deposit_amount + second_deposit_amount);
+}
+
+#[tokio::test]
+async fn test_withdraw_srm_updates_group_fee_tier() {
+    // Test that the cached SRM vault balance follows deposits and withdrawals across a tier boundary
+    let program_id = Pubkey::new_unique();
+
+    let mut test = ProgramTest::new("mango", program_id, processor!(process_instruction));
+
+    // limit to track compute unit increase
+    test.set_bpf_compute_max_units(50_000);
+
+    let deposit_amount = 150_000_000;
+    let withdraw_amount = 60_000_000;
+
+    let user = Keypair::new();
+    let user_pk = user.pubkey();
+    let mango_group = add_mango_group_prodlike(&mut test, program_id);
+    let mango_srm_account_pk = Pubkey::new_unique();
+    test.add_account(
+        mango_srm_account_pk,
+        Account::new(u32::MAX as u64, size_of::<MangoSrmAccount>(), &program_id),
+    );
+    let user_srm_account = add_token_account(
+        &mut test,
+        user_pk,
+        mango_group.srm_mint.pubkey,
+        deposit_amount,
+    );
+
+    let (mut banks_client, payer, recent_blockhash) = test.start().await;
+
+    let mut transaction = Transaction::new_with_payer(
+        &[
+            mango_group.init_mango_group(&payer.pubkey()),
+            deposit_srm(
+                &program_id,
+                &mango_group.mango_group_pk,
+                &mango_srm_account_pk,
+                &user_pk,
+                &user_srm_account.pubkey,
+                &mango_group.srm_vault.pubkey,
+                deposit_amount,
+            )
+            .unwrap(),
+        ],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer, &user], recent_blockhash);
+    assert!(banks_client.process_transaction(transaction).await.is_ok());
+
+    let group = get_mango_group(&mut banks_client, &program_id, mango_group.mango_group_pk).await;
+    assert_eq!(group.srm_vault_balance, deposit_amount);
+    assert_eq!(group.get_group_fee_tier(), 1);
+
+    let mut transaction = Transaction::new_with_payer(
+        &[withdraw_srm(
+            &program_id,
+            &mango_group.mango_group_pk,
+            &mango_srm_account_pk,
+            &user_pk,
+            &user_srm_account.pubkey,
+            &mango_group.srm_vault.pubkey,
+            &mango_group.signer_pk,
+            withdraw_amount,
+        )
+        .unwrap()],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&user, &payer], recent_blockhash);
+    assert!(banks_client.process_transaction(transaction).await.is_ok());
+
+    // 90 SRM is below the 100 SRM of the first tier
+    let group = get_mango_group(&mut banks_client, &program_id, mango_group.mango_group_pk).await;
+    let vault_balance = get_token_balance(&mut banks_client, mango_group.srm_vault.pubkey).await;
+    assert_eq!(group.srm_vault_balance, vault_balance);
+    assert_eq!(group.srm_vault_balance, deposit_amount - withdraw_amount);
+    assert_eq!(group.get_group_fee_tier(), 0);
+}
+
+#[tokio::test]
+async fn test_deposit_srm_wrong_vault_leaves_account_empty() {
+    // Test that a deposit into something other than the group's SRM vault fails before the
+    // MangoSrmAccount is initialized
+    let program_id = Pubkey::new_unique();
+
+    let mut test = ProgramTest::new("mango", program_id, processor!(process_instruction));
+
+    // limit to track compute unit increase
+    test.set_bpf_compute_max_units(50_000);
+
+    let user = Keypair::new();
+    let mango_group = add_mango_group_prodlike(&mut test, program_id);
+    let mango_srm_account_pk = Pubkey::new_unique();
+    test.add_account(
+        mango_srm_account_pk,
+        Account::new(u32::MAX as u64, size_of::<MangoSrmAccount>(), &program_id),
+    );
+    let user_srm_account =
+        add_token_account(&mut test, user.pubkey(), mango_group.srm_mint.pubkey, 500);
+    let wrong_vault =
+        add_token_account(&mut test, user.pubkey(), mango_group.srm_mint.pubkey, 0);
+
+    let (mut banks_client, payer, recent_blockhash) = test.start().await;
+
+    let mut transaction = Transaction::new_with_payer(
+        &[
+            mango_group.init_mango_group(&payer.pubkey()),
+            deposit_srm(
+                &program_id,
+                &mango_group.mango_group_pk,
+                &mango_srm_account_pk,
+                &user.pubkey(),
+                &user_srm_account.pubkey,
+                &wrong_vault.pubkey,
+                100,
+            )
+            .unwrap(),
+        ],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer, &user], recent_blockhash);
+    assert_eq!(
+        banks_client.process_transaction(transaction).await.unwrap_err().unwrap(),
+        TransactionError::InstructionError(
+            1,
+            InstructionError::Custom(MangoErrorCode::InvalidMangoVault.into())
+        )
+    );
+
+    let mango_srm_account = banks_client
+        .get_account(mango_srm_account_pk)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(mango_srm_account.data.iter().all(|&b| b == 0));
+    assert_eq!(get_token_balance(&mut banks_client, user_srm_account.pubkey).await, 500);
+}
+
+#[tokio::test]
+async fn test_withdraw_excess_srm_leaves_user_srm() {
+    // Test that the admin can only withdraw SRM that was sent to the vault on top of user deposits
+    let program_id = Pubkey::new_unique();
+
+    let mut test = ProgramTest::new("mango", program_id, processor!(process_instruction));
+
+    // limit to track compute unit increase
+    test.set_bpf_compute_max_units(50_000);
+
+    let deposit_amount = 100;
+    let rebate_amount = 30;
+
+    let user = Keypair::new();
+    let mango_group = add_mango_group_prodlike(&mut test, program_id);
+    let mango_srm_account_pk = Pubkey::new_unique();
+    test.add_account(
+        mango_srm_account_pk,
+        Account::new(u32::MAX as u64, size_of::<MangoSrmAccount>(), &program_id),
+    );
+    let user_srm_account = add_token_account(
+        &mut test,
+        user.pubkey(),
+        mango_group.srm_mint.pubkey,
+        deposit_amount + rebate_amount,
+    );
+    let admin_srm_account = add_token_account(
+        &mut test,
+        Pubkey::new_unique(),
+        mango_group.srm_mint.pubkey,
+        0,
+    );
+
+    let (mut banks_client, payer, recent_blockhash) = test.start().await;
+
+    // SRM transferred straight to the vault stands in for fee rebates
+    let mut transaction = Transaction::new_with_payer(
+        &[
+            mango_group.init_mango_group(&payer.pubkey()),
+            deposit_srm(
+                &program_id,
+                &mango_group.mango_group_pk,
+                &mango_srm_account_pk,
+                &user.pubkey(),
+                &user_srm_account.pubkey,
+                &mango_group.srm_vault.pubkey,
+                deposit_amount,
+            )
+            .unwrap(),
+            spl_token::instruction::transfer(
+                &spl_token::id(),
+                &user_srm_account.pubkey,
+                &mango_group.srm_vault.pubkey,
+                &user.pubkey(),
+                &[],
+                rebate_amount,
+            )
+            .unwrap(),
+        ],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer, &user], recent_blockhash);
+    assert!(banks_client.process_transaction(transaction).await.is_ok());
+
+    let mango_group_state =
+        get_mango_group(&mut banks_client, &program_id, mango_group.mango_group_pk).await;
+    assert_eq!(mango_group_state.total_user_srm, deposit_amount);
+
+    // Test the admin can't dip into user SRM
+    let mut transaction = Transaction::new_with_payer(
+        &[withdraw_excess_srm(
+            &program_id,
+            &mango_group.mango_group_pk,
+            &payer.pubkey(),
+            &admin_srm_account.pubkey,
+            &mango_group.srm_vault.pubkey,
+            &mango_group.signer_pk,
+            rebate_amount + 1,
+        )
+        .unwrap()],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer], recent_blockhash);
+    assert_eq!(
+        banks_client
+            .process_transaction(transaction)
+            .await
+            .unwrap_err()
+            .unwrap(),
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(MangoErrorCode::InsufficientFunds.into())
+        )
+    );
+
+    // Test the excess can be withdrawn
+    let mut transaction = Transaction::new_with_payer(
+        &[withdraw_excess_srm(
+            &program_id,
+            &mango_group.mango_group_pk,
+            &payer.pubkey(),
+            &admin_srm_account.pubkey,
+            &mango_group.srm_vault.pubkey,
+            &mango_group.signer_pk,
+            rebate_amount,
+        )
+        .unwrap()],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer], recent_blockhash);
+    assert!(banks_client.process_transaction(transaction).await.is_ok());
+
+    assert_eq!(
+        get_token_balance(&mut banks_client, admin_srm_account.pubkey).await,
+        rebate_amount
+    );
+    assert_eq!(
+        get_token_balance(&mut banks_client, mango_group.srm_vault.pubkey).await,
+        deposit_amount
+    );
+
+    // the user can still withdraw everything they deposited
+    let mut transaction = Transaction::new_with_payer(
+        &[withdraw_srm(
+            &program_id,
+            &mango_group.mango_group_pk,
+            &mango_srm_account_pk,
+            &user.pubkey(),
+            &user_srm_account.pubkey,
+            &mango_group.srm_vault.pubkey,
+            &mango_group.signer_pk,
+            deposit_amount,
+        )
+        .unwrap()],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer, &user], recent_blockhash);
+    assert!(banks_client.process_transaction(transaction).await.is_ok());
+
+    let mango_group_state =
+        get_mango_group(&mut banks_client, &program_id, mango_group.mango_group_pk).await;
+    assert_eq!(mango_group_state.total_user_srm, 0);
+}
+
+#[tokio::test]
+async fn test_withdraw_srm_over_balance_leaves_balances() {
+    // Test that withdrawing more SRM than deposited fails with InsufficientFunds and changes
+    // neither the token balances nor the SRM amounts recorded by mango
+    let program_id = Pubkey::new_unique();
+
+    let mut test = ProgramTest::new("mango", program_id, processor!(process_instruction));
+
+    // limit to track compute unit increase
+    test.set_bpf_compute_max_units(50_000);
+
+    let deposit_amount = 100;
+
+    let user = Keypair::new();
+    let user_pk = user.pubkey();
+    let mango_group = add_mango_group_prodlike(&mut test, program_id);
+    let mango_srm_account_pk = Pubkey::new_unique();
+    test.add_account(
+        mango_srm_account_pk,
+        Account::new(u32::MAX as u64, size_of::<MangoSrmAccount>(), &program_id),
+    );
+    let user_srm_account = add_token_account(
+        &mut test,
+        user_pk,
+        mango_group.srm_mint.pubkey,
+        deposit_amount,
+    );
+
+    let (mut banks_client, payer, recent_blockhash) = test.start().await;
+
+    let mut transaction = Transaction::new_with_payer(
+        &[
+            mango_group.init_mango_group(&payer.pubkey()),
+            deposit_srm(
+                &program_id,
+                &mango_group.mango_group_pk,
+                &mango_srm_account_pk,
+                &user_pk,
+                &user_srm_account.pubkey,
+                &mango_group.srm_vault.pubkey,
+                deposit_amount,
+            )
+            .unwrap(),
+        ],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer, &user], recent_blockhash);
+    assert!(banks_client.process_transaction(transaction).await.is_ok());
+
+    let mut transaction = Transaction::new_with_payer(
+        &[withdraw_srm(
+            &program_id,
+            &mango_group.mango_group_pk,
+            &mango_srm_account_pk,
+            &user_pk,
+            &user_srm_account.pubkey,
+            &mango_group.srm_vault.pubkey,
+            &mango_group.signer_pk,
+            deposit_amount + 1,
+        )
+        .unwrap()],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&user, &payer], recent_blockhash);
+    assert_eq!(
+        banks_client.process_transaction(transaction).await.unwrap_err().unwrap(),
+        TransactionError::InstructionError(0, InstructionError::Custom(MangoErrorCode::InsufficientFunds.into()))
+    );
+
+    assert_eq!(get_token_balance(&mut banks_client, user_srm_account.pubkey).await, 0);
+    assert_eq!(get_token_balance(&mut banks_client, mango_group.srm_vault.pubkey).await, deposit_amount);
+    let group = get_mango_group(&mut banks_client, &program_id, mango_group.mango_group_pk).await;
+    assert_eq!(group.total_user_srm, deposit_amount);
+    assert_eq!(group.srm_vault_balance, deposit_amount);
+
+    let mut mango_srm_account = banks_client
+        .get_account(mango_srm_account_pk)
+        .await
+        .unwrap()
+        .unwrap();
+    let account_info: AccountInfo = (&mango_srm_account_pk, &mut mango_srm_account).into();
+    let mango_srm_account = MangoSrmAccount::load_mut_checked(
+        &program_id,
+        &account_info,
+        &mango_group.mango_group_pk,
+    )
+    .unwrap();
+    assert_eq!(mango_srm_account.amount, deposit_amount);
+}
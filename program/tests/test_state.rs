@@ -0,0 +1,794 @@
+// Tests related to the view helpers on MangoGroup and MarginAccount
+#![cfg(feature="test-bpf")]
+
+mod helpers;
+
+use std::mem::size_of;
+
+use bytemuck::Zeroable;
+use fixed::types::U64F64;
+use helpers::*;
+use serum_dex::state::{OpenOrders, ToAlignedBytes};
+use solana_program::{account_info::AccountInfo, clock::Clock, program_pack::Pack};
+use solana_sdk::{
+    account::Account,
+    pubkey::Pubkey,
+};
+use spl_token::state::{Account as Token, AccountState};
+
+use mango::error::{MangoError, MangoErrorCode};
+use mango::processor::settle_all_borrows;
+use mango::state::{check_open_orders_unique, count_open_orders, DAY, get_srm_fee_tier, GRACE_COLL_WEIGHT, load_market_state_checked, load_open_orders_totals, MANGO_GROUP_SIZE, MangoGroup, MangoSrmAccount, MarginAccount, median_oracle_price, NUM_MARKETS, NUM_TOKENS, ONE_U64F64, OpenOrdersTotals, QUOTE_INDEX, verify_vault_authority, YEAR};
+
+#[test]
+fn test_max_borrowable_collateral_bound() {
+    // Test that the collateral ratio is the binding constraint when the borrow limit is high
+    let mut mango_group = new_mango_group_in_memory([1000, 1000, 1000]);
+    mango_group.init_coll_ratio = U64F64::from_num(1.25);
+    let prices = get_prodlike_prices();
+    let pk = Pubkey::default();
+    let mut accounts = [Account::default(), Account::default()];
+    let open_orders_accs = empty_open_orders(&pk, &mut accounts);
+
+    let mut margin_account = MarginAccount::zeroed();
+    margin_account.deposits[0] = U64F64::from_num(1);
+
+    // 1 BTC = 50000 in value; (50000 - 0) / ((1.25 - 1) * 2000) = 100 ETH
+    let max_borrow = margin_account.get_max_borrowable(&mango_group, &prices, &open_orders_accs, 1, 0).unwrap();
+    assert_eq!(max_borrow, 100);
+
+    // Borrowing the max should leave the account exactly at init_coll_ratio
+    margin_account.deposits[1] = U64F64::from_num(max_borrow);
+    margin_account.borrows[1] = U64F64::from_num(max_borrow);
+    let coll_ratio = margin_account.get_collateral_ratio(&mango_group, &prices, &open_orders_accs, 0).unwrap();
+    assert!(coll_ratio >= mango_group.init_coll_ratio);
+    assert_eq!(margin_account.get_max_borrowable(&mango_group, &prices, &open_orders_accs, 1, 0).unwrap(), 0);
+}
+
+#[test]
+fn test_max_borrowable_borrow_limit_bound() {
+    // Test that the group borrow limit is the binding constraint when collateral is plentiful
+    let mut mango_group = new_mango_group_in_memory([1000, 40, 1000]);
+    mango_group.init_coll_ratio = U64F64::from_num(1.25);
+    let prices = get_prodlike_prices();
+    let pk = Pubkey::default();
+    let mut accounts = [Account::default(), Account::default()];
+    let open_orders_accs = empty_open_orders(&pk, &mut accounts);
+
+    let mut margin_account = MarginAccount::zeroed();
+    margin_account.deposits[0] = U64F64::from_num(1);
+
+    let max_borrow = margin_account.get_max_borrowable(&mango_group, &prices, &open_orders_accs, 1, 0).unwrap();
+    assert_eq!(max_borrow, 40);
+
+    // Existing borrows count against the limit
+    margin_account.deposits[1] = U64F64::from_num(15);
+    margin_account.borrows[1] = U64F64::from_num(15);
+    let max_borrow = margin_account.get_max_borrowable(&mango_group, &prices, &open_orders_accs, 1, 0).unwrap();
+    assert_eq!(max_borrow, 25);
+
+    // A tighter per account cap binds first
+    mango_group.max_borrow_per_account[1] = 20;
+    let max_borrow = margin_account.get_max_borrowable(&mango_group, &prices, &open_orders_accs, 1, 0).unwrap();
+    assert_eq!(max_borrow, 5);
+}
+
+#[test]
+fn test_reserve_factor_depositor_share() {
+    // Test that with a 10% reserve factor depositors earn 90% of the interest paid by borrowers
+    let mut mango_group = new_mango_group_in_memory([0, 0, 0]);
+    mango_group.reserve_factor = U64F64::from_num(0.1);
+    mango_group.total_deposits[0] = U64F64::from_num(1_000_000);
+    mango_group.total_borrows[0] = U64F64::from_num(500_000);
+
+    let clock = Clock { unix_timestamp: DAY as i64, ..Clock::default() };
+    mango_group.update_indexes(&clock).unwrap();
+
+    let index = mango_group.indexes[0];
+    let deposit_interest = ((index.deposit - ONE_U64F64) * mango_group.total_deposits[0]).to_num::<f64>();
+    let borrow_interest = ((index.borrow - ONE_U64F64) * mango_group.total_borrows[0]).to_num::<f64>();
+    let fees = (mango_group.fees_accrued[0] * index.deposit).to_num::<f64>();
+
+    assert!(borrow_interest > 0.0);
+    assert!((deposit_interest - 0.9 * borrow_interest).abs() < 1e-6);
+    assert!((fees - 0.1 * borrow_interest).abs() < 1e-6);
+}
+
+#[test]
+fn test_coll_weight_lowers_coll_ratio() {
+    // Test that a 0.8 weighted BTC deposit counts for less collateral than an unweighted one
+    let mut mango_group = new_mango_group_in_memory([0, 0, 0]);
+    let prices = get_prodlike_prices();
+    let pk = Pubkey::default();
+    let mut accounts = [Account::default(), Account::default()];
+    let open_orders_accs = empty_open_orders(&pk, &mut accounts);
+
+    let mut margin_account = MarginAccount::zeroed();
+    margin_account.deposits[0] = U64F64::from_num(1);
+    margin_account.borrows[2] = U64F64::from_num(10_000);
+
+    let unweighted = margin_account.get_collateral_ratio(&mango_group, &prices, &open_orders_accs, 0).unwrap();
+    mango_group.coll_weights[0] = U64F64::from_num(0.8);
+    let weighted = margin_account.get_collateral_ratio(&mango_group, &prices, &open_orders_accs, 0).unwrap();
+
+    assert_eq!(unweighted, U64F64::from_num(5));
+    assert!(weighted < unweighted);
+    assert!((weighted.to_num::<f64>() - 4.0).abs() < 1e-9);
+}
+
+#[test]
+fn test_liab_weight_lowers_coll_ratio() {
+    // Test that a 1.1 weighted ETH borrow counts as a larger liability than an unweighted one
+    let mut mango_group = new_mango_group_in_memory([0, 0, 0]);
+    let prices = get_prodlike_prices();
+    let pk = Pubkey::default();
+    let mut accounts = [Account::default(), Account::default()];
+    let open_orders_accs = empty_open_orders(&pk, &mut accounts);
+
+    let mut margin_account = MarginAccount::zeroed();
+    margin_account.deposits[2] = U64F64::from_num(22_000);
+    margin_account.borrows[1] = U64F64::from_num(10);
+
+    let unweighted = margin_account.get_collateral_ratio(&mango_group, &prices, &open_orders_accs, 0).unwrap();
+    mango_group.liab_weights[1] = U64F64::from_num(1.1);
+    let weighted = margin_account.get_collateral_ratio(&mango_group, &prices, &open_orders_accs, 0).unwrap();
+
+    // 22000 / 20000 = 1.1 unweighted; 22000 / 22000 = 1.0 weighted
+    assert!((unweighted.to_num::<f64>() - 1.1).abs() < 1e-9);
+    assert!(weighted < unweighted);
+    assert!((weighted.to_num::<f64>() - 1.0).abs() < 1e-9);
+}
+
+#[test]
+fn test_update_indexes_one_year() {
+    // Test that over a year of accrual depositors earn exactly what borrowers pay less reserves
+    let mut mango_group = new_mango_group_in_memory([0, 0, 0]);
+    mango_group.reserve_factor = U64F64::from_num(0.2);
+    mango_group.total_deposits[2] = U64F64::from_num(10_000_000_000u64);
+    mango_group.total_borrows[2] = U64F64::from_num(7_000_000_000u64);
+
+    // accrue hourly so the index compounds the way it would with regular activity
+    let hours = (YEAR / U64F64::from_num(3600)).to_num::<i64>();
+    for h in 1..=hours {
+        let clock = Clock { unix_timestamp: h * 3600, ..Clock::default() };
+        mango_group.update_indexes(&clock).unwrap();
+    }
+
+    let index = mango_group.indexes[2];
+    let deposit_interest = ((index.deposit - ONE_U64F64) * mango_group.total_deposits[2]).to_num::<f64>();
+    let borrow_interest = ((index.borrow - ONE_U64F64) * mango_group.total_borrows[2]).to_num::<f64>();
+
+    assert!(borrow_interest > 0.0);
+    assert!((deposit_interest - 0.8 * borrow_interest).abs() / borrow_interest < 1e-6);
+}
+
+#[test]
+fn test_update_indexes_borrows_without_deposits() {
+    // Test that borrows keep accruing interest when there are no deposits left
+    let mut mango_group = new_mango_group_in_memory([0, 0, 0]);
+    mango_group.total_borrows[2] = U64F64::from_num(1_000_000);
+
+    let clock = Clock { unix_timestamp: DAY as i64, ..Clock::default() };
+    mango_group.update_indexes(&clock).unwrap();
+
+    let index = mango_group.indexes[2];
+    assert!(index.borrow > ONE_U64F64);
+    assert_eq!(index.deposit, ONE_U64F64);
+    assert_eq!(index.last_update, DAY);
+    assert!(mango_group.fees_accrued[2] > U64F64::from_num(0));
+
+    // tokens with neither deposits nor borrows are untouched
+    assert_eq!(mango_group.indexes[0].borrow, ONE_U64F64);
+    assert_eq!(mango_group.indexes[0].last_update, 0);
+}
+
+#[test]
+fn test_borrow_and_deposit_apy() {
+    // Test the yearly rates at 0%, optimal and 100% utilization
+    let mut mango_group = new_mango_group_in_memory([0, 0, 0]);
+    mango_group.reserve_factor = U64F64::from_num(0.1);
+    mango_group.total_deposits[2] = U64F64::from_num(1_000_000);
+
+    assert_eq!(mango_group.get_borrow_apy(2), U64F64::from_num(0));
+    assert_eq!(mango_group.get_deposit_apy(2), U64F64::from_num(0));
+
+    // 20% for borrowers at the optimal utilization of 70%; depositors get 0.2 * 0.7 * 0.9
+    mango_group.total_borrows[2] = U64F64::from_num(700_000);
+    assert!((mango_group.get_borrow_apy(2).to_num::<f64>() - 0.2).abs() < 1e-6);
+    assert!((mango_group.get_deposit_apy(2).to_num::<f64>() - 0.126).abs() < 1e-6);
+
+    // 300% at full utilization
+    mango_group.total_borrows[2] = U64F64::from_num(1_000_000);
+    assert!((mango_group.get_borrow_apy(2).to_num::<f64>() - 3.0).abs() < 1e-6);
+    assert!((mango_group.get_deposit_apy(2).to_num::<f64>() - 2.7).abs() < 1e-6);
+}
+
+#[test]
+fn test_update_indexes_caps_accrual_interval() {
+    // Test that a ten year gap accrues at most max_accrual_interval per update and catches up later
+    let mut mango_group = new_mango_group_in_memory([0, 0, 0]);
+    mango_group.max_accrual_interval = DAY;
+    mango_group.total_deposits[2] = U64F64::from_num(1_000_000);
+    mango_group.total_borrows[2] = U64F64::from_num(1_000_000);
+
+    let ten_years = (YEAR * U64F64::from_num(10)).to_num::<i64>();
+    let clock = Clock { unix_timestamp: ten_years, ..Clock::default() };
+    mango_group.update_indexes(&clock).unwrap();
+
+    // at full utilization the rate is 300% APY, so one day adds under 1%
+    let index = mango_group.indexes[2];
+    assert_eq!(index.last_update, DAY);
+    assert!(index.borrow > ONE_U64F64);
+    assert!(index.borrow < U64F64::from_num(1.01));
+
+    mango_group.update_indexes(&clock).unwrap();
+    assert_eq!(mango_group.indexes[2].last_update, 2 * DAY);
+    assert!(mango_group.indexes[2].borrow > index.borrow);
+
+    // no cap accrues the whole gap at once
+    mango_group.max_accrual_interval = 0;
+    mango_group.update_indexes(&clock).unwrap();
+    assert_eq!(mango_group.indexes[2].last_update, ten_years as u64);
+}
+
+#[test]
+fn test_max_borrowable_uses_clock_time_after_capped_catch_up() {
+    // Test that when max_accrual_interval leaves last_update behind the clock, an expired grace
+    // deposit counts at full weight and the borrow limit ramp is read at the clock's time
+    let mut mango_group = new_mango_group_in_memory([1000, 1000, 1000]);
+    mango_group.init_coll_ratio = U64F64::from_num(1.25);
+    mango_group.max_accrual_interval = DAY;
+    mango_group.collateral_grace_period = DAY;
+    mango_group.total_deposits[0] = U64F64::from_num(1);
+    mango_group.total_deposits[1] = U64F64::from_num(1000);
+    mango_group.borrow_limit_target = [1000, 40, 1000];
+    mango_group.borrow_limit_ramp_seconds = 10 * DAY;
+    let prices = get_prodlike_prices();
+    let pk = Pubkey::default();
+    let mut accounts = [Account::default(), Account::default()];
+    let open_orders_accs = empty_open_orders(&pk, &mut accounts);
+
+    let now = 10 * DAY;
+    let clock = Clock { unix_timestamp: now as i64, ..Clock::default() };
+    mango_group.update_indexes(&clock).unwrap();
+    assert_eq!(mango_group.indexes[1].last_update, DAY);
+
+    let mut margin_account = MarginAccount::zeroed();
+    margin_account.deposits[0] = U64F64::from_num(1);
+    margin_account.record_grace_deposit(&mango_group, 0, 1, now - DAY);
+    assert_eq!(
+        margin_account.get_assets_val(&mango_group, &prices, &open_orders_accs, now).unwrap(),
+        U64F64::from_num(PRICE_BTC)
+    );
+
+    // 1 BTC at full weight allows 100 ETH, but the ramp has reached 40 by now. Read at
+    // last_update the haircut deposit would allow 50 and the ramp would still be near 1000
+    let max_borrow = margin_account.get_max_borrowable(&mango_group, &prices, &open_orders_accs, 1, now).unwrap();
+    assert_eq!(max_borrow, 40);
+
+    // with the ramp off the full weight deposit binds
+    mango_group.borrow_limit_ramp_seconds = 0;
+    let max_borrow = margin_account.get_max_borrowable(&mango_group, &prices, &open_orders_accs, 1, now).unwrap();
+    assert_eq!(max_borrow, 100);
+}
+
+#[test]
+fn test_borrow_limit_ramp_interpolates() {
+    // Test that the borrow limit moves linearly from borrow_limits to the target over the ramp
+    let mut mango_group = new_mango_group_in_memory([100, 100, 100]);
+    assert_eq!(mango_group.get_borrow_limit(0, 5000), 100);
+
+    mango_group.borrow_limit_target = [300, 100, 100];
+    mango_group.borrow_limit_start_ts = 1000;
+    mango_group.borrow_limit_ramp_seconds = 2000;
+    assert_eq!(mango_group.get_borrow_limit(0, 500), 100);
+    assert_eq!(mango_group.get_borrow_limit(0, 1000), 100);
+    assert_eq!(mango_group.get_borrow_limit(0, 2000), 200);  // midpoint
+    assert_eq!(mango_group.get_borrow_limit(0, 3000), 300);
+    assert_eq!(mango_group.get_borrow_limit(0, 10_000), 300);
+
+    // ramps down the same way
+    mango_group.borrow_limit_target[0] = 0;
+    assert_eq!(mango_group.get_borrow_limit(0, 2000), 50);
+
+    // tokens whose target is their current limit stay there
+    assert_eq!(mango_group.get_borrow_limit(1, 2000), 100);
+}
+
+#[test]
+fn test_get_solvency_reports_borrows_over_deposits() {
+    // Test that a group with more borrowed than deposited value reports borrows exceeding deposits
+    let mut mango_group = new_mango_group_in_memory([0, 0, 0]);
+    let prices = get_prodlike_prices();
+    mango_group.total_deposits[0] = U64F64::from_num(1);
+    mango_group.total_deposits[2] = U64F64::from_num(10_000);
+    mango_group.total_borrows[2] = U64F64::from_num(70_000);
+
+    let (deposits_val, borrows_val) = mango_group.get_solvency(&prices);
+    assert_eq!(deposits_val, U64F64::from_num(PRICE_BTC + 10_000));
+    assert_eq!(borrows_val, U64F64::from_num(70_000));
+    assert!(borrows_val > deposits_val);
+
+    // the indexes scale the totals into native units
+    mango_group.indexes[2].borrow = U64F64::from_num(1.5);
+    let (_, borrows_val) = mango_group.get_solvency(&prices);
+    assert_eq!(borrows_val, U64F64::from_num(105_000));
+}
+
+#[test]
+fn test_get_health_matches_individual_getters() {
+    // Test that get_health returns the same values as the individual getters
+    let mut mango_group = new_mango_group_in_memory([0, 0, 0]);
+    mango_group.coll_weights[0] = U64F64::from_num(0.9);
+    let prices = get_prodlike_prices();
+    let pk = Pubkey::default();
+    let mut accounts = [Account::default(), Account::default()];
+    let open_orders_accs = empty_open_orders(&pk, &mut accounts);
+
+    let mut margin_account = MarginAccount::zeroed();
+    margin_account.deposits[0] = U64F64::from_num(2);
+    margin_account.deposits[2] = U64F64::from_num(5_000);
+    margin_account.borrows[1] = U64F64::from_num(20);
+
+    let (assets, liabs, coll_ratio) = margin_account.get_health(&mango_group, &prices, &open_orders_accs, 0).unwrap();
+    assert_eq!(assets, margin_account.get_assets_val(&mango_group, &prices, &open_orders_accs, 0).unwrap());
+    assert_eq!(liabs, margin_account.get_liabs_val(&mango_group, &prices).unwrap());
+    assert_eq!(coll_ratio, margin_account.get_collateral_ratio(&mango_group, &prices, &open_orders_accs, 0).unwrap());
+    assert_eq!(assets - liabs, margin_account.get_equity(&mango_group, &prices, &open_orders_accs, 0).unwrap());
+
+    // No liabilities means an infinite coll ratio
+    margin_account.borrows[1] = U64F64::from_num(0);
+    let (_, liabs, coll_ratio) = margin_account.get_health(&mango_group, &prices, &open_orders_accs, 0).unwrap();
+    assert_eq!(liabs, U64F64::from_num(0));
+    assert_eq!(coll_ratio, U64F64::MAX);
+}
+
+#[test]
+fn test_open_orders_coin_counts_at_coll_weight() {
+    // Test that base currency sitting in open orders counts at the same coll weight as a deposit,
+    // so moving it into an order can't raise the value of the account
+    let mut mango_group = new_mango_group_in_memory([0, 0, 0]);
+    mango_group.coll_weights[0] = U64F64::from_num(0.75);
+    let prices = get_prodlike_prices();
+
+    let mut margin_account = MarginAccount::zeroed();
+    margin_account.deposits[0] = U64F64::from_num(2);
+    let deposited = margin_account.get_assets_val_from_totals(&mango_group, &prices, &OpenOrdersTotals::default(), 0).unwrap();
+    assert_eq!(deposited, U64F64::from_num(2 * PRICE_BTC) * U64F64::from_num(0.75));
+
+    // the same 2 BTC resting in an ask
+    margin_account.deposits[0] = U64F64::from_num(0);
+    let mut totals = OpenOrdersTotals::default();
+    totals.coin[0] = 2;
+    let in_orders = margin_account.get_assets_val_from_totals(&mango_group, &prices, &totals, 0).unwrap();
+    assert_eq!(in_orders, deposited);
+
+    // quote in open orders counts at the quote coll weight
+    totals.pc[0] = 1000;
+    let with_pc = margin_account.get_assets_val_from_totals(&mango_group, &prices, &totals, 0).unwrap();
+    assert_eq!(with_pc, deposited + U64F64::from_num(1000));
+}
+
+#[test]
+fn test_collateral_grace_period_reduces_fresh_deposit_weight() {
+    // Test that a deposit counts at GRACE_COLL_WEIGHT until collateral_grace_period has passed
+    let mut mango_group = new_mango_group_in_memory([0, 0, 0]);
+    mango_group.collateral_grace_period = DAY;
+    let prices = get_prodlike_prices();
+    let pk = Pubkey::default();
+    let mut accounts = [Account::default(), Account::default()];
+    let open_orders_accs = empty_open_orders(&pk, &mut accounts);
+
+    let mut margin_account = MarginAccount::zeroed();
+    margin_account.deposits[0] = U64F64::from_num(2);
+    margin_account.record_grace_deposit(&mango_group, 0, 2, 1000);
+
+    let fresh = margin_account.get_assets_val(&mango_group, &prices, &open_orders_accs, 1000 + DAY - 1).unwrap();
+    assert_eq!(fresh, U64F64::from_num(2 * PRICE_BTC) * GRACE_COLL_WEIGHT);
+
+    let settled = margin_account.get_assets_val(&mango_group, &prices, &open_orders_accs, 1000 + DAY).unwrap();
+    assert_eq!(settled, U64F64::from_num(2 * PRICE_BTC));
+    assert!(fresh < settled);
+
+    // with the grace period off deposits count at full weight right away
+    mango_group.collateral_grace_period = 0;
+    assert_eq!(margin_account.get_assets_val(&mango_group, &prices, &open_orders_accs, 1000).unwrap(), settled);
+}
+
+#[test]
+fn test_collateral_grace_period_only_haircuts_new_deposit() {
+    // Test that a deposit by anyone else only counts its own amount at GRACE_COLL_WEIGHT, so it
+    // can't lower the collateral of a balance that is past the grace period, and that an empty
+    // deposit changes nothing
+    let mut mango_group = new_mango_group_in_memory([0, 0, 0]);
+    mango_group.collateral_grace_period = DAY;
+    let prices = get_prodlike_prices();
+    let pk = Pubkey::default();
+    let mut accounts = [Account::default(), Account::default()];
+    let open_orders_accs = empty_open_orders(&pk, &mut accounts);
+
+    let mut margin_account = MarginAccount::zeroed();
+    margin_account.deposits[0] = U64F64::from_num(2);
+    margin_account.record_grace_deposit(&mango_group, 0, 2, 1000);
+    let now = 1000 + DAY;
+    let before = margin_account.get_assets_val(&mango_group, &prices, &open_orders_accs, now).unwrap();
+    assert_eq!(before, U64F64::from_num(2 * PRICE_BTC));
+
+    margin_account.record_grace_deposit(&mango_group, 0, 0, now);
+    assert_eq!(margin_account.get_assets_val(&mango_group, &prices, &open_orders_accs, now).unwrap(), before);
+
+    // a third party deposits 1 BTC into the account
+    margin_account.deposits[0] = U64F64::from_num(3);
+    margin_account.record_grace_deposit(&mango_group, 0, 1, now);
+    assert_eq!(margin_account.grace_deposits[0], 1);
+    let after = margin_account.get_assets_val(&mango_group, &prices, &open_orders_accs, now).unwrap();
+    assert_eq!(after, U64F64::from_num(2 * PRICE_BTC) + U64F64::from_num(PRICE_BTC) * GRACE_COLL_WEIGHT);
+    assert!(after > before);
+}
+
+#[test]
+fn test_native_deposits_and_borrows_match_per_token() {
+    // Test that the batch accessors apply each token's index like the per token getters
+    let mut mango_group = new_mango_group_in_memory([0, 0, 0]);
+    mango_group.indexes[0].deposit = U64F64::from_num(1.5);
+    mango_group.indexes[1].borrow = U64F64::from_num(1.25);
+    mango_group.indexes[2].deposit = U64F64::from_num(1.125);
+    mango_group.indexes[2].borrow = U64F64::from_num(1.375);
+
+    let mut margin_account = MarginAccount::zeroed();
+    margin_account.deposits = [U64F64::from_num(2), U64F64::from_num(0), U64F64::from_num(1000)];
+    margin_account.borrows = [U64F64::from_num(0), U64F64::from_num(40), U64F64::from_num(300)];
+
+    let native_deposits = margin_account.get_native_deposits(&mango_group);
+    let native_borrows = margin_account.get_native_borrows(&mango_group);
+    for i in 0..native_deposits.len() {
+        assert_eq!(native_deposits[i], margin_account.get_native_deposit(&mango_group.indexes[i], i));
+        assert_eq!(native_borrows[i], margin_account.get_native_borrow(&mango_group.indexes[i], i));
+    }
+    assert_eq!(native_deposits, [3, 0, 1125]);
+    assert_eq!(native_borrows, [0, 50, 413]);
+}
+
+#[test]
+fn test_native_rounding_favors_protocol() {
+    // Test that an account's native deposits round down and its native borrows round up, like the
+    // group totals, and that settling the rounded up borrow leaves no dust
+    let mut mango_group = new_mango_group_in_memory([0, 0, 0]);
+    mango_group.indexes[2].deposit = U64F64::from_num(1.5);
+    mango_group.indexes[2].borrow = U64F64::from_num(1.5);
+    let mut margin_account = MarginAccount::zeroed();
+    margin_account.deposits[2] = U64F64::from_num(3);
+    margin_account.borrows[2] = U64F64::from_num(3);
+    mango_group.total_deposits[2] = U64F64::from_num(3);
+    mango_group.total_borrows[2] = U64F64::from_num(3);
+
+    // 4.5 native each
+    assert_eq!(margin_account.get_native_deposit(&mango_group.indexes[2], 2), 4);
+    assert_eq!(margin_account.get_native_borrow(&mango_group.indexes[2], 2), 5);
+    assert_eq!(mango_group.get_total_native_deposit(2).unwrap(), 4);
+    assert_eq!(mango_group.get_total_native_borrow(2).unwrap(), 5);
+    assert_eq!(margin_account.get_total_liabs(&mango_group).unwrap()[2], 5);
+
+    mango_group.indexes[2].deposit = ONE_U64F64;
+    margin_account.deposits[2] = U64F64::from_num(10);
+    mango_group.total_deposits[2] = U64F64::from_num(10);
+    settle_all_borrows(&mut mango_group, &mut margin_account).unwrap();
+    assert_eq!(margin_account.borrows[2], U64F64::from_num(0));
+    assert_eq!(mango_group.total_borrows[2], U64F64::from_num(0));
+    assert_eq!(margin_account.deposits[2], U64F64::from_num(5));
+    assert_eq!(mango_group.total_deposits[2], U64F64::from_num(5));
+}
+
+#[test]
+fn test_get_assets_val_overflow_is_error() {
+    // Test that a near u64::MAX coin total times the BTC price returns an error instead of panicking
+    let mango_group = new_mango_group_in_memory([0, 0, 0]);
+    let prices = get_prodlike_prices();
+    let owner_pk = Pubkey::new_unique();
+    let open_orders_pk = Pubkey::new_unique();
+
+    let mut btc_usdt = open_orders_account(&owner_pk, u128::MAX);
+    let open_orders: &mut OpenOrders = bytemuck::from_bytes_mut(&mut btc_usdt.data[5..5 + size_of::<OpenOrders>()]);
+    open_orders.native_coin_total = u64::MAX - 1;
+    let mut eth_usdt = Account::default();
+    let pk = Pubkey::default();
+    let open_orders_accs: [AccountInfo; 2] = [(&open_orders_pk, &mut btc_usdt).into(), (&pk, &mut eth_usdt).into()];
+
+    let margin_account = MarginAccount::zeroed();
+    let r = margin_account.get_assets_val(&mango_group, &prices, &open_orders_accs, 0);
+    assert!(matches!(r, Err(MangoError::MangoErrorCode { mango_error_code: MangoErrorCode::Default, .. })));
+
+    // Same for a deposit that large
+    let mut accounts = [Account::default(), Account::default()];
+    let open_orders_accs = empty_open_orders(&pk, &mut accounts);
+    let mut margin_account = MarginAccount::zeroed();
+    margin_account.deposits[0] = U64F64::from_num(u64::MAX - 1);
+    let r = margin_account.get_assets_val(&mango_group, &prices, &open_orders_accs, 0);
+    assert!(matches!(r, Err(MangoError::MangoErrorCode { mango_error_code: MangoErrorCode::Default, .. })));
+}
+
+#[test]
+fn test_get_liabs_val_overflow_is_error() {
+    // Test that a near u64::MAX borrow times the BTC price returns an error instead of panicking
+    let mango_group = new_mango_group_in_memory([0, 0, 0]);
+    let prices = get_prodlike_prices();
+
+    let mut margin_account = MarginAccount::zeroed();
+    margin_account.borrows[0] = U64F64::from_num(u64::MAX - 1);
+    let r = margin_account.get_liabs_val(&mango_group, &prices);
+    assert!(matches!(r, Err(MangoError::MangoErrorCode { mango_error_code: MangoErrorCode::Default, .. })));
+
+    // Each term fits but their sum doesn't
+    let mut margin_account = MarginAccount::zeroed();
+    margin_account.borrows[2] = U64F64::from_num(u64::MAX - 1);
+    margin_account.borrows[1] = U64F64::from_num(u64::MAX / PRICE_ETH);
+    let r = margin_account.get_liabs_val(&mango_group, &prices);
+    assert!(matches!(r, Err(MangoError::MangoErrorCode { mango_error_code: MangoErrorCode::Default, .. })));
+}
+
+#[test]
+fn test_total_native_overflow_is_error() {
+    // Test that group totals near u64::MAX convert when they fit and return an error instead of
+    // panicking when they don't
+    let mut mango_group = new_mango_group_in_memory([0, 0, 0]);
+    mango_group.total_deposits[0] = U64F64::from_num(u64::MAX);
+    mango_group.total_borrows[0] = U64F64::from_num(u64::MAX - 1) + U64F64::from_num(0.5);
+    assert_eq!(mango_group.get_total_native_deposit(0).unwrap(), u64::MAX);
+    assert_eq!(mango_group.get_total_native_borrow(0).unwrap(), u64::MAX);
+    assert!(mango_group.has_valid_deposits_borrows(0).unwrap());
+
+    // rounding up the borrows overflows
+    mango_group.total_borrows[0] = U64F64::MAX;
+    let r = mango_group.get_total_native_borrow(0);
+    assert!(matches!(r, Err(MangoError::MangoErrorCode { mango_error_code: MangoErrorCode::Default, .. })));
+    assert!(mango_group.has_valid_deposits_borrows(0).is_err());
+
+    // accrued interest pushes the deposits past u64::MAX
+    mango_group.indexes[0].deposit = U64F64::from_num(1.5);
+    let r = mango_group.get_total_native_deposit(0);
+    assert!(matches!(r, Err(MangoError::MangoErrorCode { mango_error_code: MangoErrorCode::Default, .. })));
+    assert!(mango_group.get_vault_shortfall(0, 0).is_err());
+}
+
+#[test]
+fn test_duplicate_open_orders_fails() {
+    // Test that the same open orders account cannot be passed for two markets
+    let pk = Pubkey::new_unique();
+    let mut accounts = [Account::default(), Account::default()];
+    let open_orders_accs = empty_open_orders(&pk, &mut accounts);
+    assert!(check_open_orders_unique(&open_orders_accs).is_err());
+
+    // Default keys mark unused slots and may repeat
+    let pk = Pubkey::default();
+    let mut accounts = [Account::default(), Account::default()];
+    let open_orders_accs = empty_open_orders(&pk, &mut accounts);
+    assert!(check_open_orders_unique(&open_orders_accs).is_ok());
+}
+
+#[test]
+fn test_get_utilization() {
+    // Test utilization with no deposits and with half of deposits borrowed
+    let mut mango_group = new_mango_group_in_memory([0, 0, 0]);
+    assert_eq!(mango_group.get_utilization(0), U64F64::from_num(0));
+
+    mango_group.total_borrows[0] = U64F64::from_num(100);
+    assert_eq!(mango_group.get_utilization(0), U64F64::from_num(0));
+
+    mango_group.total_deposits[0] = U64F64::from_num(200);
+    assert_eq!(mango_group.get_utilization(0), U64F64::from_num(0.5));
+}
+
+#[test]
+fn test_median_oracle_price() {
+    // Test combining a primary and secondary oracle under a 2% divergence limit
+    let max_divergence = U64F64::from_num(0.02);
+
+    // agreement
+    let price = median_oracle_price(U64F64::from_num(50_000), U64F64::from_num(50_000), max_divergence).unwrap();
+    assert_eq!(price, U64F64::from_num(50_000));
+
+    // mild divergence in either direction uses the midpoint
+    let price = median_oracle_price(U64F64::from_num(50_000), U64F64::from_num(50_500), max_divergence).unwrap();
+    assert_eq!(price, U64F64::from_num(50_250));
+    let price = median_oracle_price(U64F64::from_num(50_500), U64F64::from_num(50_000), max_divergence).unwrap();
+    assert_eq!(price, U64F64::from_num(50_250));
+
+    // large divergence fails the price read
+    assert!(median_oracle_price(U64F64::from_num(50_000), U64F64::from_num(52_000), max_divergence).is_err());
+    assert!(median_oracle_price(U64F64::from_num(52_000), U64F64::from_num(50_000), max_divergence).is_err());
+}
+
+#[test]
+fn test_get_srm_fee_tier() {
+    // Test that SRM balances map to the serum dex fee tiers, with 6 decimals
+    let srm = 1_000_000u64;
+    assert_eq!(get_srm_fee_tier(0), 0);
+    assert_eq!(get_srm_fee_tier(99 * srm), 0);
+    assert_eq!(get_srm_fee_tier(100 * srm), 1);
+    assert_eq!(get_srm_fee_tier(999 * srm), 1);
+    assert_eq!(get_srm_fee_tier(1_000 * srm), 2);
+    assert_eq!(get_srm_fee_tier(10_000 * srm), 3);
+    assert_eq!(get_srm_fee_tier(100_000 * srm), 4);
+    assert_eq!(get_srm_fee_tier(1_000_000 * srm), 5);
+    assert_eq!(get_srm_fee_tier(u64::MAX), 5);
+
+    let mut mango_srm_account = MangoSrmAccount::zeroed();
+    mango_srm_account.amount = 2_500 * srm;
+    assert_eq!(mango_srm_account.get_fee_tier(), 2);
+}
+
+#[test]
+fn test_num_open_orders_tracks_place_and_cancel() {
+    // Test that syncing after placing and cancelling keeps num_open_orders equal to the used slots
+    assert_eq!(count_open_orders(u128::MAX), 0);
+    assert_eq!(count_open_orders(u128::MAX << 3), 3);
+    assert_eq!(count_open_orders(0), 128);
+
+    let owner_pk = Pubkey::new_unique();
+    let open_orders_pk = Pubkey::new_unique();
+    let mut margin_account = MarginAccount::zeroed();
+
+    // place an order in slot 0
+    let mut account = open_orders_account(&owner_pk, u128::MAX & !1);
+    margin_account.update_num_open_orders(0, &(&open_orders_pk, &mut account).into()).unwrap();
+    assert_eq!(margin_account.num_open_orders, [1, 0]);
+
+    // place a second order in slot 1
+    let mut account = open_orders_account(&owner_pk, u128::MAX & !3);
+    margin_account.update_num_open_orders(0, &(&open_orders_pk, &mut account).into()).unwrap();
+    assert_eq!(margin_account.num_open_orders, [2, 0]);
+
+    // cancel the first one
+    let mut account = open_orders_account(&owner_pk, u128::MAX & !2);
+    margin_account.update_num_open_orders(0, &(&open_orders_pk, &mut account).into()).unwrap();
+    assert_eq!(margin_account.num_open_orders, [1, 0]);
+
+    // an open orders account the dex has not initialized yet has no orders
+    let mut account = Account::new(u32::MAX as u64, 3228, &Pubkey::default());
+    margin_account.update_num_open_orders(1, &(&open_orders_pk, &mut account).into()).unwrap();
+    assert_eq!(margin_account.num_open_orders, [1, 0]);
+}
+
+#[test]
+fn test_mango_group_size() {
+    // Test that new MangoGroup fields come out of the reserved bytes instead of changing the layout
+    assert_eq!(size_of::<MangoGroup>(), MANGO_GROUP_SIZE);
+}
+
+#[test]
+fn test_verify_vault_authority() {
+    // Test that a vault whose authority was moved off the mango signer is rejected
+    let signer_pk = Pubkey::new_unique();
+    let vault_pk = Pubkey::new_unique();
+    let vault_account = |owner: Pubkey| {
+        let mut account = Account::new(u32::MAX as u64, Token::LEN, &spl_token::id());
+        let token = Token { mint: Pubkey::new_unique(), owner, state: AccountState::Initialized, ..Token::default() };
+        Token::pack(token, &mut account.data).unwrap();
+        account
+    };
+
+    let mut account = vault_account(signer_pk);
+    assert!(verify_vault_authority(&(&vault_pk, &mut account).into(), &signer_pk).is_ok());
+
+    let mut account = vault_account(Pubkey::new_unique());
+    let r = verify_vault_authority(&(&vault_pk, &mut account).into(), &signer_pk);
+    assert!(matches!(r, Err(MangoError::MangoErrorCode { mango_error_code: MangoErrorCode::InvalidMangoVault, .. })));
+}
+
+#[test]
+fn test_load_market_state_checked_rejects_wrong_owner() {
+    // Test that the read-only market loader only accepts markets owned by the dex program
+    let dex_prog_id = Pubkey::new_unique();
+    let market_pk = Pubkey::new_unique();
+    let base_mint = Pubkey::new_unique();
+
+    let mut account = dex_market_account(&market_pk, base_mint, Pubkey::new_unique(), dex_prog_id);
+    let market_acc: AccountInfo = (&market_pk, &mut account).into();
+    let coin_mint = load_market_state_checked(&market_acc, &dex_prog_id).unwrap().coin_mint;
+    assert_eq!(coin_mint, base_mint.to_aligned_bytes());
+
+    let r = load_market_state_checked(&market_acc, &Pubkey::new_unique());
+    assert!(matches!(r, Err(MangoError::MangoErrorCode { mango_error_code: MangoErrorCode::Default, .. })));
+}
+
+#[test]
+fn test_quote_index_is_shared() {
+    // Test that the quote token of every market is the same last token in the per token arrays
+    assert_eq!(QUOTE_INDEX, NUM_MARKETS);
+    assert_eq!(QUOTE_INDEX, NUM_TOKENS - 1);
+    assert_eq!(get_prodlike_prices()[QUOTE_INDEX], ONE_U64F64);
+
+    // quote held on both markets' open orders is counted as the same token
+    let mango_group = new_mango_group_in_memory([0, 0, 0]);
+    let owner_pk = Pubkey::new_unique();
+    let open_orders_pks = [Pubkey::new_unique(), Pubkey::new_unique()];
+    let mut accounts = [open_orders_account(&owner_pk, u128::MAX), open_orders_account(&owner_pk, u128::MAX)];
+    for (i, account) in accounts.iter_mut().enumerate() {
+        let open_orders: &mut OpenOrders = bytemuck::from_bytes_mut(&mut account.data[5..5 + size_of::<OpenOrders>()]);
+        open_orders.native_pc_total = 100 * (i as u64 + 1);
+    }
+    let [btc_usdt, eth_usdt] = &mut accounts;
+    let open_orders_accs: [AccountInfo; 2] = [
+        (&open_orders_pks[0], btc_usdt).into(), (&open_orders_pks[1], eth_usdt).into()
+    ];
+
+    let mut margin_account = MarginAccount::zeroed();
+    margin_account.deposits[QUOTE_INDEX] = U64F64::from_num(5);
+    let assets = margin_account.get_total_assets(&mango_group, &open_orders_accs).unwrap();
+    assert_eq!(assets, [0, 0, 305]);
+}
+
+#[test]
+fn test_health_from_open_orders_totals() {
+    // Test that health computed from open orders read once matches reading the accounts each time
+    let mut mango_group = new_mango_group_in_memory([0, 0, 0]);
+    mango_group.coll_weights[0] = U64F64::from_num(0.5);
+    let prices = get_prodlike_prices();
+    let owner_pk = Pubkey::new_unique();
+    let open_orders_pk = Pubkey::new_unique();
+
+    let mut btc_usdt = open_orders_account(&owner_pk, u128::MAX);
+    let open_orders: &mut OpenOrders = bytemuck::from_bytes_mut(&mut btc_usdt.data[5..5 + size_of::<OpenOrders>()]);
+    open_orders.native_coin_total = 2;
+    open_orders.native_pc_total = 1000;
+    let mut eth_usdt = Account::default();
+    let pk = Pubkey::default();
+    let open_orders_accs: [AccountInfo; 2] = [(&open_orders_pk, &mut btc_usdt).into(), (&pk, &mut eth_usdt).into()];
+
+    let totals = load_open_orders_totals(&open_orders_accs).unwrap();
+    assert_eq!(totals.coin, [2, 0]);
+    assert_eq!(totals.pc, [1000, 0]);
+
+    let mut margin_account = MarginAccount::zeroed();
+    margin_account.deposits[0] = U64F64::from_num(1);
+    margin_account.borrows[2] = U64F64::from_num(40000);
+
+    // 2 * 50000 + 1000 in open orders and 0.5 * 50000 deposited against 40000 borrowed
+    let health = margin_account.get_health_from_totals(&mango_group, &prices, &totals, 0).unwrap();
+    assert_eq!(health, margin_account.get_health(&mango_group, &prices, &open_orders_accs, 0).unwrap());
+    assert_eq!(health.0, U64F64::from_num(126000));
+    assert_eq!(health.1, U64F64::from_num(40000));
+    assert_eq!(
+        margin_account.get_total_assets_from_totals(&mango_group, &totals).unwrap(),
+        margin_account.get_total_assets(&mango_group, &open_orders_accs).unwrap()
+    );
+}
+
+#[test]
+fn test_has_open_orders() {
+    // Test that only markets with a saved open orders account count as active
+    let mut margin_account = MarginAccount::zeroed();
+    assert!(!margin_account.has_open_orders(0));
+    assert!(!margin_account.has_open_orders(1));
+
+    margin_account.open_orders[1] = Pubkey::new_unique();
+    assert!(!margin_account.has_open_orders(0));
+    assert!(margin_account.has_open_orders(1));
+}
+
+#[test]
+fn test_get_maintenance_deficit() {
+    // Test the quote value that brings an account back to maint_coll_ratio
+    let mut mango_group = new_mango_group_in_memory([0, 0, 0]);
+    mango_group.maint_coll_ratio = U64F64::from_num(1.125);
+    let prices = get_prodlike_prices();
+    let pk = Pubkey::default();
+    let mut accounts = [Account::default(), Account::default()];
+    let open_orders_accs = empty_open_orders(&pk, &mut accounts);
+
+    // no liabs
+    let mut margin_account = MarginAccount::zeroed();
+    margin_account.deposits[0] = U64F64::from_num(1);
+    assert_eq!(margin_account.get_maintenance_deficit(&mango_group, &prices, &open_orders_accs, 0).unwrap(), 0);
+
+    // above maint: 50000 >= 1.125 * 44000
+    margin_account.borrows[2] = U64F64::from_num(44000);
+    assert_eq!(margin_account.get_maintenance_deficit(&mango_group, &prices, &open_orders_accs, 0).unwrap(), 0);
+
+    // below maint: 1.125 * 46000 - 50000
+    margin_account.borrows[2] = U64F64::from_num(46000);
+    assert_eq!(margin_account.get_maintenance_deficit(&mango_group, &prices, &open_orders_accs, 0).unwrap(), 1750);
+
+    // smaller than the deficit to init_coll_ratio of 1.2: 1.2 * 46000 - 50000 = 5200
+    assert!(margin_account.get_collateral_deficit(&mango_group, &prices, &open_orders_accs, 0).unwrap() > 1750);
+}
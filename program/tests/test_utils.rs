@@ -0,0 +1,21 @@
+// Tests related to the helpers in utils
+#![cfg(feature="test-bpf")]
+
+use fixed::types::U64F64;
+
+use mango::utils::format_u64f64;
+
+#[test]
+fn test_format_u64f64() {
+    // Test that values print as rounded decimals with the requested number of fraction digits
+    assert_eq!(format_u64f64(U64F64::from_num(1.2), 6), "1.200000");
+    assert_eq!(format_u64f64(U64F64::from_num(0.0001), 4), "0.0001");
+    assert_eq!(format_u64f64(U64F64::from_num(0.0001), 6), "0.000100");
+    assert_eq!(format_u64f64(U64F64::from_num(50000), 2), "50000.00");
+    assert_eq!(format_u64f64(U64F64::from_num(1.2), 0), "1");
+
+    // rounding can carry into the integer part
+    assert_eq!(format_u64f64(U64F64::from_num(0.99996), 4), "1.0000");
+    assert_eq!(format_u64f64(U64F64::from_num(2.5), 0), "3");
+    assert_eq!(format_u64f64(U64F64::from_num(u64::MAX), 19), "18446744073709551615.0000000000000000000");
+}
@@ -0,0 +1,82 @@
+// Tests related to verifying the configuration of a MangoGroup
+#![cfg(feature="test-bpf")]
+
+mod helpers;
+
+use helpers::*;
+use solana_program_test::*;
+use solana_sdk::{
+    instruction::{Instruction, InstructionError},
+    pubkey::Pubkey,
+    signature::Signer,
+    transaction::{Transaction, TransactionError},
+};
+
+use mango::{
+    entrypoint::process_instruction,
+    error::MangoErrorCode,
+    instruction::verify_group_config,
+};
+
+fn verify_group_config_instruction(mango_group: &TestMangoGroup, vault_pks: &[Pubkey]) -> Instruction {
+    verify_group_config(
+        &mango_group.program_id,
+        &mango_group.mango_group_pk,
+        mango_group.mints.iter().map(|m| m.pubkey).collect::<Vec<Pubkey>>().as_slice(),
+        vault_pks,
+        mango_group.dexes.iter().map(|m| m.pubkey).collect::<Vec<Pubkey>>().as_slice(),
+        mango_group.oracles.iter().map(|m| m.pubkey).collect::<Vec<Pubkey>>().as_slice(),
+    ).unwrap()
+}
+
+#[tokio::test]
+async fn test_verify_group_config() {
+    // Test that a freshly initialized group passes verification without changing, and that
+    // swapping two of its vaults fails it
+    let program_id = Pubkey::new_unique();
+
+    let mut test = ProgramTest::new(
+        "mango",
+        program_id,
+        processor!(process_instruction),
+    );
+
+    // limit to track compute unit increase
+    test.set_bpf_compute_max_units(50_000);
+
+    let mango_group = add_mango_group_prodlike(&mut test, program_id);
+    let vault_pks = mango_group.vaults.iter().map(|m| m.pubkey).collect::<Vec<Pubkey>>();
+
+    let (mut banks_client, payer, recent_blockhash) = test.start().await;
+
+    let mut transaction = Transaction::new_with_payer(
+        &[mango_group.init_mango_group(&payer.pubkey())],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer], recent_blockhash);
+    assert!(banks_client.process_transaction(transaction).await.is_ok());
+
+    let mango_group_before = banks_client.get_account(mango_group.mango_group_pk).await.unwrap().unwrap();
+
+    let mut transaction = Transaction::new_with_payer(
+        &[verify_group_config_instruction(&mango_group, vault_pks.as_slice())],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer], recent_blockhash);
+    assert!(banks_client.process_transaction(transaction).await.is_ok());
+
+    let mango_group_after = banks_client.get_account(mango_group.mango_group_pk).await.unwrap().unwrap();
+    assert_eq!(mango_group_before.data, mango_group_after.data);
+
+    let mut swapped = vault_pks.clone();
+    swapped.swap(0, 1);
+    let mut transaction = Transaction::new_with_payer(
+        &[verify_group_config_instruction(&mango_group, swapped.as_slice())],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer], recent_blockhash);
+    assert_eq!(
+        banks_client.process_transaction(transaction).await.unwrap_err().unwrap(),
+        TransactionError::InstructionError(0, InstructionError::Custom(MangoErrorCode::GroupConfigMismatch.into()))
+    );
+}
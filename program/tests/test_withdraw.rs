@@ -0,0 +1,99 @@
+// Tests related to withdrawing from a MarginAccount
+#![cfg(feature="test-bpf")]
+
+mod helpers;
+
+use fixed::types::U64F64;
+use helpers::*;
+use solana_program_test::*;
+use solana_sdk::{
+    account::Account,
+    instruction::InstructionError,
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+    transaction::{Transaction, TransactionError},
+};
+
+use mango::{
+    entrypoint::process_instruction,
+    error::MangoErrorCode,
+    instruction::{deposit, withdraw},
+    state::NUM_MARKETS,
+};
+
+#[tokio::test]
+async fn test_withdraw_wrong_mint_fails() {
+    // Test that withdrawing to a token account of another mint fails before touching any balance
+    let program_id = Pubkey::new_unique();
+
+    let mut test = ProgramTest::new(
+        "mango",
+        program_id,
+        processor!(process_instruction),
+    );
+
+    // limit to track compute unit increase
+    test.set_bpf_compute_max_units(50_000);
+
+    let mango_group = add_mango_group_prodlike(&mut test, program_id);
+    let user = Keypair::new();
+    test.add_account(user.pubkey(), Account::new(u32::MAX as u64, 0, &user.pubkey()));
+    let btc_account = add_token_account(&mut test, user.pubkey(), mango_group.mints[0].pubkey, 2);
+    let eth_account = add_token_account(&mut test, user.pubkey(), mango_group.mints[1].pubkey, 0);
+    let margin_account_pk = add_margin_account(
+        &mut test, program_id, mango_group.mango_group_pk, user.pubkey(), [0, 0, 0], [0, 0, 0]
+    );
+
+    let (mut banks_client, payer, recent_blockhash) = test.start().await;
+
+    let mut transaction = Transaction::new_with_payer(
+        &[
+            mango_group.init_mango_group(&payer.pubkey()),
+            deposit(
+                &program_id, &mango_group.mango_group_pk, &margin_account_pk, &user.pubkey(),
+                &btc_account.pubkey, &mango_group.vaults[0].pubkey, 2
+            ).unwrap(),
+        ],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer, &user], recent_blockhash);
+    assert!(banks_client.process_transaction(transaction).await.is_ok());
+
+    let withdraw_to = |token_account_pk: &Pubkey| withdraw(
+        &program_id,
+        &mango_group.mango_group_pk,
+        &margin_account_pk,
+        &user.pubkey(),
+        token_account_pk,
+        &mango_group.vaults[0].pubkey,
+        &mango_group.signer_pk,
+        &[Pubkey::default(); NUM_MARKETS],
+        mango_group.oracles.iter().map(|m| m.pubkey).collect::<Vec<Pubkey>>().as_slice(),
+        1,
+    ).unwrap();
+
+    let mut transaction = Transaction::new_with_payer(
+        &[withdraw_to(&eth_account.pubkey)],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer, &user], recent_blockhash);
+    assert_eq!(
+        banks_client.process_transaction(transaction).await.unwrap_err().unwrap(),
+        TransactionError::InstructionError(0, InstructionError::Custom(MangoErrorCode::InvalidTokenAccount.into()))
+    );
+
+    let margin_account = get_margin_account(
+        &mut banks_client, &program_id, &mango_group.mango_group_pk, margin_account_pk
+    ).await;
+    assert_eq!(margin_account.deposits[0], U64F64::from_num(2));
+    assert_eq!(get_token_balance(&mut banks_client, mango_group.vaults[0].pubkey).await, 2);
+
+    // Test the same withdrawal to a BTC account goes through
+    let mut transaction = Transaction::new_with_payer(
+        &[withdraw_to(&btc_account.pubkey)],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer, &user], recent_blockhash);
+    assert!(banks_client.process_transaction(transaction).await.is_ok());
+    assert_eq!(get_token_balance(&mut banks_client, btc_account.pubkey).await, 1);
+}
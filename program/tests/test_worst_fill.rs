@@ -0,0 +1,30 @@
+// Tests related to the worst_fill slippage bound of PlaceAndSettle
+#![cfg(feature="test-bpf")]
+
+use serum_dex::matching::Side;
+
+use mango::{
+    error::{MangoError, MangoErrorCode},
+    processor::check_worst_fill,
+};
+
+#[test]
+fn test_worst_fill() {
+    // Test that a settled fill worse than worst_fill reverts the instruction
+    let is_slippage = |r| matches!(r, Err(MangoError::MangoErrorCode { mango_error_code: MangoErrorCode::SlippageExceeded, .. }));
+
+    // bid that spent 50000 quote
+    assert!(check_worst_fill(Side::Bid, 100_000, 50_000, 50_000).is_ok());
+    assert!(is_slippage(check_worst_fill(Side::Bid, 100_000, 50_000, 49_999)));
+
+    // ask that received 48000 quote
+    assert!(check_worst_fill(Side::Ask, 2000, 50_000, 48_000).is_ok());
+    assert!(is_slippage(check_worst_fill(Side::Ask, 2000, 50_000, 48_001)));
+
+    // an ask that didn't fill received nothing
+    assert!(is_slippage(check_worst_fill(Side::Ask, 2000, 2000, 1)));
+
+    // 0 means no check
+    assert!(check_worst_fill(Side::Bid, 100_000, 0, 0).is_ok());
+    assert!(check_worst_fill(Side::Ask, 2000, 2000, 0).is_ok());
+}